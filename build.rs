@@ -0,0 +1,29 @@
+use std::env;
+use std::process::Command;
+
+// Rebuild when migrations change so `sqlx::migrate!()` embeds the latest set,
+// and record the commit and build time reported by GET /version
+fn main() {
+    println!("cargo:rerun-if-changed=migrations");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+    println!("cargo:rerun-if-env-changed=GIT_SHA");
+
+    println!("cargo:rustc-env=BUILD_GIT_SHA={}", git_sha());
+    println!("cargo:rustc-env=BUILD_TIMESTAMP={}", chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true));
+}
+
+// GIT_SHA when the build environment provides it (e.g. a Docker build without .git), else ask git
+fn git_sha() -> String {
+    if let Ok(sha) = env::var("GIT_SHA") {
+        return sha;
+    }
+    Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|sha| sha.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
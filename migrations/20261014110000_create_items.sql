@@ -0,0 +1,6 @@
+-- Items served by the REST API
+CREATE TABLE IF NOT EXISTS items (
+    id UUID PRIMARY KEY,
+    name TEXT NOT NULL,
+    description TEXT NOT NULL
+);
@@ -0,0 +1,2 @@
+-- Optimistic concurrency control: bumped on every update
+ALTER TABLE items ADD COLUMN IF NOT EXISTS version INTEGER NOT NULL DEFAULT 1;
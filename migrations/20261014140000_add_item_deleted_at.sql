@@ -0,0 +1,2 @@
+-- Soft deletes: a non-null deleted_at hides the item from default reads
+ALTER TABLE items ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMPTZ;
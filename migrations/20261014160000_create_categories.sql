@@ -0,0 +1,10 @@
+-- Categories group items; an item belongs to at most one
+CREATE TABLE IF NOT EXISTS categories (
+    id UUID PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+ALTER TABLE items ADD COLUMN IF NOT EXISTS category_id UUID REFERENCES categories (id);
+
+CREATE INDEX IF NOT EXISTS items_category_id_idx ON items (category_id);
@@ -0,0 +1,3 @@
+-- Item names are unique ignoring case; soft-deleted items don't reserve their name.
+-- Existing live duplicates must be renamed or deleted before this migration can apply.
+CREATE UNIQUE INDEX IF NOT EXISTS items_name_lower_key ON items (lower(name)) WHERE deleted_at IS NULL;
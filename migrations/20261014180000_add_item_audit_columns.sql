@@ -0,0 +1,3 @@
+-- Who created and last changed each item; null for rows written without an authenticated user
+ALTER TABLE items ADD COLUMN IF NOT EXISTS created_by UUID;
+ALTER TABLE items ADD COLUMN IF NOT EXISTS updated_by UUID;
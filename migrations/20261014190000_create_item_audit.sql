@@ -0,0 +1,32 @@
+-- Change history for items, written by a trigger in the same transaction as each mutation.
+-- There is no foreign key so the history outlives hard deletes.
+CREATE TABLE IF NOT EXISTS item_audit (
+    id BIGSERIAL PRIMARY KEY,
+    item_id UUID NOT NULL,
+    operation TEXT NOT NULL CHECK (operation IN ('create', 'update', 'delete')),
+    snapshot JSONB NOT NULL,
+    actor UUID,
+    changed_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS item_audit_item_id_idx ON item_audit (item_id, id);
+
+-- Soft deletes are recorded as deletes; deletes snapshot the row as it was just before
+CREATE OR REPLACE FUNCTION record_item_audit() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        INSERT INTO item_audit (item_id, operation, snapshot, actor) VALUES (NEW.id, 'create', to_jsonb(NEW), NEW.created_by);
+    ELSIF TG_OP = 'UPDATE' AND OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL THEN
+        INSERT INTO item_audit (item_id, operation, snapshot, actor) VALUES (NEW.id, 'delete', to_jsonb(OLD), NEW.updated_by);
+    ELSIF TG_OP = 'UPDATE' THEN
+        INSERT INTO item_audit (item_id, operation, snapshot, actor) VALUES (NEW.id, 'update', to_jsonb(NEW), NEW.updated_by);
+    ELSE
+        INSERT INTO item_audit (item_id, operation, snapshot, actor) VALUES (OLD.id, 'delete', to_jsonb(OLD), NULL);
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS items_audit ON items;
+CREATE TRIGGER items_audit AFTER INSERT OR UPDATE OR DELETE ON items
+    FOR EACH ROW EXECUTE FUNCTION record_item_audit();
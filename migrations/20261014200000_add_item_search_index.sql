@@ -0,0 +1,2 @@
+-- Full-text search over name and description; the expression must match SEARCH_VECTOR in src/db.rs
+CREATE INDEX IF NOT EXISTS items_search_idx ON items USING GIN (to_tsvector('english', name || ' ' || description));
@@ -0,0 +1,12 @@
+-- Every item belongs to one tenant; rows written before multi-tenancy belong to the default (nil) tenant
+ALTER TABLE items ADD COLUMN IF NOT EXISTS tenant_id UUID NOT NULL DEFAULT '00000000-0000-0000-0000-000000000000';
+CREATE INDEX IF NOT EXISTS items_tenant_id_idx ON items (tenant_id);
+
+-- Names only need to be unique within a tenant
+DROP INDEX IF EXISTS items_name_lower_key;
+CREATE UNIQUE INDEX IF NOT EXISTS items_name_lower_key ON items (tenant_id, lower(name)) WHERE deleted_at IS NULL;
+
+-- Idempotency keys are chosen by clients, so two tenants may pick the same one
+ALTER TABLE idempotency_keys ADD COLUMN IF NOT EXISTS tenant_id UUID NOT NULL DEFAULT '00000000-0000-0000-0000-000000000000';
+ALTER TABLE idempotency_keys DROP CONSTRAINT IF EXISTS idempotency_keys_pkey;
+ALTER TABLE idempotency_keys ADD PRIMARY KEY (tenant_id, key);
@@ -0,0 +1,2 @@
+-- Archived items stay fetchable and editable but are left out of listings unless asked for
+ALTER TABLE items ADD COLUMN IF NOT EXISTS archived BOOLEAN NOT NULL DEFAULT false;
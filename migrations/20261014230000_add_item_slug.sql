@@ -0,0 +1,32 @@
+-- URL slug derived from the name, unique per tenant across live and deleted items so restoring
+-- an item never clashes. New slugs are generated by the application; existing rows are backfilled
+-- here with the same rules, oldest first, taking a numeric suffix when a slug is already used.
+-- Character classes depend on the database locale, so the backfill only strips ASCII punctuation
+-- and keeps every non-ASCII character rather than risk dropping letters.
+ALTER TABLE items ADD COLUMN IF NOT EXISTS slug TEXT;
+CREATE UNIQUE INDEX IF NOT EXISTS items_tenant_slug_key ON items (tenant_id, slug);
+
+DO $$
+DECLARE
+    row RECORD;
+    base TEXT;
+    candidate TEXT;
+    suffix INT;
+BEGIN
+    FOR row IN SELECT id, tenant_id, name FROM items WHERE slug IS NULL ORDER BY created_at, id LOOP
+        base := regexp_replace(lower(row.name), '[\x01-\x08\x0e-\x1f\x21-\x2c\x3a-\x40\x5b-\x5e\x60\x7b-\x7f]', '', 'g');
+        base := trim(BOTH '-' FROM regexp_replace(base, '[[:space:]/._-]+', '-', 'g'));
+        IF base = '' THEN
+            base := 'item';
+        END IF;
+        candidate := base;
+        suffix := 1;
+        WHILE EXISTS (SELECT 1 FROM items WHERE tenant_id = row.tenant_id AND slug = candidate) LOOP
+            suffix := suffix + 1;
+            candidate := base || '-' || suffix;
+        END LOOP;
+        UPDATE items SET slug = candidate WHERE id = row.id;
+    END LOOP;
+END $$;
+
+ALTER TABLE items ALTER COLUMN slug SET NOT NULL;
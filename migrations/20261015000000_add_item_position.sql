@@ -0,0 +1,2 @@
+-- Client-chosen display order set by PATCH /items/reorder; items never positioned have none
+ALTER TABLE items ADD COLUMN IF NOT EXISTS position INTEGER;
@@ -0,0 +1,3 @@
+-- Trigram index behind the typo-tolerant `fuzzy` name filter
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+CREATE INDEX IF NOT EXISTS items_name_trgm_idx ON items USING GIN (name gin_trgm_ops);
@@ -0,0 +1,39 @@
+-- Live item count per tenant, kept by a trigger in the same transaction as each mutation so
+-- GET /items/count needn't scan the table. Counts what an unfiltered count reports: items
+-- neither soft-deleted nor archived.
+CREATE TABLE IF NOT EXISTS item_counters (
+    tenant_id UUID PRIMARY KEY,
+    live BIGINT NOT NULL
+);
+
+-- Concurrent writers for one tenant queue on its counter row, so no increment is lost
+CREATE OR REPLACE FUNCTION count_live_items() RETURNS trigger AS $$
+DECLARE
+    delta BIGINT := 0;
+    tenant UUID;
+BEGIN
+    IF TG_OP IN ('UPDATE', 'DELETE') AND OLD.deleted_at IS NULL AND NOT OLD.archived THEN
+        delta := delta - 1;
+        tenant := OLD.tenant_id;
+    END IF;
+    IF TG_OP IN ('INSERT', 'UPDATE') AND NEW.deleted_at IS NULL AND NOT NEW.archived THEN
+        delta := delta + 1;
+        tenant := NEW.tenant_id;
+    END IF;
+    IF delta <> 0 THEN
+        INSERT INTO item_counters (tenant_id, live) VALUES (tenant, delta)
+        ON CONFLICT (tenant_id) DO UPDATE SET live = item_counters.live + EXCLUDED.live;
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS items_count ON items;
+CREATE TRIGGER items_count AFTER INSERT OR UPDATE OF deleted_at, archived OR DELETE ON items
+    FOR EACH ROW EXECUTE FUNCTION count_live_items();
+
+-- Writers wait until the counters are seeded, so none is counted twice or missed
+LOCK TABLE items IN SHARE MODE;
+INSERT INTO item_counters (tenant_id, live)
+SELECT tenant_id, COUNT(*) FROM items WHERE deleted_at IS NULL AND NOT archived GROUP BY tenant_id
+ON CONFLICT (tenant_id) DO UPDATE SET live = EXCLUDED.live;
@@ -0,0 +1,6 @@
+-- Items need not have a description: NULL means none, while existing empty strings stay as they are.
+-- The search index is rebuilt so a missing description doesn't null the whole document; the
+-- expression must match SEARCH_VECTOR in src/db.rs.
+ALTER TABLE items ALTER COLUMN description DROP NOT NULL;
+DROP INDEX IF EXISTS items_search_idx;
+CREATE INDEX items_search_idx ON items USING GIN (to_tsvector('english', name || ' ' || coalesce(description, '')));
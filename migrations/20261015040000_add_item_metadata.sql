@@ -0,0 +1,4 @@
+-- Free-form per-deployment fields on items, stored as sent. NULL means none was ever set.
+-- The GIN index serves containment lookups on the document.
+ALTER TABLE items ADD COLUMN metadata JSONB;
+CREATE INDEX items_metadata_idx ON items USING GIN (metadata);
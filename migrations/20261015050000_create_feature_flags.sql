@@ -0,0 +1,7 @@
+-- Feature flags switched at runtime through PUT /admin/flags/{name}. A flag without a row keeps
+-- its built-in default; every instance picks up changes on its next refresh.
+CREATE TABLE IF NOT EXISTS feature_flags (
+    name TEXT PRIMARY KEY,
+    enabled BOOLEAN NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
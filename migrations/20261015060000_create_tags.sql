@@ -0,0 +1,23 @@
+-- Tags label items, many per item and many items per tag. Names are stored trimmed and lowercased,
+-- unique within a tenant, and created the first time they are assigned.
+CREATE TABLE IF NOT EXISTS tags (
+    id UUID PRIMARY KEY,
+    tenant_id UUID NOT NULL,
+    name TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE (tenant_id, name)
+);
+
+-- Removing an item, even permanently, takes its assignments with it
+CREATE TABLE IF NOT EXISTS item_tags (
+    item_id UUID NOT NULL REFERENCES items (id) ON DELETE CASCADE,
+    tag_id UUID NOT NULL REFERENCES tags (id) ON DELETE CASCADE,
+    PRIMARY KEY (item_id, tag_id)
+);
+
+CREATE INDEX IF NOT EXISTS item_tags_tag_id_idx ON item_tags (tag_id);
+
+-- An item's tag names in order, for the column lists that return items
+CREATE OR REPLACE FUNCTION item_tag_names(item UUID) RETURNS TEXT[] AS $$
+    SELECT ARRAY(SELECT tags.name FROM item_tags JOIN tags ON tags.id = item_tags.tag_id WHERE item_tags.item_id = item ORDER BY tags.name)
+$$ LANGUAGE sql STABLE;
@@ -0,0 +1,3 @@
+-- Price in currency units with cents, kept exact as NUMERIC rather than a float.
+-- NULL means the item has no price.
+ALTER TABLE items ADD COLUMN IF NOT EXISTS price NUMERIC(12, 2) CHECK (price >= 0);
@@ -0,0 +1,12 @@
+-- SHA-256 of an item's name and description as duplicate detection compares them: the name ignoring
+-- case, as the unique name index does, and the description without surrounding whitespace, a missing
+-- one counting as empty. Declared immutable so a generated column can use it; convert_to only
+-- depends on the database encoding, which never changes.
+CREATE OR REPLACE FUNCTION item_content_hash(name TEXT, description TEXT) RETURNS BYTEA AS $$
+    SELECT sha256(convert_to(lower(name) || E'\n' || btrim(coalesce(description, ''), E' \t\r\n'), 'UTF8'))
+$$ LANGUAGE sql IMMUTABLE;
+
+-- Kept current by every write, so creates with ?dedupe=true can find an existing item with the same content
+ALTER TABLE items ADD COLUMN IF NOT EXISTS content_hash BYTEA GENERATED ALWAYS AS (item_content_hash(name, description)) STORED;
+
+CREATE UNIQUE INDEX IF NOT EXISTS items_content_hash_key ON items (tenant_id, content_hash) WHERE deleted_at IS NULL;
@@ -0,0 +1,173 @@
+use tokio::sync::OnceCell;
+use uuid::Uuid;
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::error::ApiError;
+use crate::models::Item;
+
+// Recently fetched items kept in memory for GET /items/{id}, shared by all workers.
+// Off unless ITEM_CACHE_SIZE is set; handlers that change an item must invalidate it after committing.
+// Reads of an item that aren't cached are shared by concurrent requests for it either way.
+pub struct ItemCache {
+    capacity: usize,
+    ttl: Duration,
+    state: Mutex<CacheState>,
+    loads: Mutex<HashMap<LoadKey, Arc<PendingLoad>>>,
+}
+
+// Which read of an item a load is: the tenant, the item id and whether deleted items count
+type LoadKey = (Uuid, Uuid, bool);
+
+// The outcome of a load, set once by whichever request ran it
+type PendingLoad = OnceCell<Result<Item, ApiError>>;
+
+#[derive(Default)]
+struct CacheState {
+    entries: HashMap<Uuid, CachedItem>,
+    // Item ids by when they were last used, least recent first
+    recency: BTreeMap<u64, Uuid>,
+    clock: u64,
+    // Bumped by every invalidation, so a lookup that raced a change doesn't cache what it read
+    epoch: u64,
+}
+
+struct CachedItem {
+    tenant: Uuid,
+    item: Item,
+    stored_at: Instant,
+    last_used: u64,
+}
+
+impl ItemCache {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        ItemCache { capacity, ttl, state: Mutex::new(CacheState::default()), loads: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    fn state(&self) -> MutexGuard<'_, CacheState> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // The cached copy of `tenant`'s item `id`, if one was stored less than the TTL ago
+    pub fn get(&self, tenant: Uuid, id: Uuid) -> Option<Item> {
+        let mut state = self.state();
+        let state = &mut *state;
+        let entry = state.entries.get_mut(&id)?;
+        if entry.stored_at.elapsed() >= self.ttl {
+            state.recency.remove(&entry.last_used);
+            state.entries.remove(&id);
+            return None;
+        }
+        if entry.tenant != tenant {
+            return None;
+        }
+        state.clock += 1;
+        state.recency.remove(&entry.last_used);
+        state.recency.insert(state.clock, id);
+        entry.last_used = state.clock;
+        Some(entry.item.clone())
+    }
+
+    // `tenant`'s item `id` as read by `load`, which is only called when no identical read is already
+    // under way; requests arriving while one is get its result instead of querying again. The item
+    // read is cached. A load whose request goes away is picked up by one of the others waiting on it.
+    pub async fn load<F, Fut>(&self, tenant: Uuid, id: Uuid, include_deleted: bool, load: F) -> Result<Item, ApiError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Item, ApiError>>,
+    {
+        let key = (tenant, id, include_deleted);
+        let pending = self.loads.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).entry(key).or_default().clone();
+        let result = pending
+            .get_or_init(|| async {
+                let epoch = self.epoch();
+                let result = load().await;
+                if let Ok(item) = &result {
+                    self.insert(tenant, item.clone(), epoch);
+                }
+                result
+            })
+            .await
+            .clone();
+
+        // Later requests read afresh, unless a newer load for the key has already taken this one's place
+        let mut loads = self.loads.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if loads.get(&key).is_some_and(|current| Arc::ptr_eq(current, &pending)) {
+            loads.remove(&key);
+        }
+        result
+    }
+
+    // Taken before reading an item from the database and handed back to `insert`
+    pub fn epoch(&self) -> u64 {
+        self.state().epoch
+    }
+
+    // Cache `item` unless something was invalidated since `epoch`, evicting the least recently used item when full
+    pub fn insert(&self, tenant: Uuid, item: Item, epoch: u64) {
+        if !self.enabled() {
+            return;
+        }
+        let mut state = self.state();
+        if state.epoch != epoch {
+            return;
+        }
+        if let Some(previous) = state.entries.remove(&item.id) {
+            state.recency.remove(&previous.last_used);
+        }
+        while state.entries.len() >= self.capacity {
+            let Some((_, oldest)) = state.recency.pop_first() else { break };
+            state.entries.remove(&oldest);
+        }
+        state.clock += 1;
+        let last_used = state.clock;
+        state.recency.insert(last_used, item.id);
+        state.entries.insert(item.id, CachedItem { tenant, item, stored_at: Instant::now(), last_used });
+    }
+
+    // Forget the listed items after a change to them commits
+    pub fn invalidate(&self, ids: impl IntoIterator<Item = Uuid>) {
+        if !self.enabled() {
+            return;
+        }
+        let mut state = self.state();
+        state.epoch += 1;
+        for id in ids {
+            if let Some(entry) = state.entries.remove(&id) {
+                state.recency.remove(&entry.last_used);
+            }
+        }
+    }
+
+    // Forget every item of `tenant`, for changes that don't say which items they touched
+    pub fn invalidate_tenant(&self, tenant: Uuid) {
+        if !self.enabled() {
+            return;
+        }
+        let mut state = self.state();
+        let state = &mut *state;
+        state.epoch += 1;
+        let recency = &mut state.recency;
+        state.entries.retain(|_, entry| {
+            let keep = entry.tenant != tenant;
+            if !keep {
+                recency.remove(&entry.last_used);
+            }
+            keep
+        });
+    }
+}
@@ -0,0 +1,235 @@
+use sqlx::postgres::PgConnectOptions;
+use std::fs::File;
+use std::io::BufReader;
+use std::str::FromStr;
+use std::time::Duration;
+use std::{env, fmt};
+
+use crate::db::{self, PoolSettings, RetryPolicy, TransactionRetry};
+use crate::flags;
+use crate::handlers::{BulkTruncate, DevMode, ImportLimits, MaintenanceOps, MigrationCheck};
+use crate::middleware::{ApiKeyAuth, BodyLogging, ExportLinks, JwtAuth, QueryLimit, RequestTimeout};
+use crate::models::PageSizes;
+use crate::negotiate::JsonStyle;
+use crate::routes::ApiRoutes;
+use crate::DEFAULT_MAX_JSON_BODY_BYTES;
+
+// Seconds an idle keep-alive connection stays open unless HTTP_KEEP_ALIVE_SECS says otherwise
+const DEFAULT_KEEP_ALIVE_SECS: u64 = 75;
+
+// Seconds a cached item is served before it is looked up again, unless ITEM_CACHE_TTL_SECS says otherwise
+const DEFAULT_ITEM_CACHE_TTL_SECS: u64 = 5;
+
+// Where settings come from: the process environment, or a fixed set of variables in tests
+type Lookup<'a> = dyn Fn(&str) -> Option<String> + 'a;
+
+// Reads settings from a lookup, noting every problem instead of stopping at the first
+pub struct EnvReader<'a> {
+    lookup: Box<Lookup<'a>>,
+    problems: Vec<String>,
+}
+
+impl<'a> EnvReader<'a> {
+    pub fn new(lookup: impl Fn(&str) -> Option<String> + 'a) -> Self {
+        EnvReader { lookup: Box::new(lookup), problems: Vec::new() }
+    }
+
+    // The raw value of `name`, if it is set
+    pub fn string(&self, name: &str) -> Option<String> {
+        (self.lookup)(name)
+    }
+
+    // `name` parsed as a `T`, or `default` when it is unset. A value that doesn't parse is noted
+    // and replaced by the default, so the remaining settings are still checked.
+    pub fn parse_or<T>(&mut self, name: &str, default: T) -> T
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        match self.string(name) {
+            Some(value) => value.parse().unwrap_or_else(|err| {
+                self.problem(format!("{} has an invalid value '{}': {}", name, value, err));
+                default
+            }),
+            None => default,
+        }
+    }
+
+    // Note a problem no single parse catches, such as two settings that conflict
+    pub fn problem(&mut self, message: impl Into<String>) {
+        self.problems.push(message.into());
+    }
+}
+
+// Every problem found in the environment at startup
+#[derive(Debug)]
+pub struct ConfigError {
+    pub problems: Vec<String>,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid configuration ({} problems)", self.problems.len())?;
+        for problem in &self.problems {
+            write!(f, "\n  - {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+// Everything the server reads from its environment, loaded and validated once at startup.
+// Shared with handlers as app data so they can consult feature flags.
+pub struct Config {
+    pub database_url: String,
+    pub replica_url: Option<String>,
+    pub pool: PoolSettings,
+    pub retry_policy: RetryPolicy,
+    pub transaction_retry: TransactionRetry,
+    pub bind_addr: String,
+    pub port: u16,
+    pub tls: Option<rustls::ServerConfig>,
+    pub workers: usize,
+    pub keep_alive_secs: u64,
+    pub max_json_bytes: usize,
+    pub query_limit: QueryLimit,
+    pub cors_allowed_origins: Vec<String>,
+    pub cors_max_age_secs: usize,
+    pub api_key_auth: ApiKeyAuth,
+    pub export_links: ExportLinks,
+    pub jwt_auth: JwtAuth,
+    pub import_limits: ImportLimits,
+    pub bulk_truncate: BulkTruncate,
+    pub json_style: JsonStyle,
+    pub page_sizes: PageSizes,
+    pub api_routes: ApiRoutes,
+    pub body_logging: BodyLogging,
+    pub timeouts: RequestTimeout,
+    pub rate_limit_rpm: u32,
+    pub trust_proxy_headers: bool,
+    pub maintenance_mode: bool,
+    pub maintenance_ops: MaintenanceOps,
+    pub dev_mode: DevMode,
+    pub migration_check: MigrationCheck,
+    pub feature_flag_refresh: Duration,
+    pub item_cache_size: usize,
+    pub item_cache_ttl: Duration,
+}
+
+impl Config {
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Config::from_lookup(|name| env::var(name).ok())
+    }
+
+    // Load from `lookup` instead of the process environment
+    pub fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
+        let mut env = EnvReader::new(lookup);
+
+        let database_url = db::database_url_from_env(&mut env);
+        let replica_url = env.string("DATABASE_REPLICA_URL");
+        for (name, url) in [("DATABASE_URL", Some(&database_url)), ("DATABASE_REPLICA_URL", replica_url.as_ref())] {
+            if let Some(url) = url.filter(|url| !url.is_empty()) {
+                if let Err(err) = PgConnectOptions::from_str(url) {
+                    env.problem(format!("{} is not a valid Postgres URL: {}", name, err));
+                }
+            }
+        }
+
+        let default_workers = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        let workers = env.parse_or("HTTP_WORKERS", default_workers);
+        if workers == 0 {
+            env.problem("HTTP_WORKERS must be at least 1");
+        }
+
+        let cors_allowed_origins = env
+            .string("CORS_ALLOWED_ORIGINS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|origin| origin.trim().to_string())
+            .filter(|origin| !origin.is_empty())
+            .collect();
+
+        let config = Config {
+            database_url,
+            replica_url,
+            pool: PoolSettings::from_env(&mut env),
+            retry_policy: RetryPolicy::from_env(&mut env),
+            transaction_retry: TransactionRetry::from_env(&mut env),
+            bind_addr: env.string("BIND_ADDR").unwrap_or_else(|| "127.0.0.1".to_string()),
+            port: env.parse_or("PORT", 8080),
+            tls: tls_from_env(&mut env),
+            workers,
+            keep_alive_secs: env.parse_or("HTTP_KEEP_ALIVE_SECS", DEFAULT_KEEP_ALIVE_SECS),
+            max_json_bytes: env.parse_or("MAX_JSON_BODY_BYTES", DEFAULT_MAX_JSON_BODY_BYTES),
+            query_limit: QueryLimit::from_env(&mut env),
+            cors_allowed_origins,
+            cors_max_age_secs: env.parse_or("CORS_MAX_AGE_SECS", 3600),
+            api_key_auth: ApiKeyAuth::from_env(&mut env),
+            export_links: ExportLinks::from_env(&mut env),
+            jwt_auth: JwtAuth::from_env(&mut env),
+            import_limits: ImportLimits::from_env(&mut env),
+            bulk_truncate: BulkTruncate::from_env(&mut env),
+            json_style: JsonStyle::from_env(&mut env),
+            page_sizes: PageSizes::from_env(&mut env),
+            api_routes: ApiRoutes::from_env(&mut env),
+            body_logging: BodyLogging::from_env(&mut env),
+            timeouts: RequestTimeout::from_env(&mut env),
+            rate_limit_rpm: env.parse_or("RATE_LIMIT_RPM", 0),
+            trust_proxy_headers: env.parse_or("TRUST_PROXY_HEADERS", false),
+            maintenance_mode: env.parse_or("MAINTENANCE_MODE", false),
+            maintenance_ops: MaintenanceOps::from_env(&mut env),
+            dev_mode: DevMode::from_env(&mut env),
+            migration_check: MigrationCheck::from_env(&mut env),
+            feature_flag_refresh: flags::refresh_interval_from_env(&mut env),
+            item_cache_size: env.parse_or("ITEM_CACHE_SIZE", 0),
+            item_cache_ttl: Duration::from_secs(env.parse_or("ITEM_CACHE_TTL_SECS", DEFAULT_ITEM_CACHE_TTL_SECS)),
+        };
+        if env.problems.is_empty() {
+            Ok(config)
+        } else {
+            Err(ConfigError { problems: env.problems })
+        }
+    }
+}
+
+// The rustls server config from the PEM files in TLS_CERT_PATH and TLS_KEY_PATH when both are set.
+// None means plaintext.
+fn tls_from_env(env: &mut EnvReader) -> Option<rustls::ServerConfig> {
+    let (cert_path, key_path) = match (env.string("TLS_CERT_PATH"), env.string("TLS_KEY_PATH")) {
+        (None, None) => return None,
+        (Some(cert), Some(key)) => (cert, key),
+        _ => {
+            env.problem("TLS_CERT_PATH and TLS_KEY_PATH must be set together");
+            return None;
+        }
+    };
+    match load_tls(&cert_path, &key_path) {
+        Ok(config) => Some(config),
+        Err(problem) => {
+            env.problem(problem);
+            None
+        }
+    }
+}
+
+fn load_tls(cert_path: &str, key_path: &str) -> Result<rustls::ServerConfig, String> {
+    let cert_file = File::open(cert_path).map_err(|err| format!("Failed to open TLS_CERT_PATH '{}': {}", cert_path, err))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(cert_file))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| format!("Failed to parse certificates in '{}': {}", cert_path, err))?;
+    if certs.is_empty() {
+        return Err(format!("No certificates found in TLS_CERT_PATH '{}'", cert_path));
+    }
+
+    let key_file = File::open(key_path).map_err(|err| format!("Failed to open TLS_KEY_PATH '{}': {}", key_path, err))?;
+    let key = rustls_pemfile::pkcs8_private_keys(&mut BufReader::new(key_file))
+        .next()
+        .ok_or_else(|| format!("No PKCS#8 private key found in TLS_KEY_PATH '{}'", key_path))?
+        .map_err(|err| format!("Failed to parse private key in '{}': {}", key_path, err))?;
+
+    rustls::ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key.into())
+        .map_err(|err| format!("Invalid TLS certificate/key pair: {}", err))
+}
@@ -0,0 +1,759 @@
+use actix_web::rt::time::sleep;
+use chrono::{DateTime, Utc};
+use futures_util::future::BoxFuture;
+use log::LevelFilter;
+use sqlx::migrate::Migrator;
+use sqlx::pool::PoolConnection;
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use sqlx::{ConnectOptions, Executor, PgConnection, PgPool, Postgres, QueryBuilder, Transaction};
+use uuid::Uuid;
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::future::Future;
+use std::ops::{Deref, DerefMut};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::error::ApiError;
+use crate::config::EnvReader;
+use crate::models::{escape_like, Item, ItemCreateRequest, ItemFilter, ItemPatchRequest, Visibility, MAX_NAME_LEN};
+
+// Connection pool sizing and timeouts, from the DB_* settings
+#[derive(Debug, Clone, Copy)]
+pub struct PoolSettings {
+    pub max_connections: u32,
+    pub min_connections: u32,
+    pub acquire_timeout_secs: u64,
+    pub idle_timeout_secs: u64,
+    pub statement_timeout_ms: u64,
+    // Pinging each connection before handing it out costs a round trip per acquire,
+    // but keeps connections severed by a failover from failing the next requests
+    pub test_before_acquire: bool,
+    pub slow_query_threshold_ms: u64,
+    // Connections older than this are closed and replaced, before a proxy or load balancer
+    // silently drops them; 0 keeps connections for as long as they stay healthy
+    pub max_lifetime_secs: u64,
+}
+
+impl Default for PoolSettings {
+    fn default() -> Self {
+        PoolSettings {
+            max_connections: 10,
+            min_connections: 0,
+            acquire_timeout_secs: 30,
+            idle_timeout_secs: 600,
+            statement_timeout_ms: 30_000,
+            test_before_acquire: true,
+            slow_query_threshold_ms: 500,
+            max_lifetime_secs: 1800,
+        }
+    }
+}
+
+impl PoolSettings {
+    pub fn from_env(env: &mut EnvReader) -> Self {
+        let defaults = PoolSettings::default();
+        let settings = PoolSettings {
+            max_connections: env.parse_or("DB_MAX_CONNECTIONS", defaults.max_connections),
+            min_connections: env.parse_or("DB_MIN_CONNECTIONS", defaults.min_connections),
+            acquire_timeout_secs: env.parse_or("DB_ACQUIRE_TIMEOUT_SECS", defaults.acquire_timeout_secs),
+            idle_timeout_secs: env.parse_or("DB_IDLE_TIMEOUT_SECS", defaults.idle_timeout_secs),
+            statement_timeout_ms: env.parse_or("DB_STATEMENT_TIMEOUT_MS", defaults.statement_timeout_ms),
+            test_before_acquire: env.parse_or("DB_TEST_BEFORE_ACQUIRE", defaults.test_before_acquire),
+            slow_query_threshold_ms: env.parse_or("SLOW_QUERY_THRESHOLD_MS", defaults.slow_query_threshold_ms),
+            max_lifetime_secs: env.parse_or("DB_MAX_LIFETIME_SECS", defaults.max_lifetime_secs),
+        };
+        if settings.max_connections == 0 {
+            env.problem("DB_MAX_CONNECTIONS must be at least 1");
+        }
+        if settings.min_connections > settings.max_connections {
+            env.problem(format!(
+                "DB_MIN_CONNECTIONS ({}) must not exceed DB_MAX_CONNECTIONS ({})",
+                settings.min_connections, settings.max_connections
+            ));
+        }
+        settings
+    }
+
+    // How long a connection may live before it is recycled, or None when DB_MAX_LIFETIME_SECS is 0
+    pub fn max_lifetime(&self) -> Option<Duration> {
+        (self.max_lifetime_secs > 0).then(|| Duration::from_secs(self.max_lifetime_secs))
+    }
+}
+
+// The libpq variables a connection string is assembled from when DATABASE_URL is unset, without
+// the optional PGPORT and PGPASSWORD
+const REQUIRED_PG_PARTS: [&str; 3] = ["PGHOST", "PGUSER", "PGDATABASE"];
+
+// DATABASE_URL, or when it is unset a URL assembled from PGHOST, PGPORT, PGUSER, PGPASSWORD and
+// PGDATABASE, as some platforms inject them. PGPORT defaults to 5432 and PGPASSWORD may be left out.
+pub fn database_url_from_env(env: &mut EnvReader) -> String {
+    if let Some(url) = env.string("DATABASE_URL") {
+        return url;
+    }
+    let missing: Vec<_> = REQUIRED_PG_PARTS.into_iter().filter(|name| env.string(name).is_none()).collect();
+    if !missing.is_empty() {
+        env.problem(format!("DATABASE_URL must be set, or else {} (missing {})", REQUIRED_PG_PARTS.join(", "), missing.join(", ")));
+        return String::new();
+    }
+    let port = env.parse_or("PGPORT", 5432);
+    let part = |name| env.string(name).unwrap_or_default();
+    connection_url(&part("PGHOST"), port, &part("PGUSER"), env.string("PGPASSWORD").as_deref(), &part("PGDATABASE"))
+}
+
+// A postgres:// URL from its parts. The user, password and database are percent-encoded, an IPv6
+// host is bracketed and a host that is a socket directory goes in the `host` parameter instead.
+pub fn connection_url(host: &str, port: u16, user: &str, password: Option<&str>, database: &str) -> String {
+    let credentials = match password {
+        Some(password) => format!("{}:{}", percent_encode(user), percent_encode(password)),
+        None => percent_encode(user),
+    };
+    let database = percent_encode(database);
+    if host.starts_with('/') {
+        format!("postgres://{}@localhost:{}/{}?host={}", credentials, port, database, percent_encode(host))
+    } else if host.contains(':') && !host.starts_with('[') {
+        format!("postgres://{}@[{}]:{}/{}", credentials, host, port, database)
+    } else {
+        format!("postgres://{}@{}:{}/{}", credentials, host, port, database)
+    }
+}
+
+// Percent-encode every byte but the unreserved characters of RFC 3986
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+// The migrations embedded in the binary from migrations/
+static MIGRATOR: Migrator = sqlx::migrate!();
+
+// Open the primary connection pool and bring the schema up to date
+pub async fn connect(database_url: &str, settings: &PoolSettings) -> PgPool {
+    let pool = open_pool(database_url, settings).await;
+    MIGRATOR
+        .run(&pool)
+        .await
+        .expect("Failed to run database migrations");
+    pool
+}
+
+// How many embedded migrations the database has not successfully applied, as recorded in the
+// _sqlx_migrations table the migrator keeps. The table is found through search_path, so a test can
+// point this at a schema of its own.
+pub async fn pending_migrations(pool: &PgPool) -> Result<usize, sqlx::Error> {
+    let applied: HashSet<i64> = sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success").fetch_all(pool).await?.into_iter().collect();
+    Ok(MIGRATOR.iter().filter(|migration| !applied.contains(&migration.version)).count())
+}
+
+// Pool for read-only queries: the replica named by DATABASE_REPLICA_URL, or the primary when none is configured
+#[derive(Clone)]
+pub struct ReadPool(pub PgPool);
+
+// Open the replica pool with the same DB_* settings; migrations only ever run on the primary
+pub async fn connect_replica(database_url: &str, settings: &PoolSettings) -> ReadPool {
+    ReadPool(open_pool(database_url, settings).await)
+}
+
+// Open a connection pool sized and timed from the DB_* settings
+async fn open_pool(database_url: &str, settings: &PoolSettings) -> PgPool {
+    let PoolSettings {
+        max_connections,
+        min_connections,
+        acquire_timeout_secs,
+        idle_timeout_secs,
+        statement_timeout_ms,
+        test_before_acquire,
+        slow_query_threshold_ms,
+        max_lifetime_secs,
+    } = *settings;
+    info!(max_connections, min_connections, acquire_timeout_secs, idle_timeout_secs, statement_timeout_ms, test_before_acquire, slow_query_threshold_ms, max_lifetime_secs, "configuring database pool");
+
+    // sqlx logs each statement's SQL text with its placeholders, never the bound values. Statements run
+    // inside the request's span, so a slow one is logged at WARN alongside the request id.
+    let mut options = PgConnectOptions::from_str(database_url).expect("Invalid database URL");
+    options
+        .log_statements(LevelFilter::Debug)
+        .log_slow_statements(LevelFilter::Warn, Duration::from_millis(slow_query_threshold_ms));
+
+    with_statement_timeout(PgPoolOptions::new(), statement_timeout_ms)
+        .max_connections(max_connections)
+        .min_connections(min_connections)
+        .acquire_timeout(Duration::from_secs(acquire_timeout_secs))
+        .idle_timeout(Duration::from_secs(idle_timeout_secs))
+        .test_before_acquire(test_before_acquire)
+        .max_lifetime(settings.max_lifetime())
+        .connect_with(options)
+        .await
+        .expect("Failed to create pool")
+}
+
+// Have Postgres cancel any statement on the pool's connections that runs longer than `timeout_ms`; 0 disables it.
+// Cancelled queries surface as a 504 through ApiError.
+pub fn with_statement_timeout(options: PgPoolOptions, timeout_ms: u64) -> PgPoolOptions {
+    options.after_connect(move |conn, _meta| {
+        Box::pin(async move {
+            conn.execute(format!("SET statement_timeout = {}", timeout_ms).as_str()).await?;
+            Ok(())
+        })
+    })
+}
+
+// Insert a new item for `tenant` with a freshly generated id and slug, recording `created_by` as its creator.
+// Must run inside a transaction, which holds the tenant's slug lock until it ends.
+pub async fn insert_item(conn: &mut PgConnection, tenant: Uuid, item: &ItemCreateRequest, created_by: Option<Uuid>) -> Result<Item, sqlx::Error> {
+    let slug = unique_slug(&mut *conn, tenant, &item.name, None).await?;
+    sqlx::query_as!(
+        Item,
+        "INSERT INTO items (id, name, description, category_id, created_by, updated_by, tenant_id, slug, metadata, price, created_at, updated_at)
+         VALUES ($1, $2, $3, $4, $5, $5, $6, $7, $8, $9, now(), now())
+         RETURNING id, name, description, created_at, updated_at, version, deleted_at, category_id, created_by, updated_by, archived, slug, position, metadata, price, item_tag_names(id) AS \"tags!\"",
+        Uuid::new_v4(),
+        item.name,
+        item.description,
+        item.category_id,
+        created_by,
+        tenant,
+        slug,
+        item.metadata,
+        item.price
+    )
+    .fetch_one(conn)
+    .await
+}
+
+// Apply a merge patch to `tenant`'s live item `id` if it is still at `version`, giving a new name a new
+// slug; renaming to the current name keeps it. None when no row matched. Must run inside a transaction.
+pub async fn apply_patch(conn: &mut PgConnection, tenant: Uuid, id: Uuid, version: i32, patch: &ItemPatchRequest, updated_by: Option<Uuid>) -> Result<Option<Item>, sqlx::Error> {
+    let slug = match &patch.name {
+        Some(name) => Some(unique_slug(&mut *conn, tenant, name, Some(id)).await?),
+        None => None,
+    };
+    sqlx::query_as!(
+        Item,
+        "UPDATE items SET name = COALESCE($1, name), description = CASE WHEN $10 THEN $2 ELSE description END,
+             category_id = CASE WHEN $7 THEN $3 ELSE category_id END, updated_by = $6, updated_at = now(), version = version + 1,
+             slug = CASE WHEN $1 IS NULL OR $1 = name THEN slug ELSE $9 END,
+             metadata = CASE WHEN $11 THEN $12 ELSE metadata END,
+             price = CASE WHEN $13 THEN $14 ELSE price END
+         WHERE id = $4 AND tenant_id = $8 AND version = $5 AND deleted_at IS NULL
+         RETURNING id, name, description, created_at, updated_at, version, deleted_at, category_id, created_by, updated_by, archived, slug, position, metadata, price, item_tag_names(id) AS \"tags!\"",
+        patch.name,
+        patch.description.clone().flatten(),
+        patch.category_id.flatten(),
+        id,
+        version,
+        updated_by,
+        patch.category_id.is_some(),
+        tenant,
+        slug,
+        patch.description.is_some(),
+        patch.metadata.is_some(),
+        patch.metadata.clone().flatten(),
+        patch.price.is_some(),
+        patch.price.flatten()
+    )
+    .fetch_optional(conn)
+    .await
+}
+
+// `tenant`'s live item `id`, locked until the transaction ends so an update in the same transaction
+// starts from exactly this row
+pub async fn lock_live_item(conn: &mut PgConnection, tenant: Uuid, id: Uuid) -> Result<Option<Item>, sqlx::Error> {
+    sqlx::query_as!(
+        Item,
+        "SELECT id, name, description, created_at, updated_at, version, deleted_at, category_id, created_by, updated_by, archived, slug, position, metadata, price, item_tag_names(id) AS \"tags!\"
+         FROM items WHERE id = $1 AND tenant_id = $2 AND deleted_at IS NULL FOR UPDATE",
+        id,
+        tenant
+    )
+    .fetch_optional(conn)
+    .await
+}
+
+// Give item `id` every tag in `tags` (already normalized) it doesn't carry yet, creating the tenant's
+// missing tags on the way. Returns how many were newly assigned. Must run inside a transaction.
+pub async fn assign_tags(conn: &mut PgConnection, tenant: Uuid, id: Uuid, tags: &[String]) -> Result<u64, sqlx::Error> {
+    for tag in tags {
+        sqlx::query!("INSERT INTO tags (id, tenant_id, name) VALUES ($1, $2, $3) ON CONFLICT (tenant_id, name) DO NOTHING", Uuid::new_v4(), tenant, tag)
+            .execute(&mut *conn)
+            .await?;
+    }
+    let assigned = sqlx::query!(
+        "INSERT INTO item_tags (item_id, tag_id) SELECT $1, id FROM tags WHERE tenant_id = $2 AND name = ANY($3) ON CONFLICT DO NOTHING",
+        id,
+        tenant,
+        tags
+    )
+    .execute(conn)
+    .await?;
+    Ok(assigned.rows_affected())
+}
+
+// Take `tag` (already normalized) off item `id`, reporting whether it was there. The tag itself is kept.
+pub async fn remove_tag(conn: &mut PgConnection, tenant: Uuid, id: Uuid, tag: &str) -> Result<bool, sqlx::Error> {
+    let removed = sqlx::query!(
+        "DELETE FROM item_tags WHERE item_id = $1 AND tag_id = (SELECT id FROM tags WHERE tenant_id = $2 AND name = $3)",
+        id,
+        tenant,
+        tag
+    )
+    .execute(conn)
+    .await?;
+    Ok(removed.rows_affected() > 0)
+}
+
+// Record a change made outside the items row, such as to its tags, bumping the version so
+// validators and sync clients notice it
+pub async fn touch_item(conn: &mut PgConnection, tenant: Uuid, id: Uuid, updated_by: Option<Uuid>) -> Result<Item, sqlx::Error> {
+    sqlx::query_as!(
+        Item,
+        "UPDATE items SET updated_by = $3, updated_at = now(), version = version + 1 WHERE id = $1 AND tenant_id = $2
+         RETURNING id, name, description, created_at, updated_at, version, deleted_at, category_id, created_by, updated_by, archived, slug, position, metadata, price, item_tag_names(id) AS \"tags!\"",
+        id,
+        tenant,
+        updated_by
+    )
+    .fetch_one(conn)
+    .await
+}
+
+// Slug used when a name has no letters or digits at all
+const FALLBACK_SLUG: &str = "item";
+
+// URL slug for an item name: lowercased letters and digits from any script, with each run of
+// whitespace, `-`, `_`, `.` or `/` becoming one hyphen and all other punctuation dropped.
+// Mirrors the backfill in the add_item_slug migration.
+pub fn slugify(name: &str) -> String {
+    let mut slug = String::with_capacity(name.len());
+    let mut separated = false;
+    for c in name.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            if separated && !slug.is_empty() {
+                slug.push('-');
+            }
+            separated = false;
+            slug.push(c);
+        } else if c.is_whitespace() || matches!(c, '-' | '_' | '.' | '/') {
+            separated = true;
+        }
+    }
+    if slug.is_empty() {
+        slug.push_str(FALLBACK_SLUG);
+    }
+    slug
+}
+
+// A slug for `name` that no other item of `tenant` has, live or deleted, adding the first free
+// numeric suffix ("widget-2") on collision. `renaming` is the item being renamed, whose current
+// slug counts as free. Slug assignment is serialised per tenant until the caller's transaction
+// ends, so concurrent writers never pick the same one and the unique index never has to reject it.
+pub async fn unique_slug(conn: &mut PgConnection, tenant: Uuid, name: &str, renaming: Option<Uuid>) -> Result<String, sqlx::Error> {
+    sqlx::query("SELECT pg_advisory_xact_lock(hashtextextended('item-slug:' || $1::text, 0))")
+        .bind(tenant)
+        .execute(&mut *conn)
+        .await?;
+
+    // Slugs hold only letters, digits and hyphens, so the base needs no LIKE escaping
+    let base = slugify(name);
+    let taken: HashSet<String> = sqlx::query_scalar!(
+        "SELECT slug FROM items WHERE tenant_id = $1 AND id IS DISTINCT FROM $2 AND (slug = $3 OR slug LIKE $4)",
+        tenant,
+        renaming,
+        base,
+        format!("{}-%", base)
+    )
+    .fetch_all(conn)
+    .await?
+    .into_iter()
+    .collect();
+
+    if !taken.contains(&base) {
+        return Ok(base);
+    }
+    let mut suffix = 2;
+    while taken.contains(&format!("{}-{}", base, suffix)) {
+        suffix += 1;
+    }
+    Ok(format!("{}-{}", base, suffix))
+}
+
+// How often a transaction that lost a serialization conflict or deadlock is rerun, from TX_RETRY_*
+#[derive(Debug, Clone, Copy)]
+pub struct TransactionRetry {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+}
+
+impl TransactionRetry {
+    pub fn from_env(env: &mut EnvReader) -> Self {
+        TransactionRetry {
+            max_retries: env.parse_or("TX_RETRY_MAX_ATTEMPTS", 3),
+            base_delay: Duration::from_millis(env.parse_or("TX_RETRY_BASE_DELAY_MS", 10)),
+        }
+    }
+
+    // Exponential backoff with half of each delay random, so transactions that collided once
+    // don't retry in lockstep and collide again
+    fn delay(&self, attempt: u32) -> Duration {
+        let half = self.base_delay.saturating_mul(2u32.saturating_pow(attempt)) / 2;
+        half + half.mul_f64(fastrand::f64())
+    }
+}
+
+// Room kept after the copied name for the longest " (copy N)" suffix we expect to need
+const COPY_SUFFIX_ROOM: usize = 13;
+
+// Serialise creates of `name` within `tenant`, ignoring case as the unique index does, until the
+// caller's transaction ends, then refuse the name if a live item already has it. Two concurrent
+// creates of one name thus meet here and the loser gets a clean 409. Take it before unique_slug,
+// whose tenant-wide lock must never be held while waiting for this one.
+pub async fn claim_item_name(conn: &mut PgConnection, tenant: Uuid, name: &str) -> Result<(), ApiError> {
+    sqlx::query("SELECT pg_advisory_xact_lock(hashtextextended('item-name:' || $1::text || ':' || lower($2), 0))")
+        .bind(tenant)
+        .bind(name)
+        .execute(&mut *conn)
+        .await?;
+
+    let taken = sqlx::query_scalar!(
+        r#"SELECT EXISTS(SELECT 1 FROM items WHERE tenant_id = $1 AND lower(name) = lower($2) AND deleted_at IS NULL) AS "taken!""#,
+        tenant,
+        name
+    )
+    .fetch_one(conn)
+    .await?;
+    if taken {
+        return Err(ApiError::Conflict("An item with that name already exists".to_string()));
+    }
+    Ok(())
+}
+
+// The live item of `tenant` with the same name and description as `item`, compared as the
+// content_hash column does: the name ignoring case, the description ignoring surrounding whitespace
+pub async fn find_duplicate_item(conn: &mut PgConnection, tenant: Uuid, item: &ItemCreateRequest) -> Result<Option<Item>, sqlx::Error> {
+    sqlx::query_as!(
+        Item,
+        "SELECT id, name, description, created_at, updated_at, version, deleted_at, category_id, created_by, updated_by, archived, slug, position, metadata, price, item_tag_names(id) AS \"tags!\" FROM items
+         WHERE tenant_id = $1 AND content_hash = item_content_hash($2, $3) AND deleted_at IS NULL",
+        tenant,
+        item.name,
+        item.description
+    )
+    .fetch_optional(conn)
+    .await
+}
+
+// The name for a copy of `name` that no live item of `tenant` has, ignoring case: "<name> (copy)",
+// then "<name> (copy 2)" and so on, with a long name shortened so the suffix fits. Serialised per
+// tenant until the caller's transaction ends, so concurrent copies never race for the same name.
+pub async fn copy_name(conn: &mut PgConnection, tenant: Uuid, name: &str) -> Result<String, sqlx::Error> {
+    sqlx::query("SELECT pg_advisory_xact_lock(hashtextextended('item-copy-name:' || $1::text, 0))")
+        .bind(tenant)
+        .execute(&mut *conn)
+        .await?;
+
+    let stem: String = name.chars().take(MAX_NAME_LEN as usize - COPY_SUFFIX_ROOM).collect();
+    let taken: HashSet<String> = sqlx::query_scalar!(
+        r#"SELECT lower(name) AS "name!" FROM items WHERE tenant_id = $1 AND deleted_at IS NULL AND lower(name) LIKE lower($2)"#,
+        tenant,
+        format!("{} (copy%", escape_like(&stem))
+    )
+    .fetch_all(conn)
+    .await?
+    .into_iter()
+    .collect();
+
+    let first = format!("{} (copy)", stem);
+    if !taken.contains(&first.to_lowercase()) {
+        return Ok(first);
+    }
+    let mut suffix = 2;
+    while taken.contains(&format!("{} (copy {})", stem, suffix).to_lowercase()) {
+        suffix += 1;
+    }
+    Ok(format!("{} (copy {})", stem, suffix))
+}
+
+// Run `operation` in a transaction, committing when it returns Ok and rolling back when it fails,
+// so an early return can never leave a transaction open or half-applied. The closure gets the
+// transaction's connection and owns whatever it needs, as the boxed future outlives no borrow.
+// An attempt that fails with a serialization failure or deadlock (40001, 40P01), including at
+// commit, is rolled back and `operation` is run again from the start, up to `retry.max_retries`
+// times; every other error is returned at once.
+pub async fn with_transaction<T, F>(pool: &PgPool, retry: &TransactionRetry, operation: F) -> Result<T, ApiError>
+where
+    F: for<'c> Fn(&'c mut PgConnection) -> BoxFuture<'c, Result<T, ApiError>>,
+{
+    let mut attempt = 0;
+    loop {
+        match timed_query(transaction_attempt(pool, &operation)).await {
+            Err(ApiError::TransactionConflict(_)) if attempt < retry.max_retries => {
+                let delay = retry.delay(attempt);
+                attempt += 1;
+                warn!(attempt, delay_ms = delay.as_millis() as u64, "transaction conflicted with a concurrent one; retrying");
+                sleep(delay).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+async fn transaction_attempt<T, F>(pool: &PgPool, operation: &F) -> Result<T, ApiError>
+where
+    F: for<'c> Fn(&'c mut PgConnection) -> BoxFuture<'c, Result<T, ApiError>>,
+{
+    let mut tx = pool.begin().await?;
+    match operation(&mut tx).await {
+        Ok(value) => {
+            tx.commit().await?;
+            Ok(value)
+        }
+        Err(err) => {
+            if let Err(rollback) = tx.rollback().await {
+                warn!(error = %rollback, "failed to roll back transaction");
+            }
+            Err(err)
+        }
+    }
+}
+
+// Look up the item `tenant` previously created under `key` within the last 24 hours; reusing a live
+// key with a different body is rejected. Serialises requests with the key until the caller's
+// transaction ends, so a retry that overlaps the original waits for it and then finds its item; take
+// it before claim_item_name. An expired copy of the key is dropped so the key can be stored afresh.
+pub async fn find_idempotent_item(conn: &mut PgConnection, tenant: Uuid, key: &str, request_hash: &str) -> Result<Option<Item>, ApiError> {
+    sqlx::query("SELECT pg_advisory_xact_lock(hashtextextended('idempotency-key:' || $1::text || ':' || $2, 0))")
+        .bind(tenant)
+        .bind(key)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query!(
+        "DELETE FROM idempotency_keys WHERE tenant_id = $1 AND key = $2 AND created_at < now() - interval '24 hours'",
+        tenant,
+        key
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    let stored = sqlx::query!("SELECT request_hash, item_id FROM idempotency_keys WHERE tenant_id = $1 AND key = $2", tenant, key)
+        .fetch_optional(&mut *conn)
+        .await?;
+    let Some(stored) = stored else {
+        return Ok(None);
+    };
+    if stored.request_hash != request_hash {
+        return Err(ApiError::Unprocessable(
+            "Idempotency-Key was already used with a different request body".to_string(),
+        ));
+    }
+
+    let item = sqlx::query_as!(
+        Item,
+        "SELECT id, name, description, created_at, updated_at, version, deleted_at, category_id, created_by, updated_by, archived, slug, position, metadata, price, item_tag_names(id) AS \"tags!\" FROM items WHERE id = $1",
+        stored.item_id
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    Ok(Some(item))
+}
+
+// How often keys older than the 24 hours find_idempotent_item honours are deleted
+pub const IDEMPOTENCY_KEY_PURGE_INTERVAL: Duration = Duration::from_secs(3600);
+
+// Delete every expired idempotency key, returning how many there were
+pub async fn purge_expired_idempotency_keys(pool: &PgPool) -> Result<u64, sqlx::Error> {
+    let purged = sqlx::query!("DELETE FROM idempotency_keys WHERE created_at < now() - interval '24 hours'").execute(pool).await?;
+    Ok(purged.rows_affected())
+}
+
+// Document searched by `search`; must match the expression of the items_search_idx GIN index
+pub const SEARCH_VECTOR: &str = "to_tsvector('english', name || ' ' || coalesce(description, ''))";
+
+// Append the WHERE clause shared by the list and count queries, binding all user input
+pub fn push_item_filters(query: &mut QueryBuilder<'_, Postgres>, tenant: Uuid, filter: &ItemFilter, visibility: &Visibility) {
+    query.push(" WHERE tenant_id = ").push_bind(tenant);
+    if let Some(search) = filter.search_term() {
+        query.push(" AND name ILIKE '%' || ").push_bind(search).push(" || '%'");
+    }
+    if let Some(search) = filter.full_text_query() {
+        query
+            .push(format_args!(" AND {} @@ plainto_tsquery('english', ", SEARCH_VECTOR))
+            .push_bind(search.to_string())
+            .push(")");
+    }
+    if let Some(fuzzy) = filter.fuzzy_term() {
+        query.push(" AND name % ").push_bind(fuzzy.to_string());
+    }
+    if let Some(category_id) = filter.category_id {
+        query.push(" AND category_id = ").push_bind(category_id);
+    }
+    if let Some(updated_since) = filter.updated_since {
+        query.push(" AND updated_at > ").push_bind(updated_since);
+    }
+    if let Some(tag) = filter.tag_name() {
+        query
+            .push(" AND EXISTS (SELECT 1 FROM item_tags JOIN tags ON tags.id = item_tags.tag_id WHERE item_tags.item_id = items.id AND tags.name = ")
+            .push_bind(tag)
+            .push(")");
+    }
+    for (key, value) in &filter.metadata {
+        query.push(" AND metadata ->> ").push_bind(key.clone()).push(" = ").push_bind(value.clone());
+    }
+    // A sync client needs to see deletions and archivals to reconcile its copy
+    if !visibility.include_deleted && filter.updated_since.is_none() {
+        query.push(" AND deleted_at IS NULL");
+    }
+    if !filter.include_archived && filter.updated_since.is_none() {
+        query.push(" AND NOT archived");
+    }
+}
+
+// How many times, and how patiently, transient database failures are retried
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+}
+
+impl RetryPolicy {
+    pub fn from_env(env: &mut EnvReader) -> Self {
+        RetryPolicy {
+            max_retries: env.parse_or("DB_RETRY_MAX_ATTEMPTS", 3),
+            base_delay: Duration::from_millis(env.parse_or("DB_RETRY_BASE_DELAY_MS", 50)),
+        }
+    }
+}
+
+// Pool timeouts and connection-level failures; constraint violations and query errors are never retried
+fn is_transient(err: &sqlx::Error) -> bool {
+    matches!(err, sqlx::Error::PoolTimedOut | sqlx::Error::Io(_) | sqlx::Error::Tls(_))
+}
+
+// Run a database operation, retrying transient failures with exponential backoff.
+// Only used for reads: after an I/O error we can't tell whether a write was committed.
+pub async fn with_retry<T, F, Fut>(policy: &RetryPolicy, mut operation: F) -> Result<T, sqlx::Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, sqlx::Error>>,
+{
+    let mut attempt = 0;
+    loop {
+        match timed_query(operation()).await {
+            Err(err) if attempt < policy.max_retries && is_transient(&err) => {
+                let delay = policy.base_delay.saturating_mul(2u32.saturating_pow(attempt));
+                attempt += 1;
+                warn!(attempt, delay_ms = delay.as_millis() as u64, error = %err, "transient database error; retrying");
+                sleep(delay).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+tokio::task_local! {
+    // Time the request being served has spent in timed_query, while measure_db_time is counting it
+    static DB_TIME: Cell<Duration>;
+}
+
+// Run `request`, adding up the time its database work spends in timed_query, and return that
+// total along with its output
+pub async fn measure_db_time<F: Future>(request: F) -> (F::Output, Duration) {
+    DB_TIME
+        .scope(Cell::new(Duration::ZERO), async move {
+            let output = request.await;
+            (output, DB_TIME.with(Cell::get))
+        })
+        .await
+}
+
+// Await `query`, counting how long it takes towards the current request's database time; outside
+// measure_db_time it is only awaited. with_retry and with_transaction time every attempt with this,
+// so it must not be nested inside them or the time is counted twice; handlers wrap every other query
+// or transaction they run in it.
+pub async fn timed_query<F: Future>(query: F) -> F::Output {
+    let started = Instant::now();
+    let output = query.await;
+    let _ = DB_TIME.try_with(|total| total.set(total.get() + started.elapsed()));
+    output
+}
+
+// A connection to run a query built with push_item_filters on. The fuzzy filter's `%` keeps names
+// at least as similar as pg_trgm.similarity_threshold, so a fuzzy query gets a transaction with that
+// set to min_sim locally: dropping the transaction rolls it back, and the pooled connection keeps its
+// own threshold. Any other query gets a plain pooled connection.
+pub async fn filter_connection(pool: &PgPool, filter: &ItemFilter) -> Result<FilterConnection, sqlx::Error> {
+    if filter.fuzzy_term().is_none() {
+        return Ok(FilterConnection::Plain(pool.acquire().await?));
+    }
+    let mut tx = pool.begin().await?;
+    sqlx::query("SELECT set_config('pg_trgm.similarity_threshold', $1, true)")
+        .bind(filter.min_similarity().to_string())
+        .execute(&mut *tx)
+        .await?;
+    Ok(FilterConnection::Fuzzy(tx))
+}
+
+// What filter_connection hands out; either one derefs to the connection to query on
+pub enum FilterConnection {
+    Plain(PoolConnection<Postgres>),
+    Fuzzy(Transaction<'static, Postgres>),
+}
+
+impl Deref for FilterConnection {
+    type Target = PgConnection;
+
+    fn deref(&self) -> &PgConnection {
+        match self {
+            FilterConnection::Plain(conn) => conn,
+            FilterConnection::Fuzzy(tx) => tx,
+        }
+    }
+}
+
+impl DerefMut for FilterConnection {
+    fn deref_mut(&mut self) -> &mut PgConnection {
+        match self {
+            FilterConnection::Plain(conn) => conn,
+            FilterConnection::Fuzzy(tx) => tx,
+        }
+    }
+}
+
+// Count the tenant's items matching the list filters
+pub async fn count_items(pool: &PgPool, tenant: Uuid, filter: &ItemFilter, visibility: &Visibility) -> Result<i64, sqlx::Error> {
+    let mut query = QueryBuilder::new("SELECT COUNT(*) FROM items");
+    push_item_filters(&mut query, tenant, filter, visibility);
+    let mut conn = filter_connection(pool, filter).await?;
+    let (count,) = query.build_query_as::<(i64,)>().fetch_one(&mut *conn).await?;
+    Ok(count)
+}
+
+// How many of the tenant's items match the list filters, and when the newest of them last changed
+pub async fn change_summary(pool: &PgPool, tenant: Uuid, filter: &ItemFilter, visibility: &Visibility) -> Result<(i64, Option<DateTime<Utc>>), sqlx::Error> {
+    let mut query = QueryBuilder::new("SELECT COUNT(*), MAX(updated_at) FROM items");
+    push_item_filters(&mut query, tenant, filter, visibility);
+    let mut conn = filter_connection(pool, filter).await?;
+    query.build_query_as::<(i64, Option<DateTime<Utc>>)>().fetch_one(&mut *conn).await
+}
+
+// Explain why a conditional update matched no rows: the item is gone (or another tenant's), or its version moved on
+pub async fn stale_or_missing<'e>(executor: impl Executor<'e, Database = Postgres>, tenant: Uuid, id: Uuid) -> ApiError {
+    let exists = sqlx::query_scalar!(
+        r#"SELECT EXISTS(SELECT 1 FROM items WHERE id = $1 AND tenant_id = $2 AND deleted_at IS NULL) AS "exists!""#,
+        id,
+        tenant
+    )
+    .fetch_one(executor)
+    .await;
+
+    match exists {
+        Ok(true) => ApiError::Conflict("Item was modified by another request; fetch the latest version and retry".to_string()),
+        Ok(false) => ApiError::NotFound("Item not found".to_string()),
+        Err(err) => err.into(),
+    }
+}
@@ -0,0 +1,345 @@
+use actix_web::http::{header, StatusCode};
+use actix_web::error::{JsonPayloadError, PathError, QueryPayloadError, UrlencodedError};
+use actix_web::{Error, HttpRequest, HttpResponse, ResponseError};
+use serde::Serialize;
+use sqlx::postgres::PgDatabaseError;
+use utoipa::ToSchema;
+use validator::{ValidationError, ValidationErrors};
+use std::collections::BTreeMap;
+use std::fmt;
+
+// Errors surfaced by the handlers, rendered as a JSON body with a matching status code
+#[derive(Debug, Clone)]
+pub enum ApiError {
+    NotFound(String),
+    MethodNotAllowed(String),
+    Conflict(String),
+    TransactionConflict(String),
+    // A foreign key refused the write, naming the constraint when Postgres reports it
+    ReferenceConflict { message: String, constraint: Option<String> },
+    Validation(String),
+    InvalidId(String),
+    InvalidFields(FieldErrors),
+    PayloadTooLarge(String),
+    UriTooLong(String),
+    UnsupportedMediaType(String),
+    PreconditionRequired(String),
+    Unauthorized(String),
+    Forbidden(String),
+    Unprocessable(String),
+    TooManyRequests(String),
+    GatewayTimeout(String),
+    Overloaded(String),
+    Maintenance(String),
+    // The database refuses writes, as a read-only standby or a full disk does; reads still work
+    ReadOnly(String),
+    Internal,
+}
+
+// JSON body returned for every error response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ErrorBody {
+    error: &'static str,
+    message: String,
+    // Every invalid field and what is wrong with it, for validation failures
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fields: Option<BTreeMap<String, String>>,
+    // The foreign key that refused the write, for reference conflicts
+    #[serde(skip_serializing_if = "Option::is_none")]
+    constraint: Option<String>,
+}
+
+// Field problems collected while validating a request body, so all of them are reported at once
+#[derive(Debug, Clone, Default)]
+pub struct FieldErrors(BTreeMap<String, String>);
+
+impl FieldErrors {
+    // Record a problem with `field`, keeping the first one found for each field
+    pub fn add(&mut self, field: &str, problem: String) {
+        self.0.entry(field.to_string()).or_insert(problem);
+    }
+}
+
+// One message per failing field, from the rule's own message or a description of the rule and its bounds
+impl From<ValidationErrors> for FieldErrors {
+    fn from(errors: ValidationErrors) -> Self {
+        let mut fields = FieldErrors::default();
+        for (field, failures) in errors.field_errors() {
+            if let Some(failure) = failures.first() {
+                fields.add(&field, describe_rule(failure));
+            }
+        }
+        fields
+    }
+}
+
+fn describe_rule(failure: &ValidationError) -> String {
+    if let Some(message) = &failure.message {
+        return message.to_string();
+    }
+    let bound = |name: &str| failure.params.get(name).map(|value| value.to_string());
+    match (failure.code.as_ref(), bound("min"), bound("max")) {
+        ("length", Some(min), Some(max)) => format!("must be between {} and {} characters", min, max),
+        ("length", Some(min), None) => format!("must be at least {} characters", min),
+        ("length", None, Some(max)) => format!("must be at most {} characters", max),
+        (rule, _, _) => format!("fails the {} rule", rule),
+    }
+}
+
+impl From<ValidationErrors> for ApiError {
+    fn from(errors: ValidationErrors) -> Self {
+        ApiError::InvalidFields(errors.into())
+    }
+}
+
+impl fmt::Display for FieldErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, (field, problem)) in self.0.iter().enumerate() {
+            if index > 0 {
+                f.write_str("; ")?;
+            }
+            write!(f, "{} {}", field, problem)?;
+        }
+        Ok(())
+    }
+}
+
+// Postgres SQLSTATEs for unique_violation, foreign_key_violation, query_canceled,
+// read_only_sql_transaction and disk_full
+const PG_UNIQUE_VIOLATION: &str = "23505";
+const PG_FOREIGN_KEY_VIOLATION: &str = "23503";
+const PG_QUERY_CANCELED: &str = "57014";
+const PG_READ_ONLY_TRANSACTION: &str = "25006";
+const PG_DISK_FULL: &str = "53100";
+const PG_SERIALIZATION_FAILURE: &str = "40001";
+const PG_DEADLOCK_DETECTED: &str = "40P01";
+
+// Seconds clients are asked to wait before retrying when the connection pool is exhausted
+const OVERLOADED_RETRY_AFTER_SECS: u64 = 1;
+
+// Seconds clients are asked to wait before retrying a write refused during maintenance
+const MAINTENANCE_RETRY_AFTER_SECS: u64 = 60;
+
+// Seconds clients are asked to wait before retrying a write the database refused as read-only
+const READ_ONLY_RETRY_AFTER_SECS: u64 = 30;
+
+// Unique indexes whose violations get a friendlier conflict message
+const ITEM_NAME_UNIQUE_INDEX: &str = "items_name_lower_key";
+const ITEM_CONTENT_HASH_UNIQUE_INDEX: &str = "items_content_hash_key";
+const CATEGORY_NAME_UNIQUE_INDEX: &str = "categories_name_key";
+
+// Foreign keys whose violations get a message naming the dependency
+const ITEM_CATEGORY_FOREIGN_KEY: &str = "items_category_id_fkey";
+const IDEMPOTENCY_KEY_ITEM_FOREIGN_KEY: &str = "idempotency_keys_item_id_fkey";
+
+impl ApiError {
+    // Stable, machine-readable identifier for the error kind
+    fn code(&self) -> &'static str {
+        match self {
+            ApiError::NotFound(_) => "not_found",
+            ApiError::MethodNotAllowed(_) => "method_not_allowed",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::TransactionConflict(_) => "transaction_conflict",
+            ApiError::ReferenceConflict { .. } => "reference_conflict",
+            ApiError::Validation(_) => "validation",
+            ApiError::InvalidId(_) => "invalid_id",
+            ApiError::InvalidFields(_) => "validation",
+            ApiError::PayloadTooLarge(_) => "payload_too_large",
+            ApiError::UriTooLong(_) => "uri_too_long",
+            ApiError::UnsupportedMediaType(_) => "unsupported_media_type",
+            ApiError::PreconditionRequired(_) => "precondition_required",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::Unprocessable(_) => "unprocessable",
+            ApiError::TooManyRequests(_) => "rate_limited",
+            ApiError::GatewayTimeout(_) => "timeout",
+            ApiError::Overloaded(_) => "overloaded",
+            ApiError::Maintenance(_) => "maintenance",
+            ApiError::ReadOnly(_) => "read_only",
+            ApiError::Internal => "internal",
+        }
+    }
+}
+
+impl ApiError {
+    // The JSON body describing this error, for responses and for WebSocket replies
+    pub fn body(&self) -> ErrorBody {
+        let fields = match self {
+            ApiError::InvalidFields(FieldErrors(fields)) => Some(fields.clone()),
+            _ => None,
+        };
+        let constraint = match self {
+            ApiError::ReferenceConflict { constraint, .. } => constraint.clone(),
+            _ => None,
+        };
+        ErrorBody { error: self.code(), message: self.to_string(), fields, constraint }
+    }
+
+    // Prefix the message with the position of the offending element in a batch request
+    pub fn at_index(self, index: usize) -> Self {
+        match self {
+            ApiError::NotFound(message) => ApiError::NotFound(format!("item {}: {}", index, message)),
+            ApiError::Conflict(message) => ApiError::Conflict(format!("item {}: {}", index, message)),
+            ApiError::ReferenceConflict { message, constraint } => {
+                ApiError::ReferenceConflict { message: format!("item {}: {}", index, message), constraint }
+            }
+            ApiError::Validation(message) => ApiError::Validation(format!("item {}: {}", index, message)),
+            ApiError::InvalidFields(FieldErrors(fields)) => ApiError::InvalidFields(FieldErrors(
+                fields.into_iter().map(|(field, problem)| (format!("{}.{}", index, field), problem)).collect(),
+            )),
+            other => other,
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::NotFound(message)
+            | ApiError::MethodNotAllowed(message)
+            | ApiError::Conflict(message)
+            | ApiError::TransactionConflict(message)
+            | ApiError::Validation(message)
+            | ApiError::InvalidId(message)
+            | ApiError::PayloadTooLarge(message)
+            | ApiError::UriTooLong(message)
+            | ApiError::UnsupportedMediaType(message)
+            | ApiError::PreconditionRequired(message)
+            | ApiError::Unauthorized(message)
+            | ApiError::Forbidden(message)
+            | ApiError::Unprocessable(message)
+            | ApiError::TooManyRequests(message)
+            | ApiError::GatewayTimeout(message)
+            | ApiError::Overloaded(message)
+            | ApiError::Maintenance(message)
+            | ApiError::ReadOnly(message)
+            | ApiError::ReferenceConflict { message, .. } => f.write_str(message),
+            ApiError::InvalidFields(errors) => errors.fmt(f),
+            ApiError::Internal => f.write_str("An internal error occurred"),
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::MethodNotAllowed(_) => StatusCode::METHOD_NOT_ALLOWED,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::TransactionConflict(_) => StatusCode::CONFLICT,
+            ApiError::ReferenceConflict { .. } => StatusCode::CONFLICT,
+            ApiError::Validation(_) => StatusCode::BAD_REQUEST,
+            ApiError::InvalidId(_) => StatusCode::BAD_REQUEST,
+            ApiError::InvalidFields(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::UriTooLong(_) => StatusCode::URI_TOO_LONG,
+            ApiError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ApiError::PreconditionRequired(_) => StatusCode::PRECONDITION_REQUIRED,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::Unprocessable(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::GatewayTimeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            ApiError::Overloaded(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Maintenance(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::ReadOnly(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        match self {
+            ApiError::Overloaded(_) => response.insert_header((header::RETRY_AFTER, OVERLOADED_RETRY_AFTER_SECS)),
+            ApiError::Maintenance(_) => response.insert_header((header::RETRY_AFTER, MAINTENANCE_RETRY_AFTER_SECS)),
+            ApiError::ReadOnly(_) => response.insert_header((header::RETRY_AFTER, READ_ONLY_RETRY_AFTER_SECS)),
+            _ => &mut response,
+        };
+        response.json(self.body())
+    }
+}
+
+impl From<sqlx::Error> for ApiError {
+    fn from(err: sqlx::Error) -> Self {
+        match &err {
+            sqlx::Error::RowNotFound => ApiError::NotFound("Item not found".to_string()),
+            sqlx::Error::Database(db_err) if db_err.code().as_deref() == Some(PG_UNIQUE_VIOLATION) => {
+                let message = match db_err.constraint() {
+                    // Equal content means an equal name, and either index may be the one to notice
+                    Some(ITEM_NAME_UNIQUE_INDEX | ITEM_CONTENT_HASH_UNIQUE_INDEX) => "An item with that name already exists".to_string(),
+                    Some(CATEGORY_NAME_UNIQUE_INDEX) => "A category with that name already exists".to_string(),
+                    _ => db_err.message().to_string(),
+                };
+                ApiError::Conflict(message)
+            }
+            // Either the write names a row that doesn't exist, or it removes one that others still point at;
+            // only Postgres' detail line tells the two apart
+            sqlx::Error::Database(db_err) if db_err.code().as_deref() == Some(PG_FOREIGN_KEY_VIOLATION) => {
+                let still_referenced = db_err
+                    .try_downcast_ref::<PgDatabaseError>()
+                    .and_then(|err| err.detail())
+                    .is_some_and(|detail| detail.contains("is still referenced"));
+                let constraint = db_err.constraint().map(str::to_string);
+                let message = match (constraint.as_deref(), still_referenced) {
+                    (Some(ITEM_CATEGORY_FOREIGN_KEY), false) => "category_id does not refer to an existing category".to_string(),
+                    (Some(ITEM_CATEGORY_FOREIGN_KEY), true) => "Cannot delete category with existing items".to_string(),
+                    (Some(IDEMPOTENCY_KEY_ITEM_FOREIGN_KEY), true) => "Cannot delete item while idempotency keys refer to it".to_string(),
+                    (_, false) => "The request refers to a row that does not exist".to_string(),
+                    (_, true) => "Cannot delete a row that other rows still refer to".to_string(),
+                };
+                ApiError::ReferenceConflict { message, constraint }
+            }
+            // Postgres gave up on the transaction to keep concurrent ones consistent; rerunning it may succeed
+            sqlx::Error::Database(db_err)
+                if matches!(db_err.code().as_deref(), Some(PG_SERIALIZATION_FAILURE | PG_DEADLOCK_DETECTED)) =>
+            {
+                ApiError::TransactionConflict("The request conflicted with concurrent writes; retry it".to_string())
+            }
+            // statement_timeout fired, so the request ran out of time rather than failed
+            sqlx::Error::Database(db_err) if db_err.code().as_deref() == Some(PG_QUERY_CANCELED) => {
+                ApiError::GatewayTimeout("Database query took too long and was cancelled".to_string())
+            }
+            // The primary can't take writes right now, as when a standby answers or the disk is full;
+            // that is an outage to wait out, not a bug in the request
+            sqlx::Error::Database(db_err) if matches!(db_err.code().as_deref(), Some(PG_READ_ONLY_TRANSACTION | PG_DISK_FULL)) => {
+                ApiError::ReadOnly("The database is not accepting writes right now; reads are still served".to_string())
+            }
+            // Every connection stayed busy for the whole acquire timeout: the server is saturated, not broken
+            sqlx::Error::PoolTimedOut => {
+                ApiError::Overloaded("All database connections are busy; retry shortly".to_string())
+            }
+            _ => ApiError::Internal,
+        }
+    }
+}
+
+// Render extractor failures (malformed JSON, query strings and path segments) in the standard error body
+pub fn json_error_handler(err: JsonPayloadError, _req: &HttpRequest) -> Error {
+    match err {
+        JsonPayloadError::Overflow { .. } | JsonPayloadError::OverflowKnownLength { .. } => {
+            ApiError::PayloadTooLarge(err.to_string()).into()
+        }
+        // serde_json names the field for missing fields and gives the position of type and syntax errors
+        JsonPayloadError::Deserialize(err) if err.is_syntax() || err.is_eof() => {
+            ApiError::Validation(format!("Malformed JSON body: {}", err)).into()
+        }
+        JsonPayloadError::Deserialize(err) => ApiError::Validation(format!("Invalid JSON body: {}", err)).into(),
+        err => ApiError::Validation(format!("Invalid JSON body: {}", err)).into(),
+    }
+}
+
+pub fn form_error_handler(err: UrlencodedError, _req: &HttpRequest) -> Error {
+    match err {
+        UrlencodedError::Overflow { .. } => ApiError::PayloadTooLarge(err.to_string()).into(),
+        err => ApiError::Validation(format!("Invalid form body: {}", err)).into(),
+    }
+}
+
+pub fn query_error_handler(err: QueryPayloadError, _req: &HttpRequest) -> Error {
+    ApiError::Validation(format!("Invalid query string: {}", err)).into()
+}
+
+// Every path parameter is an id, so a segment that doesn't parse is reported as a malformed id
+pub fn path_error_handler(err: PathError, _req: &HttpRequest) -> Error {
+    ApiError::InvalidId(format!("Invalid id in path: {}", err)).into()
+}
@@ -0,0 +1,113 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use std::collections::BTreeSet;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+use crate::error::ApiError;
+use crate::models::Item;
+
+// Buffered events per subscriber before a slow one starts missing messages
+const ITEM_EVENTS_CAPACITY: usize = 256;
+
+// How often an idle event stream sends a comment, so dead connections are noticed and dropped
+pub const EVENTS_KEEPALIVE: Duration = Duration::from_secs(15);
+
+// A WebSocket client that sends nothing, not even a pong, for this long is disconnected
+pub const SOCKET_CLIENT_TIMEOUT: Duration = Duration::from_secs(45);
+
+// Most item ids a single WebSocket connection may subscribe to
+pub const MAX_SOCKET_SUBSCRIPTIONS: usize = 100;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ItemEventKind {
+    Created,
+    Updated,
+    Deleted,
+}
+
+impl ItemEventKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ItemEventKind::Created => "created",
+            ItemEventKind::Updated => "updated",
+            ItemEventKind::Deleted => "deleted",
+        }
+    }
+}
+
+// A committed change to an item, as delivered to event stream subscribers
+#[derive(Debug, Clone, Serialize)]
+pub struct ItemEvent {
+    #[serde(rename = "type")]
+    pub kind: ItemEventKind,
+    pub id: Uuid,
+    item: Item,
+    // Only subscribers acting for the same tenant receive the event
+    #[serde(skip)]
+    pub tenant: Uuid,
+}
+
+// Fan-out of item changes to every connected event stream, shared by all workers
+#[derive(Clone)]
+pub struct ItemEvents {
+    sender: broadcast::Sender<ItemEvent>,
+}
+
+impl ItemEvents {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(ITEM_EVENTS_CAPACITY);
+        ItemEvents { sender }
+    }
+
+    // Called after the write is committed; having no subscribers is not an error
+    pub fn publish(&self, tenant: Uuid, kind: ItemEventKind, item: &Item) {
+        let _ = self.sender.send(ItemEvent { kind, id: item.id, item: item.clone(), tenant });
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ItemEvent> {
+        self.sender.subscribe()
+    }
+}
+
+// A WebSocket client message changing which item ids the connection hears about
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SocketRequest {
+    #[serde(default)]
+    subscribe: Vec<Uuid>,
+    #[serde(default)]
+    unsubscribe: Vec<Uuid>,
+}
+
+// The item ids one WebSocket connection receives events for; serialized as the reply to every change
+#[derive(Debug, Default, Serialize)]
+pub struct SocketSubscriptions {
+    subscribed: BTreeSet<Uuid>,
+}
+
+impl SocketSubscriptions {
+    // Apply a client message, rejecting it whole if it is malformed or would exceed MAX_SOCKET_SUBSCRIPTIONS
+    pub fn apply(&mut self, message: &str) -> Result<(), ApiError> {
+        let request: SocketRequest = serde_json::from_str(message)
+            .map_err(|err| ApiError::Validation(format!("Invalid subscription message: {}", err)))?;
+        let mut subscribed = self.subscribed.clone();
+        subscribed.extend(request.subscribe);
+        for id in &request.unsubscribe {
+            subscribed.remove(id);
+        }
+        if subscribed.len() > MAX_SOCKET_SUBSCRIPTIONS {
+            return Err(ApiError::Validation(format!(
+                "A connection may subscribe to at most {} items",
+                MAX_SOCKET_SUBSCRIPTIONS
+            )));
+        }
+        self.subscribed = subscribed;
+        Ok(())
+    }
+
+    pub fn wants(&self, event: &ItemEvent) -> bool {
+        self.subscribed.contains(&event.id)
+    }
+}
@@ -0,0 +1,104 @@
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::sync::{RwLock, RwLockReadGuard};
+use std::time::Duration;
+
+use crate::config::EnvReader;
+use crate::error::ApiError;
+use crate::middleware::MaintenanceMode;
+use crate::models::FeatureFlag;
+
+// Refuses writes while on, like POST /admin/maintenance, but on every instance
+pub const MAINTENANCE_MODE: &str = "maintenance_mode";
+// Accepts the fuzzy list filter; off answers it with 400
+pub const FUZZY_SEARCH: &str = "fuzzy_search";
+
+// Every flag that can be switched, in name order, with its value while the table has no row for it
+const FLAGS: [(&str, bool); 2] = [(FUZZY_SEARCH, true), (MAINTENANCE_MODE, false)];
+
+// Feature flags stored in the feature_flags table, cached in memory so handlers never query for
+// them. The cache is refreshed every FEATURE_FLAG_REFRESH_SECS, and at once on the instance a flag
+// is switched through; other instances follow within one refresh interval.
+// maintenance_mode is kept in MaintenanceMode: a stored value is applied when it changes, so
+// MAINTENANCE_MODE and POST /admin/maintenance still work until the flag is next switched.
+pub struct FeatureFlags {
+    stored: RwLock<HashMap<String, bool>>,
+    maintenance: MaintenanceMode,
+}
+
+// How often the flag cache is reloaded, unless FEATURE_FLAG_REFRESH_SECS says otherwise
+const DEFAULT_REFRESH_SECS: u64 = 15;
+
+pub fn refresh_interval_from_env(env: &mut EnvReader) -> Duration {
+    let secs = env.parse_or("FEATURE_FLAG_REFRESH_SECS", DEFAULT_REFRESH_SECS);
+    if secs == 0 {
+        env.problem("FEATURE_FLAG_REFRESH_SECS must be at least 1");
+    }
+    Duration::from_secs(secs.max(1))
+}
+
+impl FeatureFlags {
+    pub fn new(maintenance: MaintenanceMode) -> Self {
+        FeatureFlags { stored: RwLock::new(HashMap::new()), maintenance }
+    }
+
+    fn stored(&self) -> RwLockReadGuard<'_, HashMap<String, bool>> {
+        self.stored.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // The flag's current value; `name` must be one of the constants above
+    pub fn is_enabled(&self, name: &str) -> bool {
+        if name == MAINTENANCE_MODE {
+            return self.maintenance.enabled();
+        }
+        let default = FLAGS.iter().find(|(flag, _)| *flag == name).is_some_and(|(_, default)| *default);
+        self.stored().get(name).copied().unwrap_or(default)
+    }
+
+    // Every flag and its current value
+    pub fn list(&self) -> Vec<FeatureFlag> {
+        FLAGS.iter().map(|(name, _)| FeatureFlag { name: name.to_string(), enabled: self.is_enabled(name) }).collect()
+    }
+
+    // Reload the stored flags. Rows naming flags this build doesn't know are ignored.
+    pub async fn refresh(&self, pool: &PgPool) -> Result<(), sqlx::Error> {
+        let rows = sqlx::query!("SELECT name, enabled FROM feature_flags").fetch_all(pool).await?;
+        let loaded: HashMap<String, bool> = rows.into_iter().map(|row| (row.name, row.enabled)).collect();
+        self.apply(loaded);
+        Ok(())
+    }
+
+    // Store `name`'s new value and apply it to this instance straight away
+    pub async fn set(&self, pool: &PgPool, name: &str, enabled: bool) -> Result<FeatureFlag, ApiError> {
+        if !FLAGS.iter().any(|(flag, _)| *flag == name) {
+            return Err(ApiError::NotFound("Feature flag not found".to_string()));
+        }
+        sqlx::query!(
+            "INSERT INTO feature_flags (name, enabled) VALUES ($1, $2)
+             ON CONFLICT (name) DO UPDATE SET enabled = EXCLUDED.enabled, updated_at = now()",
+            name,
+            enabled
+        )
+        .execute(pool)
+        .await?;
+
+        let mut loaded = self.stored().clone();
+        loaded.insert(name.to_string(), enabled);
+        self.apply(loaded);
+        // Switched here, so applied even if the stored value was already the same
+        if name == MAINTENANCE_MODE {
+            self.maintenance.set(enabled);
+        }
+        Ok(FeatureFlag { name: name.to_string(), enabled: self.is_enabled(name) })
+    }
+
+    fn apply(&self, loaded: HashMap<String, bool>) {
+        let mut stored = self.stored.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(&maintenance) = loaded.get(MAINTENANCE_MODE) {
+            if stored.get(MAINTENANCE_MODE) != Some(&maintenance) {
+                self.maintenance.set(maintenance);
+            }
+        }
+        *stored = loaded;
+    }
+}
@@ -0,0 +1,3025 @@
+use actix_web::http::header::{
+    self, ETag, EntityTag, Header, HttpDate, IfModifiedSince, IfNoneMatch, LastModified,
+};
+use actix_web::http::StatusCode;
+use actix_web::mime;
+use actix_multipart::Multipart;
+use actix_ws::{CloseCode, Message, MessageStream, Session};
+use actix_web::rt::time::{interval, timeout};
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, HttpResponseBuilder};
+use async_stream::{stream, try_stream};
+use chrono::{DateTime, Utc};
+use prometheus::{Encoder, TextEncoder};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use sqlx::{Connection, PgConnection, PgPool, Postgres, QueryBuilder, Transaction};
+use uuid::Uuid;
+use validator::Validate;
+use futures_util::{Stream, TryStreamExt};
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::broadcast;
+use tracing::warn;
+
+use crate::cache::ItemCache;
+use crate::db::{
+    self, copy_name, count_items, find_idempotent_item, insert_item, push_item_filters,
+    stale_or_missing, timed_query, with_retry, with_transaction, ReadPool, RetryPolicy,
+    TransactionRetry, SEARCH_VECTOR,
+};
+use crate::error::{ApiError, ErrorBody, FieldErrors};
+use crate::flags::{FeatureFlags, FUZZY_SEARCH};
+use crate::events::{
+    ItemEvent, ItemEventKind, ItemEvents, SocketSubscriptions, EVENTS_KEEPALIVE,
+    SOCKET_CLIENT_TIMEOUT,
+};
+use crate::config::{Config, EnvReader};
+use crate::middleware::{ExportLinks, MaintenanceMode, Metrics, Principal};
+use crate::negotiate::{Body, Format, FormOrBody};
+use crate::routes::{self, ApiPrefix};
+use crate::models::{
+    normalize_tag, project_item, project_items, AnalyzeReport, BatchCreateReport, BatchFailure,
+    BatchModeParam, BuildInfo, Category, CategoryCount, CategoryCreateRequest, CountReconciliation,
+    Cursor, DedupeParam, DeletedCount, DiffParam, DrainStatus, DryRunParam, EnvelopeParam,
+    ExportLink, FeatureFlag, FeatureFlagUpdate, FieldSelection, HealthStatus, ImportRowError,
+    ImportSummary, Item, ItemAuditRecord, ItemBatchGetRequest, ItemBounds, ItemCount,
+    ItemCreateRequest, ItemDeleteBatchRequest, ItemDeleted, ItemDescription, ItemDiff, ItemFilter,
+    ItemParams, ItemPatchRequest, ItemPosition, ItemStatsParams, ItemTagsRequest,
+    ItemUpdateRequest, ItemView, KeysetPagination, ListMeta, ListParams, ListResponse,
+    MaintenanceStatus, MigrationStatus, MovedCount, NameAvailability, NameCheck, PageSizes,
+    Pagination, PoolStats, Ranked, Scored, SearchRow, SeedParams, SeedReport, Sorting, StreamParam,
+    SuggestParams, TruncateParams, Visibility,
+};
+
+// Path the item routes are mounted under, used to build resource URIs
+const ITEMS_PATH: &str = "/items";
+
+// Path of a resource under the API prefix, for Location headers; aliased legacy routes
+// still point clients at the prefixed path
+fn resource_path(req: &HttpRequest, collection: &str, id: Uuid) -> String {
+    let prefix = req.app_data::<web::Data<ApiPrefix>>().map_or("", |prefix| prefix.0.as_str());
+    format!("{}{}/{}", prefix, collection, id)
+}
+
+// Fallback for requests that match no route
+pub async fn route_not_found() -> Result<HttpResponse, ApiError> {
+    Err(ApiError::NotFound("No route matches this request".to_string()))
+}
+
+// Create a new item
+#[utoipa::path(
+    post,
+    path = "/items",
+    tag = "items",
+    request_body(content(
+        (ItemCreateRequest = "application/json"),
+        (ItemCreateRequest = "application/msgpack"),
+        (ItemCreateRequest = "application/x-www-form-urlencoded")
+    )),
+    params(
+        ("Idempotency-Key" = Option<String>, Header, description = "Replays the original response for retried requests"),
+        ("Prefer" = Option<String>, Header, description = "`dry-run` does the same as `dry_run=true`"),
+        DryRunParam,
+        DedupeParam
+    ),
+    responses(
+        (status = 201, description = "Item created", body = Item),
+        (status = 200, description = "Dry run: the item that would have been created, marked by `X-Dry-Run: true`. Nothing was stored and its id is never issued again. \
+            With dedupe=true: the existing item with the same name and description, which was left as it is", body = Item),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 409, description = "Conflicting item or stale version", body = ErrorBody),
+        (status = 415, description = "Body is not JSON, MessagePack or a form", body = ErrorBody),
+        (status = 422, description = "Invalid fields, or Idempotency-Key reused with a different body", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody)
+    )
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn create_item(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    events: web::Data<ItemEvents>,
+    principal: Principal,
+    format: Format,
+    dry_run: web::Query<DryRunParam>,
+    dedupe: web::Query<DedupeParam>,
+    item: FormOrBody<ItemCreateRequest>,
+) -> Result<HttpResponse, ApiError> {
+    item.validate()?;
+    let idempotency_key = idempotency_key(&req)?;
+    let dry_run = dry_run.requested(&req);
+
+    // A duplicate found through dedupe=true comes back as Err and is answered like a GET. It leaves no
+    // idempotency key behind, so retrying with the key finds it again.
+    let stored = timed_query(async {
+        let mut tx = pool.begin().await?;
+        let stored = match &idempotency_key {
+            Some(key) => {
+                let request_hash = item.fingerprint();
+                match find_idempotent_item(&mut tx, principal.tenant, key, &request_hash).await? {
+                    Some(existing) => (existing, true),
+                    None => {
+                        if let Some(existing) = claim_unless_duplicate(&mut tx, principal.tenant, &item, dedupe.dedupe).await? {
+                            tx.rollback().await?;
+                            return Ok(Err(existing));
+                        }
+                        let created = insert_item(&mut tx, principal.tenant, &item, principal.user).await?;
+                        sqlx::query!(
+                            "INSERT INTO idempotency_keys (tenant_id, key, request_hash, item_id) VALUES ($1, $2, $3, $4)",
+                            principal.tenant,
+                            key,
+                            request_hash,
+                            created.id
+                        )
+                        .execute(&mut tx)
+                        .await?;
+                        (created, false)
+                    }
+                }
+            }
+            None => {
+                if let Some(existing) = claim_unless_duplicate(&mut tx, principal.tenant, &item, dedupe.dedupe).await? {
+                    tx.rollback().await?;
+                    return Ok(Err(existing));
+                }
+                (insert_item(&mut tx, principal.tenant, &item, principal.user).await?, false)
+            }
+        };
+        commit_unless_dry_run(tx, dry_run).await?;
+        Ok::<_, ApiError>(Ok(stored))
+    })
+    .await?;
+    let (created, replayed) = match stored {
+        Ok(stored) => stored,
+        Err(existing) => return Ok(duplicate_response(format, dry_run, &existing)),
+    };
+    if dry_run {
+        return Ok(dry_run_response(format, &created));
+    }
+    if !replayed {
+        events.publish(principal.tenant, ItemEventKind::Created, &created);
+    }
+
+    Ok(format.respond(
+        HttpResponse::Created().insert_header((header::LOCATION, resource_path(&req, ITEMS_PATH, created.id))),
+        &created,
+    ))
+}
+
+// Claim the name of a new item, or with `dedupe` return the live item it would duplicate instead.
+// A duplicate has the same name, so it is only looked for once the claim finds the name taken, and
+// the claim's lock keeps concurrent creates of the name from slipping in between.
+async fn claim_unless_duplicate(conn: &mut PgConnection, tenant: Uuid, item: &ItemCreateRequest, dedupe: bool) -> Result<Option<Item>, ApiError> {
+    match db::claim_item_name(&mut *conn, tenant, &item.name).await {
+        Err(ApiError::Conflict(message)) if dedupe => match db::find_duplicate_item(conn, tenant, item).await? {
+            Some(existing) => Ok(Some(existing)),
+            None => Err(ApiError::Conflict(message)),
+        },
+        claimed => claimed.map(|()| None),
+    }
+}
+
+// The existing item a deduplicated create found, as a 200; the transaction only held the name lock
+fn duplicate_response(format: Format, dry_run: bool, existing: &Item) -> HttpResponse {
+    if dry_run {
+        return dry_run_response(format, existing);
+    }
+    format.respond(&mut HttpResponse::Ok(), existing)
+}
+
+// Response header marking a dry run, whose changes were rolled back
+const DRY_RUN_HEADER: &str = "x-dry-run";
+
+// Commit a mutation, or roll it back when the client asked for a dry run
+async fn commit_unless_dry_run(tx: Transaction<'_, Postgres>, dry_run: bool) -> Result<(), ApiError> {
+    if dry_run {
+        tx.rollback().await?;
+    } else {
+        tx.commit().await?;
+    }
+    Ok(())
+}
+
+// What a dry run would have stored; always a 200, since nothing was created or changed
+fn dry_run_response(format: Format, item: &Item) -> HttpResponse {
+    format.respond(HttpResponse::Ok().insert_header((DRY_RUN_HEADER, "true")), item)
+}
+
+// Respond with an updated item, or with diff=true with the item and its changes from `old`
+fn updated_response(format: Format, response: &mut HttpResponseBuilder, old: Option<Item>, item: Item, diff: bool) -> HttpResponse {
+    if diff {
+        format.respond(response, &ItemDiff::between(old.as_ref(), item))
+    } else {
+        format.respond(response, &item)
+    }
+}
+
+const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+const MAX_IDEMPOTENCY_KEY_LEN: usize = 255;
+
+// The Idempotency-Key header value, if the client sent one
+fn idempotency_key(req: &HttpRequest) -> Result<Option<String>, ApiError> {
+    let Some(value) = req.headers().get(IDEMPOTENCY_KEY_HEADER) else {
+        return Ok(None);
+    };
+    match value.to_str() {
+        Ok(key) if !key.trim().is_empty() && key.len() <= MAX_IDEMPOTENCY_KEY_LEN => Ok(Some(key.trim().to_string())),
+        _ => Err(ApiError::Validation(format!(
+            "Idempotency-Key must be non-empty text of at most {} characters",
+            MAX_IDEMPOTENCY_KEY_LEN
+        ))),
+    }
+}
+
+// Maximum number of items accepted by a single batch request
+const MAX_BATCH_SIZE: usize = 1000;
+
+// Create several items in one transaction; any failure rolls back the whole batch.
+// With mode=best_effort each row is inserted under its own savepoint instead, so failing rows
+// are reported and skipped while the rest are still created.
+#[utoipa::path(
+    post,
+    path = "/items/batch",
+    tag = "items",
+    params(BatchModeParam),
+    request_body = Vec<ItemCreateRequest>,
+    responses(
+        (status = 201, description = "All items created", body = Vec<Item>),
+        (status = 207, description = "Best-effort mode: the items created and the rows that failed", body = BatchCreateReport),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 409, description = "Conflicting item or stale version", body = ErrorBody),
+        (status = 413, description = "Too many items in the batch", body = ErrorBody),
+        (status = 422, description = "Invalid fields, keyed by item index", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody)
+    )
+)]
+pub async fn create_items_batch(
+    pool: web::Data<PgPool>,
+    retry: web::Data<TransactionRetry>,
+    events: web::Data<ItemEvents>,
+    principal: Principal,
+    format: Format,
+    mode: web::Query<BatchModeParam>,
+    items: Body<Vec<ItemCreateRequest>>,
+) -> Result<HttpResponse, ApiError> {
+    if items.len() > MAX_BATCH_SIZE {
+        return Err(ApiError::PayloadTooLarge(format!("Batch may contain at most {} items", MAX_BATCH_SIZE)));
+    }
+    if mode.best_effort()? {
+        return create_items_best_effort(&pool, &retry, &events, principal, format, items.into_inner()).await;
+    }
+    for (index, item) in items.iter().enumerate() {
+        item.validate().map_err(|err| ApiError::from(err).at_index(index))?;
+    }
+
+    let Principal { tenant, user } = principal;
+    let items = items.into_inner();
+    let created = with_transaction(&pool, &retry, move |conn| {
+        let items = items.clone();
+        Box::pin(async move {
+            let mut created = Vec::with_capacity(items.len());
+            for (index, item) in items.iter().enumerate() {
+                let item = insert_item(conn, tenant, item, user).await.map_err(|err| ApiError::from(err).at_index(index))?;
+                created.push(item);
+            }
+            Ok(created)
+        })
+    })
+    .await?;
+    for item in &created {
+        events.publish(tenant, ItemEventKind::Created, item);
+    }
+
+    Ok(format.respond(&mut HttpResponse::Created(), &created))
+}
+
+// The best-effort half of POST /items/batch. Rows that fail validation or conflict are rolled back
+// to their savepoint and reported by index; only errors the client can't fix abort the batch.
+async fn create_items_best_effort(
+    pool: &PgPool,
+    retry: &TransactionRetry,
+    events: &ItemEvents,
+    principal: Principal,
+    format: Format,
+    items: Vec<ItemCreateRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let Principal { tenant, user } = principal;
+    let report = with_transaction(pool, retry, move |conn| {
+        let items = items.clone();
+        Box::pin(async move {
+            let mut report = BatchCreateReport { created: Vec::with_capacity(items.len()), failed: Vec::new() };
+            for (index, item) in items.iter().enumerate() {
+                if let Err(err) = item.validate() {
+                    report.failed.push(BatchFailure { index, reason: ApiError::from(err).to_string() });
+                    continue;
+                }
+                let mut savepoint = conn.begin().await?;
+                match insert_item(&mut savepoint, tenant, item, user).await.map_err(ApiError::from) {
+                    Ok(item) => {
+                        savepoint.commit().await?;
+                        report.created.push(item);
+                    }
+                    Err(err @ (ApiError::Internal | ApiError::Overloaded(_) | ApiError::GatewayTimeout(_) | ApiError::TransactionConflict(_) | ApiError::ReadOnly(_))) => {
+                        return Err(err)
+                    }
+                    Err(err) => {
+                        savepoint.rollback().await?;
+                        report.failed.push(BatchFailure { index, reason: err.to_string() });
+                    }
+                }
+            }
+            Ok(report)
+        })
+    })
+    .await?;
+    for item in &report.created {
+        events.publish(tenant, ItemEventKind::Created, item);
+    }
+
+    Ok(format.respond(&mut HttpResponse::build(StatusCode::MULTI_STATUS), &report))
+}
+
+// Get all items
+#[utoipa::path(
+    get,
+    path = "/items",
+    tag = "items",
+    params(
+        Pagination, ItemFilter, Visibility, Sorting, KeysetPagination, EnvelopeParam, FieldSelection, StreamParam,
+        ("metadata.{key}" = Option<String>, Query, description = "Only items whose metadata has top-level `key` with this value as text; repeatable with other keys")
+    ),
+    responses(
+        (status = 200, description = "Items, as a bare array unless an envelope was requested. With stream=true every matching item is \
+            sent in one chunked JSON array with none of the headers below; the status is sent before the first item, \
+            so a database error part way through ends the body early, leaving the array unterminated", body = Vec<Item>,
+            headers(("X-Total-Count" = i64, description = "Total matching items (offset mode)"),
+                    ("Content-Range" = String, description = "`items <first>-<last>/<total>`, zero-based and inclusive, or `items */<total>` for an empty page (offset mode)"),
+                    ("Link" = String, description = "RFC 8288 first, prev, next and last page links (offset mode)"),
+                    ("X-Next-Cursor" = String, description = "Cursor for the next page (keyset mode)"),
+                    ("ETag" = String, description = "Weak validator for the page, changing when any item on it or the page's composition does"),
+                    ("X-Page-Size-Clamped" = bool, description = "`true` when per_page or limit exceeded MAX_PAGE_SIZE and the page was cut to that size"))),
+        (status = 206, description = "One page of a larger offset-paginated result, with the same headers as a 200", body = Vec<Item>),
+        (status = 304, description = "Page unchanged since the If-None-Match validator"),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody)
+    )
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn get_items(
+    req: HttpRequest,
+    pool: web::Data<ReadPool>,
+    params: ListParams,
+    principal: Principal,
+    retry: web::Data<RetryPolicy>,
+    config: web::Data<Config>,
+    flags: web::Data<FeatureFlags>,
+    format: Format,
+) -> Result<HttpResponse, ApiError> {
+    let ListParams { pagination, filter, visibility, sorting, keyset, envelope, fields, stream } = params;
+    let sizes = &config.page_sizes;
+    let enveloped = envelope.requested(&req);
+    let fields = fields.resolve()?;
+    if filter.fuzzy_term().is_some() && !flags.is_enabled(FUZZY_SEARCH) {
+        return Err(ApiError::Validation("Fuzzy search is switched off".to_string()));
+    }
+    if stream.stream {
+        if keyset.is_requested() || pagination.page.is_some() || pagination.per_page.is_some() || enveloped {
+            return Err(ApiError::Validation("stream=true cannot be combined with page, per_page, after, limit or envelope".to_string()));
+        }
+        let (column, direction) = sorting.order_by().map_err(ApiError::Validation)?;
+        return Ok(stream_item_list(pool.0.clone(), principal.tenant, filter, visibility, (column, direction), fields));
+    }
+    if keyset.is_requested() {
+        if pagination.page.is_some() || pagination.per_page.is_some() || sorting.sort.is_some() || sorting.order.is_some() {
+            return Err(ApiError::Validation(
+                "after/limit cannot be combined with page, per_page, sort or order".to_string(),
+            ));
+        }
+        if filter.updated_since.is_some() {
+            return Err(ApiError::Validation("after/limit cannot be combined with updated_since".to_string()));
+        }
+        let (mut response, etag, items) = get_items_after(&pool.0, principal.tenant, &retry, &filter, &visibility, &keyset, sizes, fields.as_deref()).await?;
+        if etag_matches(&req, &etag) {
+            return Ok(HttpResponse::NotModified().insert_header(ETag(etag)).finish());
+        }
+        response.insert_header(ETag(etag));
+        if sizes.clamps(keyset.limit) {
+            response.insert_header((PAGE_SIZE_CLAMPED_HEADER, "true"));
+        }
+        if enveloped {
+            return Ok(format.respond(&mut response, &ListResponse { data: items, meta: None }));
+        }
+        return Ok(format.respond(&mut response, &items));
+    }
+
+    let (limit, offset) = pagination.limit_offset(sizes).map_err(|message| ApiError::Validation(message.to_string()))?;
+    let (column, direction) = sorting.order_by().map_err(ApiError::Validation)?;
+
+    // A sync poll's validator comes from the newest change and the number of changes, so an idle
+    // client is answered 304 from one aggregate query without a row being read
+    let (total, sync_etag) = match filter.updated_since {
+        Some(_) => {
+            let (total, newest) = with_retry(&retry, || db::change_summary(&pool.0, principal.tenant, &filter, &visibility)).await?;
+            let etag = sync_etag(newest, total, req.query_string());
+            if etag_matches(&req, &etag) {
+                return Ok(HttpResponse::NotModified().insert_header(ETag(etag)).finish());
+            }
+            (total, Some(etag))
+        }
+        None => (with_retry(&retry, || count_items(&pool.0, principal.tenant, &filter, &visibility)).await?, None),
+    };
+
+    let search = filter.full_text_query();
+    let fuzzy = filter.fuzzy_term();
+    let rows = with_retry(&retry, || async {
+        let mut query = QueryBuilder::new("SELECT id, name, description, created_at, updated_at, version, deleted_at, category_id, created_by, updated_by, archived, slug, position, metadata, price, item_tag_names(id) AS tags, ");
+        match search {
+            Some(search) => query
+                .push(format_args!("ts_rank({}, plainto_tsquery('english', ", SEARCH_VECTOR))
+                .push_bind(search)
+                .push(")) AS rank, "),
+            None => query.push("NULL::real AS rank, "),
+        };
+        match fuzzy {
+            Some(fuzzy) => query.push("similarity(name, ").push_bind(fuzzy).push(") AS similarity"),
+            None => query.push("NULL::real AS similarity"),
+        };
+        query.push(" FROM items");
+        push_item_filters(&mut query, principal.tenant, &filter, &visibility);
+        // ORDER BY can't be bound as a parameter, so it is assembled from allowlisted static strings.
+        // Unless the client picked a sort column, sync requests replay changes oldest first, fuzzy
+        // matches come closest first and searches are ordered by relevance. The id tiebreaker keeps
+        // pages stable when values repeat, and items never positioned come after positioned ones
+        // whichever way position is sorted.
+        match (filter.updated_since, fuzzy, search, &sorting.sort) {
+            (Some(_), _, _, None) => query.push(" ORDER BY updated_at ASC, id ASC"),
+            (None, Some(_), _, None) => query.push(" ORDER BY similarity DESC, created_at DESC, id DESC"),
+            (None, None, Some(_), None) => query.push(format_args!(" ORDER BY rank {}, created_at DESC, id DESC", direction)),
+            _ => query.push(format_args!(" ORDER BY {} {} NULLS LAST, id {}", column, direction, direction)),
+        };
+        query.push(" LIMIT ").push_bind(limit).push(" OFFSET ").push_bind(offset);
+        query.build_query_as::<SearchRow>().fetch_all(&mut *db::filter_connection(&pool.0, &filter).await?).await
+    })
+    .await?;
+
+    let mut items = Vec::with_capacity(rows.len());
+    let mut ranks = Vec::with_capacity(rows.len());
+    let mut similarities = Vec::with_capacity(rows.len());
+    for row in rows {
+        items.push(row.item);
+        ranks.push(row.rank);
+        similarities.push(row.similarity);
+    }
+    let etag = sync_etag.unwrap_or_else(|| page_etag(&items, &total.to_string()));
+    if etag_matches(&req, &etag) {
+        return Ok(HttpResponse::NotModified().insert_header(ETag(etag)).finish());
+    }
+    let items = project_items(items, fields.as_deref());
+    let page = pagination.page.unwrap_or(1);
+    let (status, range) = content_range(offset, items.len(), total);
+    let mut response = HttpResponse::build(status);
+    response.insert_header(ETag(etag));
+    response.insert_header(("X-Total-Count", total.to_string()));
+    response.insert_header((header::CONTENT_RANGE, range));
+    response.insert_header((header::LINK, pagination_links(&req, page, limit, total)));
+    if sizes.clamps(pagination.per_page) {
+        response.insert_header((PAGE_SIZE_CLAMPED_HEADER, "true"));
+    }
+    // A fuzzy match is always reported with its score, enveloped or not
+    if fuzzy.is_some() {
+        let data: Vec<_> = items
+            .into_iter()
+            .zip(similarities)
+            .map(|(item, similarity)| Scored { item, similarity: similarity.unwrap_or_default() })
+            .collect();
+        if enveloped {
+            let meta = ListMeta { total, page, per_page: limit };
+            return Ok(format.respond(&mut response, &ListResponse { data, meta: Some(meta) }));
+        }
+        return Ok(format.respond(&mut response, &data));
+    }
+    if enveloped {
+        let meta = ListMeta { total, page, per_page: limit };
+        if search.is_some() {
+            let data = items
+                .into_iter()
+                .zip(ranks)
+                .map(|(item, rank)| Ranked { item, rank: rank.unwrap_or_default() })
+                .collect();
+            return Ok(format.respond(&mut response, &ListResponse { data, meta: Some(meta) }));
+        }
+        return Ok(format.respond(&mut response, &ListResponse { data: items, meta: Some(meta) }));
+    }
+    Ok(format.respond(&mut response, &items))
+}
+
+// Marks a list page cut down to MAX_PAGE_SIZE because the client asked for more
+const PAGE_SIZE_CLAMPED_HEADER: &str = "x-page-size-clamped";
+
+// Weak validator for a page of items. Every change to an item bumps its version, and items joining,
+// leaving or moving within the page change the sequence of ids; `meta` covers what the headers
+// report about the rest of the result, such as the total or the next cursor.
+fn page_etag(items: &[Item], meta: &str) -> EntityTag {
+    let mut hasher = Sha256::new();
+    for item in items {
+        hasher.update(item.id.as_bytes());
+        hasher.update(item.version.to_be_bytes());
+    }
+    hasher.update(meta.as_bytes());
+    EntityTag::new_weak(hex::encode(&hasher.finalize()[..16]))
+}
+
+// Weak validator for a page of an updated_since sync. Every write moves the changed item's
+// updated_at past all others and a hard delete lowers the count, so either changing means the
+// result may have; the query string tells apart the pages, orders and field selections of one sync.
+fn sync_etag(newest: Option<DateTime<Utc>>, total: i64, query: &str) -> EntityTag {
+    let mut hasher = Sha256::new();
+    hasher.update(newest.map_or(0, |newest| newest.timestamp_micros()).to_be_bytes());
+    hasher.update(total.to_be_bytes());
+    hasher.update(query.as_bytes());
+    EntityTag::new_weak(hex::encode(&hasher.finalize()[..16]))
+}
+
+// Content-Range for an offset page, as table libraries such as react-admin expect: `items 0-24/100`,
+// or `items */100` for a page past the end. A page holding only part of the matches is a 206.
+fn content_range(offset: i64, served: usize, total: i64) -> (StatusCode, String) {
+    if served == 0 {
+        return (StatusCode::OK, format!("items */{}", total));
+    }
+    let last = offset + served as i64 - 1;
+    let status = if offset == 0 && last + 1 >= total { StatusCode::OK } else { StatusCode::PARTIAL_CONTENT };
+    (status, format!("items {}-{}/{}", offset, last, total))
+}
+
+// RFC 8288 Link header for offset pagination: first and last always, prev and next unless at an edge.
+// Links repeat the request's path and query with only `page` replaced.
+fn pagination_links(req: &HttpRequest, page: i64, per_page: i64, total: i64) -> String {
+    let last = ((total + per_page - 1) / per_page).max(1);
+    let query: Vec<&str> = req
+        .query_string()
+        .split('&')
+        .filter(|pair| !pair.is_empty() && pair.split('=').next() != Some("page"))
+        .collect();
+    let link = |page: i64, rel: &str| {
+        let mut query = query.clone();
+        let page = format!("page={}", page);
+        query.push(&page);
+        format!("<{}?{}>; rel=\"{}\"", req.path(), query.join("&"), rel)
+    };
+
+    let mut links = vec![link(1, "first")];
+    if page > 1 {
+        links.push(link((page - 1).min(last), "prev"));
+    }
+    if page < last {
+        links.push(link(page + 1, "next"));
+    }
+    links.push(link(last, "last"));
+    links.join(", ")
+}
+
+// Every item matching the list filters as one JSON array, written chunk by chunk from a database
+// cursor so memory stays flat however many items match. Items come in the requested sort order,
+// or oldest change first with updated_since; search relevance and similarity don't apply. A database
+// error after the first chunk can only end the body early, since the 200 has already gone out.
+fn stream_item_list(
+    pool: PgPool,
+    tenant: Uuid,
+    filter: ItemFilter,
+    visibility: Visibility,
+    (column, direction): (&'static str, &'static str),
+    fields: Option<Vec<&'static str>>,
+) -> HttpResponse {
+    let body = try_stream! {
+        let mut conn = db::filter_connection(&pool, &filter).await?;
+        let mut query = QueryBuilder::new("SELECT id, name, description, created_at, updated_at, version, deleted_at, category_id, created_by, updated_by, archived, slug, position, metadata, price, item_tag_names(id) AS tags FROM items");
+        push_item_filters(&mut query, tenant, &filter, &visibility);
+        match filter.updated_since {
+            Some(_) => query.push(" ORDER BY updated_at ASC, id ASC"),
+            None => query.push(format_args!(" ORDER BY {} {} NULLS LAST, id {}", column, direction, direction)),
+        };
+        let mut items = query.build_query_as::<Item>().fetch(&mut *conn);
+        let mut chunk = Vec::with_capacity(STREAM_CHUNK_BYTES);
+        chunk.push(b'[');
+        let mut first = true;
+        while let Some(item) = items.try_next().await? {
+            if !first {
+                chunk.push(b',');
+            }
+            first = false;
+            match &fields {
+                Some(fields) => serde_json::to_writer(&mut chunk, &project_item(&item, fields)),
+                None => serde_json::to_writer(&mut chunk, &item),
+            }
+            .expect("items always serialize to JSON");
+            if chunk.len() >= STREAM_CHUNK_BYTES {
+                yield web::Bytes::from(std::mem::replace(&mut chunk, Vec::with_capacity(STREAM_CHUNK_BYTES)));
+            }
+        }
+        chunk.push(b']');
+        yield web::Bytes::from(chunk);
+    };
+    let body = body.inspect_err(|err: &sqlx::Error| warn!(error = %err, "streamed item list aborted"));
+
+    HttpResponse::Ok().content_type(mime::APPLICATION_JSON).streaming(body)
+}
+
+// Keyset-paginated listing, newest first, resuming strictly after the cursor's row.
+// Returns the page and a response carrying X-Next-Cursor when another page follows.
+#[allow(clippy::too_many_arguments)]
+async fn get_items_after(
+    pool: &PgPool,
+    tenant: Uuid,
+    retry: &RetryPolicy,
+    filter: &ItemFilter,
+    visibility: &Visibility,
+    keyset: &KeysetPagination,
+    sizes: &PageSizes,
+    fields: Option<&[&str]>,
+) -> Result<(HttpResponseBuilder, EntityTag, Vec<ItemView>), ApiError> {
+    let limit = keyset.limit(sizes)?;
+    let after = keyset.after.as_deref().map(Cursor::decode).transpose()?;
+
+    let mut items = with_retry(retry, || async {
+        let mut query = QueryBuilder::new("SELECT id, name, description, created_at, updated_at, version, deleted_at, category_id, created_by, updated_by, archived, slug, position, metadata, price, item_tag_names(id) AS tags FROM items");
+        push_item_filters(&mut query, tenant, filter, visibility);
+        if let Some(after) = &after {
+            query
+                .push(" AND (created_at, id) < (")
+                .push_bind(after.created_at)
+                .push(", ")
+                .push_bind(after.id)
+                .push(")");
+        }
+        // Fetch one extra row to learn whether another page follows
+        query.push(" ORDER BY created_at DESC, id DESC LIMIT ").push_bind(limit + 1);
+        query.build_query_as::<Item>().fetch_all(&mut *db::filter_connection(pool, filter).await?).await
+    })
+    .await?;
+
+    let mut response = HttpResponse::Ok();
+    let mut next_cursor = String::new();
+    if items.len() as i64 > limit {
+        items.truncate(limit as usize);
+        if let Some(last) = items.last() {
+            next_cursor = Cursor::for_item(last).encode();
+            response.insert_header(("X-Next-Cursor", next_cursor.as_str()));
+        }
+    }
+    let etag = page_etag(&items, &next_cursor);
+    Ok((response, etag, project_items(items, fields)))
+}
+
+// Count items, honoring the same filters as the list endpoint
+#[utoipa::path(
+    get,
+    path = "/items/count",
+    tag = "items",
+    params(ItemFilter, Visibility),
+    responses(
+        (status = 200, description = "Number of matching items", body = ItemCount),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody)
+    )
+)]
+pub async fn get_item_count(
+    pool: web::Data<ReadPool>,
+    filter: web::Query<ItemFilter>,
+    visibility: web::Query<Visibility>,
+    principal: Principal,
+    retry: web::Data<RetryPolicy>,
+    format: Format,
+) -> Result<HttpResponse, ApiError> {
+    // An unfiltered count of live items is kept up to date by a trigger; any other count scans.
+    // A tenant that has never had items has no counter yet and is counted the slow way.
+    if filter.is_unfiltered() && !visibility.include_deleted {
+        let stored = with_retry(&retry, || {
+            sqlx::query_scalar!("SELECT live FROM item_counters WHERE tenant_id = $1", principal.tenant).fetch_optional(&pool.0)
+        })
+        .await?;
+        if let Some(count) = stored {
+            return Ok(format.respond(&mut HttpResponse::Ok(), &ItemCount { count }));
+        }
+    }
+    let count = with_retry(&retry, || count_items(&pool.0, principal.tenant, &filter, &visibility)).await?;
+    Ok(format.respond(&mut HttpResponse::Ok(), &ItemCount { count }))
+}
+
+// Live item counts per category, uncategorized items included, for dashboards that would otherwise
+// fetch every item to size the groups. Categories without live items are left out.
+#[utoipa::path(
+    get,
+    path = "/items/stats",
+    tag = "items",
+    params(ItemStatsParams),
+    responses(
+        (status = 200, description = "Item counts by category, the uncategorized bucket last", body = Vec<CategoryCount>),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody)
+    )
+)]
+pub async fn get_item_stats(
+    pool: web::Data<ReadPool>,
+    params: web::Query<ItemStatsParams>,
+    principal: Principal,
+    retry: web::Data<RetryPolicy>,
+    format: Format,
+) -> Result<HttpResponse, ApiError> {
+    let counts = with_retry(&retry, || {
+        sqlx::query_as!(
+            CategoryCount,
+            r#"SELECT category_id, COUNT(*) AS "count!" FROM items
+             WHERE tenant_id = $1 AND deleted_at IS NULL AND NOT archived AND ($2::timestamptz IS NULL OR created_at > $2)
+             GROUP BY category_id ORDER BY category_id NULLS LAST"#,
+            principal.tenant,
+            params.since
+        )
+        .fetch_all(&pool.0)
+    })
+    .await?;
+    Ok(format.respond(&mut HttpResponse::Ok(), &counts))
+}
+
+// The newest and oldest live items, for "recently added" views that shouldn't page through the whole
+// list. Both are fetched in one query, ordered newest first.
+#[utoipa::path(
+    get,
+    path = "/items/bounds",
+    tag = "items",
+    responses(
+        (status = 200, description = "The newest and oldest items, null when there are no items", body = ItemBounds),
+        (status = 500, description = "Internal error", body = ErrorBody)
+    )
+)]
+pub async fn get_item_bounds(
+    pool: web::Data<ReadPool>,
+    principal: Principal,
+    retry: web::Data<RetryPolicy>,
+    format: Format,
+) -> Result<HttpResponse, ApiError> {
+    let mut items = with_retry(&retry, || {
+        sqlx::query_as!(
+            Item,
+            "SELECT id, name, description, created_at, updated_at, version, deleted_at, category_id, created_by, updated_by, archived, slug, position, metadata, price, item_tag_names(id) AS \"tags!\" FROM items
+             WHERE id IN (
+                 (SELECT id FROM items WHERE tenant_id = $1 AND deleted_at IS NULL AND NOT archived ORDER BY created_at DESC, id DESC LIMIT 1),
+                 (SELECT id FROM items WHERE tenant_id = $1 AND deleted_at IS NULL AND NOT archived ORDER BY created_at ASC, id ASC LIMIT 1)
+             )
+             ORDER BY created_at DESC, id DESC",
+            principal.tenant
+        )
+        .fetch_all(&pool.0)
+    })
+    .await?;
+    let oldest = items.pop();
+    let newest = items.pop().or_else(|| oldest.clone());
+    Ok(format.respond(&mut HttpResponse::Ok(), &ItemBounds { newest, oldest }))
+}
+
+// Recompute the tenant's live item count from the table and store it, for when the counter has drifted
+// (say, after rows were changed with the trigger disabled)
+#[utoipa::path(
+    post,
+    path = "/items/count/reconcile",
+    tag = "items",
+    responses(
+        (status = 200, description = "The recomputed count, and how far the stored one was off", body = CountReconciliation),
+        (status = 500, description = "Internal error", body = ErrorBody)
+    )
+)]
+pub async fn reconcile_item_count(
+    pool: web::Data<PgPool>,
+    retry: web::Data<TransactionRetry>,
+    principal: Principal,
+    format: Format,
+) -> Result<HttpResponse, ApiError> {
+    let tenant = principal.tenant;
+    let reconciliation = with_transaction(&pool, &retry, move |conn| {
+        Box::pin(async move {
+            // Holding the counter row makes this tenant's writers wait, so the count can't change underneath
+            sqlx::query!("INSERT INTO item_counters (tenant_id, live) VALUES ($1, 0) ON CONFLICT (tenant_id) DO NOTHING", tenant)
+                .execute(&mut *conn)
+                .await?;
+            let stored = sqlx::query_scalar!("SELECT live FROM item_counters WHERE tenant_id = $1 FOR UPDATE", tenant)
+                .fetch_one(&mut *conn)
+                .await?;
+            let count = sqlx::query_scalar!(
+                r#"SELECT COUNT(*) AS "count!" FROM items WHERE tenant_id = $1 AND deleted_at IS NULL AND NOT archived"#,
+                tenant
+            )
+            .fetch_one(&mut *conn)
+            .await?;
+            sqlx::query!("UPDATE item_counters SET live = $2 WHERE tenant_id = $1", tenant, count).execute(&mut *conn).await?;
+            Ok(CountReconciliation { count, drift: stored - count })
+        })
+    })
+    .await?;
+    if reconciliation.drift != 0 {
+        warn!(%tenant, drift = reconciliation.drift, "corrected a drifted item count");
+    }
+    Ok(format.respond(&mut HttpResponse::Ok(), &reconciliation))
+}
+
+// Report whether a name is free for a new item, without returning any row data
+#[utoipa::path(
+    get,
+    path = "/items/validate",
+    tag = "items",
+    params(NameCheck),
+    responses(
+        (status = 200, description = "Whether the name is available", body = NameAvailability),
+        (status = 400, description = "Invalid name", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody)
+    )
+)]
+pub async fn validate_item_name(
+    pool: web::Data<PgPool>,
+    params: web::Query<NameCheck>,
+    principal: Principal,
+    retry: web::Data<RetryPolicy>,
+    format: Format,
+) -> Result<HttpResponse, ApiError> {
+    let name = params.name.split_whitespace().collect::<Vec<_>>().join(" ");
+    let check = NameCheck { name };
+    check.validate().map_err(|err| ApiError::Validation(FieldErrors::from(err).to_string()))?;
+    let name = check.name;
+    // Matches the live-item unique index on (tenant_id, lower(name)), so a create with this name won't conflict
+    let taken = with_retry(&retry, || async {
+        sqlx::query_scalar!(
+            r#"SELECT EXISTS(SELECT 1 FROM items WHERE tenant_id = $1 AND lower(name) = lower($2) AND deleted_at IS NULL) AS "exists!""#,
+            principal.tenant,
+            name
+        )
+        .fetch_one(pool.get_ref())
+        .await
+    })
+    .await?;
+    Ok(format.respond(&mut HttpResponse::Ok(), &NameAvailability { available: !taken }))
+}
+
+// Distinct live item names starting with `prefix`, ignoring case, for search box autocompletion
+#[utoipa::path(
+    get,
+    path = "/items/suggest",
+    tag = "items",
+    params(SuggestParams),
+    responses(
+        (status = 200, description = "Distinct matching names in alphabetical order", body = Vec<String>),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody)
+    )
+)]
+pub async fn suggest_item_names(
+    pool: web::Data<ReadPool>,
+    params: web::Query<SuggestParams>,
+    principal: Principal,
+    retry: web::Data<RetryPolicy>,
+    format: Format,
+) -> Result<HttpResponse, ApiError> {
+    let limit = params.limit().map_err(|message| ApiError::Validation(message.to_string()))?;
+    let pattern = params.pattern();
+    let names = with_retry(&retry, || {
+        sqlx::query_scalar!(
+            "SELECT name FROM items WHERE tenant_id = $1 AND name ILIKE $2 AND deleted_at IS NULL
+             GROUP BY name ORDER BY lower(name), name LIMIT $3",
+            principal.tenant,
+            pattern,
+            limit
+        )
+        .fetch_all(&pool.0)
+    })
+    .await?;
+    Ok(format.respond(&mut HttpResponse::Ok(), &names))
+}
+
+// Export every live item of the tenant as CSV, streamed row by row from a database cursor
+#[utoipa::path(
+    get,
+    path = "/items/export.csv",
+    tag = "items",
+    responses(
+        (status = 200, description = "Items as CSV with a header row", content_type = "text/csv", body = String),
+        (status = 500, description = "Internal error", body = ErrorBody)
+    )
+)]
+pub async fn export_items_csv(pool: web::Data<PgPool>, principal: Principal) -> HttpResponse {
+    let pool = pool.get_ref().clone();
+    let rows = try_stream! {
+        yield web::Bytes::from_static(b"id,name,description,created_at\r\n");
+        let mut items = sqlx::query_as!(
+            Item,
+            "SELECT id, name, description, created_at, updated_at, version, deleted_at, category_id, created_by, updated_by, archived, slug, position, metadata, price, item_tag_names(id) AS \"tags!\" FROM items
+             WHERE tenant_id = $1 AND deleted_at IS NULL ORDER BY created_at, id",
+            principal.tenant
+        )
+        .fetch(&pool);
+        while let Some(item) = items.try_next().await? {
+            yield web::Bytes::from(csv_row(&[
+                &item.id.to_string(),
+                &item.name,
+                item.description.as_deref().unwrap_or_default(),
+                &item.created_at.to_rfc3339(),
+            ]));
+        }
+    };
+    let rows = rows.inspect_err(|err: &sqlx::Error| warn!(error = %err, "CSV export aborted"));
+
+    HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header((header::CONTENT_DISPOSITION, "attachment; filename=\"items.csv\""))
+        .streaming(rows)
+}
+
+// Sign a link to the caller's CSV export that works without the API key or a bearer token until it
+// expires, for handing to a BI tool. The link is bound to the tenant it was made for.
+#[utoipa::path(
+    post,
+    path = "/items/export/link",
+    tag = "items",
+    responses(
+        (status = 200, description = "The signed export link and when it expires", body = ExportLink),
+        (status = 403, description = "No signing secret is configured", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody)
+    )
+)]
+pub async fn create_export_link(req: HttpRequest, links: web::Data<ExportLinks>, principal: Principal, format: Format) -> Result<HttpResponse, ApiError> {
+    let prefix = req.app_data::<web::Data<ApiPrefix>>().map_or("", |prefix| prefix.0.as_str());
+    let path = format!("{}{}", prefix, routes::EXPORT_CSV_PATH);
+    let expires_at = Utc::now() + links.ttl;
+    let signature = links
+        .sign(&path, principal.tenant, expires_at.timestamp())
+        .ok_or_else(|| ApiError::Forbidden("Export links are switched off; set EXPORT_LINK_SECRET to enable them".to_string()))?;
+    let base_url = match &links.base_url {
+        Some(base_url) => base_url.clone(),
+        None => {
+            let info = req.connection_info();
+            format!("{}://{}", info.scheme(), info.host())
+        }
+    };
+    let url = format!("{}{}?tenant={}&exp={}&sig={}", base_url, path, principal.tenant, expires_at.timestamp(), signature);
+    Ok(format.respond(&mut HttpResponse::Ok(), &ExportLink { url, expires_at }))
+}
+
+// Streamed JSON is batched into chunks of about this size before being sent
+const STREAM_CHUNK_BYTES: usize = 32 * 1024;
+
+// Stream every live item of the tenant as newline-delimited JSON, one item per line, read from a
+// database cursor so memory stays flat however many items there are. A client that disconnects
+// drops the response stream and with it the cursor, which hands the connection back to the pool.
+#[utoipa::path(
+    get,
+    path = "/items/stream",
+    tag = "items",
+    responses(
+        (status = 200, description = "One JSON item per line", content_type = "application/x-ndjson", body = Item),
+        (status = 500, description = "Internal error", body = ErrorBody)
+    )
+)]
+pub async fn stream_items(pool: web::Data<PgPool>, principal: Principal) -> HttpResponse {
+    let pool = pool.get_ref().clone();
+    let lines = try_stream! {
+        let mut items = sqlx::query_as!(
+            Item,
+            "SELECT id, name, description, created_at, updated_at, version, deleted_at, category_id, created_by, updated_by, archived, slug, position, metadata, price, item_tag_names(id) AS \"tags!\" FROM items
+             WHERE tenant_id = $1 AND deleted_at IS NULL ORDER BY created_at, id",
+            principal.tenant
+        )
+        .fetch(&pool);
+        let mut chunk = Vec::with_capacity(STREAM_CHUNK_BYTES);
+        while let Some(item) = items.try_next().await? {
+            serde_json::to_writer(&mut chunk, &item).expect("items always serialize to JSON");
+            chunk.push(b'\n');
+            if chunk.len() >= STREAM_CHUNK_BYTES {
+                yield web::Bytes::from(std::mem::replace(&mut chunk, Vec::with_capacity(STREAM_CHUNK_BYTES)));
+            }
+        }
+        if !chunk.is_empty() {
+            yield web::Bytes::from(chunk);
+        }
+    };
+    let lines = lines.inspect_err(|err: &sqlx::Error| warn!(error = %err, "NDJSON stream aborted"));
+
+    HttpResponse::Ok().content_type("application/x-ndjson").streaming(lines)
+}
+
+// One CSV record terminated by CRLF, quoting fields that contain separators, quotes or line breaks
+fn csv_row(fields: &[&str]) -> String {
+    let mut row = String::new();
+    for (index, field) in fields.iter().enumerate() {
+        if index > 0 {
+            row.push(',');
+        }
+        if field.contains([',', '"', '\n', '\r']) {
+            row.push('"');
+            row.push_str(&field.replace('"', "\"\""));
+            row.push('"');
+        } else {
+            row.push_str(field);
+        }
+    }
+    row.push_str("\r\n");
+    row
+}
+
+// Upper bound on the size of an uploaded CSV file
+#[derive(Debug, Clone, Copy)]
+pub struct ImportLimits {
+    pub max_bytes: usize,
+}
+
+impl ImportLimits {
+    pub fn from_env(env: &mut EnvReader) -> Self {
+        ImportLimits { max_bytes: env.parse_or("IMPORT_MAX_BYTES", 10 * 1024 * 1024) }
+    }
+}
+
+// Import items from a CSV upload in one transaction, skipping and reporting rows that fail validation
+#[utoipa::path(
+    post,
+    path = "/items/import",
+    tag = "items",
+    request_body(content = String, description = "CSV with a header row naming the name and description columns, sent as text/csv or as the `file` part of multipart/form-data", content_type = "text/csv"),
+    responses(
+        (status = 200, description = "Rows inserted and rows skipped", body = ImportSummary),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 409, description = "Conflicting item", body = ErrorBody),
+        (status = 413, description = "Upload exceeds IMPORT_MAX_BYTES", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody)
+    )
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn import_items_csv(
+    req: HttpRequest,
+    payload: web::Payload,
+    pool: web::Data<PgPool>,
+    retry: web::Data<TransactionRetry>,
+    events: web::Data<ItemEvents>,
+    principal: Principal,
+    format: Format,
+    limits: web::Data<ImportLimits>,
+) -> Result<HttpResponse, ApiError> {
+    let upload = read_csv_upload(&req, payload, limits.max_bytes).await?;
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(upload.as_ref());
+    let headers = reader
+        .headers()
+        .map_err(|err| ApiError::Validation(format!("Invalid CSV header: {}", err)))?
+        .clone();
+    let column = |name: &str| headers.iter().position(|header| header.trim().eq_ignore_ascii_case(name));
+    let name_column = column("name").ok_or_else(|| ApiError::Validation("CSV header must include a name column".to_string()))?;
+    let description_column = column("description");
+
+    let mut summary = ImportSummary { inserted: 0, errors: Vec::new() };
+    let mut valid = Vec::new();
+    for record in reader.records() {
+        let record = match record {
+            Ok(record) => record,
+            Err(err) => {
+                let line = err.position().map_or(0, |position| position.line());
+                summary.errors.push(ImportRowError { line, reason: err.to_string() });
+                continue;
+            }
+        };
+        let line = record.position().map_or(0, |position| position.line());
+        if record.len() != headers.len() {
+            let reason = format!("expected {} fields, found {}", headers.len(), record.len());
+            summary.errors.push(ImportRowError { line, reason });
+            continue;
+        }
+
+        let item = ItemCreateRequest {
+            name: record[name_column].to_string(),
+            description: description_column.map(|column| record[column].to_string()),
+            category_id: None,
+            metadata: None,
+            price: None,
+        };
+        if let Err(err) = item.validate() {
+            summary.errors.push(ImportRowError { line, reason: FieldErrors::from(err).to_string() });
+            continue;
+        }
+        valid.push(item);
+    }
+
+    let Principal { tenant, user } = principal;
+    let created = with_transaction(&pool, &retry, move |conn| {
+        let valid = valid.clone();
+        Box::pin(async move {
+            let mut created = Vec::with_capacity(valid.len());
+            for item in &valid {
+                created.push(insert_item(conn, tenant, item, user).await?);
+            }
+            Ok(created)
+        })
+    })
+    .await?;
+    summary.inserted = created.len() as u64;
+    for item in &created {
+        events.publish(tenant, ItemEventKind::Created, item);
+    }
+
+    Ok(format.respond(&mut HttpResponse::Ok(), &summary))
+}
+
+// Read the CSV from a raw text/csv body or the `file` part of a multipart form, up to `max_bytes`
+async fn read_csv_upload(req: &HttpRequest, payload: web::Payload, max_bytes: usize) -> Result<web::BytesMut, ApiError> {
+    let mime = req.mime_type().map_err(|_| ApiError::Validation("Invalid Content-Type header".to_string()))?;
+    match mime.as_ref().map(|mime| mime.essence_str()) {
+        Some("text/csv") => read_limited(payload, max_bytes).await,
+        Some("multipart/form-data") => {
+            let mut form = Multipart::new(req.headers(), payload);
+            while let Some(field) = form.try_next().await.map_err(|err| ApiError::Validation(err.to_string()))? {
+                if field.name() == Some("file") {
+                    return read_limited(field, max_bytes).await;
+                }
+            }
+            Err(ApiError::Validation("multipart upload must include a file part".to_string()))
+        }
+        _ => Err(ApiError::Validation("Expected a text/csv or multipart/form-data body".to_string())),
+    }
+}
+
+// Buffer a body stream, failing with 413 once it grows past `max_bytes`
+async fn read_limited<S, E>(mut stream: S, max_bytes: usize) -> Result<web::BytesMut, ApiError>
+where
+    S: Stream<Item = Result<web::Bytes, E>> + Unpin,
+    E: fmt::Display,
+{
+    let mut body = web::BytesMut::new();
+    while let Some(chunk) = stream.try_next().await.map_err(|err| ApiError::Validation(err.to_string()))? {
+        if body.len() + chunk.len() > max_bytes {
+            return Err(ApiError::PayloadTooLarge(format!("CSV upload may be at most {} bytes", max_bytes)));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+// Get a specific item by ID
+#[utoipa::path(
+    get,
+    path = "/items/{id}",
+    tag = "items",
+    params(("id" = Uuid, Path, description = "Item id"), Visibility, FieldSelection),
+    responses(
+        (status = 200, description = "The item", body = Item,
+            headers(("ETag" = String, description = "Weak validator for the item version"),
+                    ("Last-Modified" = String, description = "When the item last changed, as an HTTP-date"))),
+        (status = 304, description = "Item unchanged since the If-None-Match validator or If-Modified-Since date"),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 404, description = "Item not found", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody)
+    )
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn get_item(
+    req: HttpRequest,
+    primary: web::Data<PgPool>,
+    pool: web::Data<ReadPool>,
+    item_id: web::Path<Uuid>,
+    params: ItemParams,
+    principal: Principal,
+    retry: web::Data<RetryPolicy>,
+    cache: web::Data<ItemCache>,
+    metrics: web::Data<Metrics>,
+    format: Format,
+) -> Result<HttpResponse, ApiError> {
+    let ItemParams { visibility, fields } = params;
+    let fields = fields.resolve()?;
+    if cache.enabled() {
+        if let Some(item) = cache.get(principal.tenant, *item_id) {
+            metrics.item_cache.with_label_values(&["hit"]).inc();
+            if item.deleted_at.is_some() && !visibility.include_deleted {
+                return Err(ApiError::NotFound("Item not found".to_string()));
+            }
+            return respond_with_item(&req, &item, fields, format);
+        }
+        metrics.item_cache.with_label_values(&["miss"]).inc();
+    }
+    // What goes in the cache is read from the primary: a lagging replica's copy would be served
+    // stale until it expired, as the invalidation after a write has already happened
+    let source = if cache.enabled() { primary.get_ref() } else { &pool.0 };
+    let item = cache
+        .load(principal.tenant, *item_id, visibility.include_deleted, || async {
+            with_retry(&retry, || {
+                sqlx::query_as!(
+                    Item,
+                    "SELECT id, name, description, created_at, updated_at, version, deleted_at, category_id, created_by, updated_by, archived, slug, position, metadata, price, item_tag_names(id) AS \"tags!\" FROM items
+                     WHERE id = $1 AND tenant_id = $2 AND ($3 OR deleted_at IS NULL)",
+                    *item_id,
+                    principal.tenant,
+                    visibility.include_deleted
+                )
+                .fetch_one(source)
+            })
+            .await
+            .map_err(ApiError::from)
+        })
+        .await?;
+    respond_with_item(&req, &item, fields, format)
+}
+
+// Get a specific item by its URL slug
+#[utoipa::path(
+    get,
+    path = "/items/by-slug/{slug}",
+    tag = "items",
+    params(("slug" = String, Path, description = "Item slug"), Visibility, FieldSelection),
+    responses(
+        (status = 200, description = "The item", body = Item,
+            headers(("ETag" = String, description = "Weak validator for the item version"),
+                    ("Last-Modified" = String, description = "When the item last changed, as an HTTP-date"))),
+        (status = 304, description = "Item unchanged since the If-None-Match validator or If-Modified-Since date"),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 404, description = "Item not found", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody)
+    )
+)]
+pub async fn get_item_by_slug(
+    req: HttpRequest,
+    pool: web::Data<ReadPool>,
+    slug: web::Path<String>,
+    params: ItemParams,
+    principal: Principal,
+    retry: web::Data<RetryPolicy>,
+    format: Format,
+) -> Result<HttpResponse, ApiError> {
+    let ItemParams { visibility, fields } = params;
+    let fields = fields.resolve()?;
+    let item = with_retry(&retry, || {
+        sqlx::query_as!(
+            Item,
+            "SELECT id, name, description, created_at, updated_at, version, deleted_at, category_id, created_by, updated_by, archived, slug, position, metadata, price, item_tag_names(id) AS \"tags!\" FROM items
+             WHERE slug = $1 AND tenant_id = $2 AND ($3 OR deleted_at IS NULL)",
+            slug.as_str(),
+            principal.tenant,
+            visibility.include_deleted
+        )
+        .fetch_one(&pool.0)
+    })
+    .await?;
+    respond_with_item(&req, &item, fields, format)
+}
+
+// Get a specific item by its name, ignoring case. Runs of whitespace in the requested name count
+// as one space and surrounding whitespace is dropped. Live names are unique, but soft-deleted items
+// keep theirs, so with include_deleted more than one item can match: that is a 409 and the caller
+// has to use the id or slug instead.
+#[utoipa::path(
+    get,
+    path = "/items/by-name/{name}",
+    tag = "items",
+    params(("name" = String, Path, description = "Item name, URL-encoded"), Visibility, FieldSelection),
+    responses(
+        (status = 200, description = "The item", body = Item,
+            headers(("ETag" = String, description = "Weak validator for the item version"),
+                    ("Last-Modified" = String, description = "When the item last changed, as an HTTP-date"))),
+        (status = 304, description = "Item unchanged since the If-None-Match validator or If-Modified-Since date"),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 404, description = "Item not found", body = ErrorBody),
+        (status = 409, description = "More than one item has that name", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody)
+    )
+)]
+pub async fn get_item_by_name(
+    req: HttpRequest,
+    pool: web::Data<ReadPool>,
+    name: web::Path<String>,
+    params: ItemParams,
+    principal: Principal,
+    retry: web::Data<RetryPolicy>,
+    format: Format,
+) -> Result<HttpResponse, ApiError> {
+    let ItemParams { visibility, fields } = params;
+    let fields = fields.resolve()?;
+    let name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+    if name.is_empty() {
+        return Err(ApiError::Validation("name must not be blank".to_string()));
+    }
+    let mut matches = with_retry(&retry, || {
+        sqlx::query_as!(
+            Item,
+            "SELECT id, name, description, created_at, updated_at, version, deleted_at, category_id, created_by, updated_by, archived, slug, position, metadata, price, item_tag_names(id) AS \"tags!\" FROM items
+             WHERE lower(name) = lower($1) AND tenant_id = $2 AND ($3 OR deleted_at IS NULL)
+             LIMIT 2",
+            name,
+            principal.tenant,
+            visibility.include_deleted
+        )
+        .fetch_all(&pool.0)
+    })
+    .await?;
+    if matches.len() > 1 {
+        return Err(ApiError::Conflict("More than one item has that name; look it up by id or slug".to_string()));
+    }
+    let item = matches.pop().ok_or_else(|| ApiError::NotFound("Item not found".to_string()))?;
+    respond_with_item(&req, &item, fields, format)
+}
+
+// A single item with its validators, or 304 when the client's cached copy is current
+fn respond_with_item(req: &HttpRequest, item: &Item, fields: Option<Vec<&str>>, format: Format) -> Result<HttpResponse, ApiError> {
+    let etag = item.etag();
+    let last_modified = LastModified(HttpDate::from(SystemTime::from(item.updated_at)));
+    if not_modified(req, &etag, item)? {
+        return Ok(HttpResponse::NotModified().insert_header(ETag(etag)).insert_header(last_modified).finish());
+    }
+
+    let mut response = HttpResponse::Ok();
+    response.insert_header(ETag(etag));
+    response.insert_header(last_modified);
+    match fields {
+        Some(fields) => Ok(format.respond(&mut response, &project_item(item, &fields))),
+        None => Ok(format.respond(&mut response, item)),
+    }
+}
+
+// Whether the client's cached copy is current. If-None-Match takes precedence, as RFC 7232 requires;
+// HTTP-dates have one-second resolution, so updated_at is compared in whole seconds.
+fn not_modified(req: &HttpRequest, etag: &EntityTag, item: &Item) -> Result<bool, ApiError> {
+    if req.headers().contains_key(header::IF_NONE_MATCH) {
+        return Ok(etag_matches(req, etag));
+    }
+    if !req.headers().contains_key(header::IF_MODIFIED_SINCE) {
+        return Ok(false);
+    }
+    let IfModifiedSince(since) = IfModifiedSince::parse(req)
+        .map_err(|_| ApiError::Validation("If-Modified-Since must be an HTTP-date".to_string()))?;
+    Ok(item.updated_at.timestamp() <= DateTime::<Utc>::from(SystemTime::from(since)).timestamp())
+}
+
+// Whether If-None-Match names `etag` (or is `*`), compared weakly as RFC 7232 requires for GET
+fn etag_matches(req: &HttpRequest, etag: &EntityTag) -> bool {
+    match req.get_header::<IfNoneMatch>() {
+        Some(IfNoneMatch::Any) => true,
+        Some(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(etag)),
+        None => false,
+    }
+}
+
+// Fetch several live items by id, in the order the ids were given; unknown ids are left out
+#[utoipa::path(
+    post,
+    path = "/items/batch-get",
+    tag = "items",
+    request_body = ItemBatchGetRequest,
+    responses(
+        (status = 200, description = "The items found, in request order", body = Vec<Item>),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 413, description = "Too many ids in the batch", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody)
+    )
+)]
+pub async fn get_items_by_ids(
+    pool: web::Data<ReadPool>,
+    principal: Principal,
+    retry: web::Data<RetryPolicy>,
+    format: Format,
+    batch: Body<ItemBatchGetRequest>,
+) -> Result<HttpResponse, ApiError> {
+    if batch.ids.len() > MAX_BATCH_SIZE {
+        return Err(ApiError::PayloadTooLarge(format!("Batch may contain at most {} ids", MAX_BATCH_SIZE)));
+    }
+
+    let items = with_retry(&retry, || {
+        sqlx::query_as!(
+            Item,
+            "SELECT id, name, description, created_at, updated_at, version, deleted_at, category_id, created_by, updated_by, archived, slug, position, metadata, price, item_tag_names(id) AS \"tags!\" FROM items
+             WHERE id = ANY($1) AND tenant_id = $2 AND deleted_at IS NULL ORDER BY array_position($1, id)",
+            &batch.ids[..],
+            principal.tenant
+        )
+        .fetch_all(&pool.0)
+    })
+    .await?;
+    Ok(format.respond(&mut HttpResponse::Ok(), &items))
+}
+
+// Check whether an item exists without fetching or sending its body
+#[utoipa::path(
+    head,
+    path = "/items/{id}",
+    tag = "items",
+    params(("id" = Uuid, Path, description = "Item id"), Visibility),
+    responses(
+        (status = 200, description = "Item exists"),
+        (status = 404, description = "Item not found"),
+        (status = 500, description = "Internal error")
+    )
+)]
+pub async fn item_exists(
+    pool: web::Data<PgPool>,
+    item_id: web::Path<Uuid>,
+    visibility: web::Query<Visibility>,
+    principal: Principal,
+    retry: web::Data<RetryPolicy>,
+) -> Result<HttpResponse, ApiError> {
+    let exists = with_retry(&retry, || {
+        sqlx::query_scalar!(
+            r#"SELECT EXISTS(SELECT 1 FROM items WHERE id = $1 AND tenant_id = $2 AND ($3 OR deleted_at IS NULL)) AS "exists!""#,
+            *item_id,
+            principal.tenant,
+            visibility.include_deleted
+        )
+        .fetch_one(pool.get_ref())
+    })
+    .await?;
+
+    if !exists {
+        return Err(ApiError::NotFound("Item not found".to_string()));
+    }
+    Ok(HttpResponse::Ok().insert_header((header::CONTENT_LENGTH, 0)).finish())
+}
+
+// Create or replace an item under a client-chosen id. Creating needs no version;
+// replacing an existing item still requires the expected one.
+#[utoipa::path(
+    put,
+    path = "/items/{id}",
+    tag = "items",
+    request_body(content(
+        (ItemUpdateRequest = "application/json"),
+        (ItemUpdateRequest = "application/msgpack"),
+        (ItemUpdateRequest = "application/x-www-form-urlencoded")
+    )),
+    params(
+        ("id" = Uuid, Path, description = "Item id"),
+        ("If-Match" = Option<String>, Header, description = "Expected item version"),
+        ("Prefer" = Option<String>, Header, description = "`dry-run` does the same as `dry_run=true`"),
+        DryRunParam,
+        DiffParam
+    ),
+    responses(
+        (status = 200, description = "Item replaced, or on a dry run the item that would have been stored, marked by `X-Dry-Run: true`. With diff=true an ItemDiff.", body = Item),
+        (status = 201, description = "Item created. With diff=true an ItemDiff listing every field as new.", body = Item),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 404, description = "Item not found", body = ErrorBody),
+        (status = 409, description = "Conflicting item, stale version or deleted item", body = ErrorBody),
+        (status = 415, description = "Body is not JSON, MessagePack or a form", body = ErrorBody),
+        (status = 422, description = "Invalid fields", body = ErrorBody),
+        (status = 428, description = "Item exists and no expected version was supplied", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody)
+    )
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn update_item(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    events: web::Data<ItemEvents>,
+    cache: web::Data<ItemCache>,
+    principal: Principal,
+    format: Format,
+    item_id: web::Path<Uuid>,
+    dry_run: web::Query<DryRunParam>,
+    diff: web::Query<DiffParam>,
+    item: FormOrBody<ItemUpdateRequest>,
+) -> Result<HttpResponse, ApiError> {
+    item.validate()?;
+    let version = supplied_version(&req, item.version)?;
+    let dry_run = dry_run.requested(&req);
+
+    // xmax is zero only for a freshly inserted row, which tells a create from a replace.
+    // An id taken by another tenant matches no row to update, so it is reported as missing.
+    // A replacement keeps its slug unless the name changed.
+    let (old, row) = timed_query(async {
+        let mut tx = pool.begin().await?;
+        let old = if diff.diff { db::lock_live_item(&mut tx, principal.tenant, *item_id).await? } else { None };
+        let slug = db::unique_slug(&mut tx, principal.tenant, &item.name, Some(*item_id)).await?;
+        let row = sqlx::query!(
+            r#"INSERT INTO items (id, name, description, category_id, created_by, updated_by, tenant_id, slug, metadata, price, created_at, updated_at)
+             VALUES ($1, $2, $3, $4, $6, $6, $7, $8, $9, $10, now(), now())
+             ON CONFLICT (id) DO UPDATE SET name = EXCLUDED.name, description = EXCLUDED.description, category_id = EXCLUDED.category_id,
+                 metadata = EXCLUDED.metadata, price = EXCLUDED.price,
+                 slug = CASE WHEN items.name = EXCLUDED.name THEN items.slug ELSE EXCLUDED.slug END,
+                 updated_by = EXCLUDED.updated_by, updated_at = now(), version = items.version + 1
+             WHERE items.tenant_id = EXCLUDED.tenant_id AND items.deleted_at IS NULL AND items.version = $5
+             RETURNING id, name, description, created_at, updated_at, version, deleted_at, category_id, created_by, updated_by, archived, slug, position, metadata, price, item_tag_names(id) AS "tags!", (xmax = 0) AS "inserted!""#,
+            *item_id,
+            item.name,
+            item.description,
+            item.category_id,
+            version,
+            principal.user,
+            principal.tenant,
+            slug,
+            item.metadata,
+            item.price
+        )
+        .fetch_optional(&mut tx)
+        .await?;
+        commit_unless_dry_run(tx, dry_run).await?;
+        Ok::<_, ApiError>((old, row))
+    })
+    .await?;
+    if !dry_run {
+        cache.invalidate([*item_id]);
+    }
+
+    let Some(row) = row else {
+        return Err(timed_query(replace_rejected(pool.get_ref(), principal.tenant, *item_id, version)).await);
+    };
+    let stored = Item {
+        id: row.id,
+        name: row.name,
+        slug: row.slug,
+        description: row.description,
+        created_at: row.created_at,
+        updated_at: row.updated_at,
+        version: row.version,
+        deleted_at: row.deleted_at,
+        category_id: row.category_id,
+        created_by: row.created_by,
+        updated_by: row.updated_by,
+        archived: row.archived,
+        position: row.position,
+        metadata: row.metadata,
+        price: row.price,
+        tags: row.tags,
+    };
+    if dry_run {
+        return Ok(updated_response(format, HttpResponse::Ok().insert_header((DRY_RUN_HEADER, "true")), old, stored, diff.diff));
+    }
+    if row.inserted {
+        events.publish(principal.tenant, ItemEventKind::Created, &stored);
+        let location = resource_path(&req, ITEMS_PATH, stored.id);
+        return Ok(updated_response(format, HttpResponse::Created().insert_header((header::LOCATION, location)), None, stored, diff.diff));
+    }
+    events.publish(principal.tenant, ItemEventKind::Updated, &stored);
+    Ok(updated_response(format, &mut HttpResponse::Ok(), old, stored, diff.diff))
+}
+
+// Explain why an upsert hit an existing row but did not replace it
+async fn replace_rejected(pool: &PgPool, tenant: Uuid, id: Uuid, version: Option<i32>) -> ApiError {
+    let deleted = sqlx::query_scalar!(r#"SELECT deleted_at IS NOT NULL AS "deleted!" FROM items WHERE id = $1 AND tenant_id = $2"#, id, tenant)
+        .fetch_optional(pool)
+        .await;
+
+    match (deleted, version) {
+        (Ok(Some(true)), _) => ApiError::Conflict("Item was deleted; restore it before replacing it".to_string()),
+        (Ok(Some(false)), None) => {
+            ApiError::PreconditionRequired("Replacing an item requires an If-Match header or a version field".to_string())
+        }
+        (Ok(Some(false)), Some(_)) => {
+            ApiError::Conflict("Item was modified by another request; fetch the latest version and retry".to_string())
+        }
+        (Ok(None), _) => ApiError::NotFound("Item not found".to_string()),
+        (Err(err), _) => err.into(),
+    }
+}
+
+// Partially update an item by ID with JSON Merge Patch semantics: omitted fields are untouched,
+// null clears description or category_id
+#[utoipa::path(
+    patch,
+    path = "/items/{id}",
+    tag = "items",
+    request_body(content = ItemPatchRequest, description = "JSON Merge Patch (RFC 7386); null clears description or category_id", content_type = "application/merge-patch+json"),
+    params(
+        ("id" = Uuid, Path, description = "Item id"),
+        ("If-Match" = Option<String>, Header, description = "Expected item version"),
+        ("Prefer" = Option<String>, Header, description = "`dry-run` does the same as `dry_run=true`"),
+        DryRunParam,
+        DiffParam
+    ),
+    responses(
+        (status = 200, description = "Item updated, or on a dry run the item as it would have been stored, marked by `X-Dry-Run: true`. With diff=true an ItemDiff.", body = Item),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 404, description = "Item not found", body = ErrorBody),
+        (status = 409, description = "Conflicting item or stale version", body = ErrorBody),
+        (status = 422, description = "Invalid fields", body = ErrorBody),
+        (status = 428, description = "No expected version supplied", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody)
+    )
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn patch_item(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    events: web::Data<ItemEvents>,
+    cache: web::Data<ItemCache>,
+    principal: Principal,
+    format: Format,
+    item_id: web::Path<Uuid>,
+    dry_run: web::Query<DryRunParam>,
+    diff: web::Query<DiffParam>,
+    body: Body<Value>,
+) -> Result<HttpResponse, ApiError> {
+    let item = ItemPatchRequest::from_merge_patch(body.into_inner())?;
+    item.require_changes()?;
+    item.validate()?;
+    let version = expected_version(&req, item.version)?;
+    let dry_run = dry_run.requested(&req);
+
+    let (old, updated) = timed_query(async {
+        let mut tx = pool.begin().await?;
+        let old = if diff.diff { db::lock_live_item(&mut tx, principal.tenant, *item_id).await? } else { None };
+        let updated = db::apply_patch(&mut tx, principal.tenant, *item_id, version, &item, principal.user).await?;
+        commit_unless_dry_run(tx, dry_run).await?;
+        Ok::<_, ApiError>((old, updated))
+    })
+    .await?;
+
+    match updated {
+        Some(item) if dry_run => Ok(updated_response(format, HttpResponse::Ok().insert_header((DRY_RUN_HEADER, "true")), old, item, diff.diff)),
+        Some(item) => {
+            cache.invalidate([item.id]);
+            events.publish(principal.tenant, ItemEventKind::Updated, &item);
+            Ok(updated_response(format, &mut HttpResponse::Ok(), old, item, diff.diff))
+        }
+        None => Err(timed_query(stale_or_missing(pool.get_ref(), principal.tenant, *item_id)).await),
+    }
+}
+
+// Apply a merge patch to each of several items in one transaction, with the same semantics as
+// PATCH /items/{id}. Each patch names its item by `id` and carries the expected `version`.
+// If any id names no live item nothing is changed, and the 404 lists every such id.
+#[utoipa::path(
+    patch,
+    path = "/items/batch",
+    tag = "items",
+    request_body(content = Vec<ItemPatchRequest>, description = "Merge patches, each with the `id` of the item it applies to and its expected `version`"),
+    responses(
+        (status = 200, description = "The updated items, in request order", body = Vec<Item>),
+        (status = 400, description = "Repeated id, or an invalid request", body = ErrorBody),
+        (status = 404, description = "Some ids name no live item; nothing was changed", body = ErrorBody),
+        (status = 409, description = "Conflicting item or stale version, keyed by item index", body = ErrorBody),
+        (status = 413, description = "Too many items in the batch", body = ErrorBody),
+        (status = 422, description = "Invalid fields, keyed by item index", body = ErrorBody),
+        (status = 428, description = "A patch carries no expected version", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody)
+    )
+)]
+pub async fn update_items_batch(
+    pool: web::Data<PgPool>,
+    retry: web::Data<TransactionRetry>,
+    events: web::Data<ItemEvents>,
+    cache: web::Data<ItemCache>,
+    principal: Principal,
+    format: Format,
+    body: Body<Vec<Value>>,
+) -> Result<HttpResponse, ApiError> {
+    if body.len() > MAX_BATCH_SIZE {
+        return Err(ApiError::PayloadTooLarge(format!("Batch may contain at most {} items", MAX_BATCH_SIZE)));
+    }
+    let mut seen = HashSet::with_capacity(body.len());
+    let mut patches = Vec::with_capacity(body.len());
+    for (index, value) in body.into_inner().into_iter().enumerate() {
+        let Value::Object(mut fields) = value else {
+            return Err(ApiError::Validation("Patch must be a JSON object".to_string()).at_index(index));
+        };
+        let id: Uuid = fields
+            .remove("id")
+            .and_then(|id| serde_json::from_value(id).ok())
+            .ok_or_else(|| ApiError::Validation("id must be an item id".to_string()).at_index(index))?;
+        if !seen.insert(id) {
+            return Err(ApiError::Validation(format!("Item {} is listed more than once", id)).at_index(index));
+        }
+        let patch = ItemPatchRequest::from_merge_patch(Value::Object(fields)).map_err(|err| err.at_index(index))?;
+        patch.require_changes().map_err(|err| err.at_index(index))?;
+        patch.validate().map_err(|err| ApiError::from(err).at_index(index))?;
+        let version = patch
+            .version
+            .ok_or_else(|| ApiError::PreconditionRequired(format!("item {}: Updates require a version field", index)))?;
+        patches.push((id, version, patch));
+    }
+
+    let Principal { tenant, user } = principal;
+    let updated = with_transaction(&pool, &retry, move |conn| {
+        let patches = patches.clone();
+        Box::pin(async move {
+            let mut updated = Vec::with_capacity(patches.len());
+            let mut missing = Vec::new();
+            for (index, (id, version, patch)) in patches.iter().enumerate() {
+                match db::apply_patch(conn, tenant, *id, *version, patch, user).await.map_err(|err| ApiError::from(err).at_index(index))? {
+                    Some(item) => updated.push(item),
+                    None => match stale_or_missing(&mut *conn, tenant, *id).await {
+                        ApiError::NotFound(_) => missing.push(id.to_string()),
+                        err => return Err(err.at_index(index)),
+                    },
+                }
+            }
+            // Failing rolls back the patches already applied
+            if !missing.is_empty() {
+                return Err(ApiError::NotFound(format!("Items not found: {}", missing.join(", "))));
+            }
+            Ok(updated)
+        })
+    })
+    .await?;
+    cache.invalidate(updated.iter().map(|item| item.id));
+    for item in &updated {
+        events.publish(tenant, ItemEventKind::Updated, item);
+    }
+    Ok(format.respond(&mut HttpResponse::Ok(), &updated))
+}
+
+// Replace only an item's description with a raw text body, so note-style editors needn't wrap one field in JSON.
+// If-Match is honoured when sent; without it the description is replaced whatever the version.
+#[utoipa::path(
+    put,
+    path = "/items/{id}/description",
+    tag = "items",
+    request_body(content = String, description = "The new description as UTF-8 text", content_type = "text/plain"),
+    params(
+        ("id" = Uuid, Path, description = "Item id"),
+        ("If-Match" = Option<String>, Header, description = "Expected item version")
+    ),
+    responses(
+        (status = 200, description = "Description replaced", body = Item),
+        (status = 400, description = "Body is not valid UTF-8, or an invalid request", body = ErrorBody),
+        (status = 404, description = "Item not found", body = ErrorBody),
+        (status = 409, description = "Stale version", body = ErrorBody),
+        (status = 415, description = "Body is not text", body = ErrorBody),
+        (status = 422, description = "Description too long", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody)
+    )
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn put_item_description(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    events: web::Data<ItemEvents>,
+    cache: web::Data<ItemCache>,
+    principal: Principal,
+    format: Format,
+    item_id: web::Path<Uuid>,
+    body: web::Bytes,
+) -> Result<HttpResponse, ApiError> {
+    let content_type = req.mime_type().map_err(|_| ApiError::Validation("Invalid Content-Type header".to_string()))?;
+    let Some(content_type) = content_type.filter(|content_type| content_type.type_() == mime::TEXT) else {
+        return Err(ApiError::UnsupportedMediaType("The description must be sent as text/plain".to_string()));
+    };
+    if content_type.get_param(mime::CHARSET).is_some_and(|charset| charset != mime::UTF_8) {
+        return Err(ApiError::UnsupportedMediaType("The description must be UTF-8 text".to_string()));
+    }
+    let description = String::from_utf8(body.to_vec()).map_err(|_| ApiError::Validation("Body is not valid UTF-8".to_string()))?;
+    let item = ItemDescription { description };
+    item.validate()?;
+    let version = supplied_version(&req, None)?;
+
+    let updated = timed_query(
+        sqlx::query_as!(
+            Item,
+            "UPDATE items SET description = $1, updated_by = $2, updated_at = now(), version = version + 1
+             WHERE id = $3 AND tenant_id = $4 AND deleted_at IS NULL AND ($5::int IS NULL OR version = $5)
+             RETURNING id, name, description, created_at, updated_at, version, deleted_at, category_id, created_by, updated_by, archived, slug, position, metadata, price, item_tag_names(id) AS \"tags!\"",
+            item.description,
+            principal.user,
+            *item_id,
+            principal.tenant,
+            version
+        )
+        .fetch_optional(pool.get_ref()),
+    )
+    .await?;
+
+    match updated {
+        Some(item) => {
+            cache.invalidate([item.id]);
+            events.publish(principal.tenant, ItemEventKind::Updated, &item);
+            Ok(format.respond(&mut HttpResponse::Ok(), &item))
+        }
+        None => Err(timed_query(stale_or_missing(pool.get_ref(), principal.tenant, *item_id)).await),
+    }
+}
+
+// The version a conditional update expects, from the body's `version` field or the If-Match header
+fn expected_version(req: &HttpRequest, body_version: Option<i32>) -> Result<i32, ApiError> {
+    supplied_version(req, body_version)?
+        .ok_or_else(|| ApiError::PreconditionRequired("Updates require an If-Match header or a version field".to_string()))
+}
+
+// The version from the body or If-Match, or None when the client sent neither
+fn supplied_version(req: &HttpRequest, body_version: Option<i32>) -> Result<Option<i32>, ApiError> {
+    if let Some(version) = body_version {
+        return Ok(Some(version));
+    }
+    let Some(if_match) = req.headers().get(header::IF_MATCH) else {
+        return Ok(None);
+    };
+    if_match
+        .to_str()
+        .ok()
+        .map(|value| value.trim().trim_start_matches("W/").trim_matches('"'))
+        .and_then(|value| value.parse().ok())
+        .map(Some)
+        .ok_or_else(|| ApiError::Validation("If-Match must contain an item version".to_string()))
+}
+
+// Delete an item by ID: the row is kept with deleted_at set so it can be restored
+#[utoipa::path(
+    delete,
+    path = "/items/{id}",
+    tag = "items",
+    params(("id" = Uuid, Path, description = "Item id")),
+    responses(
+        (status = 200, description = "Item soft-deleted", body = ItemDeleted),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 404, description = "Item not found", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody)
+    )
+)]
+pub async fn delete_item(
+    pool: web::Data<PgPool>,
+    events: web::Data<ItemEvents>,
+    cache: web::Data<ItemCache>,
+    principal: Principal,
+    format: Format,
+    item_id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    let deleted = timed_query(
+        sqlx::query_as!(
+            Item,
+            "UPDATE items SET deleted_at = now(), updated_by = $2, updated_at = now(), version = version + 1
+             WHERE id = $1 AND tenant_id = $3 AND deleted_at IS NULL
+             RETURNING id, name, description, created_at, updated_at, version, deleted_at, category_id, created_by, updated_by, archived, slug, position, metadata, price, item_tag_names(id) AS \"tags!\"",
+            *item_id,
+            principal.user,
+            principal.tenant
+        )
+        .fetch_optional(pool.get_ref()),
+    )
+    .await?
+    .ok_or_else(|| ApiError::NotFound("Item not found".to_string()))?;
+    cache.invalidate([deleted.id]);
+    events.publish(principal.tenant, ItemEventKind::Deleted, &deleted);
+
+    Ok(format.respond(&mut HttpResponse::Ok(), &ItemDeleted { deleted: true, id: deleted.id }))
+}
+
+// Soft-delete every listed item in one statement; unknown or already deleted ids are skipped
+#[utoipa::path(
+    post,
+    path = "/items/delete-batch",
+    tag = "items",
+    request_body = ItemDeleteBatchRequest,
+    responses(
+        (status = 200, description = "Number of items deleted", body = DeletedCount),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 413, description = "Too many ids in the batch", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody)
+    )
+)]
+pub async fn delete_items_batch(
+    pool: web::Data<PgPool>,
+    retry: web::Data<TransactionRetry>,
+    events: web::Data<ItemEvents>,
+    cache: web::Data<ItemCache>,
+    principal: Principal,
+    format: Format,
+    batch: Body<ItemDeleteBatchRequest>,
+) -> Result<HttpResponse, ApiError> {
+    if batch.ids.len() > MAX_BATCH_SIZE {
+        return Err(ApiError::PayloadTooLarge(format!("Batch may contain at most {} ids", MAX_BATCH_SIZE)));
+    }
+
+    let Principal { tenant, user } = principal;
+    let ids = batch.into_inner().ids;
+    let deleted = with_transaction(&pool, &retry, move |conn| {
+        let ids = ids.clone();
+        Box::pin(async move {
+            let deleted = sqlx::query_as!(
+                Item,
+                "UPDATE items SET deleted_at = now(), updated_by = $2, updated_at = now(), version = version + 1
+                 WHERE id = ANY($1) AND tenant_id = $3 AND deleted_at IS NULL
+                 RETURNING id, name, description, created_at, updated_at, version, deleted_at, category_id, created_by, updated_by, archived, slug, position, metadata, price, item_tag_names(id) AS \"tags!\"",
+                &ids[..],
+                user,
+                tenant
+            )
+            .fetch_all(conn)
+            .await?;
+            Ok(deleted)
+        })
+    })
+    .await?;
+    cache.invalidate(deleted.iter().map(|item| item.id));
+    for item in &deleted {
+        events.publish(tenant, ItemEventKind::Deleted, item);
+    }
+
+    Ok(format.respond(&mut HttpResponse::Ok(), &DeletedCount { deleted: deleted.len() as u64 }))
+}
+
+// Give every listed item its new position in one transaction, so a drag-and-drop order is saved
+// whole or not at all. Ids and positions must each be unique and every id must name a live item.
+#[utoipa::path(
+    patch,
+    path = "/items/reorder",
+    tag = "items",
+    request_body = Vec<ItemPosition>,
+    responses(
+        (status = 200, description = "The repositioned items, in request order", body = Vec<Item>),
+        (status = 400, description = "Repeated id or position, or an invalid request", body = ErrorBody),
+        (status = 404, description = "An id names no live item; nothing was changed", body = ErrorBody),
+        (status = 413, description = "Too many items in the request", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody)
+    )
+)]
+pub async fn reorder_items(
+    pool: web::Data<PgPool>,
+    retry: web::Data<TransactionRetry>,
+    events: web::Data<ItemEvents>,
+    cache: web::Data<ItemCache>,
+    principal: Principal,
+    format: Format,
+    entries: Body<Vec<ItemPosition>>,
+) -> Result<HttpResponse, ApiError> {
+    if entries.len() > MAX_BATCH_SIZE {
+        return Err(ApiError::PayloadTooLarge(format!("Reorder may contain at most {} items", MAX_BATCH_SIZE)));
+    }
+    let mut order = HashMap::with_capacity(entries.len());
+    let mut positions = HashSet::with_capacity(entries.len());
+    for (index, entry) in entries.iter().enumerate() {
+        if order.insert(entry.id, index).is_some() {
+            return Err(ApiError::Validation(format!("Item {} is listed more than once", entry.id)).at_index(index));
+        }
+        if !positions.insert(entry.position) {
+            return Err(ApiError::Validation(format!("Position {} is given to more than one item", entry.position)).at_index(index));
+        }
+    }
+
+    let ids: Vec<Uuid> = entries.iter().map(|entry| entry.id).collect();
+    let positions: Vec<i32> = entries.iter().map(|entry| entry.position).collect();
+    let Principal { tenant, user } = principal;
+    let mut moved = with_transaction(&pool, &retry, move |conn| {
+        let (ids, positions) = (ids.clone(), positions.clone());
+        Box::pin(async move {
+            let moved = sqlx::query_as!(
+                Item,
+                "UPDATE items SET position = ($2::int[])[array_position($1, id)], updated_by = $3, updated_at = now(), version = version + 1
+                 WHERE id = ANY($1) AND tenant_id = $4 AND deleted_at IS NULL
+                 RETURNING id, name, description, created_at, updated_at, version, deleted_at, category_id, created_by, updated_by, archived, slug, position, metadata, price, item_tag_names(id) AS \"tags!\"",
+                &ids[..],
+                &positions[..],
+                user,
+                tenant
+            )
+            .fetch_all(conn)
+            .await?;
+            // Failing on an unknown id rolls back the positions already set
+            if moved.len() < ids.len() {
+                let found: HashSet<Uuid> = moved.iter().map(|item| item.id).collect();
+                if let Some((index, missing)) = ids.iter().enumerate().find(|(_, id)| !found.contains(id)) {
+                    return Err(ApiError::NotFound(format!("Item {} not found", missing)).at_index(index));
+                }
+            }
+            Ok(moved)
+        })
+    })
+    .await?;
+    cache.invalidate(moved.iter().map(|item| item.id));
+
+    moved.sort_by_key(|item| order[&item.id]);
+    for item in &moved {
+        events.publish(tenant, ItemEventKind::Updated, item);
+    }
+    Ok(format.respond(&mut HttpResponse::Ok(), &moved))
+}
+
+// Whether DELETE /items may wipe the table; off unless ALLOW_BULK_TRUNCATE=true
+#[derive(Clone, Copy)]
+pub struct BulkTruncate {
+    pub allowed: bool,
+}
+
+impl BulkTruncate {
+    pub fn from_env(env: &mut EnvReader) -> Self {
+        BulkTruncate { allowed: env.parse_or("ALLOW_BULK_TRUNCATE", false) }
+    }
+}
+
+// Permanently remove every item of the tenant, live or soft-deleted, along with its idempotency keys.
+// Meant for test environments: disabled unless ALLOW_BULK_TRUNCATE is set, and requires confirm=true.
+#[utoipa::path(
+    delete,
+    path = "/items",
+    tag = "items",
+    params(TruncateParams),
+    responses(
+        (status = 200, description = "Number of items removed", body = DeletedCount),
+        (status = 400, description = "confirm=true was not given", body = ErrorBody),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Bulk truncate is disabled", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody)
+    )
+)]
+pub async fn truncate_items(
+    pool: web::Data<PgPool>,
+    truncate: web::Data<BulkTruncate>,
+    cache: web::Data<ItemCache>,
+    params: web::Query<TruncateParams>,
+    principal: Principal,
+    format: Format,
+) -> Result<HttpResponse, ApiError> {
+    if params.confirm != Some(true) {
+        return Err(ApiError::Validation("Deleting every item requires confirm=true".to_string()));
+    }
+    if !truncate.allowed {
+        return Err(ApiError::Forbidden("Bulk truncate is disabled; set ALLOW_BULK_TRUNCATE=true to enable it".to_string()));
+    }
+
+    let deleted = timed_query(async {
+        let mut tx = pool.begin().await?;
+        sqlx::query!("DELETE FROM idempotency_keys WHERE tenant_id = $1", principal.tenant).execute(&mut tx).await?;
+        let deleted = sqlx::query!("DELETE FROM items WHERE tenant_id = $1", principal.tenant).execute(&mut tx).await?.rows_affected();
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(deleted)
+    })
+    .await?;
+    cache.invalidate_tenant(principal.tenant);
+    warn!(deleted, tenant = %principal.tenant, "truncated tenant's items");
+
+    Ok(format.respond(&mut HttpResponse::Ok(), &DeletedCount { deleted }))
+}
+
+// Restore a soft-deleted item by clearing its deleted_at timestamp
+#[utoipa::path(
+    post,
+    path = "/items/{id}/restore",
+    tag = "items",
+    params(("id" = Uuid, Path, description = "Item id")),
+    responses(
+        (status = 200, description = "Item restored", body = Item),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 404, description = "No soft-deleted item with this id", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody)
+    )
+)]
+pub async fn restore_item(
+    pool: web::Data<PgPool>,
+    events: web::Data<ItemEvents>,
+    cache: web::Data<ItemCache>,
+    principal: Principal,
+    format: Format,
+    item_id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    let item = timed_query(
+        sqlx::query_as!(
+            Item,
+            "UPDATE items SET deleted_at = NULL, updated_by = $2, updated_at = now(), version = version + 1
+             WHERE id = $1 AND tenant_id = $3 AND deleted_at IS NOT NULL
+             RETURNING id, name, description, created_at, updated_at, version, deleted_at, category_id, created_by, updated_by, archived, slug, position, metadata, price, item_tag_names(id) AS \"tags!\"",
+            *item_id,
+            principal.user,
+            principal.tenant
+        )
+        .fetch_optional(pool.get_ref()),
+    )
+    .await?
+    .ok_or_else(|| ApiError::NotFound("Deleted item not found".to_string()))?;
+    cache.invalidate([item.id]);
+    events.publish(principal.tenant, ItemEventKind::Updated, &item);
+
+    Ok(format.respond(&mut HttpResponse::Ok(), &item))
+}
+
+// Copy a live item under a new id, named "<name> (copy)" or the first free "<name> (copy N)".
+// The description, category, metadata, price and tags are copied; the copy starts unarchived at the
+// default position.
+#[utoipa::path(
+    post,
+    path = "/items/{id}/duplicate",
+    tag = "items",
+    params(("id" = Uuid, Path, description = "Id of the item to copy")),
+    responses(
+        (status = 201, description = "The copy", body = Item,
+            headers(("Location" = String, description = "URL of the copy"))),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 404, description = "Item not found", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody)
+    )
+)]
+pub async fn duplicate_item(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    retry: web::Data<TransactionRetry>,
+    events: web::Data<ItemEvents>,
+    principal: Principal,
+    format: Format,
+    item_id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    let Principal { tenant, user } = principal;
+    let source_id = *item_id;
+    let copy = with_transaction(&pool, &retry, move |conn| {
+        Box::pin(async move {
+            let source = sqlx::query!(
+                "SELECT name, description, category_id, metadata, price FROM items WHERE id = $1 AND tenant_id = $2 AND deleted_at IS NULL",
+                source_id,
+                tenant
+            )
+            .fetch_optional(&mut *conn)
+            .await?
+            .ok_or_else(|| ApiError::NotFound("Item not found".to_string()))?;
+            let copy = ItemCreateRequest {
+                name: copy_name(&mut *conn, tenant, &source.name).await?,
+                description: source.description,
+                category_id: source.category_id,
+                metadata: source.metadata,
+                price: source.price,
+            };
+            let mut copy = insert_item(&mut *conn, tenant, &copy, user).await?;
+            sqlx::query!("INSERT INTO item_tags (item_id, tag_id) SELECT $1, tag_id FROM item_tags WHERE item_id = $2", copy.id, source_id)
+                .execute(&mut *conn)
+                .await?;
+            copy.tags = sqlx::query_scalar!(r#"SELECT item_tag_names($1) AS "tags!""#, copy.id).fetch_one(conn).await?;
+            Ok(copy)
+        })
+    })
+    .await?;
+    events.publish(tenant, ItemEventKind::Created, &copy);
+
+    Ok(format.respond(
+        HttpResponse::Created().insert_header((header::LOCATION, resource_path(&req, ITEMS_PATH, copy.id))),
+        &copy,
+    ))
+}
+
+// Hide a live item from listings without deleting it
+#[utoipa::path(
+    post,
+    path = "/items/{id}/archive",
+    tag = "items",
+    params(("id" = Uuid, Path, description = "Item id")),
+    responses(
+        (status = 200, description = "Item archived, or already archived", body = Item),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 404, description = "Item not found", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody)
+    )
+)]
+pub async fn archive_item(
+    pool: web::Data<PgPool>,
+    events: web::Data<ItemEvents>,
+    cache: web::Data<ItemCache>,
+    principal: Principal,
+    format: Format,
+    item_id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    let item = set_archived(&pool, &events, &cache, &principal, *item_id, true).await?;
+    Ok(format.respond(&mut HttpResponse::Ok(), &item))
+}
+
+// Return an archived item to listings
+#[utoipa::path(
+    post,
+    path = "/items/{id}/unarchive",
+    tag = "items",
+    params(("id" = Uuid, Path, description = "Item id")),
+    responses(
+        (status = 200, description = "Item unarchived, or was not archived", body = Item),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 404, description = "Item not found", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody)
+    )
+)]
+pub async fn unarchive_item(
+    pool: web::Data<PgPool>,
+    events: web::Data<ItemEvents>,
+    cache: web::Data<ItemCache>,
+    principal: Principal,
+    format: Format,
+    item_id: web::Path<Uuid>,
+) -> Result<HttpResponse, ApiError> {
+    let item = set_archived(&pool, &events, &cache, &principal, *item_id, false).await?;
+    Ok(format.respond(&mut HttpResponse::Ok(), &item))
+}
+
+// Set a live item's archived flag. Only an actual change bumps the version and is published;
+// repeating the request returns the item as it is.
+async fn set_archived(pool: &PgPool, events: &ItemEvents, cache: &ItemCache, principal: &Principal, id: Uuid, archived: bool) -> Result<Item, ApiError> {
+    let changed = timed_query(
+        sqlx::query_as!(
+            Item,
+            "UPDATE items SET archived = $2, updated_by = $3, updated_at = now(), version = version + 1
+             WHERE id = $1 AND tenant_id = $4 AND deleted_at IS NULL AND archived <> $2
+             RETURNING id, name, description, created_at, updated_at, version, deleted_at, category_id, created_by, updated_by, archived, slug, position, metadata, price, item_tag_names(id) AS \"tags!\"",
+            id,
+            archived,
+            principal.user,
+            principal.tenant
+        )
+        .fetch_optional(pool),
+    )
+    .await?;
+    if let Some(item) = changed {
+        cache.invalidate([item.id]);
+        events.publish(principal.tenant, ItemEventKind::Updated, &item);
+        return Ok(item);
+    }
+
+    let item = timed_query(
+        sqlx::query_as!(
+            Item,
+            "SELECT id, name, description, created_at, updated_at, version, deleted_at, category_id, created_by, updated_by, archived, slug, position, metadata, price, item_tag_names(id) AS \"tags!\" FROM items
+             WHERE id = $1 AND tenant_id = $2 AND deleted_at IS NULL",
+            id,
+            principal.tenant
+        )
+        .fetch_one(pool),
+    )
+    .await?;
+    Ok(item)
+}
+
+// Assign tags to a live item, creating tags the tenant doesn't have yet. Tags already on the item
+// are left alone, and only an actual change bumps the version and is published.
+#[utoipa::path(
+    post,
+    path = "/items/{id}/tags",
+    tag = "items",
+    params(("id" = Uuid, Path, description = "Item id")),
+    request_body = ItemTagsRequest,
+    responses(
+        (status = 200, description = "The item with its tags", body = Item),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 404, description = "Item not found", body = ErrorBody),
+        (status = 422, description = "Invalid tags", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody)
+    )
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn add_item_tags(
+    pool: web::Data<PgPool>,
+    retry: web::Data<TransactionRetry>,
+    events: web::Data<ItemEvents>,
+    cache: web::Data<ItemCache>,
+    principal: Principal,
+    format: Format,
+    item_id: web::Path<Uuid>,
+    request: Body<ItemTagsRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let request = request.into_inner();
+    request.validate()?;
+    let mut tags: Vec<String> = request.tags.iter().map(|tag| normalize_tag(tag)).collect();
+    tags.sort();
+    tags.dedup();
+
+    let (id, Principal { tenant, user }) = (*item_id, principal);
+    let (item, changed) = with_transaction(&pool, &retry, move |conn| {
+        let tags = tags.clone();
+        Box::pin(async move {
+            let item = db::lock_live_item(conn, tenant, id).await?.ok_or_else(|| ApiError::NotFound("Item not found".to_string()))?;
+            if db::assign_tags(conn, tenant, id, &tags).await? == 0 {
+                return Ok((item, false));
+            }
+            Ok((db::touch_item(conn, tenant, id, user).await?, true))
+        })
+    })
+    .await?;
+    if changed {
+        cache.invalidate([id]);
+        events.publish(tenant, ItemEventKind::Updated, &item);
+    }
+    Ok(format.respond(&mut HttpResponse::Ok(), &item))
+}
+
+// Take a tag off a live item. Removing a tag the item doesn't carry changes nothing and returns it as it is.
+#[utoipa::path(
+    delete,
+    path = "/items/{id}/tags/{tag}",
+    tag = "items",
+    params(("id" = Uuid, Path, description = "Item id"), ("tag" = String, Path, description = "Tag name, matched ignoring case")),
+    responses(
+        (status = 200, description = "The item with its remaining tags", body = Item),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 404, description = "Item not found", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody)
+    )
+)]
+pub async fn remove_item_tag(
+    pool: web::Data<PgPool>,
+    retry: web::Data<TransactionRetry>,
+    events: web::Data<ItemEvents>,
+    cache: web::Data<ItemCache>,
+    principal: Principal,
+    format: Format,
+    path: web::Path<(Uuid, String)>,
+) -> Result<HttpResponse, ApiError> {
+    let (id, tag) = path.into_inner();
+    let tag = normalize_tag(&tag);
+    let Principal { tenant, user } = principal;
+    let (item, changed) = with_transaction(&pool, &retry, move |conn| {
+        let tag = tag.clone();
+        Box::pin(async move {
+            let item = db::lock_live_item(conn, tenant, id).await?.ok_or_else(|| ApiError::NotFound("Item not found".to_string()))?;
+            if !db::remove_tag(conn, tenant, id, &tag).await? {
+                return Ok((item, false));
+            }
+            Ok((db::touch_item(conn, tenant, id, user).await?, true))
+        })
+    })
+    .await?;
+    if changed {
+        cache.invalidate([id]);
+        events.publish(tenant, ItemEventKind::Updated, &item);
+    }
+    Ok(format.respond(&mut HttpResponse::Ok(), &item))
+}
+
+// List every recorded change to an item, oldest first; history outlives deletes
+#[utoipa::path(
+    get,
+    path = "/items/{id}/history",
+    tag = "items",
+    params(("id" = Uuid, Path, description = "Item id")),
+    responses(
+        (status = 200, description = "Changes to the item in the order they happened", body = Vec<ItemAuditRecord>),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 404, description = "No history for this id", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody)
+    )
+)]
+pub async fn get_item_history(
+    pool: web::Data<PgPool>,
+    item_id: web::Path<Uuid>,
+    principal: Principal,
+    retry: web::Data<RetryPolicy>,
+    format: Format,
+) -> Result<HttpResponse, ApiError> {
+    // Ids are never reused, so the item row (soft-deleted or not) tells whose history this is
+    let records = with_retry(&retry, || {
+        sqlx::query_as!(
+            ItemAuditRecord,
+            "SELECT id, item_id, operation, snapshot, actor, changed_at FROM item_audit
+             WHERE item_id = $1 AND EXISTS (SELECT 1 FROM items WHERE id = $1 AND tenant_id = $2) ORDER BY id",
+            *item_id,
+            principal.tenant
+        )
+        .fetch_all(pool.get_ref())
+    })
+    .await?;
+
+    if records.is_empty() {
+        return Err(ApiError::NotFound("No history for this item".to_string()));
+    }
+    Ok(format.respond(&mut HttpResponse::Ok(), &records))
+}
+
+// Stream item changes as server-sent events. The subscription is dropped with the response body
+// when the client disconnects, which the keep-alive comments surface even on a quiet channel.
+#[utoipa::path(
+    get,
+    path = "/items/events",
+    tag = "items",
+    responses(
+        (status = 200, description = "Server-sent events named created, updated or deleted, each carrying the item as JSON", content_type = "text/event-stream", body = String)
+    )
+)]
+pub async fn item_events(events: web::Data<ItemEvents>, principal: Principal) -> HttpResponse {
+    let mut receiver = events.subscribe();
+    let stream = stream! {
+        let mut keepalive = interval(EVENTS_KEEPALIVE);
+        keepalive.tick().await;
+        loop {
+            tokio::select! {
+                event = receiver.recv() => match event {
+                    Ok(event) if event.tenant != principal.tenant => {}
+                    Ok(event) => {
+                        let data = serde_json::to_string(&event).expect("item events serialize");
+                        yield Ok::<_, Infallible>(web::Bytes::from(format!("event: {}\ndata: {}\n\n", event.kind.as_str(), data)));
+                    }
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        warn!(missed, "event stream subscriber fell behind; events dropped");
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                _ = keepalive.tick() => yield Ok(web::Bytes::from_static(b": keep-alive\n\n")),
+            }
+        }
+    };
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        // Compression would buffer events instead of flushing each one
+        .insert_header((header::CONTENT_ENCODING, "identity"))
+        .streaming(stream)
+}
+
+// Push changes to chosen items over a WebSocket. Clients send {"subscribe": [ids]} or {"unsubscribe": [ids]},
+// each answered with the full subscription set; events then arrive as JSON text shaped as on /items/events.
+pub async fn item_socket(
+    req: HttpRequest,
+    body: web::Payload,
+    events: web::Data<ItemEvents>,
+    principal: Principal,
+) -> Result<HttpResponse, actix_web::Error> {
+    let (response, session, messages) = actix_ws::handle(&req, body)?;
+    actix_web::rt::spawn(relay_item_events(session, messages, events.subscribe(), principal.tenant));
+    Ok(response)
+}
+
+// Serve one WebSocket connection until either side closes it or the client stops answering pings.
+// Returning drops the broadcast receiver, which ends the subscription.
+async fn relay_item_events(mut session: Session, mut messages: MessageStream, mut receiver: broadcast::Receiver<ItemEvent>, tenant: Uuid) {
+    let mut subscriptions = SocketSubscriptions::default();
+    let mut heartbeat = interval(EVENTS_KEEPALIVE);
+    heartbeat.tick().await;
+    let mut last_heard = Instant::now();
+    let reason = loop {
+        tokio::select! {
+            message = messages.recv() => {
+                last_heard = Instant::now();
+                let reply = match message {
+                    Some(Ok(Message::Text(text))) => match subscriptions.apply(&text) {
+                        Ok(()) => serde_json::to_string(&subscriptions),
+                        Err(err) => serde_json::to_string(&err.body()),
+                    },
+                    Some(Ok(Message::Binary(_))) => {
+                        serde_json::to_string(&ApiError::Validation("Subscription messages must be JSON text".to_string()).body())
+                    }
+                    Some(Ok(Message::Ping(bytes))) => {
+                        if session.pong(&bytes).await.is_err() {
+                            return;
+                        }
+                        continue;
+                    }
+                    Some(Ok(Message::Close(reason))) => break reason,
+                    Some(Ok(_)) => continue,
+                    Some(Err(_)) | None => break None,
+                };
+                if session.text(reply.expect("socket replies serialize")).await.is_err() {
+                    return;
+                }
+            }
+            event = receiver.recv() => match event {
+                Ok(event) if event.tenant == tenant && subscriptions.wants(&event) => {
+                    let data = serde_json::to_string(&event).expect("item events serialize");
+                    if session.text(data).await.is_err() {
+                        return;
+                    }
+                }
+                Ok(_) => {}
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    warn!(missed, "WebSocket subscriber fell behind; events dropped");
+                }
+                Err(broadcast::error::RecvError::Closed) => break None,
+            },
+            _ = heartbeat.tick() => {
+                if last_heard.elapsed() > SOCKET_CLIENT_TIMEOUT {
+                    break Some(CloseCode::Policy.into());
+                }
+                if session.ping(b"").await.is_err() {
+                    return;
+                }
+            }
+        }
+    };
+    let _ = session.close(reason).await;
+}
+
+pub const CATEGORIES_PATH: &str = "/categories";
+
+// Create a new category; names are unique
+#[utoipa::path(
+    post,
+    path = "/categories",
+    tag = "categories",
+    request_body = CategoryCreateRequest,
+    responses(
+        (status = 201, description = "Category created", body = Category),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 409, description = "A category with this name already exists", body = ErrorBody),
+        (status = 422, description = "Invalid fields", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody)
+    )
+)]
+pub async fn create_category(
+    req: HttpRequest,
+    pool: web::Data<PgPool>,
+    format: Format,
+    category: Body<CategoryCreateRequest>,
+) -> Result<HttpResponse, ApiError> {
+    category.validate()?;
+
+    let created = timed_query(
+        sqlx::query_as!(
+            Category,
+            "INSERT INTO categories (id, name, created_at) VALUES ($1, $2, now()) RETURNING id, name, created_at",
+            Uuid::new_v4(),
+            category.name
+        )
+        .fetch_one(pool.get_ref()),
+    )
+    .await?;
+
+    Ok(format.respond(
+        HttpResponse::Created().insert_header((header::LOCATION, resource_path(&req, CATEGORIES_PATH, created.id))),
+        &created,
+    ))
+}
+
+// Reassign every item of the tenant in one category to another, soft-deleted and archived ones included.
+// One UPDATE inside a transaction, so either every item moves or none does.
+#[utoipa::path(
+    post,
+    path = "/categories/{from}/move-to/{to}",
+    tag = "categories",
+    params(
+        ("from" = Uuid, Path, description = "Category the items are in now"),
+        ("to" = Uuid, Path, description = "Category to move them to")
+    ),
+    responses(
+        (status = 200, description = "How many items moved", body = MovedCount),
+        (status = 400, description = "Unknown category, or both ids are the same", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody)
+    )
+)]
+pub async fn move_category_items(
+    pool: web::Data<PgPool>,
+    retry: web::Data<TransactionRetry>,
+    events: web::Data<ItemEvents>,
+    cache: web::Data<ItemCache>,
+    principal: Principal,
+    format: Format,
+    path: web::Path<(Uuid, Uuid)>,
+) -> Result<HttpResponse, ApiError> {
+    let (from, to) = path.into_inner();
+    if from == to {
+        return Err(ApiError::Validation("Items are already in that category".to_string()));
+    }
+
+    let Principal { tenant, user } = principal;
+    let moved = with_transaction(&pool, &retry, move |conn| {
+        Box::pin(async move {
+            // Share locks keep either category from being removed before the move commits
+            let found = sqlx::query_scalar!("SELECT id FROM categories WHERE id = $1 OR id = $2 FOR SHARE", from, to)
+                .fetch_all(&mut *conn)
+                .await?;
+            for (id, role) in [(from, "source"), (to, "target")] {
+                if !found.contains(&id) {
+                    return Err(ApiError::Validation(format!("The {} category {} does not exist", role, id)));
+                }
+            }
+
+            let moved = sqlx::query_as!(
+                Item,
+                "UPDATE items SET category_id = $2, updated_by = $3, updated_at = now(), version = version + 1
+                 WHERE category_id = $1 AND tenant_id = $4
+                 RETURNING id, name, description, created_at, updated_at, version, deleted_at, category_id, created_by, updated_by, archived, slug, position, metadata, price, item_tag_names(id) AS \"tags!\"",
+                from,
+                to,
+                user,
+                tenant
+            )
+            .fetch_all(conn)
+            .await?;
+            Ok(moved)
+        })
+    })
+    .await?;
+    cache.invalidate(moved.iter().map(|item| item.id));
+    for item in &moved {
+        events.publish(tenant, ItemEventKind::Updated, item);
+    }
+
+    Ok(format.respond(&mut HttpResponse::Ok(), &MovedCount { moved: moved.len() as u64 }))
+}
+
+// List all categories by name
+#[utoipa::path(
+    get,
+    path = "/categories",
+    tag = "categories",
+    responses(
+        (status = 200, description = "All categories", body = Vec<Category>),
+        (status = 500, description = "Internal error", body = ErrorBody)
+    )
+)]
+pub async fn get_categories(pool: web::Data<PgPool>, retry: web::Data<RetryPolicy>, format: Format) -> Result<HttpResponse, ApiError> {
+    let categories = with_retry(&retry, || {
+        sqlx::query_as!(Category, "SELECT id, name, created_at FROM categories ORDER BY name").fetch_all(pool.get_ref())
+    })
+    .await?;
+    Ok(format.respond(&mut HttpResponse::Ok(), &categories))
+}
+
+// How long the health check waits on the database before reporting degraded
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Report whether the primary and read pools are reachable, for load balancer and orchestrator probes
+#[utoipa::path(
+    get,
+    path = "/health",
+    tag = "operations",
+    responses(
+        (status = 200, description = "Database reachable", body = HealthStatus),
+        (status = 503, description = "Database unreachable or slow", body = HealthStatus)
+    )
+)]
+pub async fn health(pool: web::Data<PgPool>, read_pool: web::Data<ReadPool>) -> HttpResponse {
+    let (primary, replica) = futures_util::join!(responds(pool.get_ref()), responds(&read_pool.0));
+
+    if primary && replica {
+        HttpResponse::Ok().json(HealthStatus { status: "ok" })
+    } else {
+        HttpResponse::ServiceUnavailable().json(HealthStatus { status: "degraded" })
+    }
+}
+
+// Whether GET /health/migrations fails with 503 while migrations are pending, so a probe can hold
+// back a deploy whose migrations didn't run; off unless MIGRATIONS_STRICT=true
+#[derive(Clone, Copy)]
+pub struct MigrationCheck {
+    pub strict: bool,
+}
+
+impl MigrationCheck {
+    pub fn from_env(env: &mut EnvReader) -> Self {
+        MigrationCheck { strict: env.parse_or("MIGRATIONS_STRICT", false) }
+    }
+}
+
+// Report whether every migration embedded in this build has been applied to the primary
+#[utoipa::path(
+    get,
+    path = "/health/migrations",
+    tag = "operations",
+    responses(
+        (status = 200, description = "Migration status", body = MigrationStatus),
+        (status = 503, description = "Migrations are pending and MIGRATIONS_STRICT is set", body = MigrationStatus),
+        (status = 500, description = "Internal error", body = ErrorBody)
+    )
+)]
+pub async fn migration_status(pool: web::Data<PgPool>, check: web::Data<MigrationCheck>, retry: web::Data<RetryPolicy>) -> Result<HttpResponse, ApiError> {
+    let pending_count = with_retry(&retry, || db::pending_migrations(pool.get_ref())).await?;
+    let status = MigrationStatus { migrations: if pending_count == 0 { "up_to_date" } else { "pending" }, pending_count };
+    if pending_count > 0 && check.strict {
+        return Ok(HttpResponse::ServiceUnavailable().json(status));
+    }
+    Ok(HttpResponse::Ok().json(status))
+}
+
+// Whether the database answers a trivial query within HEALTH_CHECK_TIMEOUT
+async fn responds(pool: &PgPool) -> bool {
+    matches!(timeout(HEALTH_CHECK_TIMEOUT, sqlx::query("SELECT 1").execute(pool)).await, Ok(Ok(_)))
+}
+
+// Whether a connection can be taken from the pool within HEALTH_CHECK_TIMEOUT
+async fn can_acquire(pool: &PgPool) -> bool {
+    matches!(timeout(HEALTH_CHECK_TIMEOUT, pool.acquire()).await, Ok(Ok(_)))
+}
+
+// Flipped once graceful shutdown begins so /readyz can turn load balancers away while requests drain.
+// An operator can also drain by hand through POST /admin/drain, which refuses writes too, ahead of
+// sending SIGTERM; unlike shutdown that can be undone.
+#[derive(Clone, Default)]
+pub struct Readiness {
+    draining: Arc<AtomicBool>,
+    drained: Arc<AtomicBool>,
+}
+
+impl Readiness {
+    pub fn begin_draining(&self) {
+        self.draining.store(true, Ordering::SeqCst);
+    }
+
+    pub fn set_drained(&self, drained: bool) {
+        self.drained.store(drained, Ordering::SeqCst);
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst) || self.is_drained()
+    }
+
+    // Drained by hand, when new writes are refused
+    pub fn is_drained(&self) -> bool {
+        self.drained.load(Ordering::SeqCst)
+    }
+}
+
+// Liveness probe: the process is up and serving requests; never touches the database
+#[utoipa::path(
+    get,
+    path = "/livez",
+    tag = "operations",
+    responses((status = 200, description = "Process is running", body = HealthStatus))
+)]
+pub async fn livez() -> HttpResponse {
+    HttpResponse::Ok().json(HealthStatus { status: "ok" })
+}
+
+// Which build is running, for deploy tooling; answered from compile-time constants without touching the database
+#[utoipa::path(
+    get,
+    path = "/version",
+    tag = "operations",
+    responses((status = 200, description = "Build information", body = BuildInfo))
+)]
+pub async fn version() -> HttpResponse {
+    HttpResponse::Ok().json(BuildInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        git_sha: env!("BUILD_GIT_SHA"),
+        built_at: env!("BUILD_TIMESTAMP"),
+    })
+}
+
+// Readiness probe: a connection can be acquired from both pools and the server is not shutting down
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    tag = "operations",
+    responses(
+        (status = 200, description = "Ready to serve traffic", body = HealthStatus),
+        (status = 503, description = "Database unavailable or shutting down", body = HealthStatus)
+    )
+)]
+pub async fn readyz(pool: web::Data<PgPool>, read_pool: web::Data<ReadPool>, readiness: web::Data<Readiness>) -> HttpResponse {
+    if readiness.is_draining() {
+        return HttpResponse::ServiceUnavailable().json(HealthStatus { status: "draining" });
+    }
+
+    match futures_util::join!(can_acquire(pool.get_ref()), can_acquire(&read_pool.0)) {
+        (true, true) => HttpResponse::Ok().json(HealthStatus { status: "ok" }),
+        _ => HttpResponse::ServiceUnavailable().json(HealthStatus { status: "unavailable" }),
+    }
+}
+
+// Report connection pool usage; 503 when every connection is open and none is idle
+#[utoipa::path(
+    get,
+    path = "/admin/pool",
+    tag = "operations",
+    responses(
+        (status = 200, description = "Pool has spare capacity", body = PoolStats),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 503, description = "Pool is saturated", body = PoolStats)
+    )
+)]
+pub async fn pool_stats(pool: web::Data<PgPool>, config: web::Data<Config>, format: Format) -> HttpResponse {
+    let stats = PoolStats { size: pool.size(), idle: pool.num_idle(), max: config.pool.max_connections };
+    if stats.idle == 0 && stats.size >= stats.max {
+        return format.respond(&mut HttpResponse::ServiceUnavailable(), &stats);
+    }
+    format.respond(&mut HttpResponse::Ok(), &stats)
+}
+
+// Whether writes are currently refused for maintenance
+#[utoipa::path(
+    get,
+    path = "/admin/maintenance",
+    tag = "operations",
+    responses(
+        (status = 200, description = "Current maintenance mode", body = MaintenanceStatus),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody)
+    )
+)]
+pub async fn get_maintenance(maintenance: web::Data<MaintenanceMode>, format: Format) -> HttpResponse {
+    format.respond(&mut HttpResponse::Ok(), &MaintenanceStatus { enabled: maintenance.enabled() })
+}
+
+// Switch maintenance mode on or off without a restart. Like every /admin route this needs
+// credentials whenever API keys or bearer tokens are configured.
+#[utoipa::path(
+    post,
+    path = "/admin/maintenance",
+    tag = "operations",
+    request_body = MaintenanceStatus,
+    responses(
+        (status = 200, description = "Maintenance mode after the change", body = MaintenanceStatus),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody)
+    )
+)]
+pub async fn set_maintenance(maintenance: web::Data<MaintenanceMode>, format: Format, status: Body<MaintenanceStatus>) -> HttpResponse {
+    if maintenance.enabled() != status.enabled {
+        warn!(enabled = status.enabled, "maintenance mode switched");
+    }
+    maintenance.set(status.enabled);
+    format.respond(&mut HttpResponse::Ok(), &MaintenanceStatus { enabled: maintenance.enabled() })
+}
+
+// Take the instance out of rotation for a blue-green deploy: /readyz answers 503 and new writes
+// are refused, while reads and requests already running are still served. The process keeps
+// running until it is sent SIGTERM.
+#[utoipa::path(
+    post,
+    path = "/admin/drain",
+    tag = "operations",
+    responses(
+        (status = 200, description = "Instance is draining", body = DrainStatus),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody)
+    )
+)]
+pub async fn drain(readiness: web::Data<Readiness>, format: Format) -> HttpResponse {
+    set_drained(&readiness, format, true)
+}
+
+// Put a drained instance back into rotation. One already shutting down stays draining.
+#[utoipa::path(
+    post,
+    path = "/admin/undrain",
+    tag = "operations",
+    responses(
+        (status = 200, description = "Whether the instance is still draining, as it is once shutdown has begun", body = DrainStatus),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody)
+    )
+)]
+pub async fn undrain(readiness: web::Data<Readiness>, format: Format) -> HttpResponse {
+    set_drained(&readiness, format, false)
+}
+
+fn set_drained(readiness: &Readiness, format: Format, drained: bool) -> HttpResponse {
+    if readiness.is_drained() != drained {
+        warn!(drained, "drain switched");
+    }
+    readiness.set_drained(drained);
+    format.respond(&mut HttpResponse::Ok(), &DrainStatus { draining: readiness.is_draining() })
+}
+
+// Every feature flag and its value on this instance, which lags a switch made through another
+// instance by at most one refresh interval
+#[utoipa::path(
+    get,
+    path = "/admin/flags",
+    tag = "operations",
+    responses(
+        (status = 200, description = "Every feature flag, in name order", body = Vec<FeatureFlag>),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody)
+    )
+)]
+pub async fn get_flags(flags: web::Data<FeatureFlags>, format: Format) -> HttpResponse {
+    format.respond(&mut HttpResponse::Ok(), &flags.list())
+}
+
+// Switch a feature flag for every instance without a redeploy
+#[utoipa::path(
+    put,
+    path = "/admin/flags/{name}",
+    tag = "operations",
+    request_body = FeatureFlagUpdate,
+    params(("name" = String, Path, description = "Flag name")),
+    responses(
+        (status = 200, description = "The flag after the change", body = FeatureFlag),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 404, description = "No such flag", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody)
+    )
+)]
+pub async fn put_flag(
+    pool: web::Data<PgPool>,
+    flags: web::Data<FeatureFlags>,
+    format: Format,
+    name: web::Path<String>,
+    update: Body<FeatureFlagUpdate>,
+) -> Result<HttpResponse, ApiError> {
+    let flag = flags.set(&pool, &name, update.enabled).await?;
+    warn!(flag = %flag.name, enabled = flag.enabled, "feature flag switched");
+    Ok(format.respond(&mut HttpResponse::Ok(), &flag))
+}
+
+// Whether POST /admin/analyze may run; off unless ALLOW_MAINTENANCE_OPS=true
+#[derive(Clone, Copy)]
+pub struct MaintenanceOps {
+    pub allowed: bool,
+}
+
+impl MaintenanceOps {
+    pub fn from_env(env: &mut EnvReader) -> Self {
+        MaintenanceOps { allowed: env.parse_or("ALLOW_MAINTENANCE_OPS", false) }
+    }
+}
+
+// Refresh the planner statistics of the items table with ANALYZE, e.g. after a large import.
+// Never runs VACUUM. Disabled unless ALLOW_MAINTENANCE_OPS is set, and only runs while
+// maintenance mode is on, so it can't compete with write traffic.
+#[utoipa::path(
+    post,
+    path = "/admin/analyze",
+    tag = "operations",
+    responses(
+        (status = 200, description = "Statistics refreshed", body = AnalyzeReport),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Maintenance operations are disabled", body = ErrorBody),
+        (status = 409, description = "Maintenance mode is not on", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody)
+    )
+)]
+pub async fn analyze_items(
+    pool: web::Data<PgPool>,
+    ops: web::Data<MaintenanceOps>,
+    maintenance: web::Data<MaintenanceMode>,
+    format: Format,
+) -> Result<HttpResponse, ApiError> {
+    if !ops.allowed {
+        return Err(ApiError::Forbidden("Maintenance operations are disabled; set ALLOW_MAINTENANCE_OPS=true to enable them".to_string()));
+    }
+    if !maintenance.enabled() {
+        return Err(ApiError::Conflict("ANALYZE only runs while maintenance mode is on".to_string()));
+    }
+
+    let started_at = Utc::now();
+    let started = Instant::now();
+    timed_query(sqlx::query("ANALYZE items").execute(pool.get_ref())).await?;
+    let elapsed_ms = started.elapsed().as_millis() as u64;
+    warn!(elapsed_ms, "analyzed items table");
+
+    Ok(format.respond(&mut HttpResponse::Ok(), &AnalyzeReport { table: "items".to_string(), started_at, elapsed_ms }))
+}
+
+// Whether POST /admin/seed may generate demo data; off unless DEV_MODE=true
+#[derive(Clone, Copy)]
+pub struct DevMode {
+    pub enabled: bool,
+}
+
+impl DevMode {
+    pub fn from_env(env: &mut EnvReader) -> Self {
+        DevMode { enabled: env.parse_or("DEV_MODE", false) }
+    }
+}
+
+// Items POST /admin/seed generates when no count is given, and the most one call may ask for
+const DEFAULT_SEED_COUNT: usize = 100;
+const MAX_SEED_COUNT: usize = 1000;
+
+// Seed used when the request names none, so repeated runs produce the same items
+const DEFAULT_SEED: u64 = 42;
+
+const SEED_ADJECTIVES: [&str; 8] = ["Amber", "Brisk", "Copper", "Dusty", "Electric", "Frosted", "Golden", "Humble"];
+const SEED_NOUNS: [&str; 8] = ["Widget", "Gadget", "Sprocket", "Gizmo", "Lantern", "Bracket", "Valve", "Spindle"];
+
+// `count` demo items drawn from `seed`. Names carry a random suffix as well as their position, so
+// they stay unique within a run; the same seed always gives the same items.
+fn seed_items(count: usize, seed: u64) -> Vec<ItemCreateRequest> {
+    let mut rng = fastrand::Rng::with_seed(seed);
+    (1..=count)
+        .map(|n| {
+            let adjective = SEED_ADJECTIVES[rng.usize(..SEED_ADJECTIVES.len())];
+            let noun = SEED_NOUNS[rng.usize(..SEED_NOUNS.len())];
+            let name = format!("{} {} {:04x}-{}", adjective, noun, rng.u16(..), n);
+            let description = rng.bool().then(|| format!("A {} {} for demos, {} in stock", adjective.to_lowercase(), noun.to_lowercase(), rng.u32(1..500)));
+            ItemCreateRequest { name, description, category_id: None, metadata: None, price: None }
+        })
+        .collect()
+}
+
+// Insert `count` generated items for the tenant in one transaction, for local testing and demos.
+// Disabled unless DEV_MODE is set. Running a seed again on the same tenant conflicts with the
+// items it created before, so pass another seed to add more.
+#[utoipa::path(
+    post,
+    path = "/admin/seed",
+    tag = "operations",
+    params(SeedParams),
+    responses(
+        (status = 200, description = "Items generated", body = SeedReport),
+        (status = 400, description = "count is zero or above the limit", body = ErrorBody),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "DEV_MODE is off", body = ErrorBody),
+        (status = 409, description = "An item with a generated name already exists", body = ErrorBody),
+        (status = 500, description = "Internal error", body = ErrorBody)
+    )
+)]
+pub async fn seed_demo_items(
+    pool: web::Data<PgPool>,
+    retry: web::Data<TransactionRetry>,
+    dev: web::Data<DevMode>,
+    events: web::Data<ItemEvents>,
+    params: web::Query<SeedParams>,
+    principal: Principal,
+    format: Format,
+) -> Result<HttpResponse, ApiError> {
+    if !dev.enabled {
+        return Err(ApiError::Forbidden("Seeding is disabled; set DEV_MODE=true to enable it".to_string()));
+    }
+    let count = params.count.unwrap_or(DEFAULT_SEED_COUNT);
+    if count == 0 || count > MAX_SEED_COUNT {
+        return Err(ApiError::Validation(format!("count must be between 1 and {}", MAX_SEED_COUNT)));
+    }
+    let seed = params.seed.unwrap_or(DEFAULT_SEED);
+    let items = seed_items(count, seed);
+
+    let Principal { tenant, user } = principal;
+    let created = with_transaction(&pool, &retry, move |conn| {
+        let items = items.clone();
+        Box::pin(async move {
+            let mut created = Vec::with_capacity(items.len());
+            for item in &items {
+                created.push(insert_item(conn, tenant, item, user).await?);
+            }
+            Ok(created)
+        })
+    })
+    .await?;
+    for item in &created {
+        events.publish(tenant, ItemEventKind::Created, item);
+    }
+    warn!(created = created.len(), seed, tenant = %tenant, "seeded demo items");
+
+    Ok(format.respond(&mut HttpResponse::Ok(), &SeedReport { created: created.len() as u64, seed }))
+}
+
+// Expose collected metrics in the Prometheus text format
+pub async fn metrics_endpoint(metrics: web::Data<Metrics>, pool: web::Data<PgPool>) -> Result<HttpResponse, ApiError> {
+    metrics.pool_size.set(i64::from(pool.size()));
+    metrics.pool_idle.set(pool.num_idle() as i64);
+
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+    encoder.encode(&metrics.registry.gather(), &mut buffer).map_err(|_| ApiError::Internal)?;
+    Ok(HttpResponse::Ok().content_type(encoder.format_type()).body(buffer))
+}
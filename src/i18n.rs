@@ -0,0 +1,195 @@
+use actix_web::http::header::{AcceptLanguage, Preference, Quality};
+use actix_web::HttpMessage;
+use std::borrow::Cow;
+use std::cmp::Reverse;
+
+// Languages error messages can be rendered in. Messages are written in English, so English needs
+// no catalog; adding a language means adding a variant, its tag and its catalog below.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Language {
+    English,
+    Spanish,
+}
+
+impl Language {
+    const SUPPORTED: [Language; 2] = [Language::English, Language::Spanish];
+
+    // ISO 639-1 code, as matched against Accept-Language and sent back in Content-Language
+    pub fn tag(self) -> &'static str {
+        match self {
+            Language::English => "en",
+            Language::Spanish => "es",
+        }
+    }
+
+    // English messages and their translations
+    fn catalog(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Language::English => &[],
+            Language::Spanish => SPANISH,
+        }
+    }
+
+    // How the position of an element is named in batch errors, see ApiError::at_index
+    fn item_prefix(self) -> &'static str {
+        match self {
+            Language::English => "item",
+            Language::Spanish => "elemento",
+        }
+    }
+}
+
+// The request's most preferred supported language, matched on the primary subtag so `es-MX` gets
+// Spanish. English when the header is absent, unparsable or names nothing supported.
+pub fn negotiate(req: &impl HttpMessage) -> Language {
+    let Some(accept) = req.get_header::<AcceptLanguage>() else {
+        return Language::English;
+    };
+    let mut ranked: Vec<_> = accept.0.into_iter().filter(|preference| preference.quality > Quality::ZERO).collect();
+    ranked.sort_by_key(|preference| Reverse(preference.quality));
+    for preference in ranked {
+        match preference.item {
+            Preference::Any => return Language::English,
+            Preference::Specific(tag) => {
+                let primary = tag.primary_language();
+                if let Some(language) = Language::SUPPORTED.into_iter().find(|language| primary.eq_ignore_ascii_case(language.tag())) {
+                    return language;
+                }
+            }
+        }
+    }
+    Language::English
+}
+
+// `message` in `language`, keeping the `item N: ` prefix of batch errors. Messages without a
+// translation, such as those naming request-specific values, are returned in English.
+pub fn translate(language: Language, message: &str) -> Cow<'_, str> {
+    if let Some((index, rest)) = message.strip_prefix("item ").and_then(|rest| rest.split_once(": ")) {
+        if index.parse::<usize>().is_ok() {
+            return match translate(language, rest) {
+                Cow::Owned(translated) => Cow::Owned(format!("{} {}: {}", language.item_prefix(), index, translated)),
+                Cow::Borrowed(_) => Cow::Borrowed(message),
+            };
+        }
+    }
+    match language.catalog().iter().find(|(english, _)| *english == message) {
+        Some((_, translated)) => Cow::Owned(translated.to_string()),
+        None => Cow::Borrowed(message),
+    }
+}
+
+const SPANISH: &[(&str, &str)] = &[
+    ("Item not found", "Elemento no encontrado"),
+    ("Deleted item not found", "Elemento eliminado no encontrado"),
+    ("No history for this item", "No hay historial para este elemento"),
+    ("No route matches this request", "Ninguna ruta coincide con esta solicitud"),
+    ("An item with that name already exists", "Ya existe un elemento con ese nombre"),
+    ("A category with that name already exists", "Ya existe una categoría con ese nombre"),
+    (
+        "Item was modified by another request; fetch the latest version and retry",
+        "Otra solicitud modificó el elemento; obtenga la versión más reciente y vuelva a intentarlo",
+    ),
+    ("Item was deleted; restore it before replacing it", "El elemento fue eliminado; restáurelo antes de reemplazarlo"),
+    ("category_id does not refer to an existing category", "category_id no corresponde a ninguna categoría existente"),
+    ("Cannot delete category with existing items", "No se puede eliminar una categoría que tiene elementos"),
+    ("Invalid Content-Type header", "Cabecera Content-Type no válida"),
+    ("Body is not valid UTF-8", "El cuerpo no es UTF-8 válido"),
+    ("Request body is too large", "El cuerpo de la solicitud es demasiado grande"),
+    ("Malformed cursor", "Cursor mal formado"),
+    ("name cannot be null", "name no puede ser nulo"),
+    ("Patch body must be a JSON object", "El cuerpo del parche debe ser un objeto JSON"),
+    ("Patch body must contain at least one field", "El cuerpo del parche debe contener al menos un campo"),
+    ("Updates require an If-Match header or a version field", "Las actualizaciones requieren una cabecera If-Match o un campo version"),
+    (
+        "Replacing an item requires an If-Match header or a version field",
+        "Reemplazar un elemento requiere una cabecera If-Match o un campo version",
+    ),
+    ("A valid X-API-Key header is required", "Se requiere una cabecera X-API-Key válida"),
+    ("A bearer token is required", "Se requiere un token bearer"),
+    ("Bearer token is invalid", "El token bearer no es válido"),
+    ("Bearer token has expired", "El token bearer ha caducado"),
+    ("Rate limit exceeded; retry later", "Límite de solicitudes superado; vuelva a intentarlo más tarde"),
+    ("The request conflicted with concurrent writes; retry it", "La solicitud entró en conflicto con escrituras simultáneas; vuelva a intentarla"),
+    ("Database query took too long and was cancelled", "La consulta a la base de datos tardó demasiado y se canceló"),
+    (
+        "All database connections are busy; retry shortly",
+        "Todas las conexiones a la base de datos están ocupadas; vuelva a intentarlo en breve",
+    ),
+    (
+        "The service is in maintenance mode and only accepts reads; retry later",
+        "El servicio está en modo de mantenimiento y solo acepta lecturas; vuelva a intentarlo más tarde",
+    ),
+    (
+        "This instance is draining and only accepts reads; retry later",
+        "Esta instancia se está retirando del servicio y solo acepta lecturas; vuelva a intentarlo más tarde",
+    ),
+    ("Feature flag not found", "Indicador de funcionalidad no encontrado"),
+    ("Fuzzy search is switched off", "La búsqueda aproximada está desactivada"),
+    ("Export link signature is invalid", "La firma del enlace de exportación no es válida"),
+    ("Export link has expired", "El enlace de exportación ha caducado"),
+    (
+        "The database is not accepting writes right now; reads are still served",
+        "La base de datos no acepta escrituras en este momento; las lecturas siguen disponibles",
+    ),
+    (
+        "Idempotency-Key was already used with a different request body",
+        "La Idempotency-Key ya se usó con un cuerpo de solicitud distinto",
+    ),
+    (
+        "Cannot delete item while idempotency keys refer to it",
+        "No se puede eliminar el elemento mientras haya claves de idempotencia que lo referencian",
+    ),
+    ("The request refers to a row that does not exist", "La solicitud hace referencia a una fila que no existe"),
+    (
+        "Cannot delete a row that other rows still refer to",
+        "No se puede eliminar una fila a la que otras filas siguen haciendo referencia",
+    ),
+    (
+        "stream=true cannot be combined with page, per_page, after, limit or envelope",
+        "stream=true no se puede combinar con page, per_page, after, limit ni envelope",
+    ),
+    (
+        "after/limit cannot be combined with page, per_page, sort or order",
+        "after/limit no se puede combinar con page, per_page, sort ni order",
+    ),
+    ("after/limit cannot be combined with updated_since", "after/limit no se puede combinar con updated_since"),
+    (
+        "Export links are switched off; set EXPORT_LINK_SECRET to enable them",
+        "Los enlaces de exportación están desactivados; defina EXPORT_LINK_SECRET para activarlos",
+    ),
+    ("CSV header must include a name column", "La cabecera CSV debe incluir una columna name"),
+    ("multipart upload must include a file part", "La carga multipart debe incluir una parte de archivo"),
+    ("Expected a text/csv or multipart/form-data body", "Se esperaba un cuerpo text/csv o multipart/form-data"),
+    ("name must not be blank", "name no puede estar vacío"),
+    (
+        "More than one item has that name; look it up by id or slug",
+        "Más de un elemento tiene ese nombre; búsquelo por id o slug",
+    ),
+    ("If-Modified-Since must be an HTTP-date", "If-Modified-Since debe ser una fecha HTTP"),
+    ("Patch must be a JSON object", "El parche debe ser un objeto JSON"),
+    ("id must be an item id", "id debe ser el id de un elemento"),
+    ("The description must be sent as text/plain", "La descripción debe enviarse como text/plain"),
+    ("The description must be UTF-8 text", "La descripción debe ser texto UTF-8"),
+    ("If-Match must contain an item version", "If-Match debe contener una versión del elemento"),
+    ("Deleting every item requires confirm=true", "Eliminar todos los elementos requiere confirm=true"),
+    (
+        "Bulk truncate is disabled; set ALLOW_BULK_TRUNCATE=true to enable it",
+        "El vaciado masivo está desactivado; defina ALLOW_BULK_TRUNCATE=true para activarlo",
+    ),
+    ("Subscription messages must be JSON text", "Los mensajes de suscripción deben ser texto JSON"),
+    ("Items are already in that category", "Los elementos ya están en esa categoría"),
+    (
+        "Maintenance operations are disabled; set ALLOW_MAINTENANCE_OPS=true to enable them",
+        "Las operaciones de mantenimiento están desactivadas; defina ALLOW_MAINTENANCE_OPS=true para activarlas",
+    ),
+    ("ANALYZE only runs while maintenance mode is on", "ANALYZE solo se ejecuta con el modo de mantenimiento activado"),
+    (
+        "Seeding is disabled; set DEV_MODE=true to enable it",
+        "La carga de datos de ejemplo está desactivada; defina DEV_MODE=true para activarla",
+    ),
+    ("X-Tenant-Id must be a UUID", "X-Tenant-Id debe ser un UUID"),
+    ("X-Tenant-Id does not match the token's tenant", "X-Tenant-Id no coincide con el tenant del token"),
+    ("limit must be a positive integer", "limit debe ser un entero positivo"),
+    ("fields must name at least one field", "fields debe nombrar al menos un campo"),
+    ("An internal error occurred", "Se produjo un error interno"),
+];
@@ -1,125 +1,278 @@
-use actix_web::{web, App, HttpResponse, HttpServer, Responder};
-use serde::{Deserialize, Serialize};
-use sqlx::postgres::PgPoolOptions;
-use sqlx::PgPool;
-use uuid::Uuid;
+mod cache;
+mod config;
+mod db;
+mod error;
+mod events;
+mod flags;
+mod handlers;
+mod i18n;
+mod middleware;
+mod models;
+mod negotiate;
+mod routes;
+#[cfg(test)]
+mod tests;
+
+use actix_web::middleware::{from_fn, Compress};
+use actix_web::rt::signal::ctrl_c;
+use actix_web::rt::signal::unix::{signal, SignalKind};
+use actix_web::rt::time::interval;
+use actix_web::{web, App, HttpServer};
 use dotenv::dotenv;
-use std::env;
-
-// Define a struct to represent the data
-#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
-struct Item {
-    id: Uuid,
-    name: String,
-    description: String,
-}
+use std::time::Duration;
+use tracing::{error, info, warn};
+use tracing_subscriber::EnvFilter;
+
+use crate::cache::ItemCache;
+use crate::config::Config;
+use crate::db::ReadPool;
+use crate::error::{form_error_handler, json_error_handler};
+use crate::events::ItemEvents;
+use crate::flags::FeatureFlags;
+use crate::handlers::Readiness;
+use crate::middleware::{
+    assign_request_id, capture_panic_backtraces, cors_policy, limit_query_length, localize_errors,
+    log_bodies, log_requests, rate_limit, record_metrics, require_api_key, require_bearer_token,
+    server_timing, skip_small_compression, verify_export_links, MaintenanceMode, Metrics,
+    RateLimiter, RATE_LIMIT_IDLE,
+};
+
+// Largest JSON or MessagePack request body accepted unless MAX_JSON_BODY_BYTES says otherwise
+const DEFAULT_MAX_JSON_BODY_BYTES: usize = 256 * 1024;
+
+// Seconds in-flight requests are given to finish once shutdown begins
+const SHUTDOWN_TIMEOUT_SECS: u64 = 30;
 
-// Create a new item
-async fn create_item(pool: web::Data<PgPool>, item: web::Json<ItemCreateRequest>) -> impl Responder {
-    let id = Uuid::new_v4();
-    let result = sqlx::query!(
-        "INSERT INTO items (id, name, description) VALUES ($1, $2, $3)",
-        id,
-        item.name,
-        item.description
-    )
-    .execute(pool.get_ref())
-    .await;
-
-    match result {
-        Ok(_) => HttpResponse::Created().json(Item { id, name: item.name.clone(), description: item.description.clone() }),
-        Err(_) => HttpResponse::InternalServerError().into(),
+// Resolve once the process receives SIGTERM or SIGINT
+async fn shutdown_signal() {
+    let mut sigterm = signal(SignalKind::terminate()).expect("Failed to install SIGTERM handler");
+    tokio::select! {
+        _ = ctrl_c() => {}
+        _ = sigterm.recv() => {}
     }
 }
 
-// Get all items
-async fn get_items(pool: web::Data<PgPool>) -> impl Responder {
-    let result = sqlx::query_as!(Item, "SELECT id, name, description FROM items")
-        .fetch_all(pool.get_ref())
-        .await;
+// Main function to configure and run the server
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    dotenv().ok();
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+        .init();
+
+    capture_panic_backtraces();
+
+    let config = Config::from_env().unwrap_or_else(|err| {
+        error!("{}", err);
+        std::process::exit(1);
+    });
+
+    let pool = db::connect(&config.database_url, &config.pool).await;
+    let read_pool = match &config.replica_url {
+        Some(replica_url) => {
+            info!("routing reads to the replica in DATABASE_REPLICA_URL");
+            db::connect_replica(replica_url, &config.pool).await
+        }
+        None => ReadPool(pool.clone()),
+    };
+    let read_pool = web::Data::new(read_pool);
 
-    match result {
-        Ok(items) => HttpResponse::Ok().json(items),
-        Err(_) => HttpResponse::InternalServerError().into(),
+    info!(origins = ?config.cors_allowed_origins, max_age_secs = config.cors_max_age_secs, "configuring CORS");
+
+    let api_key_auth = &config.api_key_auth;
+    if api_key_auth.enabled() {
+        info!(keys = api_key_auth.keys.len(), readonly_public = api_key_auth.readonly_public, "API key authentication enabled");
+    } else {
+        warn!("API_KEYS is not set; API key authentication is disabled");
     }
-}
 
-// Get a specific item by ID
-async fn get_item(pool: web::Data<PgPool>, item_id: web::Path<Uuid>) -> impl Responder {
-    let result = sqlx::query_as!(Item, "SELECT id, name, description FROM items WHERE id = $1", *item_id)
-        .fetch_one(pool.get_ref())
-        .await;
+    let jwt_auth = &config.jwt_auth;
+    if jwt_auth.enabled() {
+        info!(
+            read_scopes = ?jwt_auth.read_scopes,
+            write_scopes = ?jwt_auth.write_scopes,
+            "bearer token authentication enabled"
+        );
+    }
+    if config.export_links.enabled() {
+        info!(ttl_secs = config.export_links.ttl.as_secs(), "signed export links enabled");
+    }
 
-    match result {
-        Ok(item) => HttpResponse::Ok().json(item),
-        Err(_) => HttpResponse::NotFound().body("Item not found"),
+    let metrics = web::Data::new(Metrics::new());
+    let events = web::Data::new(ItemEvents::new());
+    let readiness = web::Data::new(Readiness::default());
+    let retry_policy = config.retry_policy;
+    info!(max_retries = retry_policy.max_retries, base_delay_ms = retry_policy.base_delay.as_millis() as u64, "configuring database retries");
+    let transaction_retry = config.transaction_retry;
+    info!(max_retries = transaction_retry.max_retries, base_delay_ms = transaction_retry.base_delay.as_millis() as u64, "configuring transaction conflict retries");
+
+    if config.bulk_truncate.allowed {
+        warn!("ALLOW_BULK_TRUNCATE is set; DELETE /items?confirm=true can wipe every item");
     }
-}
 
-// Update an item by ID
-async fn update_item(pool: web::Data<PgPool>, item_id: web::Path<Uuid>, item: web::Json<ItemUpdateRequest>) -> impl Responder {
-    let result = sqlx::query!(
-        "UPDATE items SET name = $1, description = $2 WHERE id = $3",
-        item.name,
-        item.description,
-        *item_id
-    )
-    .execute(pool.get_ref())
-    .await;
-
-    match result {
-        Ok(_) => HttpResponse::Ok().json(Item { id: *item_id, name: item.name.clone(), description: item.description.clone() }),
-        Err(_) => HttpResponse::InternalServerError().into(),
+    info!(pretty = config.json_style.pretty, camel_case = config.json_style.camel_case, "configuring JSON responses");
+    info!(default = config.page_sizes.default, max = config.page_sizes.max, "configuring list page sizes");
+    info!(
+        prefix = %config.api_routes.prefix,
+        legacy_aliases = config.api_routes.legacy_aliases,
+        trim_trailing_slash = config.api_routes.trim_trailing_slash,
+        "mounting API routes"
+    );
+
+    if config.body_logging.enabled {
+        warn!(max_bytes = config.body_logging.max_bytes, "LOG_BODIES is set; request and response bodies are logged at DEBUG");
     }
-}
 
-// Delete an item by ID
-async fn delete_item(pool: web::Data<PgPool>, item_id: web::Path<Uuid>) -> impl Responder {
-    let result = sqlx::query!("DELETE FROM items WHERE id = $1", *item_id)
-        .execute(pool.get_ref())
-        .await;
+    let item_cache = web::Data::new(ItemCache::new(config.item_cache_size, config.item_cache_ttl));
+    if item_cache.enabled() {
+        info!(capacity = item_cache.capacity(), ttl_secs = item_cache.ttl().as_secs(), "caching item lookups");
+    }
 
-    match result {
-        Ok(_) => HttpResponse::Ok().body("Item deleted"),
-        Err(_) => HttpResponse::InternalServerError().into(),
+    info!(timeout_secs = config.timeouts.duration.as_secs(), route_overrides = ?config.timeouts.routes, "configuring request timeout");
+
+    let rate_limiter = RateLimiter::new(config.rate_limit_rpm).trusting_proxy(config.trust_proxy_headers);
+    if rate_limiter.enabled() {
+        info!(requests_per_minute = rate_limiter.limit, trust_proxy = rate_limiter.trust_proxy, "rate limiting enabled");
+        let limiter = rate_limiter.clone();
+        actix_web::rt::spawn(async move {
+            let mut cleanup = interval(RATE_LIMIT_IDLE);
+            loop {
+                cleanup.tick().await;
+                limiter.purge_idle();
+            }
+        });
     }
-}
 
-// Request structs
-#[derive(Debug, Deserialize)]
-struct ItemCreateRequest {
-    name: String,
-    description: String,
-}
+    let purge_pool = pool.clone();
+    actix_web::rt::spawn(async move {
+        let mut purge = interval(db::IDEMPOTENCY_KEY_PURGE_INTERVAL);
+        loop {
+            purge.tick().await;
+            match db::purge_expired_idempotency_keys(&purge_pool).await {
+                Ok(purged) if purged > 0 => info!(purged, "purged expired idempotency keys"),
+                Ok(_) => {}
+                Err(err) => warn!(error = %err, "failed to purge expired idempotency keys"),
+            }
+        }
+    });
 
-#[derive(Debug, Deserialize)]
-struct ItemUpdateRequest {
-    name: String,
-    description: String,
-}
+    let maintenance = web::Data::new(MaintenanceMode::new(config.maintenance_mode));
+    if maintenance.enabled() {
+        warn!("MAINTENANCE_MODE is set; writes are refused until it is switched off via POST /admin/maintenance");
+    }
+    if config.maintenance_ops.allowed {
+        warn!("ALLOW_MAINTENANCE_OPS is set; POST /admin/analyze can run ANALYZE during maintenance");
+    }
+    if config.dev_mode.enabled {
+        warn!("DEV_MODE is set; POST /admin/seed can generate demo items. Never enable it in production");
+    }
 
-// Main function to configure and run the server
-#[actix_web::main]
-async fn main() -> std::io::Result<()> {
-    dotenv().ok();
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    let pool = PgPoolOptions::new()
-        .max_connections(5)
-        .connect(&database_url)
-        .await
-        .expect("Failed to create pool");
+    let flags = web::Data::new(FeatureFlags::new(maintenance.get_ref().clone()));
+    if let Err(err) = flags.refresh(&pool).await {
+        warn!(error = %err, "failed to load feature flags; using the defaults until the next refresh");
+    }
+    info!(flags = ?flags.list(), refresh_secs = config.feature_flag_refresh.as_secs(), "loaded feature flags");
+    let refreshed_flags = flags.clone();
+    let flag_pool = pool.clone();
+    let flag_refresh = config.feature_flag_refresh;
+    actix_web::rt::spawn(async move {
+        let mut refresh = interval(flag_refresh);
+        loop {
+            refresh.tick().await;
+            if let Err(err) = refreshed_flags.refresh(&flag_pool).await {
+                warn!(error = %err, "failed to refresh feature flags; keeping the last values");
+            }
+        }
+    });
 
     // Start HTTP server
-    HttpServer::new(move || {
+    info!(max_json_bytes = config.max_json_bytes, max_import_bytes = config.import_limits.max_bytes, "configuring request body limits");
+    info!(
+        bind_addr = %config.bind_addr,
+        port = config.port,
+        tls = config.tls.is_some(),
+        workers = config.workers,
+        keep_alive_secs = config.keep_alive_secs,
+        "starting HTTP server"
+    );
+    let config = web::Data::new(config);
+    let app_config = config.clone();
+    let app_pool = pool.clone();
+    let app_read_pool = read_pool.clone();
+    let app_readiness = readiness.clone();
+    let server = HttpServer::new(move || {
+        let config = &app_config;
         App::new()
-            .app_data(web::Data::new(pool.clone()))
-            .route("/items", web::post().to(create_item))
-            .route("/items", web::get().to(get_items))
-            .route("/items/{id}", web::get().to(get_item))
-            .route("/items/{id}", web::put().to(update_item))
-            .route("/items/{id}", web::delete().to(delete_item))
+            .wrap(from_fn(log_bodies))
+            .wrap(from_fn(require_bearer_token))
+            .wrap(from_fn(require_api_key))
+            .wrap(from_fn(verify_export_links))
+            // Outside authentication, so requests it turns away still count and guessing credentials is throttled
+            .wrap(from_fn(rate_limit))
+            .wrap(from_fn(localize_errors))
+            .wrap(from_fn(skip_small_compression))
+            .wrap(Compress::default())
+            .wrap(cors_policy(&config.cors_allowed_origins, config.cors_max_age_secs))
+            .wrap(from_fn(record_metrics))
+            .wrap(from_fn(server_timing))
+            .wrap(from_fn(limit_query_length))
+            .wrap(from_fn(log_requests))
+            .wrap(from_fn(assign_request_id))
+            .wrap(config.api_routes.normalize_path())
+            .app_data(config.clone())
+            .app_data(web::Data::new(app_pool.clone()))
+            .app_data(app_read_pool.clone())
+            .app_data(web::Data::new(config.api_key_auth.clone()))
+            .app_data(web::Data::new(config.export_links.clone()))
+            .app_data(web::Data::new(config.jwt_auth.clone()))
+            .app_data(metrics.clone())
+            .app_data(events.clone())
+            .app_data(item_cache.clone())
+            .app_data(app_readiness.clone())
+            .app_data(maintenance.clone())
+            .app_data(flags.clone())
+            .app_data(web::Data::new(config.retry_policy))
+            .app_data(web::Data::new(config.transaction_retry))
+            .app_data(web::Data::new(config.import_limits))
+            .app_data(web::Data::new(config.bulk_truncate))
+            .app_data(web::Data::new(config.maintenance_ops))
+            .app_data(web::Data::new(config.dev_mode))
+            .app_data(web::Data::new(config.migration_check))
+            .app_data(web::Data::new(rate_limiter.clone()))
+            .app_data(web::Data::new(config.timeouts.clone()))
+            .app_data(web::Data::new(config.body_logging))
+            .app_data(web::Data::new(config.query_limit))
+            .app_data(web::Data::new(config.json_style))
+            .app_data(web::JsonConfig::default().limit(config.max_json_bytes).error_handler(json_error_handler))
+            .app_data(web::FormConfig::default().limit(config.max_json_bytes).error_handler(form_error_handler))
+            .app_data(web::PayloadConfig::new(config.max_json_bytes))
+            .configure(|cfg| routes::configure(cfg, &config.api_routes))
     })
-    .bind("127.0.0.1:8080")?
-    .run()
-    .await
-}
\ No newline at end of file
+    .workers(config.workers)
+    .keep_alive(Duration::from_secs(config.keep_alive_secs));
+    let address = (config.bind_addr.as_str(), config.port);
+    let server = match &config.tls {
+        Some(tls) => server.bind_rustls_0_23(address, tls.clone())?,
+        None => server.bind(address)?,
+    };
+    let server = server
+        .shutdown_timeout(SHUTDOWN_TIMEOUT_SECS)
+        .disable_signals()
+        .run();
+
+    // Stop accepting connections on SIGTERM/SIGINT and let in-flight requests drain,
+    // failing readiness first so load balancers stop routing new traffic here
+    let handle = server.handle();
+    actix_web::rt::spawn(async move {
+        shutdown_signal().await;
+        info!("shutdown signal received; draining in-flight requests");
+        readiness.begin_draining();
+        handle.stop(true).await;
+    });
+
+    server.await?;
+    pool.close().await;
+    read_pool.0.close().await;
+    info!("shutdown complete; database pools closed");
+    Ok(())
+}
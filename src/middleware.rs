@@ -0,0 +1,1115 @@
+use actix_web::http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use actix_web::http::{Method, StatusCode};
+use actix_cors::Cors;
+use actix_web::body::{self, BodySize, EitherBody, MessageBody};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::mime::Mime;
+use actix_web::{web, Error, FromRequest, HttpMessage, HttpRequest, ResponseError};
+use chrono::Utc;
+use jsonwebtoken::errors::ErrorKind as JwtErrorKind;
+use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
+use prometheus::{HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry};
+use serde::Deserialize;
+use uuid::Uuid;
+use dashmap::DashMap;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use futures_util::{FutureExt, StreamExt};
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::{ready, Ready};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Once;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use subtle::{Choice, ConstantTimeEq};
+use tracing::{debug, error, info, info_span, warn, Instrument};
+
+use crate::error::ApiError;
+use crate::config::EnvReader;
+use crate::db;
+use crate::handlers::Readiness;
+use crate::i18n::{self, Language};
+use crate::routes::{self, ApiPrefix};
+
+// Correlation id for a request, propagated from X-Request-Id or freshly generated
+#[derive(Debug, Clone)]
+struct RequestId(String);
+
+const REQUEST_ID_HEADER: &str = "x-request-id";
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+impl RequestId {
+    fn generate() -> Self {
+        RequestId(Uuid::new_v4().to_string())
+    }
+
+    // Reuse the incoming id when it's a UUID or short token, otherwise generate a fresh one
+    fn from_header(value: Option<&HeaderValue>) -> Self {
+        value
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| is_valid_request_id(value))
+            .map(|value| RequestId(value.to_string()))
+            .unwrap_or_else(RequestId::generate)
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+// Lets handlers take the current request id as an argument
+impl FromRequest for RequestId {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Ok(req.extensions().get::<RequestId>().cloned().unwrap_or_else(RequestId::generate)))
+    }
+}
+
+// UUIDs and other tokens made of URL-safe characters, up to MAX_REQUEST_ID_LEN long
+fn is_valid_request_id(value: &str) -> bool {
+    !value.is_empty()
+        && value.len() <= MAX_REQUEST_ID_LEN
+        && value.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+// Attach a RequestId to the request extensions and echo it back in the response
+pub async fn assign_request_id(req: ServiceRequest, next: Next<impl MessageBody>) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let request_id = RequestId::from_header(req.headers().get(REQUEST_ID_HEADER));
+    req.extensions_mut().insert(request_id.clone());
+
+    let mut res = next.call(req).await?;
+    if let Ok(value) = HeaderValue::from_str(&request_id.0) {
+        res.headers_mut().insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+    }
+    Ok(res)
+}
+
+// Log method, path, status and latency for every request inside a span carrying its request id
+pub async fn log_requests(req: ServiceRequest, next: Next<impl MessageBody>) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let request_id = req.extensions().get::<RequestId>().cloned().unwrap_or_else(RequestId::generate);
+    let method = req.method().clone();
+    let path = req.path().to_owned();
+    let span = info_span!("request", %request_id);
+    let started = Instant::now();
+
+    let res = next.call(req).instrument(span.clone()).await?;
+
+    span.in_scope(|| {
+        info!(
+            %method,
+            path,
+            status = res.status().as_u16(),
+            elapsed_ms = started.elapsed().as_millis() as u64,
+            "request completed"
+        )
+    });
+    Ok(res)
+}
+
+// Where the last panic on this thread happened, recorded by the hook `capture_panic_backtraces`
+// installs so catch_panics can log it; the unwound stack is gone by the time the panic is caught
+struct PanicReport {
+    location: String,
+    backtrace: Backtrace,
+}
+
+thread_local! {
+    static LAST_PANIC: RefCell<Option<PanicReport>> = const { RefCell::new(None) };
+}
+
+static PANIC_HOOK: Once = Once::new();
+
+// Record a backtrace of every panic for catch_panics to log, whatever RUST_BACKTRACE says.
+// The previous hook still runs, so panics outside requests are reported as before.
+pub fn capture_panic_backtraces() {
+    PANIC_HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let location = info.location().map(ToString::to_string).unwrap_or_default();
+            LAST_PANIC.with(|last| *last.borrow_mut() = Some(PanicReport { location, backtrace: Backtrace::force_capture() }));
+            previous(info);
+        }));
+    });
+}
+
+// The message a panic was raised with, when it was a string
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("non-string panic payload")
+}
+
+// Answer a request whose handler panicked with the standard JSON 500 instead of dropping the
+// connection, logging the panic with the request id. Panics while streaming a body aren't caught.
+// Must wrap a resource, not the app: the request can only be kept for the response once routed.
+pub async fn catch_panics(req: ServiceRequest, next: Next<impl MessageBody>) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let request = req.request().clone();
+    let request_id = req.extensions().get::<RequestId>().cloned().unwrap_or_else(RequestId::generate);
+    match AssertUnwindSafe(next.call(req)).catch_unwind().await {
+        Ok(res) => Ok(res?.map_into_left_body()),
+        Err(payload) => {
+            let report = LAST_PANIC.with(|last| last.borrow_mut().take());
+            let (location, backtrace) = match &report {
+                Some(report) => (report.location.as_str(), report.backtrace.to_string()),
+                None => ("unknown", String::new()),
+            };
+            error!(
+                %request_id,
+                method = %request.method(),
+                path = request.path(),
+                location,
+                message = panic_message(payload.as_ref()),
+                "handler panicked\n{}",
+                backtrace
+            );
+            Ok(ServiceResponse::new(request, ApiError::Internal.error_response()).map_into_right_body())
+        }
+    }
+}
+
+// Debug logging of request and response bodies, from LOG_BODIES. Off by default and meant only
+// for integration debugging: bodies can carry personal data, and logging them means buffering.
+#[derive(Clone, Copy)]
+pub struct BodyLogging {
+    pub enabled: bool,
+    // Bytes of each body written to the log, from LOG_BODY_MAX_BYTES
+    pub max_bytes: usize,
+}
+
+impl BodyLogging {
+    pub fn from_env(env: &mut EnvReader) -> Self {
+        BodyLogging { enabled: env.parse_or("LOG_BODIES", false), max_bytes: env.parse_or("LOG_BODY_MAX_BYTES", 1024) }
+    }
+}
+
+// Longest query string accepted, from MAX_QUERY_LEN; zero accepts any
+#[derive(Clone, Copy)]
+pub struct QueryLimit {
+    pub max_len: usize,
+}
+
+impl QueryLimit {
+    pub fn from_env(env: &mut EnvReader) -> Self {
+        QueryLimit { max_len: env.parse_or("MAX_QUERY_LEN", 8 * 1024) }
+    }
+}
+
+// Answer 414 before routing when the query string is longer than QueryLimit allows, so an
+// enormous `?ids=` list or pile of filters is never parsed, let alone run
+pub async fn limit_query_length(req: ServiceRequest, next: Next<impl MessageBody>) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let max_len = req.app_data::<web::Data<QueryLimit>>().map_or(0, |limit| limit.max_len);
+    if max_len == 0 || req.query_string().len() <= max_len {
+        return Ok(next.call(req).await?.map_into_left_body());
+    }
+    let err = ApiError::UriTooLong(format!("Query string may be at most {} bytes", max_len));
+    Ok(req.into_response(err.error_response()).map_into_right_body())
+}
+
+// Request bodies declaring a larger Content-Length are passed through unbuffered and unlogged
+const MAX_BUFFERED_BODY: u64 = 1024 * 1024;
+
+// JSON keys whose values never reach the log, compared ignoring case, `_` and `-`
+const SENSITIVE_FIELDS: &[&str] = &["password", "secret", "token", "accesstoken", "refreshtoken", "apikey", "authorization", "clientsecret"];
+
+// Log request and response bodies at DEBUG when BodyLogging is enabled. Only textual bodies of a
+// known size are read; each is buffered whole and handed on unchanged, so handlers and clients
+// see exactly what they would without logging. Binary, compressed and streamed bodies are skipped.
+pub async fn log_bodies(mut req: ServiceRequest, next: Next<impl MessageBody>) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let logging = match req.app_data::<web::Data<BodyLogging>>() {
+        Some(logging) if logging.enabled => *logging.get_ref(),
+        _ => return Ok(next.call(req).await?.map_into_left_body()),
+    };
+
+    let declared_len = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    if let Some(len) = declared_len.filter(|len| (1..=MAX_BUFFERED_BODY).contains(len)) {
+        if is_loggable(req.headers()) {
+            let mut payload = req.take_payload();
+            let mut body = web::BytesMut::with_capacity(len as usize);
+            while let Some(chunk) = payload.next().await {
+                body.extend_from_slice(&chunk?);
+            }
+            let body = body.freeze();
+            debug!(content_type = content_type_of(req.headers()), bytes = body.len(), body = %loggable_body(&body, logging.max_bytes), "request body");
+            req.set_payload(Payload::from(body));
+        }
+    }
+
+    let res = next.call(req).await?;
+    let sized = matches!(res.response().body().size(), BodySize::Sized(len) if len > 0);
+    if !sized || !is_loggable(res.headers()) {
+        return Ok(res.map_into_left_body());
+    }
+    let (request, response) = res.into_parts();
+    let (response, body) = response.into_parts();
+    let body = body::to_bytes(body).await.map_err(|err| actix_web::error::ErrorInternalServerError(err.into()))?;
+    debug!(status = response.status().as_u16(), content_type = content_type_of(response.headers()), bytes = body.len(), body = %loggable_body(&body, logging.max_bytes), "response body");
+    Ok(ServiceResponse::new(request, response.set_body(body).map_into_boxed_body()).map_into_right_body())
+}
+
+fn content_type_of(headers: &HeaderMap) -> &str {
+    headers.get(header::CONTENT_TYPE).and_then(|value| value.to_str().ok()).unwrap_or("")
+}
+
+// Uncompressed JSON, form or text bodies; event streams are left alone
+fn is_loggable(headers: &HeaderMap) -> bool {
+    if headers.get(header::CONTENT_ENCODING).is_some_and(|encoding| encoding != "identity") {
+        return false;
+    }
+    let Ok(mime) = content_type_of(headers).parse::<Mime>() else {
+        return false;
+    };
+    match (mime.type_().as_str(), mime.subtype().as_str()) {
+        ("text", "event-stream") => false,
+        ("text", _) | ("application", "json" | "x-www-form-urlencoded") => true,
+        ("application", _) => mime.suffix().map(|suffix| suffix.as_str()) == Some("json"),
+        _ => false,
+    }
+}
+
+// The body as it should appear in the log: sensitive JSON fields redacted, then cut to `max_bytes`
+pub fn loggable_body(body: &[u8], max_bytes: usize) -> String {
+    let text = match serde_json::from_slice::<serde_json::Value>(body) {
+        Ok(mut value) => {
+            redact_sensitive(&mut value);
+            value.to_string()
+        }
+        Err(_) => String::from_utf8_lossy(body).into_owned(),
+    };
+    if text.len() <= max_bytes {
+        return text;
+    }
+    let mut end = max_bytes;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}... ({} bytes truncated)", &text[..end], text.len() - end)
+}
+
+fn redact_sensitive(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(fields) => {
+            for (key, field) in fields.iter_mut() {
+                let key: String = key.chars().filter(|c| !matches!(c, '_' | '-')).collect::<String>().to_lowercase();
+                if SENSITIVE_FIELDS.contains(&key.as_str()) {
+                    *field = serde_json::Value::String("[redacted]".to_string());
+                } else {
+                    redact_sensitive(field);
+                }
+            }
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(redact_sensitive),
+        _ => {}
+    }
+}
+
+// Prometheus collectors for request and connection pool metrics
+#[derive(Clone)]
+pub struct Metrics {
+    pub registry: Registry,
+    requests: IntCounterVec,
+    latency: HistogramVec,
+    pub pool_size: IntGauge,
+    pub pool_idle: IntGauge,
+    pub item_cache: IntCounterVec,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+        let requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests by method, route and status"),
+            &["method", "path", "status"],
+        )
+        .expect("valid http_requests_total metric");
+        let latency = HistogramVec::new(
+            HistogramOpts::new("http_request_duration_seconds", "HTTP request latency by method and route"),
+            &["method", "path"],
+        )
+        .expect("valid http_request_duration_seconds metric");
+        let pool_size = IntGauge::new("db_pool_connections", "Open database connections").expect("valid db_pool_connections metric");
+        let pool_idle = IntGauge::new("db_pool_idle_connections", "Idle database connections").expect("valid db_pool_idle_connections metric");
+        let item_cache = IntCounterVec::new(
+            Opts::new("item_cache_lookups_total", "Item cache lookups by whether the item was cached"),
+            &["result"],
+        )
+        .expect("valid item_cache_lookups_total metric");
+
+        registry.register(Box::new(requests.clone())).expect("register http_requests_total");
+        registry.register(Box::new(latency.clone())).expect("register http_request_duration_seconds");
+        registry.register(Box::new(pool_size.clone())).expect("register db_pool_connections");
+        registry.register(Box::new(pool_idle.clone())).expect("register db_pool_idle_connections");
+        registry.register(Box::new(item_cache.clone())).expect("register item_cache_lookups_total");
+
+        Metrics { registry, requests, latency, pool_size, pool_idle, item_cache }
+    }
+}
+
+pub const METRICS_PATH: &str = "/metrics";
+
+// Health probes and the version endpoint, which never require credentials or count against rate limits
+pub const PROBE_PATHS: [&str; 4] = ["/health", "/livez", "/readyz", "/version"];
+
+// Operational endpoints under /admin always need credentials, even for reads
+const ADMIN_PREFIX: &str = "/admin/";
+
+fn is_admin_path(req: &ServiceRequest) -> bool {
+    req.path().starts_with(ADMIN_PREFIX)
+}
+
+// Count and time every request except scrapes of the metrics endpoint itself.
+// Routes are labelled by their pattern (e.g. /items/{id}) to keep label cardinality bounded.
+pub async fn record_metrics(req: ServiceRequest, next: Next<impl MessageBody>) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let metrics = req.app_data::<web::Data<Metrics>>().cloned();
+    let skip = req.path() == METRICS_PATH;
+    let started = Instant::now();
+
+    let res = next.call(req).await?;
+
+    if let (Some(metrics), false) = (metrics, skip) {
+        let method = res.request().method().as_str().to_owned();
+        let path = res.request().match_pattern().unwrap_or_else(|| "unmatched".to_string());
+        metrics
+            .requests
+            .with_label_values(&[&method, &path, res.status().as_str()])
+            .inc();
+        metrics
+            .latency
+            .with_label_values(&[&method, &path])
+            .observe(started.elapsed().as_secs_f64());
+    }
+    Ok(res)
+}
+
+const SERVER_TIMING: HeaderName = HeaderName::from_static("server-timing");
+
+// Report where a request's time went in a Server-Timing header, for browser dev tools: `app` is the
+// whole time spent inside the app, `db` the part of it spent in queries run through db::timed_query,
+// which with_retry and with_transaction use and handlers wrap their other queries and transactions in.
+// Streamed bodies are timed until the response starts.
+pub async fn server_timing(req: ServiceRequest, next: Next<impl MessageBody>) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let started = Instant::now();
+    let (res, db_time) = db::measure_db_time(next.call(req)).await;
+    let mut res = res?;
+    let millis = |duration: Duration| duration.as_secs_f64() * 1000.0;
+    let timing = format!("db;dur={:.1}, app;dur={:.1}", millis(db_time), millis(started.elapsed()));
+    if let Ok(value) = HeaderValue::from_str(&timing) {
+        res.headers_mut().append(SERVER_TIMING, value);
+    }
+    Ok(res)
+}
+
+// API keys accepted in the X-API-Key header. Deliberately not Debug so keys can't end up in logs.
+#[derive(Clone)]
+pub struct ApiKeyAuth {
+    pub keys: Vec<String>,
+    pub readonly_public: bool,
+}
+
+const API_KEY_HEADER: &str = "x-api-key";
+
+impl ApiKeyAuth {
+    // Load keys from API_KEYS (comma-separated); with none configured authentication is disabled
+    pub fn from_env(env: &mut EnvReader) -> Self {
+        let keys = env
+            .string("API_KEYS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|key| key.trim().to_string())
+            .filter(|key| !key.is_empty())
+            .collect();
+        ApiKeyAuth { keys, readonly_public: env.parse_or("AUTH_READONLY_PUBLIC", false) }
+    }
+
+    pub fn enabled(&self) -> bool {
+        !self.keys.is_empty()
+    }
+
+    // Check every configured key in constant time so response timing doesn't reveal partial matches
+    fn accepts(&self, candidate: &[u8]) -> bool {
+        let matched = self
+            .keys
+            .iter()
+            .fold(Choice::from(0), |matched, key| matched | key.as_bytes().ct_eq(candidate));
+        matched.into()
+    }
+
+    // Health probes are always public, and reads outside /admin are when AUTH_READONLY_PUBLIC is set
+    fn is_exempt(&self, req: &ServiceRequest) -> bool {
+        PROBE_PATHS.contains(&req.path())
+            || req.extensions().contains::<SignedExport>()
+            || (self.readonly_public && !is_admin_path(req) && matches!(*req.method(), Method::GET | Method::HEAD))
+    }
+}
+
+// Reject requests without a valid X-API-Key header with a JSON 401
+pub async fn require_api_key(req: ServiceRequest, next: Next<impl MessageBody>) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let authorized = match req.app_data::<web::Data<ApiKeyAuth>>() {
+        Some(auth) if auth.enabled() && !auth.is_exempt(&req) => req
+            .headers()
+            .get(API_KEY_HEADER)
+            .is_some_and(|key| auth.accepts(key.as_bytes())),
+        _ => true,
+    };
+
+    if !authorized {
+        let err = ApiError::Unauthorized("A valid X-API-Key header is required".to_string());
+        return Ok(req.into_response(err.error_response()).map_into_right_body());
+    }
+    Ok(next.call(req).await?.map_into_left_body())
+}
+
+// Seconds a signed export link stays valid unless EXPORT_LINK_TTL_SECS says otherwise
+const DEFAULT_EXPORT_LINK_TTL_SECS: u64 = 900;
+
+// Key for signing links to the CSV export that work without credentials, so an export can be handed
+// to a tool that can't send the API key or a bearer token. Without EXPORT_LINK_SECRET no links are
+// signed and none are accepted.
+#[derive(Clone)]
+pub struct ExportLinks {
+    key: Option<Hmac<Sha256>>,
+    pub ttl: Duration,
+    // Scheme and host links are built on, e.g. https://api.example.com; without it the request's
+    // own Host header is used, which a client can set to anything
+    pub base_url: Option<String>,
+}
+
+impl ExportLinks {
+    pub fn new(secret: Option<&str>, ttl: Duration) -> Self {
+        let key = secret.map(|secret| Hmac::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any length"));
+        ExportLinks { key, ttl, base_url: None }
+    }
+
+    pub fn with_base_url(self, base_url: Option<String>) -> Self {
+        ExportLinks { base_url: base_url.map(|url| url.trim_end_matches('/').to_string()), ..self }
+    }
+
+    pub fn from_env(env: &mut EnvReader) -> Self {
+        let ttl = env.parse_or("EXPORT_LINK_TTL_SECS", DEFAULT_EXPORT_LINK_TTL_SECS);
+        if ttl == 0 {
+            env.problem("EXPORT_LINK_TTL_SECS must be at least 1");
+        }
+        let base_url = env.string("PUBLIC_BASE_URL");
+        if base_url.as_deref().is_some_and(|url| !url.starts_with("http://") && !url.starts_with("https://")) {
+            env.problem("PUBLIC_BASE_URL must start with http:// or https://");
+        }
+        ExportLinks::new(env.string("EXPORT_LINK_SECRET").as_deref(), Duration::from_secs(ttl)).with_base_url(base_url)
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.key.is_some()
+    }
+
+    // The hex HMAC-SHA256 of the path, tenant and expiry (Unix seconds) a link grants, or None when
+    // no secret is configured
+    pub fn sign(&self, path: &str, tenant: Uuid, expires: i64) -> Option<String> {
+        let mut mac = self.key.clone()?;
+        mac.update(format!("{}\n{}\n{}", path, tenant, expires).as_bytes());
+        Some(hex::encode(mac.finalize().into_bytes()))
+    }
+
+    // Check `signature` in constant time
+    fn verify(&self, path: &str, tenant: Uuid, expires: i64, signature: &str) -> bool {
+        let (Some(mut mac), Ok(signature)) = (self.key.clone(), hex::decode(signature)) else {
+            return false;
+        };
+        mac.update(format!("{}\n{}\n{}", path, tenant, expires).as_bytes());
+        mac.verify_slice(&signature).is_ok()
+    }
+}
+
+// The query parameters a signed export link carries
+#[derive(Deserialize)]
+struct ExportLinkParams {
+    sig: Option<String>,
+    exp: Option<String>,
+    tenant: Option<String>,
+}
+
+// Marks a request authorized by a signed export link, and for which tenant
+#[derive(Debug, Clone, Copy)]
+struct SignedExport {
+    tenant: Uuid,
+}
+
+// Authorize reads of the CSV export carrying a valid `sig` for their path, expiry and tenant, in
+// place of the API key or bearer token. A signature that is wrong, or one whose link has expired, is
+// a 403. Other paths are left alone, whatever their query string holds.
+pub async fn verify_export_links(req: ServiceRequest, next: Next<impl MessageBody>) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let prefix = req.app_data::<web::Data<ApiPrefix>>().map_or("", |prefix| prefix.0.as_str());
+    if req.path().strip_prefix(prefix) != Some(routes::EXPORT_CSV_PATH) {
+        return Ok(next.call(req).await?.map_into_left_body());
+    }
+    let params = web::Query::<ExportLinkParams>::from_query(req.query_string()).ok();
+    let Some(ExportLinkParams { sig: Some(signature), exp, tenant }) = params.map(web::Query::into_inner) else {
+        return Ok(next.call(req).await?.map_into_left_body());
+    };
+    let links = req.app_data::<web::Data<ExportLinks>>();
+    let link = match (exp.and_then(|exp| exp.parse::<i64>().ok()), tenant.and_then(|tenant| Uuid::parse_str(&tenant).ok())) {
+        (Some(expires), Some(tenant))
+            if matches!(*req.method(), Method::GET | Method::HEAD)
+                && links.is_some_and(|links| links.verify(req.path(), tenant, expires, &signature)) =>
+        {
+            if expires <= Utc::now().timestamp() {
+                Err(ApiError::Forbidden("Export link has expired".to_string()))
+            } else {
+                Ok(SignedExport { tenant })
+            }
+        }
+        _ => Err(ApiError::Forbidden("Export link signature is invalid".to_string())),
+    };
+    match link {
+        Ok(link) => {
+            req.extensions_mut().insert(link);
+            Ok(next.call(req).await?.map_into_left_body())
+        }
+        Err(err) => Ok(req.into_response(err.error_response()).map_into_right_body()),
+    }
+}
+
+// Per-client token buckets holding up to `limit` requests, refilled evenly over a minute.
+// Shared by all workers; disabled when RATE_LIMIT_RPM is unset or zero.
+#[derive(Clone)]
+pub struct RateLimiter {
+    pub limit: u32,
+    // Take the client address from Forwarded / X-Forwarded-For, for a deployment behind a proxy
+    // that sets them; otherwise every client would share the proxy's bucket
+    pub trust_proxy: bool,
+    buckets: Arc<DashMap<String, TokenBucket>>,
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+// Outcome of charging one request to a client's bucket
+enum RateDecision {
+    Allowed { remaining: u32 },
+    Limited { retry_after_secs: u64 },
+}
+
+// Buckets untouched this long are full again, so dropping them loses nothing
+pub const RATE_LIMIT_IDLE: Duration = Duration::from_secs(120);
+
+impl RateLimiter {
+    pub fn new(limit: u32) -> Self {
+        RateLimiter { limit, trust_proxy: false, buckets: Arc::new(DashMap::new()) }
+    }
+
+    // The same limiter, reading client addresses from proxy headers when `trusted`
+    pub fn trusting_proxy(self, trusted: bool) -> Self {
+        RateLimiter { trust_proxy: trusted, ..self }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.limit > 0
+    }
+
+    fn refill_per_sec(&self) -> f64 {
+        f64::from(self.limit) / 60.0
+    }
+
+    // Refill the client's bucket for the time elapsed, then take one token if available
+    fn check(&self, client: &str) -> RateDecision {
+        let now = Instant::now();
+        let capacity = f64::from(self.limit);
+        let mut bucket = self
+            .buckets
+            .entry(client.to_string())
+            .or_insert(TokenBucket { tokens: capacity, refilled_at: now });
+        let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.refill_per_sec()).min(capacity);
+        bucket.refilled_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            RateDecision::Allowed { remaining: bucket.tokens as u32 }
+        } else {
+            let retry_after_secs = ((1.0 - bucket.tokens) / self.refill_per_sec()).ceil() as u64;
+            RateDecision::Limited { retry_after_secs: retry_after_secs.max(1) }
+        }
+    }
+
+    // Forget clients that have been idle long enough for their bucket to refill
+    pub fn purge_idle(&self) {
+        let now = Instant::now();
+        self.buckets.retain(|_, bucket| now.duration_since(bucket.refilled_at) < RATE_LIMIT_IDLE);
+    }
+
+    // Health probes and metrics scrapes must never be throttled
+    fn is_exempt(req: &ServiceRequest) -> bool {
+        PROBE_PATHS.contains(&req.path()) || req.path() == METRICS_PATH
+    }
+}
+
+// Identify the client by its API key when one was accepted, otherwise by IP: the peer's, or with
+// `trust_proxy` the one the proxy reports
+fn rate_limit_client(req: &ServiceRequest, trust_proxy: bool) -> String {
+    let api_key = req
+        .headers()
+        .get(API_KEY_HEADER)
+        .filter(|key| req.app_data::<web::Data<ApiKeyAuth>>().is_some_and(|auth| auth.enabled() && auth.accepts(key.as_bytes())));
+    if let Some(key) = api_key.and_then(|key| key.to_str().ok()) {
+        return format!("key:{}", key);
+    }
+    let forwarded = trust_proxy.then(|| req.connection_info().realip_remote_addr().map(str::to_string)).flatten();
+    match (forwarded, req.peer_addr()) {
+        // A proxy may report the port as well, which changes from one connection to the next
+        (Some(addr), _) => match addr.parse::<std::net::SocketAddr>() {
+            Ok(addr) => format!("ip:{}", addr.ip()),
+            Err(_) => format!("ip:{}", addr),
+        },
+        (None, Some(addr)) => format!("ip:{}", addr.ip()),
+        (None, None) => "ip:unknown".to_string(),
+    }
+}
+
+// Enforce the per-client request budget, answering 429 with Retry-After once it is spent
+pub async fn rate_limit(req: ServiceRequest, next: Next<impl MessageBody>) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let limiter = match req.app_data::<web::Data<RateLimiter>>() {
+        Some(limiter) if limiter.enabled() && !RateLimiter::is_exempt(&req) => limiter.clone(),
+        _ => return Ok(next.call(req).await?.map_into_left_body()),
+    };
+
+    let limit = HeaderValue::from(limiter.limit);
+    match limiter.check(&rate_limit_client(&req, limiter.trust_proxy)) {
+        RateDecision::Allowed { remaining } => {
+            let mut res = next.call(req).await?;
+            let headers = res.headers_mut();
+            headers.insert(HeaderName::from_static("x-ratelimit-limit"), limit);
+            headers.insert(HeaderName::from_static("x-ratelimit-remaining"), HeaderValue::from(remaining));
+            Ok(res.map_into_left_body())
+        }
+        RateDecision::Limited { retry_after_secs } => {
+            let err = ApiError::TooManyRequests("Rate limit exceeded; retry later".to_string());
+            let mut res = err.error_response();
+            let headers = res.headers_mut();
+            headers.insert(header::RETRY_AFTER, HeaderValue::from(retry_after_secs));
+            headers.insert(HeaderName::from_static("x-ratelimit-limit"), limit);
+            headers.insert(HeaderName::from_static("x-ratelimit-remaining"), HeaderValue::from(0u32));
+            Ok(req.into_response(res).map_into_right_body())
+        }
+    }
+}
+
+// Upper bound on how long a handler may run before the request is answered with 504.
+// Set by REQUEST_TIMEOUT_SECS; zero disables it.
+#[derive(Clone)]
+pub struct RequestTimeout {
+    pub duration: Duration,
+    // Budgets that replace `duration` on particular routes, keyed by path pattern relative to the
+    // API prefix; see routes::route_timeouts
+    pub routes: HashMap<&'static str, Duration>,
+}
+
+impl RequestTimeout {
+    pub fn from_env(env: &mut EnvReader) -> Self {
+        RequestTimeout { duration: Duration::from_secs(env.parse_or("REQUEST_TIMEOUT_SECS", 30)), routes: routes::route_timeouts() }
+    }
+
+    // REQUEST_TIMEOUT_SECS=0 turns off every timeout, overridden routes included
+    pub fn enabled(&self) -> bool {
+        !self.duration.is_zero()
+    }
+
+    // The budget for the route matching `pattern`, or the global one for routes without their own
+    pub fn limit(&self, pattern: Option<&str>) -> Duration {
+        pattern.and_then(|pattern| self.routes.get(pattern)).copied().unwrap_or(self.duration)
+    }
+}
+
+// Answer 504 when the handler takes longer than the timeout. Dropping the timed-out future
+// cancels it, so any query it was awaiting is abandoned along with it.
+// Registered per route rather than on the App: the request can only be cloned for the 504
+// response once routing is done with it, and streaming routes are left unwrapped.
+pub async fn request_timeout(req: ServiceRequest, next: Next<impl MessageBody>) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let limit = match req.app_data::<web::Data<RequestTimeout>>() {
+        Some(timeout) if timeout.enabled() => {
+            let prefix = req.app_data::<web::Data<ApiPrefix>>().map_or("", |prefix| prefix.0.as_str());
+            let pattern = req.match_pattern();
+            timeout.limit(pattern.as_deref().map(|pattern| pattern.strip_prefix(prefix).unwrap_or(pattern)))
+        }
+        _ => return Ok(next.call(req).await?.map_into_left_body()),
+    };
+
+    let request = req.request().clone();
+    match actix_web::rt::time::timeout(limit, next.call(req)).await {
+        Ok(res) => Ok(res?.map_into_left_body()),
+        Err(_) => {
+            warn!(method = %request.method(), path = request.path(), timeout_secs = limit.as_secs(), "request timed out");
+            let err = ApiError::GatewayTimeout(format!("Request did not complete within {} seconds", limit.as_secs()));
+            Ok(ServiceResponse::new(request, err.error_response()).map_into_right_body())
+        }
+    }
+}
+
+// Whether writes are refused while the database is being migrated. Starts as MAINTENANCE_MODE says
+// and can be flipped at runtime through POST /admin/maintenance; clones share the flag.
+#[derive(Clone, Default)]
+pub struct MaintenanceMode {
+    enabled: Arc<AtomicBool>,
+}
+
+impl MaintenanceMode {
+    pub fn new(enabled: bool) -> Self {
+        MaintenanceMode { enabled: Arc::new(AtomicBool::new(enabled)) }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled.load(Ordering::SeqCst)
+    }
+
+    pub fn set(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::SeqCst);
+    }
+}
+
+// Answer a write route with 503 and Retry-After while maintenance mode is on or the instance has
+// been drained through POST /admin/drain; reads are unaffected
+pub async fn reject_writes_during_maintenance(req: ServiceRequest, next: Next<impl MessageBody>) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let message = if req.app_data::<web::Data<MaintenanceMode>>().is_some_and(|mode| mode.enabled()) {
+        "The service is in maintenance mode and only accepts reads; retry later"
+    } else if req.app_data::<web::Data<Readiness>>().is_some_and(|readiness| readiness.is_drained()) {
+        "This instance is draining and only accepts reads; retry later"
+    } else {
+        return Ok(next.call(req).await?.map_into_left_body());
+    };
+    let err = ApiError::Maintenance(message.to_string());
+    Ok(req.into_response(err.error_response()).map_into_right_body())
+}
+
+// Give the bare 405 actix sends, when a path has no route for the request's method, the JSON
+// error body every other failure has. The Allow header actix built from the routes is kept.
+pub async fn explain_method_not_allowed(req: ServiceRequest, next: Next<impl MessageBody>) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let res = next.call(req).await?;
+    if res.status() != StatusCode::METHOD_NOT_ALLOWED {
+        return Ok(res.map_into_left_body());
+    }
+
+    let allow = res.headers().get(header::ALLOW).cloned();
+    let (request, _) = res.into_parts();
+    let supported = allow.as_ref().and_then(|value| value.to_str().ok()).unwrap_or_default();
+    let err = ApiError::MethodNotAllowed(format!("{} is not supported on this path; it supports {}", request.method(), supported));
+    let mut response = err.error_response();
+    if let Some(allow) = allow {
+        response.headers_mut().insert(header::ALLOW, allow);
+    }
+    Ok(ServiceResponse::new(request, response).map_into_right_body())
+}
+
+// Translate the messages of JSON error responses into the language the request's Accept-Language
+// prefers, see the i18n module. The `error` code is never translated, so clients can keep matching
+// on it, and untranslated messages are sent in English. Content-Language is only claimed for a
+// message that was translated.
+pub async fn localize_errors(req: ServiceRequest, next: Next<impl MessageBody>) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let language = i18n::negotiate(&req);
+    let mut res = next.call(req).await?;
+    let failed = res.status().is_client_error() || res.status().is_server_error();
+    if !failed || content_type_of(res.headers()) != "application/json" || !matches!(res.response().body().size(), BodySize::Sized(_)) {
+        return Ok(res.map_into_left_body());
+    }
+    res.headers_mut().append(header::VARY, HeaderValue::from_static("accept-language"));
+    if language == Language::English {
+        return Ok(res.map_into_left_body());
+    }
+
+    let (request, response) = res.into_parts();
+    let (mut response, body) = response.into_parts();
+    let body = body::to_bytes(body).await.map_err(|err| actix_web::error::ErrorInternalServerError(err.into()))?;
+    let Ok(mut error) = serde_json::from_slice::<serde_json::Value>(&body) else {
+        return Ok(ServiceResponse::new(request, response.set_body(body).map_into_boxed_body()).map_into_right_body());
+    };
+    let translated = error.get_mut("message").is_some_and(|message| localize_text(message, language));
+    if let Some(fields) = error.get_mut("fields").and_then(|fields| fields.as_object_mut()) {
+        fields.values_mut().for_each(|problem| {
+            localize_text(problem, language);
+        });
+    }
+    if translated {
+        response.headers_mut().insert(header::CONTENT_LANGUAGE, HeaderValue::from_static(language.tag()));
+    }
+    Ok(ServiceResponse::new(request, response.set_body(error.to_string()).map_into_boxed_body()).map_into_right_body())
+}
+
+// Translate `value` in place if it is a string with a translation, telling whether it had one
+fn localize_text(value: &mut serde_json::Value, language: Language) -> bool {
+    if let serde_json::Value::String(text) = value {
+        if let Cow::Owned(translated) = i18n::translate(language, text) {
+            *text = translated;
+            return true;
+        }
+    }
+    false
+}
+
+// Claims decoded from a validated bearer token, available to handlers via `web::ReqData<Claims>`.
+// `exp` is checked by jsonwebtoken during validation.
+#[derive(Debug, Clone, Deserialize)]
+struct Claims {
+    sub: String,
+    // Space-delimited scopes, as in RFC 8693
+    #[serde(default)]
+    scope: String,
+    // Tenant the token is issued for; overrides X-Tenant-Id
+    #[serde(default)]
+    tenant_id: Option<Uuid>,
+}
+
+const TENANT_HEADER: &str = "x-tenant-id";
+
+// Tenant of requests that name none; also owns every item written before multi-tenancy
+pub const DEFAULT_TENANT: Uuid = Uuid::nil();
+
+// Who is acting and for which tenant. The user is the bearer token's `sub` when it is a UUID;
+// anonymous requests and API keys carry no identity, so they have none.
+// The tenant is the token's `tenant_id` claim or the signed export link's tenant, else the
+// X-Tenant-Id header, else the default tenant.
+#[derive(Debug, Clone, Copy)]
+pub struct Principal {
+    pub user: Option<Uuid>,
+    pub tenant: Uuid,
+}
+
+impl Principal {
+    fn resolve(req: &HttpRequest) -> Result<Self, ApiError> {
+        let header = match req.headers().get(TENANT_HEADER) {
+            Some(value) => Some(
+                value
+                    .to_str()
+                    .ok()
+                    .and_then(|value| Uuid::parse_str(value.trim()).ok())
+                    .ok_or_else(|| ApiError::Validation("X-Tenant-Id must be a UUID".to_string()))?,
+            ),
+            None => None,
+        };
+        let extensions = req.extensions();
+        let claims = extensions.get::<Claims>();
+        let user = claims.and_then(|claims| Uuid::parse_str(&claims.sub).ok());
+        let bound = claims.and_then(|claims| claims.tenant_id).or(extensions.get::<SignedExport>().map(|link| link.tenant));
+        let tenant = match (bound, header) {
+            (Some(bound), Some(header)) if bound != header => {
+                return Err(ApiError::Forbidden("X-Tenant-Id does not match the token's tenant".to_string()));
+            }
+            (Some(tenant), _) | (None, Some(tenant)) => tenant,
+            (None, None) => DEFAULT_TENANT,
+        };
+        Ok(Principal { user, tenant })
+    }
+}
+
+// Lets handlers take the current principal as an argument
+impl FromRequest for Principal {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Principal::resolve(req).map_err(Error::from))
+    }
+}
+
+impl Claims {
+    fn has_scopes(&self, required: &[String]) -> bool {
+        required.iter().all(|scope| self.scope.split_whitespace().any(|granted| granted == scope))
+    }
+}
+
+// Bearer-token verification settings, split into a read group (GET/HEAD) and a write group
+#[derive(Clone)]
+pub struct JwtAuth {
+    key: Option<(DecodingKey, Validation)>,
+    // None leaves reads public; Some requires a valid token carrying these scopes
+    pub read_scopes: Option<Vec<String>>,
+    pub write_scopes: Vec<String>,
+}
+
+impl JwtAuth {
+    // Configure from JWT_SECRET (HMAC) or JWT_PUBLIC_KEY_PATH (PEM), optionally overriding JWT_ALGORITHM.
+    // With neither set, bearer-token authentication is disabled.
+    pub fn from_env(env: &mut EnvReader) -> Self {
+        let read_scopes = env.string("JWT_READ_SCOPES").map(|scopes| parse_scopes(&scopes));
+        let write_scopes = parse_scopes(&env.string("JWT_WRITE_SCOPES").unwrap_or_default());
+
+        let key = match (env.string("JWT_SECRET"), env.string("JWT_PUBLIC_KEY_PATH")) {
+            (Some(_), Some(_)) => {
+                env.problem("Set only one of JWT_SECRET and JWT_PUBLIC_KEY_PATH");
+                None
+            }
+            (Some(secret), None) => {
+                let algorithm = env.parse_or("JWT_ALGORITHM", Algorithm::HS256);
+                Some((DecodingKey::from_secret(secret.as_bytes()), Validation::new(algorithm)))
+            }
+            (None, Some(path)) => {
+                let algorithm = env.parse_or("JWT_ALGORITHM", Algorithm::RS256);
+                match public_key(&path, algorithm) {
+                    Ok(key) => Some((key, Validation::new(algorithm))),
+                    Err(problem) => {
+                        env.problem(problem);
+                        None
+                    }
+                }
+            }
+            (None, None) => None,
+        };
+
+        JwtAuth { key, read_scopes, write_scopes }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.key.is_some()
+    }
+
+    // Scopes required for this request, or None when it needs no token at all.
+    // Reads under /admin are held to the write scopes.
+    fn required_scopes(&self, req: &ServiceRequest) -> Option<&[String]> {
+        if PROBE_PATHS.contains(&req.path()) || req.extensions().contains::<SignedExport>() {
+            return None;
+        }
+        match *req.method() {
+            Method::GET | Method::HEAD if !is_admin_path(req) => self.read_scopes.as_deref(),
+            _ => Some(&self.write_scopes),
+        }
+    }
+
+    fn decode(&self, token: &str) -> Result<Claims, ApiError> {
+        let (key, validation) = self.key.as_ref().ok_or(ApiError::Internal)?;
+        decode::<Claims>(token, key, validation).map(|data| data.claims).map_err(|err| match err.kind() {
+            JwtErrorKind::ExpiredSignature => ApiError::Unauthorized("Bearer token has expired".to_string()),
+            _ => ApiError::Unauthorized("Bearer token is invalid".to_string()),
+        })
+    }
+}
+
+// Read and parse the PEM public key at `path` for verifying `algorithm` signatures
+fn public_key(path: &str, algorithm: Algorithm) -> Result<DecodingKey, String> {
+    let pem = std::fs::read(path).map_err(|err| format!("Failed to read JWT_PUBLIC_KEY_PATH '{}': {}", path, err))?;
+    match algorithm {
+        Algorithm::RS256 | Algorithm::RS384 | Algorithm::RS512 | Algorithm::PS256 | Algorithm::PS384 | Algorithm::PS512 => {
+            DecodingKey::from_rsa_pem(&pem)
+        }
+        Algorithm::ES256 | Algorithm::ES384 => DecodingKey::from_ec_pem(&pem),
+        Algorithm::EdDSA => DecodingKey::from_ed_pem(&pem),
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => {
+            return Err(format!("JWT_ALGORITHM {:?} requires JWT_SECRET, not a public key", algorithm));
+        }
+    }
+    .map_err(|err| format!("Failed to parse JWT public key '{}': {}", path, err))
+}
+
+fn parse_scopes(scopes: &str) -> Vec<String> {
+    scopes.split([',', ' ']).filter(|scope| !scope.is_empty()).map(str::to_string).collect()
+}
+
+// Validate the Authorization bearer token for protected route groups and expose its claims
+pub async fn require_bearer_token(req: ServiceRequest, next: Next<impl MessageBody>) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let claims = match req.app_data::<web::Data<JwtAuth>>() {
+        Some(auth) if auth.enabled() => match auth.required_scopes(&req) {
+            Some(required) => {
+                let token = req
+                    .headers()
+                    .get(header::AUTHORIZATION)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| value.strip_prefix("Bearer "));
+                let verified = token
+                    .ok_or_else(|| ApiError::Unauthorized("A bearer token is required".to_string()))
+                    .and_then(|token| auth.decode(token.trim()))
+                    .and_then(|claims| {
+                        if claims.has_scopes(required) {
+                            Ok(claims)
+                        } else {
+                            Err(ApiError::Forbidden(format!("Token is missing required scopes: {}", required.join(" "))))
+                        }
+                    });
+                match verified {
+                    Ok(claims) => Some(claims),
+                    Err(err) => return Ok(req.into_response(err.error_response()).map_into_right_body()),
+                }
+            }
+            None => None,
+        },
+        _ => None,
+    };
+
+    if let Some(claims) = claims {
+        debug!(sub = %claims.sub, "bearer token accepted");
+        req.extensions_mut().insert(claims);
+    }
+    Ok(next.call(req).await?.map_into_left_body())
+}
+
+// Responses smaller than this many bytes are sent uncompressed
+const MIN_COMPRESS_SIZE: u64 = 1024;
+
+// Mark small bodies as identity-encoded so the outer Compress middleware leaves them alone
+pub async fn skip_small_compression(req: ServiceRequest, next: Next<impl MessageBody>) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let mut res = next.call(req).await?;
+    if let BodySize::Sized(size) = res.response().body().size() {
+        if size < MIN_COMPRESS_SIZE {
+            let headers = res.headers_mut();
+            headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static("identity"));
+            headers.append(header::VARY, HeaderValue::from_static("accept-encoding"));
+        }
+    }
+    Ok(res)
+}
+
+// Build the CORS policy; with no allowed origins configured, cross-origin requests are denied.
+// Browsers cache a preflight for `max_age_secs`, so repeated requests skip the extra OPTIONS round trip.
+pub fn cors_policy(allowed_origins: &[String], max_age_secs: usize) -> Cors {
+    let mut cors = Cors::default()
+        .allowed_methods(vec!["GET", "POST", "PUT", "PATCH", "DELETE"])
+        // Every request header the API reads, or browsers refuse to send it cross-origin
+        .allowed_headers(vec![
+            header::CONTENT_TYPE,
+            header::AUTHORIZATION,
+            HeaderName::from_static(API_KEY_HEADER),
+            HeaderName::from_static(TENANT_HEADER),
+            header::IF_MATCH,
+            header::IF_NONE_MATCH,
+            header::IF_MODIFIED_SINCE,
+            HeaderName::from_static("idempotency-key"),
+            HeaderName::from_static("prefer"),
+        ])
+        .max_age(max_age_secs)
+        // Let browser clients read the pagination headers on list responses, the validators and
+        // Location of items, the rate limit and the request id on any
+        .expose_headers(vec![
+            header::CONTENT_RANGE,
+            header::LINK,
+            HeaderName::from_static("x-total-count"),
+            HeaderName::from_static("x-next-cursor"),
+            HeaderName::from_static("x-page-size-clamped"),
+            header::ETAG,
+            header::LOCATION,
+            HeaderName::from_static("x-ratelimit-limit"),
+            HeaderName::from_static("x-ratelimit-remaining"),
+            header::RETRY_AFTER,
+            HeaderName::from_static(REQUEST_ID_HEADER),
+        ]);
+    if allowed_origins.iter().any(|origin| origin == "*") {
+        // Wildcard responses never allow credentials
+        cors = cors.allow_any_origin().send_wildcard();
+    } else {
+        for origin in allowed_origins {
+            cors = cors.allowed_origin(origin);
+        }
+    }
+    cors
+}
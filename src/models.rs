@@ -0,0 +1,1085 @@
+use actix_web::http::header::{self, EntityTag};
+use actix_web::dev::Payload;
+use actix_web::{web, FromRequest, HttpRequest};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use serde::de::{self, DeserializeOwned};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+use validator::{Validate, ValidationError};
+use std::collections::BTreeMap;
+use std::future::{ready, Ready};
+
+use crate::config::EnvReader;
+use crate::error::ApiError;
+
+// Define a struct to represent the data
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, ToSchema)]
+pub struct Item {
+    pub id: Uuid,
+    pub name: String,
+    // URL slug generated from the name and regenerated when it changes, unique per tenant
+    pub slug: String,
+    // None when the item has no description, which is not the same as an empty one
+    pub description: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub version: i32,
+    pub deleted_at: Option<DateTime<Utc>>,
+    pub category_id: Option<Uuid>,
+    pub created_by: Option<Uuid>,
+    pub updated_by: Option<Uuid>,
+    // Hidden from listings unless include_archived=true, but still fetchable by id
+    pub archived: bool,
+    // Display order set through the reorder endpoint; None until the item is first positioned
+    pub position: Option<i32>,
+    // Deployment-specific fields, stored and returned exactly as sent; None when never set
+    #[schema(value_type = Option<Object>)]
+    pub metadata: Option<Value>,
+    // Exact to the cent and sent as a string so no float rounding creeps in; None when unpriced
+    #[serde(serialize_with = "in_cents")]
+    pub price: Option<Decimal>,
+    // Names of the tags assigned through /items/{id}/tags, in alphabetical order
+    pub tags: Vec<String>,
+}
+
+// Prices as two-decimal strings; Postgres hands NUMERIC(12, 2) values over with four places
+fn in_cents<S: Serializer>(price: &Option<Decimal>, serializer: S) -> Result<S::Ok, S::Error> {
+    let price = price.map(|mut price| {
+        price.rescale(2);
+        price
+    });
+    price.serialize(serializer)
+}
+
+impl Item {
+    // Weak validator derived from the version, which every mutation bumps
+    pub fn etag(&self) -> EntityTag {
+        EntityTag::new_weak(self.version.to_string())
+    }
+}
+
+// One recorded change to an item, as returned by the history endpoint
+#[derive(Debug, Serialize, sqlx::FromRow, ToSchema)]
+pub struct ItemAuditRecord {
+    pub id: i64,
+    pub item_id: Uuid,
+    // create, update or delete
+    pub operation: String,
+    // The item as it was after the change, or just before it for deletes
+    #[schema(value_type = Object)]
+    pub snapshot: serde_json::Value,
+    pub actor: Option<Uuid>,
+    pub changed_at: DateTime<Utc>,
+}
+
+// Query parameters for checking whether a name is free
+#[derive(Debug, Deserialize, IntoParams, Validate)]
+#[into_params(parameter_in = Query)]
+pub struct NameCheck {
+    #[validate(custom(function = "not_blank"), length(max = MAX_NAME_LEN))]
+    pub name: String,
+}
+
+// Query parameters for name autocompletion
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SuggestParams {
+    // Names starting with this text match, ignoring case
+    pub prefix: String,
+    // At most this many names, up to MAX_SUGGESTIONS
+    pub limit: Option<i64>,
+}
+
+const DEFAULT_SUGGESTIONS: i64 = 10;
+const MAX_SUGGESTIONS: i64 = 50;
+
+impl SuggestParams {
+    // ILIKE pattern matching names that start with the prefix taken literally
+    pub fn pattern(&self) -> String {
+        format!("{}%", escape_like(&self.prefix))
+    }
+
+    // How many names to return, capped at MAX_SUGGESTIONS
+    pub fn limit(&self) -> Result<i64, &'static str> {
+        let limit = self.limit.unwrap_or(DEFAULT_SUGGESTIONS);
+        if limit < 1 {
+            return Err("limit must be a positive integer");
+        }
+        Ok(limit.min(MAX_SUGGESTIONS))
+    }
+}
+
+// Response body for the name check endpoint
+#[derive(Debug, Serialize, ToSchema)]
+pub struct NameAvailability {
+    pub available: bool,
+}
+
+// Response body for the count endpoint
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ItemCount {
+    pub count: i64,
+}
+
+// Response body for reconciling the stored item count: the true count, and how far the counter was off
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CountReconciliation {
+    pub count: i64,
+    pub drift: i64,
+}
+
+// Outcome of a CSV import: rows inserted, and the rows skipped with why
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ImportSummary {
+    pub inserted: u64,
+    pub errors: Vec<ImportRowError>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ImportRowError {
+    pub line: u64,
+    pub reason: String,
+}
+
+// Response body for a successful delete
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ItemDeleted {
+    pub deleted: bool,
+    pub id: Uuid,
+}
+
+// Request body for fetching several items by id in one call
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ItemBatchGetRequest {
+    pub ids: Vec<Uuid>,
+}
+
+// Request body assigning tags to an item; tags it doesn't carry yet are added, unknown ones created
+#[derive(Debug, Deserialize, ToSchema, Validate)]
+pub struct ItemTagsRequest {
+    #[validate(length(min = 1, max = MAX_TAGS_PER_REQUEST), custom(function = "tag_names"))]
+    pub tags: Vec<String>,
+}
+
+// Request body for the bulk delete endpoint
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ItemDeleteBatchRequest {
+    pub ids: Vec<Uuid>,
+}
+
+// One entry of a reorder request: an item and the position it moves to
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ItemPosition {
+    pub id: Uuid,
+    pub position: i32,
+}
+
+// Query parameter choosing how POST /items/batch treats failing rows: `atomic` (the default)
+// rolls back the whole batch, `best_effort` keeps the rows that succeed
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct BatchModeParam {
+    pub mode: Option<String>,
+}
+
+impl BatchModeParam {
+    pub fn best_effort(&self) -> Result<bool, ApiError> {
+        match self.mode.as_deref() {
+            None | Some("atomic") => Ok(false),
+            Some("best_effort") => Ok(true),
+            Some(other) => Err(ApiError::Validation(format!("Unknown batch mode '{}'; expected atomic or best_effort", other))),
+        }
+    }
+}
+
+// A row of a best-effort batch that was not created, and why
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BatchFailure {
+    pub index: usize,
+    pub reason: String,
+}
+
+// Response body for a best-effort batch create
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BatchCreateReport {
+    pub created: Vec<Item>,
+    pub failed: Vec<BatchFailure>,
+}
+
+// Response body for the bulk delete endpoint
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DeletedCount {
+    pub deleted: u64,
+}
+
+// Response body for moving items between categories
+#[derive(Debug, Serialize, ToSchema)]
+pub struct MovedCount {
+    pub moved: u64,
+}
+
+// One row of the per-category item counts; a null category_id counts the uncategorized items
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CategoryCount {
+    pub category_id: Option<Uuid>,
+    pub count: i64,
+}
+
+// The most and least recently created live items; both null when there are none, and the same item
+// when there is only one
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ItemBounds {
+    pub newest: Option<Item>,
+    pub oldest: Option<Item>,
+}
+
+// Query parameter limiting the per-category counts to items created after an RFC 3339 instant
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ItemStatsParams {
+    pub since: Option<DateTime<Utc>>,
+}
+
+// Query parameters for wiping every item; `confirm=true` is mandatory
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TruncateParams {
+    pub confirm: Option<bool>,
+}
+
+// Query parameters of POST /admin/seed: how many items to generate, and the RNG seed so a run can be repeated
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SeedParams {
+    pub count: Option<usize>,
+    pub seed: Option<u64>,
+}
+
+// Query parameter asking a create or update to be checked and rolled back instead of committed
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DryRunParam {
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+impl DryRunParam {
+    // A dry run via `?dry_run=true` or a `Prefer: dry-run` header
+    pub fn requested(&self, req: &HttpRequest) -> bool {
+        self.dry_run
+            || req
+                .headers()
+                .get_all("prefer")
+                .filter_map(|prefer| prefer.to_str().ok())
+                .flat_map(|prefer| prefer.split(','))
+                .any(|preference| preference.split(';').next().unwrap_or("").trim().eq_ignore_ascii_case("dry-run"))
+    }
+}
+
+// Query parameter asking a create to return the live item with the same name and description, when
+// there is one, instead of refusing the name as taken
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DedupeParam {
+    #[serde(default)]
+    pub dedupe: bool,
+}
+
+// Query parameter asking an update to report the fields it changed alongside the item
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DiffParam {
+    #[serde(default)]
+    pub diff: bool,
+}
+
+// A field's value before and after an update
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FieldChange {
+    #[schema(value_type = Object)]
+    pub from: Value,
+    #[schema(value_type = Object)]
+    pub to: Value,
+}
+
+// An updated item and the fields the update changed, as returned with diff=true
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ItemDiff {
+    pub item: Item,
+    pub changes: BTreeMap<String, FieldChange>,
+}
+
+impl ItemDiff {
+    // Set by every write, so listing them would say nothing about what the request changed
+    const BOOKKEEPING_FIELDS: [&'static str; 3] = ["version", "updated_at", "updated_by"];
+
+    // The changes from `old` to `item`; with no old item, as on a create, every non-null field is new
+    pub fn between(old: Option<&Item>, item: Item) -> Self {
+        let before = old.map(|old| serde_json::json!(old));
+        let Value::Object(after) = serde_json::json!(item) else {
+            unreachable!("items serialize to objects");
+        };
+        let changes = after
+            .into_iter()
+            .filter(|(field, _)| !Self::BOOKKEEPING_FIELDS.contains(&field.as_str()))
+            .filter_map(|(field, to)| {
+                let from = before.as_ref().and_then(|before| before.get(&field)).cloned().unwrap_or(Value::Null);
+                (from != to).then_some((field, FieldChange { from, to }))
+            })
+            .collect();
+        ItemDiff { item, changes }
+    }
+}
+
+// A named group of items
+#[derive(Debug, Serialize, sqlx::FromRow, ToSchema)]
+pub struct Category {
+    pub id: Uuid,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize, ToSchema, Validate)]
+pub struct CategoryCreateRequest {
+    #[validate(custom(function = "not_blank"), length(max = MAX_NAME_LEN))]
+    pub name: String,
+}
+
+// Response body for the pool statistics endpoint
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PoolStats {
+    pub size: u32,
+    pub idle: usize,
+    pub max: u32,
+}
+
+// Whether maintenance mode is on; the request body for switching it and the response for reading it
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct MaintenanceStatus {
+    pub enabled: bool,
+}
+
+// Whether the instance is draining, as POST /admin/drain and /admin/undrain leave it
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DrainStatus {
+    pub draining: bool,
+}
+
+// A feature flag and its current value, as listed by GET /admin/flags
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FeatureFlag {
+    pub name: String,
+    pub enabled: bool,
+}
+
+// The request body for switching a feature flag
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct FeatureFlagUpdate {
+    pub enabled: bool,
+}
+
+// Result of refreshing the planner statistics of the items table
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AnalyzeReport {
+    pub table: String,
+    pub started_at: DateTime<Utc>,
+    pub elapsed_ms: u64,
+}
+
+// Result of seeding demo items, with the seed that generated them
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SeedReport {
+    pub created: u64,
+    pub seed: u64,
+}
+
+// A link to the CSV export that needs no credentials until it expires
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ExportLink {
+    pub url: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+// Response body for the health check
+#[derive(Debug, Serialize, ToSchema)]
+pub struct HealthStatus {
+    pub status: &'static str,
+}
+
+// Response body for GET /health/migrations: up_to_date or pending, and how many have not run
+#[derive(Debug, Serialize, ToSchema)]
+pub struct MigrationStatus {
+    pub migrations: &'static str,
+    pub pending_count: usize,
+}
+
+// Response body for GET /version, fixed at compile time
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub git_sha: &'static str,
+    pub built_at: &'static str,
+}
+
+// Request structs; field rules are declared with `validator` attributes and checked by the handlers
+#[derive(Debug, Clone, Deserialize, ToSchema, Validate)]
+pub struct ItemCreateRequest {
+    #[validate(custom(function = "not_blank"), length(max = MAX_NAME_LEN))]
+    pub name: String,
+    #[validate(length(max = MAX_DESCRIPTION_LEN))]
+    pub description: Option<String>,
+    pub category_id: Option<Uuid>,
+    #[validate(custom(function = "json_object"))]
+    #[schema(value_type = Option<Object>)]
+    pub metadata: Option<Value>,
+    #[validate(custom(function = "valid_price"))]
+    pub price: Option<Decimal>,
+}
+
+impl ItemCreateRequest {
+    // SHA-256 over the request fields, used to tell replays from key reuse
+    pub fn fingerprint(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.name.as_bytes());
+        hasher.update([0]);
+        // 0xff never occurs in UTF-8, so no description hashes apart from an empty one
+        match &self.description {
+            Some(description) => hasher.update(description.as_bytes()),
+            None => hasher.update([0xff]),
+        }
+        hasher.update([0]);
+        if let Some(category_id) = self.category_id {
+            hasher.update(category_id.as_bytes());
+        }
+        hasher.update([0]);
+        // Objects serialize with sorted keys, so key order in the request doesn't matter
+        if let Some(metadata) = &self.metadata {
+            hasher.update(metadata.to_string().as_bytes());
+        }
+        hasher.update([0]);
+        // Normalized so 5, 5.0 and 5.00 are the same price
+        if let Some(price) = self.price {
+            hasher.update(price.normalize().to_string().as_bytes());
+        }
+        hex::encode(hasher.finalize())
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema, Validate)]
+pub struct ItemUpdateRequest {
+    #[validate(custom(function = "not_blank"), length(max = MAX_NAME_LEN))]
+    pub name: String,
+    #[validate(length(max = MAX_DESCRIPTION_LEN))]
+    pub description: Option<String>,
+    pub category_id: Option<Uuid>,
+    #[validate(custom(function = "json_object"))]
+    #[schema(value_type = Option<Object>)]
+    pub metadata: Option<Value>,
+    #[validate(custom(function = "valid_price"))]
+    pub price: Option<Decimal>,
+    pub version: Option<i32>,
+}
+
+// A new description sent as plain text to PUT /items/{id}/description, held to the same rule as elsewhere
+#[derive(Debug, Validate)]
+pub struct ItemDescription {
+    #[validate(length(max = MAX_DESCRIPTION_LEN))]
+    pub description: String,
+}
+
+// A JSON Merge Patch (RFC 7386) for an item: absent keys are left unchanged and null clears a field.
+// name cannot be cleared, and metadata is replaced as a whole rather than merged into.
+#[derive(Debug, Clone, ToSchema, Validate)]
+pub struct ItemPatchRequest {
+    #[validate(custom(function = "not_blank"), length(max = MAX_NAME_LEN))]
+    pub name: Option<String>,
+    // Some(None) clears the description
+    #[validate(length(max = MAX_DESCRIPTION_LEN))]
+    #[schema(value_type = Option<String>)]
+    pub description: Option<Option<String>>,
+    // Some(None) clears the category
+    #[schema(value_type = Option<Uuid>)]
+    pub category_id: Option<Option<Uuid>>,
+    // Some(None) clears the metadata
+    #[validate(custom(function = "json_object"))]
+    #[schema(value_type = Option<Object>)]
+    pub metadata: Option<Option<Value>>,
+    // Some(None) clears the price
+    #[validate(custom(function = "valid_price"))]
+    #[schema(value_type = Option<String>)]
+    pub price: Option<Option<Decimal>>,
+    pub version: Option<i32>,
+}
+
+impl ItemPatchRequest {
+    // Read a merge patch document, keeping absent keys apart from explicit nulls
+    pub fn from_merge_patch(body: Value) -> Result<Self, ApiError> {
+        let Value::Object(fields) = body else {
+            return Err(ApiError::Validation("Patch body must be a JSON object".to_string()));
+        };
+        let mut patch = ItemPatchRequest { name: None, description: None, category_id: None, metadata: None, price: None, version: None };
+        for (key, value) in fields {
+            match key.as_str() {
+                "name" => {
+                    let name = patch_field("name", value)?;
+                    patch.name = Some(name.ok_or_else(|| ApiError::Validation("name cannot be null".to_string()))?);
+                }
+                "description" => patch.description = Some(patch_field("description", value)?),
+                "category_id" => patch.category_id = Some(patch_field("category_id", value)?),
+                "metadata" => patch.metadata = Some(patch_field("metadata", value)?),
+                "price" => patch.price = Some(patch_field("price", value)?),
+                "version" => patch.version = patch_field("version", value)?,
+                _ => {}
+            }
+        }
+        Ok(patch)
+    }
+
+    // A patch that changes nothing is a malformed request rather than an invalid field
+    pub fn require_changes(&self) -> Result<(), ApiError> {
+        if self.name.is_none() && self.description.is_none() && self.category_id.is_none() && self.metadata.is_none() && self.price.is_none() {
+            return Err(ApiError::Validation("Patch body must contain at least one field".to_string()));
+        }
+        Ok(())
+    }
+}
+
+// Decode one merge patch member, mapping null to None
+fn patch_field<T: DeserializeOwned>(name: &str, value: Value) -> Result<Option<T>, ApiError> {
+    serde_json::from_value(value).map_err(|err| ApiError::Validation(format!("{} is invalid: {}", name, err)))
+}
+
+// Character limits referenced by the field rules above
+pub const MAX_NAME_LEN: u64 = 200;
+const MAX_DESCRIPTION_LEN: u64 = 10_000;
+
+// Rejects names made only of whitespace, which `length(min = 1)` would let through
+fn not_blank(value: &str) -> Result<(), ValidationError> {
+    if value.trim().is_empty() {
+        return Err(ValidationError::new("blank").with_message("must not be empty".into()));
+    }
+    Ok(())
+}
+
+const MAX_TAGS_PER_REQUEST: u64 = 50;
+const MAX_TAG_LEN: usize = 64;
+
+// Tag names as they are stored and matched: trimmed and lowercased
+pub fn normalize_tag(tag: &str) -> String {
+    tag.trim().to_lowercase()
+}
+
+fn tag_names(tags: &[String]) -> Result<(), ValidationError> {
+    for tag in tags.iter().map(|tag| normalize_tag(tag)) {
+        if tag.is_empty() {
+            return Err(ValidationError::new("blank").with_message("must not contain empty tags".into()));
+        }
+        if tag.chars().count() > MAX_TAG_LEN {
+            return Err(ValidationError::new("length").with_message(format!("must not contain tags longer than {} characters", MAX_TAG_LEN).into()));
+        }
+    }
+    Ok(())
+}
+
+// Prices are non-negative and in whole cents, which the column would otherwise round to silently
+fn valid_price(value: &Decimal) -> Result<(), ValidationError> {
+    if value.is_sign_negative() && !value.is_zero() {
+        return Err(ValidationError::new("range").with_message("must not be negative".into()));
+    }
+    if value.normalize().scale() > 2 {
+        return Err(ValidationError::new("scale").with_message("must have at most 2 decimal places".into()));
+    }
+    // The most NUMERIC(12, 2) holds
+    let max = Decimal::new(999_999_999_999, 2);
+    if *value > max {
+        return Err(ValidationError::new("range").with_message(format!("must be at most {}", max).into()));
+    }
+    Ok(())
+}
+
+// Metadata must be an object so its top-level keys can be filtered on
+fn json_object(value: &Value) -> Result<(), ValidationError> {
+    if !value.is_object() {
+        return Err(ValidationError::new("object").with_message("must be a JSON object".into()));
+    }
+    Ok(())
+}
+
+// Query parameters for paginating list results
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct Pagination {
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+}
+
+// How many items a list page holds, from DEFAULT_PAGE_SIZE and MAX_PAGE_SIZE. A page is as large as
+// the request's per_page or limit asks, else the default; either way never larger than the max.
+// A larger request is clamped to the max rather than refused, and marked by X-Page-Size-Clamped.
+#[derive(Debug, Clone, Copy)]
+pub struct PageSizes {
+    pub default: i64,
+    pub max: i64,
+}
+
+impl Default for PageSizes {
+    fn default() -> Self {
+        PageSizes { default: 20, max: 100 }
+    }
+}
+
+impl PageSizes {
+    pub fn from_env(env: &mut EnvReader) -> Self {
+        let defaults = PageSizes::default();
+        let sizes = PageSizes { default: env.parse_or("DEFAULT_PAGE_SIZE", defaults.default), max: env.parse_or("MAX_PAGE_SIZE", defaults.max) };
+        if sizes.default < 1 || sizes.max < 1 {
+            env.problem("DEFAULT_PAGE_SIZE and MAX_PAGE_SIZE must be at least 1");
+        } else if sizes.default > sizes.max {
+            env.problem(format!("DEFAULT_PAGE_SIZE ({}) must not exceed MAX_PAGE_SIZE ({})", sizes.default, sizes.max));
+        }
+        sizes
+    }
+
+    // The size of a page `requested` items long, or of a default page when None
+    fn resolve(&self, requested: Option<i64>) -> i64 {
+        requested.unwrap_or(self.default).min(self.max)
+    }
+
+    // Whether `requested` asked for more than a page may hold
+    pub fn clamps(&self, requested: Option<i64>) -> bool {
+        requested.is_some_and(|requested| requested > self.max)
+    }
+}
+
+impl Pagination {
+    // Translate page/per_page into a LIMIT/OFFSET pair, clamping per_page to the largest page size
+    pub fn limit_offset(&self, sizes: &PageSizes) -> Result<(i64, i64), &'static str> {
+        let page = self.page.unwrap_or(1);
+        if page < 1 {
+            return Err("page must be a positive integer");
+        }
+        if self.per_page.is_some_and(|per_page| per_page < 1) {
+            return Err("per_page must be a positive integer");
+        }
+        let limit = sizes.resolve(self.per_page);
+        Ok((limit, (page - 1).saturating_mul(limit)))
+    }
+}
+
+// Every query parameter group accepted by the list endpoint, parsed from the one query string
+#[derive(Debug)]
+pub struct ListParams {
+    pub pagination: Pagination,
+    pub filter: ItemFilter,
+    pub visibility: Visibility,
+    pub sorting: Sorting,
+    pub keyset: KeysetPagination,
+    pub envelope: EnvelopeParam,
+    pub fields: FieldSelection,
+    pub stream: StreamParam,
+}
+
+impl ListParams {
+    fn from_query(query: &str) -> Result<Self, ApiError> {
+        let mut filter: ItemFilter = parse_query(query)?;
+        filter.metadata = metadata_filters(query)?;
+        Ok(ListParams {
+            pagination: parse_query(query)?,
+            filter,
+            visibility: parse_query(query)?,
+            sorting: parse_query(query)?,
+            keyset: parse_query(query)?,
+            envelope: parse_query(query)?,
+            fields: parse_query(query)?,
+            stream: parse_query(query)?,
+        })
+    }
+}
+
+impl FromRequest for ListParams {
+    type Error = ApiError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(ListParams::from_query(req.query_string()))
+    }
+}
+
+// The query parameter groups accepted when fetching a single item
+#[derive(Debug)]
+pub struct ItemParams {
+    pub visibility: Visibility,
+    pub fields: FieldSelection,
+}
+
+impl ItemParams {
+    fn from_query(query: &str) -> Result<Self, ApiError> {
+        Ok(ItemParams { visibility: parse_query(query)?, fields: parse_query(query)? })
+    }
+}
+
+impl FromRequest for ItemParams {
+    type Error = ApiError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(ItemParams::from_query(req.query_string()))
+    }
+}
+
+// Longest metadata key a filter may name
+const MAX_METADATA_KEY_LEN: usize = 64;
+
+// The `metadata.<key>=<value>` parameters in `query`. Keys are bound as query parameters, but are
+// still held to letters, digits, `_` and `-` so nothing but a plain key reaches the database.
+fn metadata_filters(query: &str) -> Result<Vec<(String, String)>, ApiError> {
+    let pairs: Vec<(String, String)> = parse_query(query)?;
+    pairs
+        .into_iter()
+        .filter_map(|(name, value)| name.strip_prefix("metadata.").map(|key| (key.to_string(), value)))
+        .map(|(key, value)| {
+            let valid = !key.is_empty()
+                && key.len() <= MAX_METADATA_KEY_LEN
+                && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+            if !valid {
+                return Err(ApiError::Validation(format!(
+                    "Invalid metadata key '{}': use up to {} letters, digits, '_' or '-'",
+                    key, MAX_METADATA_KEY_LEN
+                )));
+            }
+            Ok((key, value))
+        })
+        .collect()
+}
+
+// Deserialize one parameter group, reporting bad values in the standard error body
+fn parse_query<T: DeserializeOwned>(query: &str) -> Result<T, ApiError> {
+    web::Query::<T>::from_query(query)
+        .map(web::Query::into_inner)
+        .map_err(|err| ApiError::Validation(format!("Invalid query string: {}", err)))
+}
+
+// List body used instead of a bare array when the client asks for an envelope
+#[derive(Debug, Serialize)]
+pub struct ListResponse<T> {
+    pub data: Vec<T>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meta: Option<ListMeta>,
+}
+
+// Offset pagination details for an enveloped list
+#[derive(Debug, Serialize)]
+pub struct ListMeta {
+    pub total: i64,
+    pub page: i64,
+    pub per_page: i64,
+}
+
+const ENVELOPE_MEDIA_TYPE: &str = "application/vnd.api+json";
+
+// Query parameter asking for every matching item in one streamed JSON array instead of a page
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct StreamParam {
+    #[serde(default)]
+    pub stream: bool,
+}
+
+// Query parameter opting into the list envelope
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct EnvelopeParam {
+    #[serde(default)]
+    pub envelope: bool,
+}
+
+impl EnvelopeParam {
+    // Enveloped via `?envelope=true` or an Accept header naming the envelope media type
+    pub fn requested(&self, req: &HttpRequest) -> bool {
+        self.envelope
+            || req
+                .headers()
+                .get(header::ACCEPT)
+                .and_then(|accept| accept.to_str().ok())
+                .is_some_and(|accept| accept.contains(ENVELOPE_MEDIA_TYPE))
+    }
+}
+
+// Query parameters for keyset (cursor) pagination
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct KeysetPagination {
+    pub after: Option<String>,
+    pub limit: Option<i64>,
+}
+
+impl KeysetPagination {
+    pub fn is_requested(&self) -> bool {
+        self.after.is_some() || self.limit.is_some()
+    }
+
+    pub fn limit(&self, sizes: &PageSizes) -> Result<i64, ApiError> {
+        if self.limit.is_some_and(|limit| limit < 1) {
+            return Err(ApiError::Validation("limit must be a positive integer".to_string()));
+        }
+        Ok(sizes.resolve(self.limit))
+    }
+}
+
+// Position of the last row a client has seen, handed out as an opaque base64 token
+#[derive(Debug)]
+pub struct Cursor {
+    pub created_at: DateTime<Utc>,
+    pub id: Uuid,
+}
+
+impl Cursor {
+    pub fn for_item(item: &Item) -> Self {
+        Cursor { created_at: item.created_at, id: item.id }
+    }
+
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(format!("{}|{}", self.created_at.to_rfc3339(), self.id))
+    }
+
+    pub fn decode(token: &str) -> Result<Self, ApiError> {
+        let invalid = || ApiError::Validation("Malformed cursor".to_string());
+        let decoded = URL_SAFE_NO_PAD.decode(token).map_err(|_| invalid())?;
+        let decoded = String::from_utf8(decoded).map_err(|_| invalid())?;
+        let (created_at, id) = decoded.split_once('|').ok_or_else(invalid)?;
+        Ok(Cursor {
+            created_at: DateTime::parse_from_rfc3339(created_at).map_err(|_| invalid())?.with_timezone(&Utc),
+            id: Uuid::parse_str(id).map_err(|_| invalid())?,
+        })
+    }
+}
+
+// Query parameters for filtering list results
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ItemFilter {
+    pub q: Option<String>,
+    // Full-text search over name and description, ranked by relevance
+    pub search: Option<String>,
+    pub category_id: Option<Uuid>,
+    // Incremental sync: only items changed after this RFC 3339 instant, soft-deleted and archived ones included
+    pub updated_since: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub include_archived: bool,
+    // Typo-tolerant trigram match on name, scored and ordered by similarity
+    pub fuzzy: Option<String>,
+    // Lowest similarity, from 0 to 1, a fuzzy match may have
+    #[serde(default, deserialize_with = "similarity_threshold")]
+    pub min_sim: Option<f32>,
+    // `metadata.<key>=<value>` pairs, each matching items whose metadata has that top-level key
+    // with that value as text. Only the list endpoint reads them, see metadata_filters.
+    #[serde(skip)]
+    pub metadata: Vec<(String, String)>,
+    // Only items carrying this tag, matched ignoring case
+    pub tag: Option<String>,
+}
+
+// pg_trgm's own default similarity threshold
+const DEFAULT_MIN_SIMILARITY: f32 = 0.3;
+
+// Reject a min_sim outside the range pg_trgm similarities take
+fn similarity_threshold<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<f32>, D::Error> {
+    let threshold = f32::deserialize(deserializer)?;
+    if !(0.0..=1.0).contains(&threshold) {
+        return Err(de::Error::custom("min_sim must be between 0 and 1"));
+    }
+    Ok(Some(threshold))
+}
+
+impl ItemFilter {
+    // The `q` value with LIKE wildcards escaped, or None when absent or empty
+    pub fn search_term(&self) -> Option<String> {
+        self.q.as_deref().filter(|q| !q.is_empty()).map(escape_like)
+    }
+
+    // The `search` text, or None when absent or blank
+    pub fn full_text_query(&self) -> Option<&str> {
+        self.search.as_deref().map(str::trim).filter(|search| !search.is_empty())
+    }
+
+    // The `fuzzy` term, or None when absent or blank
+    pub fn fuzzy_term(&self) -> Option<&str> {
+        self.fuzzy.as_deref().map(str::trim).filter(|fuzzy| !fuzzy.is_empty())
+    }
+
+    // The `tag` normalized as stored, or None when absent or blank
+    pub fn tag_name(&self) -> Option<String> {
+        self.tag.as_deref().map(normalize_tag).filter(|tag| !tag.is_empty())
+    }
+
+    pub fn min_similarity(&self) -> f32 {
+        self.min_sim.unwrap_or(DEFAULT_MIN_SIMILARITY)
+    }
+
+    // Whether every item a plain listing shows matches, so the stored live count answers a count
+    pub fn is_unfiltered(&self) -> bool {
+        self.search_term().is_none()
+            && self.full_text_query().is_none()
+            && self.fuzzy_term().is_none()
+            && self.category_id.is_none()
+            && self.metadata.is_empty()
+            && self.tag_name().is_none()
+            && self.updated_since.is_none()
+            && !self.include_archived
+    }
+}
+
+// Item fields a client may select with `?fields=`
+const ITEM_FIELDS: &[&str] = &["id", "name", "slug", "description", "created_at", "updated_at", "version", "deleted_at", "category_id", "created_by", "updated_by", "archived", "position", "metadata", "price", "tags"];
+
+// Named projection standing in for the fields compact clients usually want
+const SUMMARY_FIELDS: &[&str] = &["id", "name"];
+
+// Query parameter selecting a subset of item fields, e.g. `fields=id,name` or `fields=summary`
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct FieldSelection {
+    pub fields: Option<String>,
+}
+
+impl FieldSelection {
+    // The requested fields checked against the allowlist, or None when every field should be returned
+    pub fn resolve(&self) -> Result<Option<Vec<&'static str>>, ApiError> {
+        let Some(fields) = &self.fields else {
+            return Ok(None);
+        };
+        let mut selected: Vec<&'static str> = Vec::new();
+        for field in fields.split(',').map(str::trim).filter(|field| !field.is_empty()) {
+            let names = match field {
+                "summary" => SUMMARY_FIELDS,
+                _ => match ITEM_FIELDS.iter().find(|name| **name == field) {
+                    Some(name) => std::slice::from_ref(name),
+                    None => {
+                        return Err(ApiError::Validation(format!(
+                            "Unknown field '{}'; expected summary or any of: {}",
+                            field,
+                            ITEM_FIELDS.join(", ")
+                        )))
+                    }
+                },
+            };
+            for name in names {
+                if !selected.contains(name) {
+                    selected.push(name);
+                }
+            }
+        }
+        if selected.is_empty() {
+            return Err(ApiError::Validation("fields must name at least one field".to_string()));
+        }
+        Ok(Some(selected))
+    }
+}
+
+// The item as a JSON object holding only the selected fields
+pub fn project_item(item: &Item, fields: &[&str]) -> serde_json::Value {
+    let mut value = serde_json::to_value(item).expect("items serialize");
+    if let serde_json::Value::Object(object) = &mut value {
+        object.retain(|key, _| fields.contains(&key.as_str()));
+    }
+    value
+}
+
+// An item in a list response, whole or cut down to the selected fields
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum ItemView {
+    Full(Box<Item>),
+    Projected(serde_json::Value),
+}
+
+// A listed row with its full-text relevance and name similarity, each NULL unless the list was
+// searched or fuzzy-matched
+#[derive(Debug, sqlx::FromRow)]
+pub struct SearchRow {
+    #[sqlx(flatten)]
+    pub item: Item,
+    pub rank: Option<f32>,
+    pub similarity: Option<f32>,
+}
+
+// An enveloped search result: the item plus how well it matched
+#[derive(Debug, Serialize)]
+pub struct Ranked<T> {
+    #[serde(flatten)]
+    pub item: T,
+    pub rank: f32,
+}
+
+// A fuzzy match: the item plus how similar its name is to the term
+#[derive(Debug, Serialize)]
+pub struct Scored<T> {
+    #[serde(flatten)]
+    pub item: T,
+    pub similarity: f32,
+}
+
+pub fn project_items(items: Vec<Item>, fields: Option<&[&str]>) -> Vec<ItemView> {
+    items
+        .into_iter()
+        .map(|item| match fields {
+            Some(fields) => ItemView::Projected(project_item(&item, fields)),
+            None => ItemView::Full(Box::new(item)),
+        })
+        .collect()
+}
+
+// Query parameter opting into soft-deleted items
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct Visibility {
+    #[serde(default)]
+    pub include_deleted: bool,
+}
+
+// Query parameters for ordering list results
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct Sorting {
+    pub sort: Option<String>,
+    pub order: Option<String>,
+}
+
+impl Sorting {
+    // Resolve sort/order against the allowlist, defaulting to newest first, or to ascending for position
+    pub fn order_by(&self) -> Result<(&'static str, &'static str), String> {
+        let column = match self.sort.as_deref() {
+            None | Some("created_at") => "created_at",
+            Some("name") => "name",
+            Some("position") => "position",
+            Some(other) => return Err(format!("Cannot sort by '{}'; expected one of: name, created_at, position", other)),
+        };
+        let direction = match self.order.as_deref() {
+            // A manual order reads top to bottom
+            None if column == "position" => "ASC",
+            None => "DESC",
+            Some(order) if order.eq_ignore_ascii_case("desc") => "DESC",
+            Some(order) if order.eq_ignore_ascii_case("asc") => "ASC",
+            Some(other) => return Err(format!("Invalid order '{}'; expected asc or desc", other)),
+        };
+        Ok((column, direction))
+    }
+}
+
+// Escape LIKE/ILIKE metacharacters so user input matches literally
+pub fn escape_like(input: &str) -> String {
+    let mut escaped = String::with_capacity(input.len());
+    for c in input.chars() {
+        if matches!(c, '\\' | '%' | '_') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
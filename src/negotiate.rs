@@ -0,0 +1,249 @@
+use actix_web::dev::Payload;
+use actix_web::http::header::{self, Accept, ContentType, Header};
+use actix_web::http::StatusCode;
+use actix_web::{
+    web, Error, FromRequest, HttpMessage, HttpRequest, HttpResponse, HttpResponseBuilder,
+    ResponseError,
+};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::future::{ready, Future, Ready};
+use std::ops::Deref;
+use std::pin::Pin;
+
+use crate::config::EnvReader;
+use crate::error::ApiError;
+
+const MSGPACK_CONTENT_TYPE: &str = "application/msgpack";
+const FORM_CONTENT_TYPE: &str = "application/x-www-form-urlencoded";
+
+// How JSON bodies are written: indented when PRETTY_JSON=true, and with camelCase field names
+// when JSON_CAMEL_CASE=true, in which case request bodies may use either convention.
+// Both are off by default, leaving responses compact and snake_case.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct JsonStyle {
+    pub pretty: bool,
+    pub camel_case: bool,
+}
+
+impl JsonStyle {
+    pub fn from_env(env: &mut EnvReader) -> Self {
+        JsonStyle { pretty: env.parse_or("PRETTY_JSON", false), camel_case: env.parse_or("JSON_CAMEL_CASE", false) }
+    }
+
+    // The style configured for the app, or the default when none is registered
+    fn of(req: &HttpRequest) -> Self {
+        req.app_data::<web::Data<JsonStyle>>().map(|style| *style.get_ref()).unwrap_or_default()
+    }
+}
+
+// Wire formats handlers can speak, chosen per request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json(JsonStyle),
+    MessagePack,
+}
+
+impl Format {
+    // The first format the Accept header ranks that we support; JSON for anything else
+    fn from_accept(req: &HttpRequest) -> Self {
+        let json = Format::Json(JsonStyle::of(req));
+        let Ok(accept) = Accept::parse(req) else {
+            return json;
+        };
+        for mime in accept.ranked() {
+            match (mime.type_().as_str(), mime.subtype().as_str()) {
+                ("application", "msgpack" | "x-msgpack") => return Format::MessagePack,
+                ("application", "json") | ("application" | "*", "*") => return json,
+                _ => {}
+            }
+        }
+        json
+    }
+
+    // The format of the request body, from its Content-Type
+    fn from_content_type(req: &HttpRequest) -> Self {
+        match req.content_type() {
+            "application/msgpack" | "application/x-msgpack" => Format::MessagePack,
+            _ => Format::Json(JsonStyle::of(req)),
+        }
+    }
+
+    // Finish `response` with `body` serialized in this format and the matching Content-Type.
+    // Vary: Accept keeps caches from serving one format to a client that asked for the other.
+    pub fn respond<T: Serialize>(self, response: &mut HttpResponseBuilder, body: &T) -> HttpResponse {
+        response.append_header((header::VARY, "accept"));
+        match self {
+            Format::Json(JsonStyle { pretty: false, camel_case: false }) => response.json(body),
+            Format::Json(style) => match to_styled_json(body, style) {
+                Ok(text) => response.insert_header(ContentType::json()).body(text),
+                Err(_) => ApiError::Internal.error_response(),
+            },
+            Format::MessagePack => match to_msgpack(body) {
+                Ok(bytes) => response.content_type(MSGPACK_CONTENT_TYPE).body(bytes),
+                Err(_) => ApiError::Internal.error_response(),
+            },
+        }
+    }
+}
+
+fn to_styled_json<T: Serialize>(body: &T, style: JsonStyle) -> Result<String, serde_json::Error> {
+    let mut value = serde_json::to_value(body)?;
+    if style.camel_case {
+        value = rename_keys(value, camel_case);
+    }
+    if style.pretty {
+        serde_json::to_string_pretty(&value)
+    } else {
+        serde_json::to_string(&value)
+    }
+}
+
+// Rename the keys of every object in `value`, however deeply nested
+fn rename_keys(value: Value, rename: fn(&str) -> String) -> Value {
+    match value {
+        Value::Object(object) => Value::Object(object.into_iter().map(|(key, value)| (rename(&key), rename_keys(value, rename))).collect()),
+        Value::Array(values) => Value::Array(values.into_iter().map(|value| rename_keys(value, rename)).collect()),
+        value => value,
+    }
+}
+
+// created_at -> createdAt
+fn camel_case(key: &str) -> String {
+    let mut renamed = String::with_capacity(key.len());
+    let mut upper = false;
+    for c in key.chars() {
+        match c {
+            '_' => upper = true,
+            c if upper => {
+                renamed.extend(c.to_uppercase());
+                upper = false;
+            }
+            c => renamed.push(c),
+        }
+    }
+    renamed
+}
+
+// createdAt -> created_at; keys already in snake_case come through unchanged
+fn snake_case(key: &str) -> String {
+    let mut renamed = String::with_capacity(key.len() + 2);
+    for c in key.chars() {
+        if c.is_ascii_uppercase() {
+            renamed.push('_');
+            renamed.push(c.to_ascii_lowercase());
+        } else {
+            renamed.push(c);
+        }
+    }
+    renamed
+}
+
+// Structs become maps keyed by field name, and ids and timestamps stay strings as they are in JSON
+fn to_msgpack<T: Serialize>(body: &T) -> Result<Vec<u8>, rmp_serde::encode::Error> {
+    let mut bytes = Vec::new();
+    body.serialize(&mut rmp_serde::Serializer::new(&mut bytes).with_struct_map().with_human_readable())?;
+    Ok(bytes)
+}
+
+// Lets handlers take the negotiated response format as an argument
+impl FromRequest for Format {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Ok(Format::from_accept(req)))
+    }
+}
+
+// A request body decoded from MessagePack when Content-Type says so, and as JSON otherwise.
+// JSON bodies go through web::Json, so JsonConfig's limit and error handler still apply;
+// MessagePack bodies are capped by PayloadConfig. With JSON_CAMEL_CASE on, camelCase keys
+// are converted to snake_case before decoding.
+#[derive(Debug)]
+pub struct Body<T>(pub T);
+
+impl<T> Body<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for Body<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: DeserializeOwned + 'static> FromRequest for Body<T> {
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        match Format::from_content_type(req) {
+            Format::Json(JsonStyle { camel_case: true, .. }) => {
+                let json = web::Json::<Value>::from_request(req, payload);
+                return Box::pin(async move {
+                    let value = rename_keys(json.await?.into_inner(), snake_case);
+                    let body = T::deserialize(value).map_err(|err| ApiError::Validation(format!("Invalid JSON body: {}", err)))?;
+                    Ok(Body(body))
+                });
+            }
+            Format::Json(_) => {
+                let json = web::Json::<T>::from_request(req, payload);
+                return Box::pin(async move { Ok(Body(json.await?.into_inner())) });
+            }
+            Format::MessagePack => {}
+        }
+
+        let bytes = web::Bytes::from_request(req, payload);
+        Box::pin(async move {
+            let bytes = bytes.await.map_err(|err| match err.as_response_error().status_code() {
+                StatusCode::PAYLOAD_TOO_LARGE => ApiError::PayloadTooLarge("Request body is too large".to_string()),
+                _ => ApiError::Validation(format!("Invalid request body: {}", err)),
+            })?;
+            let body = T::deserialize(&mut rmp_serde::Deserializer::from_read_ref(&bytes).with_human_readable())
+                .map_err(|err| ApiError::Validation(format!("Invalid MessagePack body: {}", err)))?;
+            Ok(Body(body))
+        })
+    }
+}
+
+
+// A `Body` that legacy clients may also send as an HTML form. Form bodies go through web::Form,
+// so FormConfig's limit and error handler apply, and their field names are always snake_case.
+// A Content-Type that is neither JSON, MessagePack nor a form is rejected with a 415.
+#[derive(Debug)]
+pub struct FormOrBody<T>(pub T);
+
+impl<T> Deref for FormOrBody<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: DeserializeOwned + 'static> FromRequest for FormOrBody<T> {
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let content_type = req.content_type();
+        if content_type == FORM_CONTENT_TYPE {
+            let form = web::Form::<T>::from_request(req, payload);
+            return Box::pin(async move { Ok(FormOrBody(form.await?.into_inner())) });
+        }
+        // A missing Content-Type is left to the JSON extractor, which explains what it expected
+        let json = content_type.is_empty() || content_type == "application/json" || content_type.ends_with("+json");
+        if !json && Format::from_content_type(req) != Format::MessagePack {
+            let message = format!("Unsupported Content-Type '{}'; send JSON, MessagePack or {}", content_type, FORM_CONTENT_TYPE);
+            return Box::pin(ready(Err(ApiError::UnsupportedMediaType(message).into())));
+        }
+        let body = Body::<T>::from_request(req, payload);
+        Box::pin(async move { Ok(FormOrBody(body.await?.into_inner())) })
+    }
+}
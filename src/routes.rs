@@ -0,0 +1,317 @@
+use actix_web::middleware::{from_fn, Condition, NormalizePath};
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceFactory, ServiceRequest, ServiceResponse};
+use actix_web::{web, Error, Resource, Route};
+use utoipa::openapi::server::Server;
+use utoipa::OpenApi;
+use utoipa_swagger_ui::SwaggerUi;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::config::EnvReader;
+use crate::error::{path_error_handler, query_error_handler, ErrorBody};
+use crate::handlers::{
+    self, add_item_tags, analyze_items, archive_item, create_category, create_export_link,
+    create_item, create_items_batch, delete_item, delete_items_batch, drain, duplicate_item,
+    export_items_csv, get_categories, get_flags, get_item, get_item_bounds, get_item_by_name,
+    get_item_by_slug, get_item_count, get_item_history, get_item_stats, get_items,
+    get_items_by_ids, get_maintenance, health, import_items_csv, item_events, item_exists,
+    item_socket, livez, metrics_endpoint, migration_status, move_category_items, patch_item,
+    pool_stats, put_flag, put_item_description, readyz, reconcile_item_count, remove_item_tag,
+    reorder_items, restore_item, route_not_found, seed_demo_items, set_maintenance, stream_items,
+    suggest_item_names, truncate_items, unarchive_item, undrain, update_item, update_items_batch,
+    validate_item_name, version, CATEGORIES_PATH,
+};
+use crate::middleware::{
+    catch_panics, explain_method_not_allowed, reject_writes_during_maintenance, request_timeout,
+    METRICS_PATH,
+};
+use crate::models::{
+    AnalyzeReport, BatchCreateReport, BatchFailure, BuildInfo, Category, CategoryCount,
+    CategoryCreateRequest, CountReconciliation, DeletedCount, DrainStatus, ExportLink, FeatureFlag,
+    FeatureFlagUpdate, FieldChange, HealthStatus, ImportRowError, ImportSummary, Item,
+    ItemAuditRecord, ItemBatchGetRequest, ItemBounds, ItemCount, ItemCreateRequest,
+    ItemDeleteBatchRequest, ItemDeleted, ItemDiff, ItemPatchRequest, ItemPosition, ItemTagsRequest,
+    ItemUpdateRequest, MaintenanceStatus, MigrationStatus, MovedCount, NameAvailability, PoolStats,
+    SeedReport,
+};
+
+// OpenAPI description of the API, served at /api-docs/openapi.json
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        handlers::create_item,
+        handlers::create_items_batch,
+        handlers::get_items,
+        handlers::get_item_count,
+        handlers::reconcile_item_count,
+        handlers::get_item_stats,
+        handlers::get_item_bounds,
+        handlers::validate_item_name,
+        handlers::suggest_item_names,
+        handlers::export_items_csv,
+        handlers::create_export_link,
+        handlers::stream_items,
+        handlers::import_items_csv,
+        handlers::item_events,
+        handlers::get_item,
+        handlers::get_item_by_slug,
+        handlers::get_item_by_name,
+        handlers::get_items_by_ids,
+        handlers::reorder_items,
+        handlers::item_exists,
+        handlers::update_item,
+        handlers::patch_item,
+        handlers::update_items_batch,
+        handlers::put_item_description,
+        handlers::delete_item,
+        handlers::delete_items_batch,
+        handlers::truncate_items,
+        handlers::restore_item,
+        handlers::archive_item,
+        handlers::add_item_tags,
+        handlers::remove_item_tag,
+        handlers::duplicate_item,
+        handlers::unarchive_item,
+        handlers::get_item_history,
+        handlers::create_category,
+        handlers::get_categories,
+        handlers::move_category_items,
+        handlers::health,
+        handlers::migration_status,
+        handlers::livez,
+        handlers::readyz,
+        handlers::version,
+        handlers::pool_stats,
+        handlers::get_maintenance,
+        handlers::set_maintenance,
+        handlers::drain,
+        handlers::get_flags,
+        handlers::put_flag,
+        handlers::undrain,
+        handlers::analyze_items,
+        handlers::seed_demo_items
+    ),
+    components(schemas(
+        Item,
+        ItemCreateRequest,
+        ItemUpdateRequest,
+        ItemPatchRequest,
+        ItemCount,
+        CountReconciliation,
+        CategoryCount,
+        ItemBounds,
+        NameAvailability,
+        ItemDeleted,
+        ItemDiff,
+        FieldChange,
+        ItemDeleteBatchRequest,
+        ItemPosition,
+        ItemTagsRequest,
+        ItemBatchGetRequest,
+        ItemAuditRecord,
+        DeletedCount,
+        ImportSummary,
+        ImportRowError,
+        Category,
+        CategoryCreateRequest,
+        MovedCount,
+        ErrorBody,
+        HealthStatus,
+        MigrationStatus,
+        BuildInfo,
+        PoolStats,
+        MaintenanceStatus,
+        DrainStatus,
+        FeatureFlag,
+        FeatureFlagUpdate,
+        BatchFailure,
+        BatchCreateReport,
+        AnalyzeReport,
+        SeedReport,
+        ExportLink
+    )),
+    tags(
+        (name = "items", description = "Item management"),
+        (name = "categories", description = "Item grouping"),
+        (name = "operations", description = "Health and monitoring")
+    )
+)]
+struct ApiDoc;
+
+// Routes whose budget differs from REQUEST_TIMEOUT_SECS, by path relative to the API prefix. Each
+// applies to every method on its path, and only until the response starts, so a streamed export
+// only needs time for its first rows:
+//   /items/export.csv  120s, as a large export can take a while to start
+//   /items/{id}        5s, as one item by id is never expected to be slow
+pub fn route_timeouts() -> HashMap<&'static str, Duration> {
+    HashMap::from([(EXPORT_CSV_PATH, Duration::from_secs(120)), ("/items/{id}", Duration::from_secs(5))])
+}
+
+// Cut the route's handler off with 504 after REQUEST_TIMEOUT_SECS, or the route's own budget
+fn timed(route: Route) -> Route {
+    route.wrap(from_fn(request_timeout))
+}
+
+// A timed route that changes data, refused with 503 while maintenance mode is on
+fn timed_write(route: Route) -> Route {
+    timed(route).wrap(from_fn(reject_writes_during_maintenance))
+}
+
+// Every route for one path is registered on a single resource, so a method the path has no
+// route for is answered with 405 and an Allow header rather than the 404 fallback.
+// A handler that panics is answered with a 500 rather than a dropped connection.
+fn resource(path: &str) -> Resource<impl ServiceFactory<ServiceRequest, Config = (), Response = ServiceResponse<impl MessageBody>, Error = Error, InitError = ()>> {
+    web::resource(path).wrap(from_fn(explain_method_not_allowed)).wrap(from_fn(catch_panics))
+}
+
+// Where the versioned API is mounted, from API_PREFIX, whether the old unprefixed paths
+// still answer as aliases (LEGACY_UNPREFIXED_ROUTES) while clients migrate, and whether a
+// trailing slash is ignored so `/items/` finds `/items` (TRIM_TRAILING_SLASH, on by default)
+#[derive(Debug, Clone)]
+pub struct ApiRoutes {
+    pub prefix: String,
+    pub legacy_aliases: bool,
+    pub trim_trailing_slash: bool,
+}
+
+// Mount point of the item and category routes unless API_PREFIX says otherwise
+const DEFAULT_API_PREFIX: &str = "/api/v1";
+
+impl ApiRoutes {
+    pub fn from_env(env: &mut EnvReader) -> Self {
+        ApiRoutes {
+            prefix: normalize_prefix(&env.string("API_PREFIX").unwrap_or_else(|| DEFAULT_API_PREFIX.to_string())),
+            legacy_aliases: env.parse_or("LEGACY_UNPREFIXED_ROUTES", false),
+            trim_trailing_slash: env.parse_or("TRIM_TRAILING_SLASH", true),
+        }
+    }
+
+    // Wraps the app so paths are routed without trailing slashes, and repeated slashes are merged,
+    // before any middleware looks at them
+    pub fn normalize_path(&self) -> Condition<NormalizePath> {
+        Condition::new(self.trim_trailing_slash, NormalizePath::trim())
+    }
+}
+
+impl Default for ApiRoutes {
+    fn default() -> Self {
+        ApiRoutes { prefix: DEFAULT_API_PREFIX.to_string(), legacy_aliases: false, trim_trailing_slash: true }
+    }
+}
+
+// "api/v1/" and "/api/v1" both mount at "/api/v1"; a blank prefix mounts at the root
+fn normalize_prefix(prefix: &str) -> String {
+    let prefix = prefix.trim().trim_matches('/');
+    if prefix.is_empty() {
+        String::new()
+    } else {
+        format!("/{}", prefix)
+    }
+}
+
+// The CSV export, the one path a signed export link can open
+pub const EXPORT_CSV_PATH: &str = "/items/export.csv";
+
+// The API prefix, shared with handlers so Location headers point at the prefixed paths
+#[derive(Debug, Clone)]
+pub struct ApiPrefix(pub String);
+
+// Register every route; shared by the server and tests so route definitions live in one place.
+// Query and path extractor errors are rendered here too so every mount reports them as JSON.
+// Operational endpoints stay at the root; item and category routes live under the API prefix.
+pub fn configure(cfg: &mut web::ServiceConfig, api: &ApiRoutes) {
+    let mut openapi = ApiDoc::openapi();
+    if !api.prefix.is_empty() {
+        openapi.servers = Some(vec![Server::new(&api.prefix)]);
+    }
+    cfg.app_data(web::QueryConfig::default().error_handler(query_error_handler))
+        .app_data(web::PathConfig::default().error_handler(path_error_handler))
+        .app_data(web::Data::new(ApiPrefix(api.prefix.clone())))
+        .service(SwaggerUi::new("/swagger-ui/{_:.*}").url("/api-docs/openapi.json", openapi))
+        // Trimming turns /swagger-ui/ into /swagger-ui, which the pattern above doesn't match
+        .service(web::redirect("/swagger-ui", "/swagger-ui/index.html"))
+        .service(resource("/health").route(timed(web::get().to(health))))
+        .service(resource("/health/migrations").route(timed(web::get().to(migration_status))))
+        .service(resource("/livez").route(timed(web::get().to(livez))))
+        .service(resource("/readyz").route(timed(web::get().to(readyz))))
+        .service(resource("/version").route(timed(web::get().to(version))))
+        .service(resource(METRICS_PATH).route(timed(web::get().to(metrics_endpoint))))
+        .service(resource("/admin/pool").route(timed(web::get().to(pool_stats))))
+        .service(
+            resource("/admin/maintenance")
+                .route(timed(web::get().to(get_maintenance)))
+                .route(timed(web::post().to(set_maintenance))),
+        )
+        .service(resource("/admin/drain").route(timed(web::post().to(drain))))
+        .service(resource("/admin/undrain").route(timed(web::post().to(undrain))))
+        .service(resource("/admin/flags").route(timed(web::get().to(get_flags))))
+        .service(resource("/admin/flags/{name}").route(timed(web::put().to(put_flag))))
+        .service(resource("/admin/analyze").route(timed(web::post().to(analyze_items))))
+        .service(resource("/admin/seed").route(timed_write(web::post().to(seed_demo_items))));
+    if api.prefix.is_empty() {
+        cfg.configure(resource_routes);
+    } else {
+        cfg.service(web::scope(&api.prefix).configure(resource_routes));
+        if api.legacy_aliases {
+            cfg.configure(resource_routes);
+        }
+    }
+    cfg.default_service(web::to(route_not_found));
+}
+
+// The item and category routes, relative to the API prefix.
+// Every route is timed except the long-lived NDJSON export, event stream and WebSocket,
+// and every route that changes data is refused during maintenance.
+fn resource_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        resource("/items")
+            .route(timed_write(web::post().to(create_item)))
+            .route(timed(web::get().to(get_items)))
+            .route(timed_write(web::delete().to(truncate_items))),
+    )
+    .service(
+        resource("/items/batch")
+            .route(timed_write(web::post().to(create_items_batch)))
+            .route(timed_write(web::patch().to(update_items_batch))),
+    )
+    .service(resource("/items/delete-batch").route(timed_write(web::post().to(delete_items_batch))))
+    .service(resource("/items/batch-get").route(timed(web::post().to(get_items_by_ids))))
+    .service(resource("/items/count").route(timed(web::get().to(get_item_count))))
+    .service(resource("/items/stats").route(timed(web::get().to(get_item_stats))))
+    .service(resource("/items/bounds").route(timed(web::get().to(get_item_bounds))))
+    .service(resource("/items/count/reconcile").route(timed_write(web::post().to(reconcile_item_count))))
+    .service(resource("/items/validate").route(timed(web::get().to(validate_item_name))))
+    .service(resource("/items/suggest").route(timed(web::get().to(suggest_item_names))))
+    .service(resource(EXPORT_CSV_PATH).route(timed(web::get().to(export_items_csv))))
+    .service(resource("/items/export/link").route(timed(web::post().to(create_export_link))))
+    .service(resource("/items/stream").route(web::get().to(stream_items)))
+    .service(resource("/items/import").route(timed_write(web::post().to(import_items_csv))))
+    .service(resource("/items/events").route(web::get().to(item_events)))
+    .service(resource("/ws").route(web::get().to(item_socket)))
+    .service(resource("/items/reorder").route(timed_write(web::patch().to(reorder_items))))
+    .service(resource("/items/by-slug/{slug}").route(timed(web::get().to(get_item_by_slug))))
+    .service(resource("/items/by-name/{name}").route(timed(web::get().to(get_item_by_name))))
+    .service(
+        resource("/items/{id}")
+            .route(timed(web::get().to(get_item)))
+            .route(timed(web::head().to(item_exists)))
+            .route(timed_write(web::put().to(update_item)))
+            .route(timed_write(web::patch().to(patch_item)))
+            .route(timed_write(web::delete().to(delete_item))),
+    )
+    .service(resource("/items/{id}/description").route(timed_write(web::put().to(put_item_description))))
+    .service(resource("/items/{id}/restore").route(timed_write(web::post().to(restore_item))))
+    .service(resource("/items/{id}/archive").route(timed_write(web::post().to(archive_item))))
+    .service(resource("/items/{id}/tags").route(timed_write(web::post().to(add_item_tags))))
+    .service(resource("/items/{id}/tags/{tag}").route(timed_write(web::delete().to(remove_item_tag))))
+    .service(resource("/items/{id}/duplicate").route(timed_write(web::post().to(duplicate_item))))
+    .service(resource("/items/{id}/unarchive").route(timed_write(web::post().to(unarchive_item))))
+    .service(resource("/items/{id}/history").route(timed(web::get().to(get_item_history))))
+    .service(
+        resource(CATEGORIES_PATH)
+            .route(timed_write(web::post().to(create_category)))
+            .route(timed(web::get().to(get_categories))),
+    )
+    .service(resource("/categories/{from}/move-to/{to}").route(timed_write(web::post().to(move_category_items))));
+}
@@ -0,0 +1,2166 @@
+use actix_web::http::{header, Method, StatusCode};
+use actix_web::rt::time::sleep;
+use actix_web::{test, web, App};
+use futures_util::future::{join3, join_all};
+use serde_json::json;
+use sqlx::postgres::PgPoolOptions;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+use uuid::Uuid;
+
+use super::{
+    cleanup, content_type, init_app, init_app_with, init_app_with_routes, json_body, test_database_url, test_pool,
+    unique_prefix,
+};
+use crate::cache::ItemCache;
+use crate::db::{
+    self, claim_item_name, insert_item, with_transaction, FilterConnection, ReadPool, RetryPolicy,
+    TransactionRetry,
+};
+use crate::error::ApiError;
+use crate::middleware::{Metrics, DEFAULT_TENANT};
+use crate::models::{ItemCreateRequest, ItemFilter};
+use crate::routes::{self, ApiRoutes};
+use crate::DEFAULT_MAX_JSON_BODY_BYTES;
+
+#[actix_web::test]
+async fn item_crud_lifecycle() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let prefix = unique_prefix();
+    let name = format!("{}widget", prefix);
+
+    // Create
+    let req = test::TestRequest::post()
+        .uri("/api/v1/items")
+        .set_json(json!({ "name": name, "description": "first" }))
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let location = res.headers().get(header::LOCATION).expect("Location header").to_str().unwrap().to_string();
+    let created = json_body(res).await;
+    let id = created["id"].as_str().expect("id").to_string();
+    assert_eq!(location, format!("/api/v1/items/{}", id));
+    assert_eq!(created["name"], name);
+    assert_eq!(created["version"], 1);
+    // Anonymous writes have no principal to record
+    assert!(created["created_by"].is_null() && created["updated_by"].is_null());
+
+    // Read
+    let res = test::call_service(&app, test::TestRequest::get().uri(&location).to_request()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let etag = res.headers().get(header::ETAG).expect("ETag header").clone();
+    assert_eq!(json_body(res).await["description"], "first");
+
+    let req = test::TestRequest::get().uri(&location).insert_header((header::IF_NONE_MATCH, etag)).to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+
+    let res = test::call_service(&app, test::TestRequest::default().method(Method::HEAD).uri(&location).to_request()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers().get(header::CONTENT_LENGTH).unwrap(), "0");
+    assert!(test::read_body(res).await.is_empty());
+
+    // List
+    let req = test::TestRequest::get().uri(&format!("/api/v1/items?q={}", prefix)).to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers().get("X-Total-Count").unwrap(), "1");
+    let listed = json_body(res).await;
+    assert_eq!(listed.as_array().map(Vec::len), Some(1));
+    assert_eq!(listed[0]["id"], id.as_str());
+
+    // Update
+    let req = test::TestRequest::put()
+        .uri(&location)
+        .set_json(json!({ "name": name, "description": "second", "version": 1 }))
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let updated = json_body(res).await;
+    assert_eq!(updated["description"], "second");
+    assert_eq!(updated["version"], 2);
+
+    // A stale version is rejected
+    let req = test::TestRequest::put()
+        .uri(&location)
+        .set_json(json!({ "name": name, "description": "stale", "version": 1 }))
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::CONFLICT);
+
+    // Partial update
+    let req = test::TestRequest::patch()
+        .uri(&location)
+        .insert_header((header::IF_MATCH, "W/\"2\""))
+        .set_json(json!({ "description": "third" }))
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let patched = json_body(res).await;
+    assert_eq!(patched["name"], name);
+    assert_eq!(patched["description"], "third");
+    assert_eq!(patched["version"], 3);
+
+    // Delete
+    let res = test::call_service(&app, test::TestRequest::delete().uri(&location).to_request()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(content_type(&res), "application/json");
+    assert_eq!(json_body(res).await, json!({ "deleted": true, "id": id }));
+
+    let res = test::call_service(&app, test::TestRequest::get().uri(&location).to_request()).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    let res = test::call_service(&app, test::TestRequest::default().method(Method::HEAD).uri(&location).to_request()).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+    // History survives the delete, which snapshots the item as it was just before
+    let res = test::call_service(&app, test::TestRequest::get().uri(&format!("{}/history", location)).to_request()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let history = json_body(res).await;
+    let operations: Vec<_> = history.as_array().unwrap().iter().map(|record| record["operation"].as_str().unwrap()).collect();
+    assert_eq!(operations, ["create", "update", "update", "delete"]);
+    let last = &history[3]["snapshot"];
+    assert_eq!(last["description"], "third");
+    assert!(last["deleted_at"].is_null());
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn if_modified_since_is_honoured() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let prefix = unique_prefix();
+
+    let req = test::TestRequest::post().uri("/api/v1/items").set_json(json!({ "name": format!("{}dated", prefix), "description": "" })).to_request();
+    let location = test::call_service(&app, req).await.headers().get(header::LOCATION).unwrap().to_str().unwrap().to_string();
+    let res = test::call_service(&app, test::TestRequest::get().uri(&location).to_request()).await;
+    let last_modified = res.headers().get(header::LAST_MODIFIED).expect("Last-Modified header").clone();
+
+    let get = |headers: Vec<(header::HeaderName, &str)>| {
+        let mut req = test::TestRequest::get().uri(&location);
+        for header in headers {
+            req = req.insert_header(header);
+        }
+        test::call_service(&app, req.to_request())
+    };
+    let since = last_modified.to_str().unwrap();
+    let res = get(vec![(header::IF_MODIFIED_SINCE, since)]).await;
+    assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(res.headers().get(header::LAST_MODIFIED), Some(&last_modified));
+    let res = get(vec![(header::IF_MODIFIED_SINCE, "Sat, 01 Jan 2000 00:00:00 GMT")]).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    // A validator that no longer matches wins over a date that still would
+    let res = get(vec![(header::IF_NONE_MATCH, "W/\"0\""), (header::IF_MODIFIED_SINCE, since)]).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let res = get(vec![(header::IF_MODIFIED_SINCE, "yesterday")]).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn unprefixed_paths_are_aliases_only_when_enabled() {
+    let pool = test_pool().await;
+    let prefix = unique_prefix();
+
+    let app = init_app(&pool).await;
+    let res = test::call_service(&app, test::TestRequest::get().uri("/items/count").to_request()).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+    let app = init_app_with_routes(&pool, ApiRoutes { prefix: "/v2".to_string(), legacy_aliases: true, ..ApiRoutes::default() }).await;
+    let req = test::TestRequest::post().uri("/items").set_json(json!({ "name": format!("{}legacy", prefix), "description": "" })).to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let id = json_body(res).await["id"].as_str().unwrap().to_string();
+    // Location always names the prefixed path clients should move to
+    let req = test::TestRequest::post().uri("/v2/items").set_json(json!({ "name": format!("{}prefixed", prefix), "description": "" })).to_request();
+    let res = test::call_service(&app, req).await;
+    let location = res.headers().get(header::LOCATION).unwrap().to_str().unwrap().to_string();
+    assert!(location.starts_with("/v2/items/"), "{}", location);
+
+    for uri in [format!("/items/{}", id), format!("/v2/items/{}", id), location] {
+        let res = test::call_service(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK, "{}", uri);
+    }
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn unsupported_methods_are_not_allowed() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+
+    for (method, uri, allow) in [
+        (Method::PATCH, "/api/v1/items".to_string(), "POST, GET, DELETE"),
+        (Method::PUT, "/api/v1/items/count".to_string(), "GET"),
+        (Method::POST, format!("/api/v1/items/{}", uuid::Uuid::new_v4()), "GET, HEAD, PUT, PATCH, DELETE"),
+        (Method::DELETE, "/health".to_string(), "GET"),
+    ] {
+        let res = test::call_service(&app, test::TestRequest::default().method(method.clone()).uri(&uri).to_request()).await;
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED, "{} {}", method, uri);
+        assert_eq!(res.headers().get(header::ALLOW).unwrap(), allow, "{} {}", method, uri);
+        assert_eq!(json_body(res).await["error"], "method_not_allowed");
+    }
+
+    // Paths nothing is registered at are still unknown rather than disallowed
+    let res = test::call_service(&app, test::TestRequest::delete().uri("/api/v1/widgets").to_request()).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    assert_eq!(json_body(res).await["error"], "not_found");
+}
+
+#[actix_web::test]
+async fn missing_items_are_not_found() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let location = format!("/api/v1/items/{}", uuid::Uuid::new_v4());
+
+    let res = test::call_service(&app, test::TestRequest::get().uri(&location).to_request()).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    assert_eq!(json_body(res).await["error"], "not_found");
+
+    let res = test::call_service(&app, test::TestRequest::default().method(Method::HEAD).uri(&location).to_request()).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+    let req = test::TestRequest::patch()
+        .uri(&location)
+        .set_json(json!({ "description": "", "version": 1 }))
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+    let res = test::call_service(&app, test::TestRequest::delete().uri(&location).to_request()).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn database_failures_are_not_reported_as_missing() {
+    let unreachable = PgPoolOptions::new()
+        .acquire_timeout(Duration::from_millis(200))
+        .connect_lazy("postgres://postgres@127.0.0.1:1/unreachable")
+        .expect("valid database URL");
+    let app = init_app(&unreachable).await;
+    let location = format!("/api/v1/items/{}", uuid::Uuid::new_v4());
+
+    // The pool keeps retrying the connection until the acquire timeout, so an unreachable
+    // database surfaces as an exhausted pool and clients are told to back off
+    let res = test::call_service(&app, test::TestRequest::get().uri(&location).to_request()).await;
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(json_body(res).await["error"], "overloaded");
+
+    let res = test::call_service(&app, test::TestRequest::default().method(Method::HEAD).uri(&location).to_request()).await;
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+}
+
+#[actix_web::test]
+async fn put_creates_then_replaces() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let prefix = unique_prefix();
+    let id = uuid::Uuid::new_v4();
+    let location = format!("/api/v1/items/{}", id);
+
+    let put = |body: serde_json::Value| test::TestRequest::put().uri(&location).set_json(body).to_request();
+
+    let res = test::call_service(&app, put(json!({ "name": format!("{}owned", prefix), "description": "v1" }))).await;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    assert_eq!(res.headers().get(header::LOCATION).unwrap(), location.as_str());
+    let created = json_body(res).await;
+    assert_eq!(created["id"], id.to_string());
+    assert_eq!(created["version"], 1);
+
+    // Replacing an existing item still needs the expected version
+    let res = test::call_service(&app, put(json!({ "name": format!("{}owned", prefix), "description": "v2" }))).await;
+    assert_eq!(res.status(), StatusCode::PRECONDITION_REQUIRED);
+
+    let res = test::call_service(&app, put(json!({ "name": format!("{}owned", prefix), "description": "v2", "version": 1 }))).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let replaced = json_body(res).await;
+    assert_eq!(replaced["description"], "v2");
+    assert_eq!(replaced["version"], 2);
+
+    let res = test::call_service(&app, test::TestRequest::delete().uri(&location).to_request()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let res = test::call_service(&app, put(json!({ "name": format!("{}owned", prefix), "description": "v3", "version": 3 }))).await;
+    assert_eq!(res.status(), StatusCode::CONFLICT);
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn patch_follows_merge_patch_semantics() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let prefix = unique_prefix();
+
+    let req = test::TestRequest::post().uri("/api/v1/categories").set_json(json!({ "name": format!("{}tools", prefix) })).to_request();
+    let category = json_body(test::call_service(&app, req).await).await;
+    let req = test::TestRequest::post()
+        .uri("/api/v1/items")
+        .set_json(json!({ "name": format!("{}hammer", prefix), "description": "heavy", "category_id": category["id"] }))
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    let location = res.headers().get(header::LOCATION).unwrap().to_str().unwrap().to_string();
+
+    let patch = |body: serde_json::Value| {
+        test::TestRequest::patch()
+            .uri(&location)
+            .insert_header((header::CONTENT_TYPE, "application/merge-patch+json"))
+            .set_payload(body.to_string())
+            .to_request()
+    };
+
+    // Absent keys are untouched; null clears the category and the description
+    let res = test::call_service(&app, patch(json!({ "description": null, "category_id": null, "version": 1 }))).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let patched = json_body(res).await;
+    assert_eq!(patched["name"], format!("{}hammer", prefix));
+    assert!(patched["description"].is_null());
+    assert!(patched["category_id"].is_null());
+
+    for body in [json!({ "name": null, "version": 2 }), json!([{ "name": "x" }]), json!({ "version": 2 })] {
+        let res = test::call_service(&app, patch(body)).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn updates_report_changed_fields_when_asked() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let prefix = unique_prefix();
+
+    let req = test::TestRequest::post()
+        .uri("/api/v1/items")
+        .set_json(json!({ "name": format!("{}lamp", prefix), "description": "dim" }))
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    let location = res.headers().get(header::LOCATION).unwrap().to_str().unwrap().to_string();
+
+    // Without diff the response is the plain item
+    let req = test::TestRequest::patch().uri(&location).set_json(json!({ "description": "dim", "version": 1 })).to_request();
+    let plain = json_body(test::call_service(&app, req).await).await;
+    assert_eq!(plain["version"], 2);
+    assert!(plain.get("changes").is_none());
+
+    let req = test::TestRequest::patch()
+        .uri(&format!("{}?diff=true", location))
+        .set_json(json!({ "description": "bright", "version": 2 }))
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body = json_body(res).await;
+    assert_eq!(body["item"]["description"], "bright");
+    assert_eq!(body["changes"], json!({ "description": { "from": "dim", "to": "bright" } }));
+
+    let req = test::TestRequest::put()
+        .uri(&format!("{}?diff=true", location))
+        .set_json(json!({ "name": format!("{}desk lamp", prefix), "description": "bright", "version": 3 }))
+        .to_request();
+    let body = json_body(test::call_service(&app, req).await).await;
+    let changes = body["changes"].as_object().unwrap();
+    assert_eq!(changes.keys().collect::<Vec<_>>(), ["name", "slug"]);
+    assert_eq!(changes["name"]["from"], format!("{}lamp", prefix));
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn metadata_is_stored_as_sent_and_filterable() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let prefix = unique_prefix();
+
+    let metadata = json!({ "color": "red", "sizes": [1, 2], "warehouse": { "aisle": 4 } });
+    let req = test::TestRequest::post()
+        .uri("/api/v1/items")
+        .set_json(json!({ "name": format!("{}scarf", prefix), "metadata": metadata }))
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let scarf = json_body(res).await;
+    assert_eq!(scarf["metadata"], metadata);
+    let req = test::TestRequest::post().uri("/api/v1/items").set_json(json!({ "name": format!("{}hat", prefix), "metadata": { "color": "blue" } })).to_request();
+    test::call_service(&app, req).await;
+
+    let names = |query: String| {
+        let app = &app;
+        async move {
+            let res = test::call_service(app, test::TestRequest::get().uri(&query).to_request()).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            json_body(res).await.as_array().unwrap().iter().map(|item| item["name"].as_str().unwrap().to_string()).collect::<Vec<_>>()
+        }
+    };
+    assert_eq!(names(format!("/api/v1/items?q={}&metadata.color=red", prefix)).await, [format!("{}scarf", prefix)]);
+    assert!(names(format!("/api/v1/items?q={}&metadata.color=red&metadata.size=1", prefix)).await.is_empty());
+
+    for query in ["metadata.color'%3D''%20OR%201=1--=x", "metadata.=x"] {
+        let res = test::call_service(&app, test::TestRequest::get().uri(&format!("/api/v1/items?{}", query)).to_request()).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+    let req = test::TestRequest::post().uri("/api/v1/items").set_json(json!({ "name": format!("{}cap", prefix), "metadata": [1] })).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    // A merge patch replaces the metadata whole, and null clears it
+    let uri = format!("/api/v1/items/{}", scarf["id"].as_str().unwrap());
+    let req = test::TestRequest::patch().uri(&uri).set_json(json!({ "metadata": { "color": "green" }, "version": 1 })).to_request();
+    assert_eq!(json_body(test::call_service(&app, req).await).await["metadata"], json!({ "color": "green" }));
+    let req = test::TestRequest::patch().uri(&uri).set_json(json!({ "metadata": null, "version": 2 })).to_request();
+    assert!(json_body(test::call_service(&app, req).await).await["metadata"].is_null());
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn prices_round_trip_exactly() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let prefix = unique_prefix();
+    let create = |name: &str, price: serde_json::Value| {
+        test::TestRequest::post().uri("/api/v1/items").set_json(json!({ "name": format!("{}{}", prefix, name), "price": price })).to_request()
+    };
+
+    // Strings and JSON numbers are both read exactly and always sent back as strings
+    let res = test::call_service(&app, create("string", json!("19.99"))).await;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let item = json_body(res).await;
+    assert_eq!(item["price"], "19.99");
+    let res = test::call_service(&app, create("number", json!(19.99))).await;
+    assert_eq!(json_body(res).await["price"], "19.99");
+    let stored: String = sqlx::query_scalar("SELECT price::text FROM items WHERE name = $1").bind(format!("{}number", prefix)).fetch_one(&pool).await.unwrap();
+    assert_eq!(stored, "19.99");
+
+    let location = format!("/api/v1/items/{}", item["id"].as_str().unwrap());
+    let res = test::call_service(&app, test::TestRequest::get().uri(&location).to_request()).await;
+    assert_eq!(json_body(res).await["price"], "19.99");
+    let req = test::TestRequest::patch().uri(&location).set_json(json!({ "price": "0.30", "version": 1 })).to_request();
+    assert_eq!(json_body(test::call_service(&app, req).await).await["price"], "0.30");
+    let req = test::TestRequest::patch().uri(&location).set_json(json!({ "price": null, "version": 2 })).to_request();
+    assert!(json_body(test::call_service(&app, req).await).await["price"].is_null());
+
+    for (name, price) in [("negative", json!("-1.00")), ("fractional-cent", json!("0.001")), ("huge", json!("10000000000"))] {
+        let res = test::call_service(&app, create(name, price)).await;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY, "{}", name);
+        assert!(json_body(res).await["fields"]["price"].is_string());
+    }
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn concurrent_creates_of_one_name_conflict_cleanly() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let prefix = unique_prefix();
+    let name = format!("{}Twin", prefix);
+
+    // Hold the name so both creates are waiting on it at once, then let them go
+    let mut holder = pool.begin().await.unwrap();
+    claim_item_name(&mut holder, DEFAULT_TENANT, &name).await.unwrap();
+    let create = |name: String| {
+        let app = &app;
+        async move {
+            let req = test::TestRequest::post().uri("/api/v1/items").set_json(json!({ "name": name })).to_request();
+            test::call_service(app, req).await
+        }
+    };
+    let release = async {
+        sleep(Duration::from_millis(100)).await;
+        holder.rollback().await.unwrap();
+    };
+    let (first, second, ()) = join3(create(name.clone()), create(name.to_lowercase()), release).await;
+
+    let mut statuses = [first.status(), second.status()];
+    statuses.sort();
+    assert_eq!(statuses, [StatusCode::CREATED, StatusCode::CONFLICT]);
+    let loser = if first.status() == StatusCode::CONFLICT { first } else { second };
+    assert_eq!(json_body(loser).await["message"], "An item with that name already exists");
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn overlapping_retries_with_an_idempotency_key_replay_the_original() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let prefix = unique_prefix();
+    let name = format!("{}retried", prefix);
+    let key = Uuid::new_v4().to_string();
+    let create = |description: &'static str| {
+        let (app, name, key) = (&app, name.clone(), key.clone());
+        async move {
+            let req = test::TestRequest::post()
+                .uri("/api/v1/items")
+                .insert_header(("Idempotency-Key", key))
+                .set_json(json!({ "name": name, "description": description }));
+            test::call_service(app, req.to_request()).await
+        }
+    };
+
+    // Hold the name so the original is still running when its retry arrives
+    let mut holder = pool.begin().await.unwrap();
+    claim_item_name(&mut holder, DEFAULT_TENANT, &name).await.unwrap();
+    let release = async {
+        sleep(Duration::from_millis(100)).await;
+        holder.rollback().await.unwrap();
+    };
+    let (original, retry, ()) = join3(create("first"), create("first"), release).await;
+    assert_eq!(original.status(), StatusCode::CREATED);
+    assert_eq!(retry.status(), StatusCode::CREATED);
+    assert_eq!(json_body(original).await, json_body(retry).await);
+
+    // A key older than a day is forgotten, so it can be used again for another item
+    sqlx::query("UPDATE idempotency_keys SET created_at = now() - interval '25 hours' WHERE key = $1").bind(&key).execute(&pool).await.unwrap();
+    sqlx::query("UPDATE items SET name = name || '-old' WHERE name = $1").bind(&name).execute(&pool).await.unwrap();
+    assert_eq!(create("second").await.status(), StatusCode::CREATED);
+    sqlx::query("UPDATE idempotency_keys SET created_at = now() - interval '25 hours' WHERE key = $1").bind(&key).execute(&pool).await.unwrap();
+    assert!(db::purge_expired_idempotency_keys(&pool).await.unwrap() >= 1);
+    let left: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM idempotency_keys WHERE key = $1").bind(&key).fetch_one(&pool).await.unwrap();
+    assert_eq!(left, 0);
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn foreign_key_violations_are_conflicts_naming_the_constraint() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let prefix = unique_prefix();
+
+    let req = test::TestRequest::post()
+        .uri("/api/v1/items")
+        .set_json(json!({ "name": format!("{}orphan", prefix), "category_id": uuid::Uuid::new_v4() }))
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::CONFLICT);
+    assert_eq!(
+        json_body(res).await,
+        json!({
+            "error": "reference_conflict",
+            "message": "category_id does not refer to an existing category",
+            "constraint": "items_category_id_fkey"
+        })
+    );
+
+    // No route deletes categories yet, so remove a referenced one directly
+    let req = test::TestRequest::post().uri("/api/v1/categories").set_json(json!({ "name": format!("{}tools", prefix) })).to_request();
+    let category = json_body(test::call_service(&app, req).await).await;
+    let req = test::TestRequest::post()
+        .uri("/api/v1/items")
+        .set_json(json!({ "name": format!("{}hammer", prefix), "category_id": category["id"] }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::CREATED);
+    let err = sqlx::query("DELETE FROM categories WHERE id::text = $1").bind(category["id"].as_str()).execute(&pool).await.unwrap_err();
+    let err = ApiError::from(err);
+    assert_eq!(err.to_string(), "Cannot delete category with existing items");
+    assert_eq!(serde_json::to_value(err.body()).unwrap()["constraint"], "items_category_id_fkey");
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn moving_a_category_is_all_or_nothing() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let prefix = unique_prefix();
+
+    let mut categories = Vec::new();
+    for name in ["from", "to"] {
+        let req = test::TestRequest::post().uri("/api/v1/categories").set_json(json!({ "name": format!("{}{}", prefix, name) })).to_request();
+        categories.push(json_body(test::call_service(&app, req).await).await["id"].as_str().unwrap().to_string());
+    }
+    for name in ["a", "b", "poison"] {
+        let req = test::TestRequest::post()
+            .uri("/api/v1/items")
+            .set_json(json!({ "name": format!("{}{}", prefix, name), "description": "", "category_id": categories[0] }))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::CREATED);
+    }
+    let move_uri = format!("/api/v1/categories/{}/move-to/{}", categories[0], categories[1]);
+    let in_target = || async {
+        sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM items WHERE name LIKE $1 AND category_id::text = $2")
+            .bind(format!("{}%", prefix))
+            .bind(&categories[1])
+            .fetch_one(&pool)
+            .await
+            .unwrap()
+    };
+
+    let same = format!("/api/v1/categories/{}/move-to/{}", categories[0], categories[0]);
+    assert_eq!(test::call_service(&app, test::TestRequest::post().uri(&same).to_request()).await.status(), StatusCode::BAD_REQUEST);
+    let unknown = format!("/api/v1/categories/{}/move-to/{}", categories[0], uuid::Uuid::new_v4());
+    assert_eq!(test::call_service(&app, test::TestRequest::post().uri(&unknown).to_request()).await.status(), StatusCode::BAD_REQUEST);
+
+    // A trigger failing on one row aborts the whole move
+    let function = format!("\"fail_{}\"", prefix);
+    sqlx::query(&format!("CREATE FUNCTION {}() RETURNS trigger AS $$ BEGIN RAISE EXCEPTION 'forced failure'; END; $$ LANGUAGE plpgsql", function))
+        .execute(&pool)
+        .await
+        .unwrap();
+    sqlx::query(&format!(
+        "CREATE TRIGGER {} BEFORE UPDATE ON items FOR EACH ROW WHEN (NEW.name = '{}poison') EXECUTE FUNCTION {}()",
+        function, prefix, function
+    ))
+    .execute(&pool)
+    .await
+    .unwrap();
+    let res = test::call_service(&app, test::TestRequest::post().uri(&move_uri).to_request()).await;
+    sqlx::query(&format!("DROP TRIGGER {} ON items", function)).execute(&pool).await.unwrap();
+    sqlx::query(&format!("DROP FUNCTION {}()", function)).execute(&pool).await.unwrap();
+    assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(in_target().await, 0);
+
+    let res = test::call_service(&app, test::TestRequest::post().uri(&move_uri).to_request()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(json_body(res).await["moved"], 3);
+    assert_eq!(in_target().await, 3);
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn messagepack_is_negotiated() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let prefix = unique_prefix();
+
+    let body = rmp_serde::to_vec_named(&json!({ "name": format!("{}packed", prefix), "description": "binary" })).unwrap();
+    let req = test::TestRequest::post()
+        .uri("/api/v1/items")
+        .insert_header((header::CONTENT_TYPE, "application/msgpack"))
+        .insert_header((header::ACCEPT, "application/msgpack"))
+        .set_payload(body)
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    assert_eq!(content_type(&res), "application/msgpack");
+    let location = res.headers().get(header::LOCATION).unwrap().to_str().unwrap().to_string();
+    let created: serde_json::Value = rmp_serde::from_slice(&test::read_body(res).await).unwrap();
+    assert_eq!(created["description"], "binary");
+
+    // Unknown Accept values fall back to JSON
+    let req = test::TestRequest::get().uri(&location).insert_header((header::ACCEPT, "text/x-unknown")).to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(content_type(&res), "application/json");
+    assert_eq!(json_body(res).await["id"], created["id"]);
+
+    let req = test::TestRequest::post()
+        .uri("/api/v1/items")
+        .insert_header((header::CONTENT_TYPE, "application/msgpack"))
+        .set_payload(vec![0xc1])
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn search_ranks_name_and_description_matches() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let prefix = unique_prefix();
+    let word = format!("w{}", uuid::Uuid::new_v4().simple());
+
+    for (name, description) in [
+        (format!("{}plain", prefix), "nothing to see".to_string()),
+        (format!("{}{}", prefix, word), String::new()),
+        (format!("{}described", prefix), format!("{} and {} again, {}", word, word, word)),
+    ] {
+        let req = test::TestRequest::post().uri("/api/v1/items").set_json(json!({ "name": name, "description": description })).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::CREATED);
+    }
+
+    let req = test::TestRequest::get().uri(&format!("/api/v1/items?search={}&envelope=true", word)).to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body = json_body(res).await;
+    assert_eq!(body["meta"]["total"], 2);
+    let data = body["data"].as_array().unwrap();
+    assert_eq!(data[0]["name"], format!("{}described", prefix));
+    assert_eq!(data[1]["name"], format!("{}{}", prefix, word));
+    assert!(data[0]["rank"].as_f64().unwrap() >= data[1]["rank"].as_f64().unwrap());
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn name_availability_is_case_and_whitespace_insensitive() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let prefix = unique_prefix();
+
+    let req = test::TestRequest::post().uri("/api/v1/items").set_json(json!({ "name": format!("{}taken", prefix), "description": "" })).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::CREATED);
+
+    for (name, available) in [(format!("%20%20{}TAKEN%20", prefix), false), (format!("{}free", prefix), true)] {
+        let res = test::call_service(&app, test::TestRequest::get().uri(&format!("/api/v1/items/validate?name={}", name)).to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(json_body(res).await["available"], available);
+    }
+
+    for uri in ["/api/v1/items/validate?name=%20%20", "/api/v1/items/validate"] {
+        let res = test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn suggestions_match_name_prefixes_literally() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let prefix = unique_prefix();
+
+    for name in ["Bolt", "bracket", "axle", "c%d", "cad"] {
+        let req = test::TestRequest::post().uri("/api/v1/items").set_json(json!({ "name": format!("{}{}", prefix, name), "description": "" })).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::CREATED);
+    }
+    let suggest = |query: String| {
+        let app = &app;
+        async move { json_body(test::call_service(app, test::TestRequest::get().uri(&format!("/api/v1/items/suggest?{}", query)).to_request()).await).await }
+    };
+    let named = |names: &[&str]| json!(names.iter().map(|name| format!("{}{}", prefix, name)).collect::<Vec<_>>());
+
+    assert_eq!(suggest(format!("prefix={}B", prefix)).await, named(&["Bolt", "bracket"]));
+    assert_eq!(suggest(format!("prefix={}c%25", prefix)).await, named(&["c%d"]));
+    assert_eq!(suggest(format!("prefix={}&limit=2", prefix)).await, named(&["axle", "Bolt"]));
+
+    let res = test::call_service(&app, test::TestRequest::get().uri("/api/v1/items/suggest?prefix=a&limit=0").to_request()).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn batch_get_returns_found_items_in_request_order() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let prefix = unique_prefix();
+
+    let mut ids = Vec::new();
+    for name in ["first", "second"] {
+        let req = test::TestRequest::post().uri("/api/v1/items").set_json(json!({ "name": format!("{}{}", prefix, name), "description": "" })).to_request();
+        ids.push(json_body(test::call_service(&app, req).await).await["id"].clone());
+    }
+
+    let missing = uuid::Uuid::new_v4().to_string();
+    let req = test::TestRequest::post().uri("/api/v1/items/batch-get").set_json(json!({ "ids": [ids[1], missing, ids[0]] })).to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let found: Vec<_> = json_body(res).await.as_array().unwrap().iter().map(|item| item["id"].clone()).collect();
+    assert_eq!(found, [ids[1].clone(), ids[0].clone()]);
+
+    let req = test::TestRequest::post().uri("/api/v1/items/batch-get").set_json(json!({ "ids": [missing] })).to_request();
+    assert_eq!(json_body(test::call_service(&app, req).await).await, json!([]));
+
+    let req = test::TestRequest::post().uri("/api/v1/items/batch-get").set_json(json!({ "ids": ["not-a-uuid"] })).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+
+    let too_many: Vec<_> = (0..1001).map(|_| uuid::Uuid::new_v4()).collect();
+    let req = test::TestRequest::post().uri("/api/v1/items/batch-get").set_json(json!({ "ids": too_many })).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn ties_are_broken_by_id_so_pages_are_stable() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let prefix = unique_prefix();
+
+    let mut ids = Vec::new();
+    for name in ["a", "b", "c", "d"] {
+        let req = test::TestRequest::post().uri("/api/v1/items").set_json(json!({ "name": format!("{}{}", prefix, name), "description": "" })).to_request();
+        ids.push(json_body(test::call_service(&app, req).await).await["id"].as_str().unwrap().to_string());
+    }
+    // Identical timestamps leave the id as the only thing telling rows apart
+    sqlx::query("UPDATE items SET created_at = '2026-01-01T00:00:00Z' WHERE name LIKE $1")
+        .bind(format!("{}%", prefix))
+        .execute(&pool)
+        .await
+        .expect("share one created_at");
+    ids.sort();
+    ids.reverse();
+
+    for _ in 0..3 {
+        let mut paged = Vec::new();
+        for page in 1..=4 {
+            let uri = format!("/api/v1/items?q={}&per_page=1&page={}", prefix, page);
+            let body = json_body(test::call_service(&app, test::TestRequest::get().uri(&uri).to_request()).await).await;
+            paged.push(body[0]["id"].as_str().unwrap().to_string());
+        }
+        assert_eq!(paged, ids);
+    }
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn offset_pages_advertise_link_relations() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let prefix = unique_prefix();
+
+    for name in ["a", "b", "c"] {
+        let req = test::TestRequest::post().uri("/api/v1/items").set_json(json!({ "name": format!("{}{}", prefix, name), "description": "" })).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::CREATED);
+    }
+
+    let link = |page: &str| {
+        let uri = format!("/api/v1/items?q={}&page={}&per_page=1", prefix, page);
+        let app = &app;
+        async move {
+            let res = test::call_service(app, test::TestRequest::get().uri(&uri).to_request()).await;
+            res.headers().get(header::LINK).expect("Link header").to_str().unwrap().to_string()
+        }
+    };
+    let page = |n: u32, rel: &str| format!("</api/v1/items?q={}&per_page=1&page={}>; rel=\"{}\"", prefix, n, rel);
+
+    assert_eq!(link("2").await, [page(1, "first"), page(1, "prev"), page(3, "next"), page(3, "last")].join(", "));
+    assert_eq!(link("1").await, [page(1, "first"), page(2, "next"), page(3, "last")].join(", "));
+    assert_eq!(link("3").await, [page(1, "first"), page(2, "prev"), page(3, "last")].join(", "));
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn content_range_describes_the_served_slice() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let prefix = unique_prefix();
+
+    for name in ["a", "b", "c"] {
+        let req = test::TestRequest::post().uri("/api/v1/items").set_json(json!({ "name": format!("{}{}", prefix, name), "description": "" })).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::CREATED);
+    }
+
+    for (query, status, range) in [
+        ("per_page=2", StatusCode::PARTIAL_CONTENT, "items 0-1/3"),
+        ("per_page=2&page=2", StatusCode::PARTIAL_CONTENT, "items 2-2/3"),
+        ("per_page=5", StatusCode::OK, "items 0-2/3"),
+        ("per_page=2&page=3", StatusCode::OK, "items */3"),
+    ] {
+        let uri = format!("/api/v1/items?q={}&{}", prefix, query);
+        let res = test::call_service(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+        assert_eq!(res.status(), status, "{}", query);
+        assert_eq!(res.headers().get(header::CONTENT_RANGE).unwrap(), range, "{}", query);
+    }
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn oversized_pages_are_clamped_to_the_max() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let prefix = unique_prefix();
+
+    for (query, clamped, per_page) in [("per_page=500", true, 100), ("per_page=100", false, 100), ("", false, 20)] {
+        let uri = format!("/api/v1/items?q={}&envelope=true&{}", prefix, query);
+        let res = test::call_service(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK, "{}", query);
+        assert_eq!(res.headers().get("x-page-size-clamped").is_some(), clamped, "{}", query);
+        assert_eq!(json_body(res).await["meta"]["per_page"], per_page, "{}", query);
+    }
+
+    let res = test::call_service(&app, test::TestRequest::get().uri(&format!("/api/v1/items?q={}&limit=1000", prefix)).to_request()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers().get("x-page-size-clamped").unwrap(), "true");
+
+    // A size below one is still an error rather than something to clamp
+    let res = test::call_service(&app, test::TestRequest::get().uri("/api/v1/items?per_page=0").to_request()).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn updated_since_returns_changes_oldest_first() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let prefix = unique_prefix();
+
+    let mut created = Vec::new();
+    for name in ["first", "second"] {
+        let req = test::TestRequest::post().uri("/api/v1/items").set_json(json!({ "name": format!("{}{}", prefix, name), "description": "" })).to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        created.push(json_body(res).await);
+    }
+    let sync_uri = format!("/api/v1/items?q={}&updated_since={}", prefix, created[0]["updated_at"].as_str().unwrap());
+
+    let body = json_body(test::call_service(&app, test::TestRequest::get().uri(&sync_uri).to_request()).await).await;
+    let names: Vec<_> = body.as_array().unwrap().iter().map(|item| item["name"].clone()).collect();
+    assert_eq!(names, [created[1]["name"].clone()]);
+
+    // Deleting the first item changes it, so it now follows the second one, tombstone and all
+    let req = test::TestRequest::delete().uri(&format!("/api/v1/items/{}", created[0]["id"].as_str().unwrap())).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    let body = json_body(test::call_service(&app, test::TestRequest::get().uri(&sync_uri).to_request()).await).await;
+    let items = body.as_array().unwrap();
+    assert_eq!(items.len(), 2);
+    assert_eq!(items[0]["id"], created[1]["id"]);
+    assert!(items[0]["deleted_at"].is_null());
+    assert_eq!(items[1]["id"], created[0]["id"]);
+    assert!(items[1]["deleted_at"].is_string());
+
+    let res = test::call_service(&app, test::TestRequest::get().uri("/api/v1/items?updated_since=yesterday").to_request()).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn idle_sync_polls_are_not_modified() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let prefix = unique_prefix();
+
+    let req = test::TestRequest::post().uri("/api/v1/items").set_json(json!({ "name": format!("{}polled", prefix) })).to_request();
+    let item = json_body(test::call_service(&app, req).await).await;
+    let sync_uri = format!("/api/v1/items?q={}&updated_since=2000-01-01T00:00:00Z", prefix);
+    let poll = |etag: Option<String>| {
+        let app = &app;
+        let mut req = test::TestRequest::get().uri(&sync_uri);
+        if let Some(etag) = etag {
+            req = req.insert_header((header::IF_NONE_MATCH, etag));
+        }
+        async move { test::call_service(app, req.to_request()).await }
+    };
+
+    let res = poll(None).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let etag = res.headers().get(header::ETAG).unwrap().to_str().unwrap().to_string();
+
+    // Nothing changed: the same validator, and no body
+    let res = poll(Some(etag.clone())).await;
+    assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(res.headers().get(header::ETAG).unwrap().to_str().unwrap(), etag);
+    assert!(test::read_body(res).await.is_empty());
+
+    // A change since the last poll is sent in full under a new validator
+    let req = test::TestRequest::patch()
+        .uri(&format!("/api/v1/items/{}", item["id"].as_str().unwrap()))
+        .set_json(json!({ "description": "changed", "version": 1 }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    let res = poll(Some(etag.clone())).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_ne!(res.headers().get(header::ETAG).unwrap().to_str().unwrap(), etag);
+    assert_eq!(json_body(res).await[0]["description"], "changed");
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn websocket_needs_an_upgrade_handshake() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+
+    let req = test::TestRequest::get()
+        .uri("/api/v1/ws")
+        .insert_header((header::UPGRADE, "websocket"))
+        .insert_header((header::CONNECTION, "upgrade"))
+        .insert_header((header::SEC_WEBSOCKET_VERSION, "13"))
+        .insert_header((header::SEC_WEBSOCKET_KEY, "dGhlIHNhbXBsZSBub25jZQ=="))
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::SWITCHING_PROTOCOLS);
+    assert_eq!(res.headers().get(header::SEC_WEBSOCKET_ACCEPT).unwrap(), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+
+    let res = test::call_service(&app, test::TestRequest::get().uri("/api/v1/ws").to_request()).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn archived_items_are_hidden_from_listings_only() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let prefix = unique_prefix();
+
+    let mut ids = Vec::new();
+    for name in ["kept", "shelved"] {
+        let req = test::TestRequest::post().uri("/api/v1/items").set_json(json!({ "name": format!("{}{}", prefix, name), "description": "" })).to_request();
+        ids.push(json_body(test::call_service(&app, req).await).await["id"].as_str().unwrap().to_string());
+    }
+    let listed = |query: &str| {
+        let uri = format!("/api/v1/items?q={}{}", prefix, query);
+        let app = &app;
+        async move {
+            let body = json_body(test::call_service(app, test::TestRequest::get().uri(&uri).to_request()).await).await;
+            body.as_array().unwrap().iter().map(|item| item["id"].as_str().unwrap().to_string()).collect::<Vec<_>>()
+        }
+    };
+
+    let archive = format!("/api/v1/items/{}/archive", ids[1]);
+    let res = test::call_service(&app, test::TestRequest::post().uri(&archive).to_request()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let archived = json_body(res).await;
+    assert_eq!(archived["archived"], true);
+    assert_eq!(archived["version"], 2);
+    // Archiving again changes nothing
+    let again = json_body(test::call_service(&app, test::TestRequest::post().uri(&archive).to_request()).await).await;
+    assert_eq!(again["version"], 2);
+
+    assert_eq!(listed("").await, [ids[0].clone()]);
+    assert_eq!(listed("&include_archived=true").await.len(), 2);
+    let count = json_body(test::call_service(&app, test::TestRequest::get().uri(&format!("/api/v1/items/count?q={}", prefix)).to_request()).await).await;
+    assert_eq!(count["count"], 1);
+    let res = test::call_service(&app, test::TestRequest::get().uri(&format!("/api/v1/items/{}", ids[1])).to_request()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(json_body(res).await["archived"], true);
+
+    let req = test::TestRequest::post().uri(&format!("/api/v1/items/{}/unarchive", ids[1])).to_request();
+    assert_eq!(json_body(test::call_service(&app, req).await).await["archived"], false);
+    assert_eq!(listed("").await.len(), 2);
+
+    // Soft-deleted items can't be archived
+    let res = test::call_service(&app, test::TestRequest::delete().uri(&format!("/api/v1/items/{}", ids[0])).to_request()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let req = test::TestRequest::post().uri(&format!("/api/v1/items/{}/archive", ids[0])).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::NOT_FOUND);
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn slugs_follow_names_and_resolve_items() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let prefix = unique_prefix();
+    let by_slug = |slug: &str| {
+        let encoded: String = slug
+            .bytes()
+            .map(|byte| if byte.is_ascii_alphanumeric() || byte == b'-' { (byte as char).to_string() } else { format!("%{:02X}", byte) })
+            .collect();
+        test::TestRequest::get().uri(&format!("/api/v1/items/by-slug/{}", encoded)).to_request()
+    };
+
+    // Punctuation is dropped, separators collapse and letters outside ASCII survive
+    let mut created = Vec::new();
+    for name in ["Crème Brûlée: Deluxe!", "crème   brûlée   deluxe"] {
+        let req = test::TestRequest::post().uri("/api/v1/items").set_json(json!({ "name": format!("{}{}", prefix, name), "description": "" })).to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        created.push(json_body(res).await);
+    }
+    let base = format!("{}crème-brûlée-deluxe", prefix);
+    assert_eq!(created[0]["slug"], base);
+    // A colliding slug takes a numeric suffix rather than failing
+    assert_eq!(created[1]["slug"], format!("{}-2", base));
+
+    let res = test::call_service(&app, by_slug(&format!("{}-2", base))).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(res.headers().contains_key(header::ETAG));
+    assert_eq!(json_body(res).await["id"], created[1]["id"]);
+
+    // Other edits keep the slug; a rename regenerates it and frees the old one
+    let location = format!("/api/v1/items/{}", created[0]["id"].as_str().unwrap());
+    let req = test::TestRequest::patch().uri(&location).set_json(json!({ "description": "rich", "version": 1 })).to_request();
+    assert_eq!(json_body(test::call_service(&app, req).await).await["slug"], base);
+    let req = test::TestRequest::put()
+        .uri(&location)
+        .set_json(json!({ "name": format!("{}Crème Brûlée: Deluxe!", prefix), "description": "richer", "version": 2 }))
+        .to_request();
+    assert_eq!(json_body(test::call_service(&app, req).await).await["slug"], base);
+    let req = test::TestRequest::patch().uri(&location).set_json(json!({ "name": format!("{}flan", prefix), "version": 3 })).to_request();
+    assert_eq!(json_body(test::call_service(&app, req).await).await["slug"], format!("{}flan", prefix));
+    assert_eq!(test::call_service(&app, by_slug(&base)).await.status(), StatusCode::NOT_FOUND);
+
+    // The freed slug goes to the next item that wants it
+    let req = test::TestRequest::post().uri("/api/v1/items").set_json(json!({ "name": format!("{}crème brûlée deluxe", prefix), "description": "" })).to_request();
+    assert_eq!(json_body(test::call_service(&app, req).await).await["slug"], base);
+    assert_eq!(crate::db::slugify("?!"), "item");
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn names_resolve_items_ignoring_case_and_spacing() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let prefix = unique_prefix();
+    let by_name = |query: &str| test::TestRequest::get().uri(&format!("/api/v1/items/by-name/{}", query)).to_request();
+
+    let req = test::TestRequest::post().uri("/api/v1/items").set_json(json!({ "name": format!("{}Blue Widget", prefix), "description": "" })).to_request();
+    let created = json_body(test::call_service(&app, req).await).await;
+
+    let res = test::call_service(&app, by_name(&format!("{}Blue%20Widget", prefix))).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(res.headers().contains_key(header::ETAG));
+    assert_eq!(json_body(res).await["id"], created["id"]);
+    let res = test::call_service(&app, by_name(&format!("%20{}BLUE%20%20%09widget%20", prefix.to_uppercase()))).await;
+    assert_eq!(json_body(res).await["id"], created["id"]);
+    assert_eq!(test::call_service(&app, by_name(&format!("{}Red%20Widget", prefix))).await.status(), StatusCode::NOT_FOUND);
+
+    // A soft-deleted item keeps its name, so a replacement makes the lookup ambiguous once deleted items count
+    let location = format!("/api/v1/items/{}", created["id"].as_str().unwrap());
+    test::call_service(&app, test::TestRequest::delete().uri(&location).to_request()).await;
+    let req = test::TestRequest::post().uri("/api/v1/items").set_json(json!({ "name": format!("{}blue widget", prefix), "description": "" })).to_request();
+    let replacement = json_body(test::call_service(&app, req).await).await;
+    let res = test::call_service(&app, by_name(&format!("{}Blue%20Widget", prefix))).await;
+    assert_eq!(json_body(res).await["id"], replacement["id"]);
+    let res = test::call_service(&app, by_name(&format!("{}Blue%20Widget?include_deleted=true", prefix))).await;
+    assert_eq!(res.status(), StatusCode::CONFLICT);
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn tags_are_assigned_removed_and_filtered_on() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let prefix = unique_prefix();
+
+    let mut ids = Vec::new();
+    for name in ["tagged", "plain"] {
+        let req = test::TestRequest::post().uri("/api/v1/items").set_json(json!({ "name": format!("{}{}", prefix, name), "description": "" })).to_request();
+        ids.push(json_body(test::call_service(&app, req).await).await["id"].as_str().unwrap().to_string());
+    }
+    let tag = |id: &str, tags: serde_json::Value| test::TestRequest::post().uri(&format!("/api/v1/items/{}/tags", id)).set_json(json!({ "tags": tags })).to_request();
+    let (red, blue) = (format!("{}red", prefix), format!("{}blue", prefix));
+
+    // Names are trimmed and lowercased, and repeats collapse
+    let res = test::call_service(&app, tag(&ids[0], json!([red.to_uppercase(), format!(" {} ", blue), red]))).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let item = json_body(res).await;
+    assert_eq!(item["tags"], json!([blue, red]));
+    assert_eq!(item["version"], 2);
+    // Tags the item already carries change nothing
+    assert_eq!(json_body(test::call_service(&app, tag(&ids[0], json!([red]))).await).await["version"], 2);
+
+    let res = test::call_service(&app, test::TestRequest::get().uri(&format!("/api/v1/items?q={}&tag={}", prefix, red.to_uppercase())).to_request()).await;
+    let listed = json_body(res).await;
+    assert_eq!(listed.as_array().unwrap().len(), 1);
+    assert_eq!(listed[0]["id"], ids[0]);
+
+    let res = test::call_service(&app, test::TestRequest::delete().uri(&format!("/api/v1/items/{}/tags/{}", ids[0], red)).to_request()).await;
+    let item = json_body(res).await;
+    assert_eq!(item["tags"], json!([blue]));
+    assert_eq!(item["version"], 3);
+    let res = test::call_service(&app, test::TestRequest::get().uri(&format!("/api/v1/items/{}", ids[0])).to_request()).await;
+    assert_eq!(json_body(res).await["tags"], json!([blue]));
+
+    let unknown = uuid::Uuid::new_v4().to_string();
+    assert_eq!(test::call_service(&app, tag(&unknown, json!([red]))).await.status(), StatusCode::NOT_FOUND);
+    let req = test::TestRequest::delete().uri(&format!("/api/v1/items/{}/tags/{}", unknown, blue)).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::NOT_FOUND);
+    assert_eq!(test::call_service(&app, tag(&ids[1], json!([]))).await.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(test::call_service(&app, tag(&ids[1], json!(["  "]))).await.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn reordering_is_all_or_nothing() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let prefix = unique_prefix();
+
+    let mut ids = Vec::new();
+    for name in ["a", "b", "c", "unplaced"] {
+        let req = test::TestRequest::post().uri("/api/v1/items").set_json(json!({ "name": format!("{}{}", prefix, name), "description": "" })).to_request();
+        ids.push(json_body(test::call_service(&app, req).await).await["id"].as_str().unwrap().to_string());
+    }
+    let reorder = |body: serde_json::Value| test::TestRequest::patch().uri("/api/v1/items/reorder").set_json(body).to_request();
+    let listed = || {
+        let uri = format!("/api/v1/items?q={}&sort=position", prefix);
+        let app = &app;
+        async move {
+            let body = json_body(test::call_service(app, test::TestRequest::get().uri(&uri).to_request()).await).await;
+            body.as_array().unwrap().iter().map(|item| item["id"].as_str().unwrap().to_string()).collect::<Vec<_>>()
+        }
+    };
+
+    let res = test::call_service(&app, reorder(json!([{ "id": ids[2], "position": 0 }, { "id": ids[0], "position": 1 }, { "id": ids[1], "position": 2 }]))).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let moved = json_body(res).await;
+    assert_eq!(moved[0]["id"], ids[2]);
+    assert_eq!(moved[0]["position"], 0);
+    assert_eq!(moved[0]["version"], 2);
+    // Items never positioned sort last
+    assert_eq!(listed().await, [ids[2].clone(), ids[0].clone(), ids[1].clone(), ids[3].clone()]);
+
+    let res = test::call_service(&app, reorder(json!([{ "id": ids[0], "position": 5 }, { "id": ids[1], "position": 5 }]))).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    let res = test::call_service(&app, reorder(json!([{ "id": ids[0], "position": 5 }, { "id": ids[0], "position": 6 }]))).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+    // An unknown id rejects the whole request, including the entries that did exist
+    let res = test::call_service(&app, reorder(json!([{ "id": ids[1], "position": 0 }, { "id": uuid::Uuid::new_v4(), "position": 1 }]))).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    assert!(json_body(res).await["message"].as_str().unwrap().starts_with("item 1: "));
+    assert_eq!(listed().await, [ids[2].clone(), ids[0].clone(), ids[1].clone(), ids[3].clone()]);
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn items_stream_as_one_json_object_per_line() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let prefix = unique_prefix();
+
+    let mut ids = Vec::new();
+    for name in ["first", "second", "third"] {
+        let req = test::TestRequest::post().uri("/api/v1/items").set_json(json!({ "name": format!("{}{}", prefix, name), "description": "line\nbreak" })).to_request();
+        ids.push(json_body(test::call_service(&app, req).await).await["id"].clone());
+    }
+
+    let res = test::call_service(&app, test::TestRequest::get().uri("/api/v1/items/stream").to_request()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(content_type(&res), "application/x-ndjson");
+    let body = test::read_body(res).await;
+    let body = std::str::from_utf8(&body).unwrap();
+    assert!(body.ends_with('\n'));
+    // Embedded newlines stay escaped, so every line is a complete item
+    let streamed: Vec<serde_json::Value> = body
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).expect("each line is JSON"))
+        .filter(|item| item["name"].as_str().unwrap().starts_with(&prefix))
+        .collect();
+    assert_eq!(streamed.iter().map(|item| item["id"].clone()).collect::<Vec<_>>(), ids);
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn fuzzy_search_tolerates_typos_and_reports_similarity() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let prefix = unique_prefix();
+
+    for name in ["blue widget", "blue widgets", "red gadget"] {
+        let req = test::TestRequest::post().uri("/api/v1/items").set_json(json!({ "name": format!("{}{}", prefix, name), "description": "" })).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::CREATED);
+    }
+    let fuzzy = |query: &str| test::TestRequest::get().uri(&format!("/api/v1/items?fuzzy={}blue%20widgte{}", prefix, query)).to_request();
+
+    let res = test::call_service(&app, fuzzy("&min_sim=0.75")).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let found = json_body(res).await;
+    let found = found.as_array().unwrap();
+    assert_eq!(found.len(), 2, "{:?}", found);
+    assert_eq!(found[0]["name"], format!("{}blue widget", prefix));
+    let scores: Vec<f64> = found.iter().map(|item| item["similarity"].as_f64().unwrap()).collect();
+    assert!(scores[0] >= scores[1] && scores[1] >= 0.75, "{:?}", scores);
+
+    // A low enough threshold lets the weaker match through too
+    let res = test::call_service(&app, fuzzy("&min_sim=0.1&envelope=true")).await;
+    let body = json_body(res).await;
+    assert!(body["meta"]["total"].as_i64().unwrap() >= 3);
+    assert!(body["data"][0]["similarity"].is_number());
+
+    for bad in ["&min_sim=1.5", "&min_sim=-0.1", "&min_sim=abc"] {
+        assert_eq!(test::call_service(&app, fuzzy(bad)).await.status(), StatusCode::BAD_REQUEST, "{}", bad);
+    }
+
+    // The threshold lasts as long as the query's transaction, not as long as its pooled connection
+    let single = PgPoolOptions::new().max_connections(1).connect(&test_database_url()).await.expect("test database");
+    let filter = web::Query::<ItemFilter>::from_query("fuzzy=widget&min_sim=0.9").unwrap().into_inner();
+    let (threshold,): (String,) = sqlx::query_as("SHOW pg_trgm.similarity_threshold").fetch_one(&mut *db::filter_connection(&single, &filter).await.unwrap()).await.unwrap();
+    assert_eq!(threshold, "0.9");
+    let (threshold,): (String,) = sqlx::query_as("SHOW pg_trgm.similarity_threshold").fetch_one(&single).await.unwrap();
+    assert_ne!(threshold, "0.9");
+    // Without a fuzzy term there is no threshold to set, and no transaction to open
+    let filter = web::Query::<ItemFilter>::from_query("q=widget").unwrap().into_inner();
+    assert!(matches!(db::filter_connection(&single, &filter).await.unwrap(), FilterConnection::Plain(_)));
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn truncate_is_guarded() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+
+    for uri in ["/api/v1/items", "/api/v1/items?confirm=false"] {
+        let res = test::call_service(&app, test::TestRequest::delete().uri(uri).to_request()).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    // The test app leaves ALLOW_BULK_TRUNCATE off, so even a confirmed request is refused
+    let res = test::call_service(&app, test::TestRequest::delete().uri("/api/v1/items?confirm=true").to_request()).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    assert_eq!(json_body(res).await["error"], "forbidden");
+}
+
+#[actix_web::test]
+async fn error_responses_are_json() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+
+    let requests = vec![
+        (test::TestRequest::get().uri(&format!("/api/v1/items/{}", uuid::Uuid::new_v4())), StatusCode::NOT_FOUND),
+        (test::TestRequest::get().uri("/api/v1/items/not-a-uuid"), StatusCode::BAD_REQUEST),
+        (test::TestRequest::get().uri("/no-such-route"), StatusCode::NOT_FOUND),
+        (test::TestRequest::get().uri("/api/v1/items?page=zero"), StatusCode::BAD_REQUEST),
+        (
+            test::TestRequest::post().uri("/api/v1/items").insert_header((header::CONTENT_TYPE, "application/json")).set_payload("{"),
+            StatusCode::BAD_REQUEST,
+        ),
+    ];
+    for (req, status) in requests {
+        let res = test::call_service(&app, req.to_request()).await;
+        assert_eq!(res.status(), status);
+        assert_eq!(content_type(&res), "application/json");
+        let body = json_body(res).await;
+        assert!(body["error"].is_string(), "missing error code in {}", body);
+        assert!(body["message"].is_string(), "missing message in {}", body);
+    }
+}
+
+#[actix_web::test]
+async fn undecodable_json_bodies_explain_the_problem() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+
+    let cases = [
+        (json!({ "description": "" }).to_string(), "missing field `name`"),
+        (json!({ "name": 123, "description": "" }).to_string(), "invalid type: integer `123`, expected a string"),
+        ("{\"name\": ".to_string(), "Malformed JSON body"),
+        ("{\"name\" \"x\"}".to_string(), "Malformed JSON body"),
+    ];
+    for (payload, expected) in cases {
+        let req = test::TestRequest::post().uri("/api/v1/items").insert_header((header::CONTENT_TYPE, "application/json")).set_payload(payload).to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body = json_body(res).await;
+        assert_eq!(body["error"], "validation");
+        let message = body["message"].as_str().unwrap();
+        assert!(message.contains(expected), "{:?} does not mention {:?}", message, expected);
+    }
+}
+
+#[actix_web::test]
+async fn every_invalid_field_is_reported() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let body = json!({ "name": " ", "description": "x".repeat(10_001) });
+
+    let req = test::TestRequest::post().uri("/api/v1/items").set_json(&body).to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let error = json_body(res).await;
+    assert_eq!(error["error"], "validation");
+    assert_eq!(error["fields"]["name"], "must not be empty");
+    assert_eq!(error["fields"]["description"], "must be at most 10000 characters");
+
+    let req = test::TestRequest::post().uri("/api/v1/items/batch").set_json(json!([{ "name": "fine", "description": "" }, body])).to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let fields = json_body(res).await["fields"].clone();
+    assert_eq!(fields.as_object().unwrap().len(), 2);
+    assert_eq!(fields["1.name"], "must not be empty");
+
+    let req = test::TestRequest::post().uri("/api/v1/categories").set_json(json!({ "name": "x".repeat(201) })).to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(json_body(res).await["fields"]["name"], "must be at most 200 characters");
+}
+
+#[actix_web::test]
+async fn malformed_ids_are_rejected() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+
+    for req in [
+        test::TestRequest::get().uri("/api/v1/items/not-a-uuid"),
+        test::TestRequest::put().uri("/api/v1/items/not-a-uuid").set_json(json!({ "name": "x", "description": "" })),
+        test::TestRequest::patch().uri("/api/v1/items/not-a-uuid").set_json(json!({ "name": "x" })),
+        test::TestRequest::delete().uri("/api/v1/items/not-a-uuid"),
+    ] {
+        let res = test::call_service(&app, req.to_request()).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body = json_body(res).await;
+        assert_eq!(body["error"], "invalid_id");
+        assert!(body["message"].is_string());
+    }
+}
+
+#[actix_web::test]
+async fn oversized_json_body_is_rejected() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+
+    let description = "x".repeat(DEFAULT_MAX_JSON_BODY_BYTES);
+    let req = test::TestRequest::post()
+        .uri("/api/v1/items")
+        .set_json(json!({ "name": "too big", "description": description }))
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(content_type(&res), "application/json");
+    assert_eq!(json_body(res).await["error"], "payload_too_large");
+}
+
+#[actix_web::test]
+async fn transactions_commit_on_success_and_roll_back_on_error() {
+    let pool = test_pool().await;
+    let prefix = unique_prefix();
+    let tenant = DEFAULT_TENANT;
+
+    let retry = TransactionRetry { max_retries: 3, base_delay: Duration::from_millis(1) };
+
+    let kept = ItemCreateRequest { name: format!("{}kept", prefix), description: None, category_id: None, metadata: None, price: None };
+    let item = with_transaction(&pool, &retry, move |conn| {
+        let kept = kept.clone();
+        Box::pin(async move { Ok(insert_item(conn, tenant, &kept, None).await?) })
+    })
+    .await
+    .expect("transaction commits");
+
+    let dropped = ItemCreateRequest { name: format!("{}dropped", prefix), description: None, category_id: None, metadata: None, price: None };
+    let err = with_transaction(&pool, &retry, move |conn| {
+        let dropped = dropped.clone();
+        Box::pin(async move {
+            insert_item(conn, tenant, &dropped, None).await?;
+            Err::<(), _>(ApiError::Validation("changed my mind".to_string()))
+        })
+    })
+    .await
+    .expect_err("transaction fails");
+    assert!(matches!(err, ApiError::Validation(_)));
+
+    let names: Vec<String> = sqlx::query_scalar("SELECT name FROM items WHERE name LIKE $1 ORDER BY name")
+        .bind(format!("{}%", prefix))
+        .fetch_all(&pool)
+        .await
+        .expect("list names");
+    assert_eq!(names, [item.name]);
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn serialization_failures_rerun_the_transaction() {
+    let pool = test_pool().await;
+    let prefix = unique_prefix();
+    let tenant = DEFAULT_TENANT;
+    let retry = TransactionRetry { max_retries: 2, base_delay: Duration::from_millis(1) };
+    // Fails like a transaction Postgres aborted to keep a concurrent one serializable
+    let conflict = "DO $$ BEGIN RAISE EXCEPTION 'could not serialize access' USING ERRCODE = 'serialization_failure'; END $$";
+
+    // The first attempt's insert is rolled back with it, so the item is created exactly once
+    let attempts = AtomicU32::new(0);
+    let item = ItemCreateRequest { name: format!("{}contended", prefix), description: None, category_id: None, metadata: None, price: None };
+    with_transaction(&pool, &retry, |conn| {
+        let attempt = attempts.fetch_add(1, Ordering::SeqCst);
+        let item = item.clone();
+        Box::pin(async move {
+            insert_item(&mut *conn, tenant, &item, None).await?;
+            if attempt == 0 {
+                sqlx::query(conflict).execute(&mut *conn).await?;
+            }
+            Ok(())
+        })
+    })
+    .await
+    .expect("the retry commits");
+    assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM items WHERE name LIKE $1")
+        .bind(format!("{}%", prefix))
+        .fetch_one(&pool)
+        .await
+        .expect("count items");
+    assert_eq!(count, 1);
+
+    // Conflicts that persist give up after the configured retries; other errors are never retried
+    let attempts = AtomicU32::new(0);
+    let err = with_transaction(&pool, &retry, |conn| {
+        attempts.fetch_add(1, Ordering::SeqCst);
+        Box::pin(async move { Ok::<_, ApiError>(sqlx::query(conflict).execute(conn).await.map(drop)?) })
+    })
+    .await
+    .expect_err("every attempt conflicts");
+    assert!(matches!(err, ApiError::TransactionConflict(_)), "{:?}", err);
+    assert_eq!(attempts.load(Ordering::SeqCst), 3);
+
+    let attempts = AtomicU32::new(0);
+    with_transaction(&pool, &retry, |_| {
+        attempts.fetch_add(1, Ordering::SeqCst);
+        Box::pin(async { Err::<(), _>(ApiError::Validation("not retried".to_string())) })
+    })
+    .await
+    .expect_err("validation fails");
+    assert_eq!(attempts.load(Ordering::SeqCst), 1);
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn cached_items_are_invalidated_by_every_change() {
+    let pool = test_pool().await;
+    let app = init_app_with(&pool, ApiRoutes::default(), ItemCache::new(16, Duration::from_secs(60))).await;
+    let prefix = unique_prefix();
+
+    let req = test::TestRequest::post()
+        .uri("/api/v1/items")
+        .set_json(json!({ "name": format!("{}cached", prefix), "description": "" }))
+        .to_request();
+    let created = json_body(test::call_service(&app, req).await).await;
+    let uri = format!("/api/v1/items/{}", created["id"].as_str().expect("id"));
+    let name_of = |item: &serde_json::Value| item["name"].as_str().unwrap_or_default().to_string();
+
+    // The first lookup misses and fills the cache, the second is served from it
+    for _ in 0..2 {
+        let fetched = json_body(test::call_service(&app, test::TestRequest::get().uri(&uri).to_request()).await).await;
+        assert_eq!(name_of(&fetched), format!("{}cached", prefix));
+    }
+
+    let req = test::TestRequest::patch()
+        .uri(&uri)
+        .insert_header((header::IF_MATCH, "W/\"1\""))
+        .set_json(json!({ "name": format!("{}renamed", prefix) }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    let fetched = json_body(test::call_service(&app, test::TestRequest::get().uri(&uri).to_request()).await).await;
+    assert_eq!(name_of(&fetched), format!("{}renamed", prefix));
+
+    let req = test::TestRequest::post()
+        .uri("/api/v1/items/delete-batch")
+        .set_json(json!({ "ids": [created["id"]] }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    let res = test::call_service(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+    let req = test::TestRequest::post().uri(&format!("{}/restore", uri)).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    let res = test::call_service(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = test::call_service(&app, test::TestRequest::get().uri("/metrics").to_request()).await;
+    let metrics = String::from_utf8(test::read_body(res).await.to_vec()).expect("metrics are text");
+    assert!(metrics.contains("item_cache_lookups_total{result=\"hit\"} 1"), "{}", metrics);
+    assert!(metrics.contains("item_cache_lookups_total{result=\"miss\"} 4"), "{}", metrics);
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn every_item_write_invalidates_the_cached_copy() {
+    let pool = test_pool().await;
+    let cached = init_app_with(&pool, ApiRoutes::default(), ItemCache::new(16, Duration::from_secs(60))).await;
+    let uncached = init_app(&pool).await;
+    let prefix = unique_prefix();
+
+    let mut categories = Vec::new();
+    for name in ["from", "to"] {
+        let req = test::TestRequest::post().uri("/api/v1/categories").set_json(json!({ "name": format!("{}{}", prefix, name) }));
+        categories.push(json_body(test::call_service(&cached, req.to_request()).await).await["id"].as_str().unwrap().to_string());
+    }
+    let req = test::TestRequest::post().uri("/api/v1/items").set_json(json!({ "name": format!("{}cached", prefix), "description": "", "category_id": categories[0] }));
+    let id = json_body(test::call_service(&cached, req.to_request()).await).await["id"].as_str().unwrap().to_string();
+    let uri = format!("/api/v1/items/{}", id);
+    let tag = format!("{}tag", prefix);
+
+    // Each write is made once the cache holds the item, at the version it holds
+    let writes = ["PATCH", "PUT", "batch PATCH", "PUT description", "archive", "unarchive", "tag", "untag", "move", "reorder", "DELETE", "restore"];
+    let get = || test::TestRequest::get().uri(&format!("{}?include_deleted=true", uri)).to_request();
+    for write in writes {
+        let before = json_body(test::call_service(&cached, get()).await).await;
+        let version = before["version"].as_i64().unwrap();
+        let if_match = (header::IF_MATCH, format!("W/\"{}\"", version));
+        let req = match write {
+            "PATCH" => test::TestRequest::patch().uri(&uri).insert_header(if_match).set_json(json!({ "description": "patched" })),
+            "PUT" => test::TestRequest::put()
+                .uri(&uri)
+                .insert_header(if_match)
+                .set_json(json!({ "name": format!("{}cached", prefix), "description": "put", "category_id": categories[0] })),
+            "batch PATCH" => test::TestRequest::patch().uri("/api/v1/items/batch").set_json(json!([{ "id": id, "version": version, "description": "batched" }])),
+            "PUT description" => test::TestRequest::put()
+                .uri(&format!("{}/description", uri))
+                .insert_header(if_match)
+                .insert_header((header::CONTENT_TYPE, "text/plain"))
+                .set_payload("plain"),
+            "archive" | "unarchive" | "restore" => test::TestRequest::post().uri(&format!("{}/{}", uri, write)),
+            "tag" => test::TestRequest::post().uri(&format!("{}/tags", uri)).set_json(json!({ "tags": [tag] })),
+            "untag" => test::TestRequest::delete().uri(&format!("{}/tags/{}", uri, tag)),
+            "move" => test::TestRequest::post().uri(&format!("/api/v1/categories/{}/move-to/{}", categories[0], categories[1])),
+            "reorder" => test::TestRequest::patch().uri("/api/v1/items/reorder").set_json(json!([{ "id": id, "position": 3 }])),
+            "DELETE" => test::TestRequest::delete().uri(&uri),
+            _ => unreachable!(),
+        };
+        let res = test::call_service(&cached, req.to_request()).await;
+        assert!(res.status().is_success(), "{}: {}", write, res.status());
+        let fresh = json_body(test::call_service(&uncached, get()).await).await;
+        assert_ne!(fresh, before, "{} changed nothing", write);
+        assert_eq!(json_body(test::call_service(&cached, get()).await).await, fresh, "{}", write);
+    }
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn cache_fills_read_the_primary_not_the_replica() {
+    let pool = test_pool().await;
+    let prefix = unique_prefix();
+    let item = ItemCreateRequest { name: format!("{}primary", prefix), description: None, category_id: None, metadata: None, price: None };
+    let mut conn = pool.acquire().await.expect("acquire a connection");
+    claim_item_name(&mut conn, DEFAULT_TENANT, &item.name).await.expect("claim the name");
+    let created = insert_item(&mut conn, DEFAULT_TENANT, &item, None).await.expect("insert the item");
+    drop(conn);
+
+    // With the replica unreachable a read only succeeds if it went to the primary
+    let replica = PgPoolOptions::new()
+        .acquire_timeout(Duration::from_millis(200))
+        .connect_lazy("postgres://postgres@127.0.0.1:1/unreachable")
+        .expect("valid replica URL");
+    let uri = format!("/api/v1/items/{}", created.id);
+    for (cache, status) in [(ItemCache::new(16, Duration::from_secs(60)), StatusCode::OK), (ItemCache::new(0, Duration::ZERO), StatusCode::SERVICE_UNAVAILABLE)] {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(ReadPool(replica.clone())))
+                .app_data(web::Data::new(RetryPolicy { max_retries: 0, base_delay: Duration::ZERO }))
+                .app_data(web::Data::new(cache))
+                .app_data(web::Data::new(Metrics::new()))
+                .configure(|cfg| routes::configure(cfg, &ApiRoutes::default())),
+        )
+        .await;
+        assert_eq!(test::call_service(&app, test::TestRequest::get().uri(&uri).to_request()).await.status(), status);
+    }
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn concurrent_reads_of_an_item_share_one_query() {
+    let pool = test_pool().await;
+    let prefix = unique_prefix();
+    let item = ItemCreateRequest { name: format!("{}popular", prefix), description: None, category_id: None, metadata: None, price: None };
+    let mut conn = pool.acquire().await.expect("acquire a connection");
+    claim_item_name(&mut conn, DEFAULT_TENANT, &item.name).await.expect("claim the name");
+    let created = insert_item(&mut conn, DEFAULT_TENANT, &item, None).await.expect("insert the item");
+    drop(conn);
+
+    // Counts the queries actually sent, held open long enough for every request to arrive meanwhile
+    let cache = ItemCache::new(0, Duration::ZERO);
+    let queries = AtomicU32::new(0);
+    let query = || async {
+        queries.fetch_add(1, Ordering::SeqCst);
+        sleep(Duration::from_millis(50)).await;
+        sqlx::query_scalar!("SELECT name FROM items WHERE id = $1", created.id).fetch_one(&pool).await?;
+        Ok(created.clone())
+    };
+    let loads = join_all((0..8).map(|_| cache.load(DEFAULT_TENANT, created.id, false, query))).await;
+    assert!(loads.iter().all(|load| load.as_ref().is_ok_and(|item| item.id == created.id)));
+    assert_eq!(queries.load(Ordering::SeqCst), 1);
+
+    // Once it finishes the next read queries again, as would a read of the same item asking for something else
+    cache.load(DEFAULT_TENANT, created.id, false, query).await.expect("a fresh load");
+    cache.load(DEFAULT_TENANT, created.id, true, query).await.expect("a load with deleted items");
+    assert_eq!(queries.load(Ordering::SeqCst), 3);
+
+    let app = init_app(&pool).await;
+    let uri = format!("/api/v1/items/{}", created.id);
+    let responses = join_all((0..8).map(|_| test::call_service(&app, test::TestRequest::get().uri(&uri).to_request()))).await;
+    for res in responses {
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(json_body(res).await["name"], item.name);
+    }
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn dry_runs_validate_and_roll_back() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let prefix = unique_prefix();
+
+    let req = test::TestRequest::post()
+        .uri("/api/v1/items?dry_run=true")
+        .set_json(json!({ "name": format!("{}draft", prefix), "description": "" }))
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers().get("x-dry-run").unwrap(), "true");
+    let draft = json_body(res).await;
+    let res = test::call_service(&app, test::TestRequest::get().uri(&format!("/api/v1/items/{}", draft["id"].as_str().unwrap())).to_request()).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+    // Validation still runs, so a bad payload fails the same way it would for real
+    let req = test::TestRequest::post().uri("/api/v1/items?dry_run=true").set_json(json!({ "name": "", "description": "" })).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    let req = test::TestRequest::post()
+        .uri("/api/v1/items")
+        .set_json(json!({ "name": format!("{}kept", prefix), "description": "" }))
+        .to_request();
+    let created = json_body(test::call_service(&app, req).await).await;
+    let uri = format!("/api/v1/items/{}", created["id"].as_str().unwrap());
+
+    let req = test::TestRequest::patch()
+        .uri(&uri)
+        .insert_header(("Prefer", "respond-async, dry-run"))
+        .insert_header((header::IF_MATCH, "W/\"1\""))
+        .set_json(json!({ "name": format!("{}renamed", prefix) }))
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers().get("x-dry-run").unwrap(), "true");
+    let preview = json_body(res).await;
+    assert_eq!(preview["name"], format!("{}renamed", prefix));
+    assert_eq!(preview["version"], 2);
+
+    let req = test::TestRequest::put()
+        .uri(&format!("{}?dry_run=true", uri))
+        .insert_header((header::IF_MATCH, "W/\"1\""))
+        .set_json(json!({ "name": format!("{}replaced", prefix), "description": "" }))
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(json_body(res).await["name"], format!("{}replaced", prefix));
+
+    let stored = json_body(test::call_service(&app, test::TestRequest::get().uri(&uri).to_request()).await).await;
+    assert_eq!(stored["name"], format!("{}kept", prefix));
+    assert_eq!(stored["version"], 1);
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn descriptions_can_be_replaced_as_plain_text() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let prefix = unique_prefix();
+
+    let req = test::TestRequest::post()
+        .uri("/api/v1/items")
+        .set_json(json!({ "name": format!("{}notes", prefix), "description": "draft" }))
+        .to_request();
+    let created = json_body(test::call_service(&app, req).await).await;
+    let uri = format!("/api/v1/items/{}/description", created["id"].as_str().unwrap());
+
+    let req = test::TestRequest::put()
+        .uri(&uri)
+        .insert_header((header::CONTENT_TYPE, "text/plain; charset=utf-8"))
+        .set_payload("line one\nline two — ünïcode")
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let updated = json_body(res).await;
+    assert_eq!(updated["description"], "line one\nline two — ünïcode");
+    assert_eq!(updated["name"], format!("{}notes", prefix));
+    assert_eq!(updated["version"], 2);
+
+    // A stale If-Match is refused; JSON bodies and overlong text are rejected
+    let req = test::TestRequest::put()
+        .uri(&uri)
+        .insert_header((header::CONTENT_TYPE, "text/plain"))
+        .insert_header((header::IF_MATCH, "W/\"1\""))
+        .set_payload("late")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::CONFLICT);
+    let req = test::TestRequest::put().uri(&uri).set_json(json!({ "description": "wrapped" })).to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    assert_eq!(json_body(res).await["error"], "unsupported_media_type");
+    let req = test::TestRequest::put()
+        .uri(&uri)
+        .insert_header((header::CONTENT_TYPE, "text/plain"))
+        .set_payload("x".repeat(10_001))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    let req = test::TestRequest::put()
+        .uri(&format!("/api/v1/items/{}/description", uuid::Uuid::new_v4()))
+        .insert_header((header::CONTENT_TYPE, "text/plain"))
+        .set_payload("orphan")
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::NOT_FOUND);
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn items_can_be_created_and_replaced_from_forms() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let prefix = unique_prefix();
+    let description = "50% off & more, see https://example.com/?a=1";
+
+    let req = test::TestRequest::post()
+        .uri("/api/v1/items")
+        .set_json(json!({ "name": format!("{}from-json", prefix), "description": description }))
+        .to_request();
+    let from_json = json_body(test::call_service(&app, req).await).await;
+    let req = test::TestRequest::post()
+        .uri("/api/v1/items")
+        .set_form([("name", format!("{}from-form", prefix)), ("description", description.to_string())])
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let from_form = json_body(res).await;
+    for field in ["description", "category_id", "version", "archived"] {
+        assert_eq!(from_form[field], from_json[field], "{}", field);
+    }
+
+    // A form replace carries its version as a field, like the JSON body does
+    let uri = format!("/api/v1/items/{}", from_form["id"].as_str().unwrap());
+    let req = test::TestRequest::put()
+        .uri(&uri)
+        .set_form([("name", format!("{}replaced", prefix)), ("description", "plain".to_string()), ("version", "1".to_string())])
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let stored = json_body(test::call_service(&app, test::TestRequest::get().uri(&uri).to_request()).await).await;
+    assert_eq!(stored["name"], format!("{}replaced", prefix));
+    assert_eq!(stored["description"], "plain");
+    assert_eq!(stored["version"], 2);
+
+    let req = test::TestRequest::post().uri("/api/v1/items").set_form([("description", "no name")]).to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(json_body(res).await["error"], "validation");
+
+    let req = test::TestRequest::post()
+        .uri("/api/v1/items")
+        .insert_header((header::CONTENT_TYPE, "text/xml"))
+        .set_payload("<item/>")
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    assert_eq!(json_body(res).await["error"], "unsupported_media_type");
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn list_pages_can_be_revalidated() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let prefix = unique_prefix();
+    let mut ids = Vec::new();
+    for n in 0..3 {
+        let req = test::TestRequest::post()
+            .uri("/api/v1/items")
+            .set_json(json!({ "name": format!("{}page-{}", prefix, n), "description": "" }))
+            .to_request();
+        ids.push(json_body(test::call_service(&app, req).await).await["id"].as_str().unwrap().to_string());
+    }
+    let offset_page = format!("/api/v1/items?q={}&sort=name&order=asc&per_page=2", prefix);
+    let keyset_page = format!("/api/v1/items?q={}&limit=2", prefix);
+    let app = &app;
+    let etag_of = |uri: String| async move {
+        let res = test::call_service(app, test::TestRequest::get().uri(&uri).to_request()).await;
+        assert!(res.status().is_success());
+        res.headers().get(header::ETAG).unwrap().to_str().unwrap().to_string()
+    };
+    let revalidate = |uri: String, etag: String| async move {
+        let req = test::TestRequest::get().uri(&uri).insert_header((header::IF_NONE_MATCH, etag)).to_request();
+        test::call_service(app, req).await.status()
+    };
+
+    let (offset_etag, keyset_etag) = (etag_of(offset_page.clone()).await, etag_of(keyset_page.clone()).await);
+    assert_eq!(revalidate(offset_page.clone(), offset_etag.clone()).await, StatusCode::NOT_MODIFIED);
+    assert_eq!(revalidate(keyset_page.clone(), keyset_etag.clone()).await, StatusCode::NOT_MODIFIED);
+
+    // Changing an item on the page invalidates it
+    let req = test::TestRequest::put()
+        .uri(&format!("/api/v1/items/{}", ids[0]))
+        .set_json(json!({ "name": format!("{}page-0", prefix), "description": "changed", "version": 1 }))
+        .to_request();
+    assert_eq!(test::call_service(app, req).await.status(), StatusCode::OK);
+    assert_eq!(revalidate(offset_page.clone(), offset_etag).await, StatusCode::PARTIAL_CONTENT);
+    let offset_etag = etag_of(offset_page.clone()).await;
+
+    // So does an item joining the result, even off this page, since the total changes
+    let req = test::TestRequest::post()
+        .uri("/api/v1/items")
+        .set_json(json!({ "name": format!("{}page-9", prefix), "description": "" }))
+        .to_request();
+    test::call_service(app, req).await;
+    assert_eq!(revalidate(offset_page.clone(), offset_etag).await, StatusCode::PARTIAL_CONTENT);
+    assert_ne!(etag_of(keyset_page).await, keyset_etag);
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn descriptions_are_optional_and_empty_ones_are_kept() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let prefix = unique_prefix();
+
+    let req = test::TestRequest::post().uri("/api/v1/items").set_json(json!({ "name": format!("{}bare", prefix) })).to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let bare = json_body(res).await;
+    assert!(bare["description"].is_null());
+
+    let req = test::TestRequest::post()
+        .uri("/api/v1/items")
+        .set_json(json!({ "name": format!("{}empty", prefix), "description": "" }))
+        .to_request();
+    let empty = json_body(test::call_service(&app, req).await).await;
+    let res = test::call_service(&app, test::TestRequest::get().uri(&format!("/api/v1/items/{}", empty["id"].as_str().unwrap())).to_request()).await;
+    assert_eq!(json_body(res).await["description"], "");
+
+    // A replace that leaves the description out clears it, and items without one are still searchable by name
+    let uri = format!("/api/v1/items/{}", empty["id"].as_str().unwrap());
+    let req = test::TestRequest::put().uri(&uri).set_json(json!({ "name": format!("{}empty", prefix), "version": 1 })).to_request();
+    assert!(json_body(test::call_service(&app, req).await).await["description"].is_null());
+    let req = test::TestRequest::get().uri(&format!("/api/v1/items?search={}bare", prefix)).to_request();
+    let found = json_body(test::call_service(&app, req).await).await;
+    assert_eq!(found.as_array().unwrap().len(), 1, "{}", found);
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn batch_patches_apply_together_or_not_at_all() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let prefix = unique_prefix();
+
+    let req = test::TestRequest::post()
+        .uri("/api/v1/items/batch")
+        .set_json(json!([
+            { "name": format!("{}one", prefix), "description": "first" },
+            { "name": format!("{}two", prefix), "description": "second" }
+        ]))
+        .to_request();
+    let created = json_body(test::call_service(&app, req).await).await;
+    let (one, two) = (created[0]["id"].as_str().unwrap().to_string(), created[1]["id"].as_str().unwrap().to_string());
+
+    let req = test::TestRequest::patch()
+        .uri("/api/v1/items/batch")
+        .set_json(json!([
+            { "id": two, "version": 1, "name": format!("{}second", prefix) },
+            { "id": one, "version": 1, "description": null }
+        ]))
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let updated = json_body(res).await;
+    assert_eq!(updated[0]["name"], format!("{}second", prefix));
+    assert_eq!(updated[0]["description"], "second");
+    assert!(updated[1]["description"].is_null());
+    assert_eq!(updated[1]["version"], 2);
+
+    // An unknown id rolls back the patches before it and is named in the 404
+    let missing = uuid::Uuid::new_v4();
+    let req = test::TestRequest::patch()
+        .uri("/api/v1/items/batch")
+        .set_json(json!([
+            { "id": one, "version": 2, "name": format!("{}renamed", prefix) },
+            { "id": missing, "version": 1, "name": format!("{}ghost", prefix) }
+        ]))
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    assert!(json_body(res).await["message"].as_str().unwrap().contains(&missing.to_string()));
+    let res = test::call_service(&app, test::TestRequest::get().uri(&format!("/api/v1/items/{}", one)).to_request()).await;
+    assert_eq!(json_body(res).await["name"], format!("{}one", prefix));
+
+    let req = test::TestRequest::patch().uri("/api/v1/items/batch").set_json(json!([{ "id": one, "version": 2 }, 7])).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+    let req = test::TestRequest::patch().uri("/api/v1/items/batch").set_json(json!([{ "id": one, "name": "x" }])).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::PRECONDITION_REQUIRED);
+    let too_many: Vec<_> = (0..1001).map(|_| json!({})).collect();
+    let req = test::TestRequest::patch().uri("/api/v1/items/batch").set_json(json!(too_many)).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn best_effort_batches_keep_the_rows_that_succeed() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let prefix = unique_prefix();
+
+    let req = test::TestRequest::post()
+        .uri("/api/v1/items/batch?mode=best_effort")
+        .set_json(json!([
+            { "name": format!("{}first", prefix) },
+            { "name": format!("{}FIRST", prefix) },
+            { "name": "" },
+            { "name": format!("{}last", prefix) }
+        ]))
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::MULTI_STATUS);
+    let report = json_body(res).await;
+    let created: Vec<_> = report["created"].as_array().unwrap().iter().map(|item| item["name"].clone()).collect();
+    assert_eq!(created, [json!(format!("{}first", prefix)), json!(format!("{}last", prefix))]);
+    let failed: Vec<_> = report["failed"].as_array().unwrap().iter().map(|failure| failure["index"].as_u64().unwrap()).collect();
+    assert_eq!(failed, [1, 2]);
+    assert!(report["failed"][0]["reason"].as_str().unwrap().contains("already exists"), "{}", report);
+
+    let req = test::TestRequest::get().uri(&format!("/api/v1/items?search={}last", prefix)).to_request();
+    assert_eq!(json_body(test::call_service(&app, req).await).await.as_array().unwrap().len(), 1);
+
+    // The default mode still rolls back the whole batch
+    let req = test::TestRequest::post()
+        .uri("/api/v1/items/batch")
+        .set_json(json!([{ "name": format!("{}other", prefix) }, { "name": format!("{}first", prefix) }]))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::CONFLICT);
+    let req = test::TestRequest::post().uri("/api/v1/items/batch?mode=eventually").set_json(json!([])).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn trailing_slashes_are_ignored() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let prefix = unique_prefix();
+
+    let req = test::TestRequest::post().uri("/api/v1/items/").set_json(json!({ "name": format!("{}slashed", prefix) })).to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let id = json_body(res).await["id"].as_str().unwrap().to_string();
+
+    for uri in [format!("/api/v1/items?search={}slashed", prefix), format!("/api/v1/items/?search={}slashed", prefix)] {
+        let res = test::call_service(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK, "{}", uri);
+        assert_eq!(json_body(res).await.as_array().unwrap().len(), 1, "{}", uri);
+    }
+    for uri in [format!("/api/v1/items/{}/", id), format!("/api/v1//items/{}", id)] {
+        let res = test::call_service(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+        assert_eq!(json_body(res).await["id"], id.as_str(), "{}", uri);
+    }
+    let res = test::call_service(&app, test::TestRequest::get().uri("/health/").to_request()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    // Swagger UI's index lives under a trailing slash, so it is redirected to by name
+    let res = test::call_service(&app, test::TestRequest::get().uri("/swagger-ui/").to_request()).await;
+    assert_eq!(res.headers().get(header::LOCATION).unwrap(), "/swagger-ui/index.html");
+    let res = test::call_service(&app, test::TestRequest::get().uri("/swagger-ui/index.html").to_request()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    // Switched off, the slash is significant again
+    let app = init_app_with_routes(&pool, ApiRoutes { trim_trailing_slash: false, ..ApiRoutes::default() }).await;
+    let res = test::call_service(&app, test::TestRequest::get().uri(&format!("/api/v1/items/{}/", id)).to_request()).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn duplicates_get_the_first_free_copy_name() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let prefix = unique_prefix();
+
+    let req = test::TestRequest::post().uri("/api/v1/categories").set_json(json!({ "name": format!("{}shelf", prefix) })).to_request();
+    let category = json_body(test::call_service(&app, req).await).await["id"].clone();
+    let req = test::TestRequest::post()
+        .uri("/api/v1/items")
+        .set_json(json!({ "name": format!("{}Widget", prefix), "description": "blue", "category_id": category }))
+        .to_request();
+    let source = json_body(test::call_service(&app, req).await).await;
+    let tags = json!([format!("{}blue", prefix), format!("{}sale", prefix)]);
+    let req = test::TestRequest::post().uri(&format!("/api/v1/items/{}/tags", source["id"].as_str().unwrap())).set_json(json!({ "tags": tags }));
+    assert_eq!(test::call_service(&app, req.to_request()).await.status(), StatusCode::OK);
+    let uri = format!("/api/v1/items/{}/duplicate", source["id"].as_str().unwrap());
+
+    let res = test::call_service(&app, test::TestRequest::post().uri(&uri).to_request()).await;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let location = res.headers().get(header::LOCATION).unwrap().to_str().unwrap().to_string();
+    let copy = json_body(res).await;
+    assert_eq!(location, format!("/api/v1/items/{}", copy["id"].as_str().unwrap()));
+    assert_ne!(copy["id"], source["id"]);
+    assert_eq!(copy["name"], format!("{}Widget (copy)", prefix));
+    assert_eq!(copy["description"], "blue");
+    assert_eq!(copy["category_id"], category);
+    assert_eq!(copy["tags"], tags);
+    assert_eq!(copy["version"], 1);
+    let stored = json_body(test::call_service(&app, test::TestRequest::get().uri(&location).to_request()).await).await;
+    assert_eq!(stored["tags"], tags);
+
+    // Taken names are skipped ignoring case, and copying a copy copies its name as it is
+    let req = test::TestRequest::post().uri("/api/v1/items").set_json(json!({ "name": format!("{}widget (COPY 2)", prefix) })).to_request();
+    test::call_service(&app, req).await;
+    let res = test::call_service(&app, test::TestRequest::post().uri(&uri).to_request()).await;
+    assert_eq!(json_body(res).await["name"], format!("{}Widget (copy 3)", prefix));
+    let req = test::TestRequest::post().uri(&format!("/api/v1/items/{}/duplicate", copy["id"].as_str().unwrap())).to_request();
+    assert_eq!(json_body(test::call_service(&app, req).await).await["name"], format!("{}Widget (copy) (copy)", prefix));
+
+    // A name at the length limit is shortened to make room for the suffix
+    let long = format!("{}{}", prefix, "x".repeat(200 - prefix.len()));
+    let req = test::TestRequest::post().uri("/api/v1/items").set_json(json!({ "name": long })).to_request();
+    let long_item = json_body(test::call_service(&app, req).await).await;
+    let req = test::TestRequest::post().uri(&format!("/api/v1/items/{}/duplicate", long_item["id"].as_str().unwrap())).to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let name = json_body(res).await["name"].as_str().unwrap().to_string();
+    assert!(name.len() <= 200 && name.ends_with(" (copy)"), "{}", name);
+
+    let req = test::TestRequest::post().uri(&format!("/api/v1/items/{}/duplicate", uuid::Uuid::new_v4())).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::NOT_FOUND);
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn whole_lists_can_be_streamed_as_one_array() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let prefix = unique_prefix();
+
+    let uri = format!("/api/v1/items?stream=true&q={}&sort=name&order=asc", prefix);
+    let res = test::call_service(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(json_body(res).await, json!([]));
+
+    for name in ["c", "a", "b"] {
+        let req = test::TestRequest::post().uri("/api/v1/items").set_json(json!({ "name": format!("{}{}", prefix, name) })).to_request();
+        test::call_service(&app, req).await;
+    }
+    let res = test::call_service(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+    assert_eq!(content_type(&res), "application/json");
+    assert!(res.headers().get("X-Total-Count").is_none());
+    let names: Vec<_> = json_body(res).await.as_array().unwrap().iter().map(|item| item["name"].as_str().unwrap().to_string()).collect();
+    assert_eq!(names, ["a", "b", "c"].map(|name| format!("{}{}", prefix, name)));
+
+    let res = test::call_service(&app, test::TestRequest::get().uri(&format!("{}&fields=name", uri)).to_request()).await;
+    assert_eq!(json_body(res).await[0], json!({ "name": format!("{}a", prefix) }));
+    let res = test::call_service(&app, test::TestRequest::get().uri(&format!("{}&per_page=2", uri)).to_request()).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn dedupe_returns_the_item_with_the_same_content() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let prefix = unique_prefix();
+    let create = |query: &str, name: String, description: &str| {
+        test::TestRequest::post().uri(&format!("/api/v1/items{}", query)).set_json(json!({ "name": name, "description": description })).to_request()
+    };
+
+    // Nothing to dedupe against yet, so the item is created
+    let res = test::call_service(&app, create("?dedupe=true", format!("{}report", prefix), "Quarterly figures")).await;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let original = json_body(res).await;
+
+    // The same content, up to case in the name and whitespace around the description, is that item
+    let res = test::call_service(&app, create("?dedupe=true", format!("{}REPORT", prefix), "  Quarterly figures\n")).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(json_body(res).await, original);
+
+    // A different description is still a clash of names, and without the flag so is everything
+    let res = test::call_service(&app, create("?dedupe=true", format!("{}report", prefix), "Annual figures")).await;
+    assert_eq!(res.status(), StatusCode::CONFLICT);
+    let res = test::call_service(&app, create("", format!("{}report", prefix), "Quarterly figures")).await;
+    assert_eq!(res.status(), StatusCode::CONFLICT);
+
+    let res = test::call_service(&app, test::TestRequest::get().uri(&format!("/api/v1/items?q={}", prefix)).to_request()).await;
+    assert_eq!(json_body(res).await.as_array().unwrap().len(), 1);
+
+    cleanup(&pool, &prefix).await;
+}
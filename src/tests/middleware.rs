@@ -0,0 +1,609 @@
+use actix_web::http::{header, Method, StatusCode};
+use actix_web::middleware::{from_fn, Compress};
+use actix_web::rt::time::sleep;
+use actix_web::{test, web, App, HttpResponse};
+use chrono::Utc;
+use futures_util::future::join;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::io::Read;
+use sqlx::PgPool;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::time::Duration;
+use uuid::Uuid;
+
+use super::{cleanup, content_type, init_app, json_body, test_pool, unique_prefix};
+use crate::db::{claim_item_name, with_retry, with_transaction, RetryPolicy, TransactionRetry};
+use crate::events::ItemEvents;
+use crate::error::ApiError;
+use crate::handlers::version;
+use crate::i18n::{self, Language};
+use crate::middleware::{
+    assign_request_id, capture_panic_backtraces, catch_panics, cors_policy, limit_query_length,
+    localize_errors, log_bodies, loggable_body, rate_limit, request_timeout, require_api_key,
+    server_timing, skip_small_compression, verify_export_links, ApiKeyAuth, BodyLogging,
+    ExportLinks, QueryLimit, RateLimiter, RequestTimeout, DEFAULT_TENANT,
+};
+use crate::negotiate::{Body, Format, JsonStyle};
+use crate::routes::{self, ApiPrefix, ApiRoutes};
+
+#[actix_web::test]
+async fn slow_requests_time_out() {
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(RequestTimeout { duration: Duration::from_millis(50), routes: HashMap::new() }))
+            .route(
+                "/slow",
+                web::get()
+                    .to(|| async {
+                        sleep(Duration::from_secs(5)).await;
+                        HttpResponse::Ok().finish()
+                    })
+                    .wrap(from_fn(request_timeout)),
+            )
+            .route("/fast", web::get().to(HttpResponse::Ok).wrap(from_fn(request_timeout))),
+    )
+    .await;
+
+    let res = test::call_service(&app, test::TestRequest::get().uri("/slow").to_request()).await;
+    assert_eq!(res.status(), StatusCode::GATEWAY_TIMEOUT);
+    assert_eq!(content_type(&res), "application/json");
+    assert_eq!(json_body(res).await["error"], "timeout");
+
+    let res = test::call_service(&app, test::TestRequest::get().uri("/fast").to_request()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn routes_can_have_their_own_timeouts() {
+    // The real budgets scaled down: the export gets longer than the default, item reads less
+    let routes = HashMap::from([("/items/export.csv", Duration::from_millis(500)), ("/items/{id}", Duration::from_millis(20))]);
+    let slow = || async {
+        sleep(Duration::from_millis(100)).await;
+        HttpResponse::Ok().finish()
+    };
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(RequestTimeout { duration: Duration::from_millis(200), routes }))
+            .app_data(web::Data::new(ApiPrefix("/api/v1".to_string())))
+            .service(
+                web::scope("/api/v1")
+                    .route("/items/export.csv", web::get().to(slow).wrap(from_fn(request_timeout)))
+                    .route("/items/{id}", web::get().to(slow).wrap(from_fn(request_timeout)))
+                    .route("/items", web::get().to(slow).wrap(from_fn(request_timeout))),
+            ),
+    )
+    .await;
+
+    let status = |uri: &'static str| {
+        let app = &app;
+        async move { test::call_service(app, test::TestRequest::get().uri(uri).to_request()).await.status() }
+    };
+    assert_eq!(status("/api/v1/items/export.csv").await, StatusCode::OK);
+    assert_eq!(status("/api/v1/items/42").await, StatusCode::GATEWAY_TIMEOUT);
+    assert_eq!(status("/api/v1/items").await, StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn server_timing_adds_up_every_query() {
+    let pool = test_pool().await;
+    // Two 50ms queries, one a retried read and one a transaction, then 50ms of work outside the database
+    let handler = |pool: web::Data<PgPool>| async move {
+        let policy = RetryPolicy { max_retries: 0, base_delay: Duration::ZERO };
+        with_retry(&policy, || sqlx::query("SELECT pg_sleep(0.05)").execute(pool.get_ref())).await.unwrap();
+        let retry = TransactionRetry { max_retries: 0, base_delay: Duration::ZERO };
+        with_transaction(&pool, &retry, |conn| {
+            Box::pin(async move {
+                sqlx::query("SELECT pg_sleep(0.05)").execute(conn).await?;
+                Ok(())
+            })
+        })
+        .await
+        .unwrap();
+        sleep(Duration::from_millis(50)).await;
+        HttpResponse::Ok().finish()
+    };
+    let app = test::init_service(
+        App::new().wrap(from_fn(server_timing)).app_data(web::Data::new(pool.clone())).route("/timed", web::get().to(handler)),
+    )
+    .await;
+
+    let res = test::call_service(&app, test::TestRequest::get().uri("/timed").to_request()).await;
+    let timing = res.headers().get("server-timing").unwrap().to_str().unwrap().to_string();
+    let duration = |metric: &str| -> f64 {
+        let entry = timing.split(", ").find(|entry| entry.starts_with(metric)).unwrap();
+        entry.split_once(";dur=").unwrap().1.parse().unwrap()
+    };
+    let (db, app) = (duration("db"), duration("app"));
+    assert!(db >= 100.0, "{}", timing);
+    assert!(app >= db + 50.0, "{}", timing);
+}
+
+#[actix_web::test]
+async fn server_timing_counts_the_queries_of_a_create() {
+    let pool = test_pool().await;
+    let prefix = unique_prefix();
+    let name = format!("{}timed", prefix);
+    let app = test::init_service(
+        App::new()
+            .wrap(from_fn(server_timing))
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(ItemEvents::new()))
+            .configure(|cfg| routes::configure(cfg, &ApiRoutes::default())),
+    )
+    .await;
+
+    // Hold the name for 100ms, so the create spends at least that long waiting in the database
+    let mut holder = pool.begin().await.unwrap();
+    claim_item_name(&mut holder, DEFAULT_TENANT, &name).await.unwrap();
+    let release = async {
+        sleep(Duration::from_millis(100)).await;
+        holder.rollback().await.unwrap();
+    };
+    let req = test::TestRequest::post().uri("/api/v1/items").set_json(json!({ "name": name, "description": "" }));
+    let (res, ()) = join(test::call_service(&app, req.to_request()), release).await;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let timing = res.headers().get("server-timing").unwrap().to_str().unwrap();
+    let db: f64 = timing.split(", ").find_map(|entry| entry.strip_prefix("db;dur=")).unwrap().parse().unwrap();
+    assert!(db >= 100.0, "{}", timing);
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn oversized_query_strings_are_refused() {
+    let app = test::init_service(
+        App::new()
+            .wrap(from_fn(limit_query_length))
+            .app_data(web::Data::new(QueryLimit { max_len: 64 }))
+            .route("/items", web::get().to(HttpResponse::Ok)),
+    )
+    .await;
+
+    let ids = ["00000000-0000-0000-0000-000000000000"; 2].join(",");
+    let res = test::call_service(&app, test::TestRequest::get().uri(&format!("/items?ids={}", ids)).to_request()).await;
+    assert_eq!(res.status(), StatusCode::URI_TOO_LONG);
+    assert_eq!(json_body(res).await["error"], "uri_too_long");
+
+    let res = test::call_service(&app, test::TestRequest::get().uri("/items?ids=00000000-0000-0000-0000-000000000000").to_request()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn admin_reads_need_a_key_even_when_reads_are_public() {
+    let auth = ApiKeyAuth { keys: vec!["secret".to_string()], readonly_public: true };
+    let app = test::init_service(
+        App::new()
+            .wrap(from_fn(require_api_key))
+            .app_data(web::Data::new(auth))
+            .route("/items", web::get().to(HttpResponse::Ok))
+            .route("/admin/pool", web::get().to(HttpResponse::Ok)),
+    )
+    .await;
+
+    let res = test::call_service(&app, test::TestRequest::get().uri("/items").to_request()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = test::call_service(&app, test::TestRequest::get().uri("/admin/pool").to_request()).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    let req = test::TestRequest::get().uri("/admin/pool").insert_header(("x-api-key", "secret")).to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn json_is_indented_only_when_pretty_printing_is_on() {
+    for enabled in [false, true] {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(JsonStyle { pretty: enabled, camel_case: false }))
+                .route("/", web::get().to(|format: Format| async move { format.respond(&mut HttpResponse::Ok(), &serde_json::json!({ "a": 1 })) })),
+        )
+        .await;
+
+        let res = test::call_service(&app, test::TestRequest::get().uri("/").to_request()).await;
+        assert_eq!(content_type(&res), "application/json");
+        let body = test::read_body(res).await;
+        assert_eq!(body, if enabled { "{\n  \"a\": 1\n}" } else { "{\"a\":1}" });
+    }
+}
+
+#[actix_web::test]
+async fn version_is_public_when_api_keys_are_required() {
+    let auth = ApiKeyAuth { keys: vec!["secret".to_string()], readonly_public: false };
+    let app = test::init_service(
+        App::new()
+            .wrap(from_fn(require_api_key))
+            .app_data(web::Data::new(auth))
+            .route("/version", web::get().to(version)),
+    )
+    .await;
+
+    let res = test::call_service(&app, test::TestRequest::get().uri("/version").to_request()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body = json_body(res).await;
+    assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+    assert!(body["git_sha"].as_str().is_some_and(|sha| !sha.is_empty()));
+    assert!(body["built_at"].as_str().is_some_and(|at| chrono::DateTime::parse_from_rfc3339(at).is_ok()));
+}
+
+#[actix_web::test]
+async fn rate_limits_throttle_each_client_including_failed_logins() {
+    for trust_proxy in [false, true] {
+        // Wrapped as the server wraps them: the limiter outside authentication
+        let app = test::init_service(
+            App::new()
+                .wrap(from_fn(require_api_key))
+                .wrap(from_fn(rate_limit))
+                .app_data(web::Data::new(ApiKeyAuth { keys: vec!["secret".to_string()], readonly_public: false }))
+                .app_data(web::Data::new(RateLimiter::new(2).trusting_proxy(trust_proxy)))
+                .route("/items", web::get().to(HttpResponse::Ok))
+                .route("/health", web::get().to(HttpResponse::Ok))
+                .route("/metrics", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        let from = |ip: &str| test::TestRequest::get().uri("/items").peer_addr(format!("{}:40000", ip).parse().unwrap());
+
+        // Wrong keys are refused, and each refusal spends the caller's budget
+        for remaining in ["1", "0"] {
+            let res = test::call_service(&app, from("10.0.0.1").insert_header(("X-Api-Key", "guess")).to_request()).await;
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+            assert_eq!(res.headers().get("x-ratelimit-limit").unwrap(), "2");
+            assert_eq!(res.headers().get("x-ratelimit-remaining").unwrap(), remaining);
+        }
+        let res = test::call_service(&app, from("10.0.0.1").insert_header(("X-Api-Key", "guess")).to_request()).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(res.headers().get(header::RETRY_AFTER).unwrap().to_str().unwrap().parse::<u64>().unwrap() >= 1);
+        assert_eq!(res.headers().get("x-ratelimit-remaining").unwrap(), "0");
+        assert_eq!(json_body(res).await["error"], "rate_limited");
+
+        // Probes and scrapes are never throttled, and an accepted key has a budget of its own
+        for uri in ["/health", "/metrics"] {
+            let req = test::TestRequest::get().uri(uri).peer_addr("10.0.0.1:40000".parse().unwrap()).to_request();
+            let res = test::call_service(&app, req).await;
+            assert_ne!(res.status(), StatusCode::TOO_MANY_REQUESTS, "{}", uri);
+            assert!(!res.headers().contains_key("x-ratelimit-limit"), "{}", uri);
+        }
+        let res = test::call_service(&app, from("10.0.0.1").insert_header(("X-Api-Key", "secret")).to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // Behind a trusted proxy, clients it reports apart are limited apart; otherwise the header is ignored
+        let req = from("10.0.0.1").insert_header(("X-Forwarded-For", "203.0.113.7")).insert_header(("X-Api-Key", "guess"));
+        let status = test::call_service(&app, req.to_request()).await.status();
+        let expected = if trust_proxy { StatusCode::UNAUTHORIZED } else { StatusCode::TOO_MANY_REQUESTS };
+        assert_eq!(status, expected, "trust_proxy = {}", trust_proxy);
+    }
+}
+
+#[actix_web::test]
+async fn large_bodies_are_gzipped_and_small_ones_left_alone() {
+    let items: Vec<Value> = (0..100).map(|n| json!({ "id": n, "name": format!("item {}", n), "description": "" })).collect();
+    let list = items.clone();
+    let app = test::init_service(
+        App::new()
+            .wrap(from_fn(skip_small_compression))
+            .wrap(Compress::default())
+            .route("/items", web::get().to(move || {
+                let list = list.clone();
+                async move { HttpResponse::Ok().json(list) }
+            }))
+            .route("/small", web::get().to(|| async { HttpResponse::Ok().json(json!({ "status": "ok" })) })),
+    )
+    .await;
+    let get = |uri: &str| test::TestRequest::get().uri(uri).insert_header((header::ACCEPT_ENCODING, "gzip")).to_request();
+
+    let res = test::call_service(&app, get("/items")).await;
+    assert_eq!(res.headers().get(header::CONTENT_ENCODING).unwrap(), "gzip");
+    let mut body = String::new();
+    flate2::read::GzDecoder::new(&test::read_body(res).await[..]).read_to_string(&mut body).unwrap();
+    assert_eq!(serde_json::from_str::<Value>(&body).unwrap(), Value::Array(items));
+
+    let res = test::call_service(&app, get("/small")).await;
+    assert!(res.headers().get(header::CONTENT_ENCODING).is_none_or(|encoding| encoding == "identity"));
+    assert_eq!(json_body(res).await, json!({ "status": "ok" }));
+}
+
+#[actix_web::test]
+async fn signed_export_links_stand_in_for_the_api_key() {
+    let pool = test_pool().await;
+    let prefix = unique_prefix();
+    let tenant = Uuid::new_v4();
+    let req = test::TestRequest::post()
+        .uri("/api/v1/items")
+        .insert_header(("X-Tenant-Id", tenant.to_string()))
+        .set_json(json!({ "name": format!("{}exported", prefix), "description": "" }));
+    assert_eq!(test::call_service(&init_app(&pool).await, req.to_request()).await.status(), StatusCode::CREATED);
+
+    let links = ExportLinks::new(Some("link-secret"), Duration::from_secs(900)).with_base_url(Some("https://exports.example.com/".to_string()));
+    let app = test::init_service(
+        App::new()
+            .wrap(from_fn(require_api_key))
+            .wrap(from_fn(verify_export_links))
+            .app_data(web::Data::new(ApiKeyAuth { keys: vec!["secret".to_string()], readonly_public: false }))
+            .app_data(web::Data::new(links.clone()))
+            .app_data(web::Data::new(pool.clone()))
+            .route("/api/v1/private", web::get().to(HttpResponse::Ok))
+            .configure(|cfg| routes::configure(cfg, &ApiRoutes::default())),
+    )
+    .await;
+    let get = |uri: &str| test::TestRequest::get().uri(uri).to_request();
+    assert_eq!(test::call_service(&app, get("/api/v1/items/export.csv")).await.status(), StatusCode::UNAUTHORIZED);
+
+    let req = test::TestRequest::post().uri("/api/v1/items/export/link").insert_header(("X-Api-Key", "secret")).insert_header(("X-Tenant-Id", tenant.to_string()));
+    let body = json_body(test::call_service(&app, req.to_request()).await).await;
+    let url = body["url"].as_str().unwrap();
+    let link = url.strip_prefix("https://exports.example.com").unwrap();
+    assert!(link.starts_with("/api/v1/items/export.csv?"), "{}", url);
+
+    // The link alone is enough, and exports the tenant it was made for
+    let res = test::call_service(&app, get(link)).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(String::from_utf8(test::read_body(res).await.to_vec()).unwrap().contains(&format!("{}exported", prefix)));
+
+    let expired = links.sign("/api/v1/items/export.csv", tenant, Utc::now().timestamp() - 1).unwrap();
+    let tampered = [
+        link.replace(&tenant.to_string(), &Uuid::new_v4().to_string()),
+        format!("{}0", link),
+        format!("/api/v1/items/export.csv?tenant={}&exp={}&sig={}", tenant, Utc::now().timestamp() - 1, expired),
+    ];
+    for (uri, message) in tampered.iter().zip(["signature is invalid", "signature is invalid", "has expired"]) {
+        let res = test::call_service(&app, get(uri)).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN, "{}", uri);
+        assert_eq!(json_body(res).await["message"], format!("Export link {}", message), "{}", uri);
+    }
+
+    // Elsewhere `sig` is just a query parameter: it opens nothing, and gets in the way of nothing
+    let elsewhere = link.replace("/items/export.csv", "/private");
+    assert_eq!(test::call_service(&app, get(&elsewhere)).await.status(), StatusCode::UNAUTHORIZED);
+    let req = test::TestRequest::get().uri(&elsewhere).insert_header(("X-Api-Key", "secret")).insert_header(("X-Tenant-Id", tenant.to_string()));
+    assert_eq!(test::call_service(&app, req.to_request()).await.status(), StatusCode::OK);
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Stamp {
+    created_at: String,
+    category_id: Option<u32>,
+}
+
+#[actix_web::test]
+async fn camel_case_is_opt_in_and_accepts_both_conventions() {
+    for camel_case in [false, true] {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(JsonStyle { pretty: false, camel_case }))
+                .route("/echo", web::post().to(|format: Format, stamp: Body<Stamp>| async move { format.respond(&mut HttpResponse::Ok(), &stamp.into_inner()) })),
+        )
+        .await;
+
+        let req = test::TestRequest::post().uri("/echo").set_json(json!({ "created_at": "now", "category_id": 1 })).to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let expected = if camel_case { json!({ "createdAt": "now", "categoryId": 1 }) } else { json!({ "created_at": "now", "category_id": 1 }) };
+        assert_eq!(json_body(res).await, expected);
+
+        let req = test::TestRequest::post().uri("/echo").set_json(json!({ "createdAt": "now", "categoryId": 1 })).to_request();
+        let res = test::call_service(&app, req).await;
+        if camel_case {
+            assert_eq!(json_body(res).await, expected);
+        } else {
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        }
+    }
+}
+
+#[actix_web::test]
+async fn logged_bodies_still_reach_the_handler_and_client() {
+    let app = test::init_service(
+        App::new()
+            .wrap(from_fn(log_bodies))
+            .app_data(web::Data::new(BodyLogging { enabled: true, max_bytes: 16 }))
+            .route("/echo", web::post().to(|body: web::Json<serde_json::Value>| async move { HttpResponse::Ok().json(body.into_inner()) })),
+    )
+    .await;
+
+    let sent = json!({ "name": "widget", "password": "hunter2", "tags": ["a", "b"] });
+    let res = test::call_service(&app, test::TestRequest::post().uri("/echo").set_json(&sent).to_request()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(json_body(res).await, sent);
+
+    // Only the logged copy is redacted and cut short
+    let logged = loggable_body(br#"{"user":{"Api-Key":"k","name":"n"},"accessToken":"t"}"#, 1024);
+    assert_eq!(logged, r#"{"accessToken":"[redacted]","user":{"Api-Key":"[redacted]","name":"n"}}"#);
+    assert_eq!(loggable_body("ééé".as_bytes(), 3), "é... (4 bytes truncated)");
+}
+
+#[actix_web::test]
+async fn cors_preflights_are_cacheable_and_custom_headers_readable() {
+    let origins = ["https://app.example".to_string()];
+    let app = test::init_service(App::new().wrap(cors_policy(&origins, 600)).route("/items", web::get().to(HttpResponse::Ok))).await;
+    let preflight = |origin: &str| {
+        test::TestRequest::default()
+            .method(Method::OPTIONS)
+            .uri("/items")
+            .insert_header((header::ORIGIN, origin))
+            .insert_header((header::ACCESS_CONTROL_REQUEST_METHOD, "GET"))
+            .to_request()
+    };
+    let listed = |headers: &header::HeaderMap, name: header::HeaderName| {
+        let mut values: Vec<String> = headers.get(name).unwrap().to_str().unwrap().split(',').map(|value| value.trim().to_lowercase()).collect();
+        values.sort_unstable();
+        values
+    };
+
+    let res = test::call_service(&app, preflight("https://app.example")).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers().get(header::ACCESS_CONTROL_MAX_AGE).unwrap(), "600");
+    assert_eq!(listed(res.headers(), header::ACCESS_CONTROL_ALLOW_METHODS), ["delete", "get", "patch", "post", "put"]);
+    assert_eq!(
+        listed(res.headers(), header::ACCESS_CONTROL_ALLOW_HEADERS),
+        ["authorization", "content-type", "idempotency-key", "if-match", "if-modified-since", "if-none-match", "prefer", "x-api-key", "x-tenant-id"]
+    );
+    let req = test::TestRequest::default()
+        .method(Method::OPTIONS)
+        .uri("/items")
+        .insert_header((header::ORIGIN, "https://app.example"))
+        .insert_header((header::ACCESS_CONTROL_REQUEST_METHOD, "PATCH"))
+        .insert_header((header::ACCESS_CONTROL_REQUEST_HEADERS, "x-api-key, x-tenant-id, if-match, idempotency-key"))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+    // An origin that isn't listed gets no CORS headers to work with
+    let res = test::call_service(&app, preflight("https://evil.example")).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    assert!(res.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+
+    // Browsers read Access-Control-Expose-Headers from the actual response, not the preflight
+    let req = test::TestRequest::get().uri("/items").insert_header((header::ORIGIN, "https://app.example")).to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(
+        listed(res.headers(), header::ACCESS_CONTROL_EXPOSE_HEADERS),
+        [
+            "content-range",
+            "etag",
+            "link",
+            "location",
+            "retry-after",
+            "x-next-cursor",
+            "x-page-size-clamped",
+            "x-ratelimit-limit",
+            "x-ratelimit-remaining",
+            "x-request-id",
+            "x-total-count"
+        ]
+    );
+
+    // A wildcard policy answers any origin, but never with credentials
+    let app = test::init_service(App::new().wrap(cors_policy(&["*".to_string()], 600)).route("/items", web::get().to(HttpResponse::Ok))).await;
+    let res = test::call_service(&app, preflight("https://anywhere.example")).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(), "*");
+    assert!(res.headers().get(header::ACCESS_CONTROL_ALLOW_CREDENTIALS).is_none());
+    let req = test::TestRequest::get().uri("/items").insert_header((header::ORIGIN, "https://anywhere.example")).to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(), "*");
+    assert!(res.headers().get(header::ACCESS_CONTROL_ALLOW_CREDENTIALS).is_none());
+}
+
+#[actix_web::test]
+async fn panicking_handlers_answer_with_a_json_500() {
+    capture_panic_backtraces();
+    let app = test::init_service(
+        App::new()
+            .wrap(from_fn(assign_request_id))
+            .service(
+                web::resource("/panics")
+                    .wrap(from_fn(catch_panics))
+                    .route(web::get().to(|| async { panic!("deliberate test panic") as HttpResponse })),
+            )
+            .service(web::resource("/fine").wrap(from_fn(catch_panics)).route(web::get().to(HttpResponse::Ok))),
+    )
+    .await;
+
+    let req = test::TestRequest::get().uri("/panics").insert_header(("x-request-id", "panic-test")).to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(res.headers().get("x-request-id").unwrap(), "panic-test");
+    assert_eq!(content_type(&res), "application/json");
+    assert_eq!(json_body(res).await["error"], "internal");
+
+    // The worker survives to serve the next request
+    let res = test::call_service(&app, test::TestRequest::get().uri("/fine").to_request()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn error_messages_follow_accept_language() {
+    let app = test::init_service(
+        App::new()
+            .wrap(from_fn(localize_errors))
+            .route("/missing", web::get().to(|| async { Err::<HttpResponse, _>(ApiError::NotFound("Item not found".to_string())) }))
+            .route(
+                "/batch",
+                web::post().to(|| async { Err::<HttpResponse, _>(ApiError::Conflict("An item with that name already exists".to_string()).at_index(3)) }),
+            )
+            .route("/fine", web::get().to(|| async { HttpResponse::Ok().json(json!({ "message": "Item not found" })) }))
+            .route("/unknown", web::get().to(|| async { Err::<HttpResponse, _>(ApiError::Validation(format!("page must be at most {}", 10))) })),
+    )
+    .await;
+    let get = |uri: &str, language: Option<&str>| {
+        let req = test::TestRequest::get().uri(uri);
+        match language {
+            Some(language) => req.insert_header((header::ACCEPT_LANGUAGE, language)).to_request(),
+            None => req.to_request(),
+        }
+    };
+
+    let res = test::call_service(&app, get("/missing", None)).await;
+    assert!(res.headers().get(header::CONTENT_LANGUAGE).is_none());
+    assert_eq!(res.headers().get(header::VARY).unwrap(), "accept-language");
+    assert_eq!(json_body(res).await["message"], "Item not found");
+
+    for language in ["es", "es-MX", "fr, es;q=0.5", "de;q=0.9, ES-es;q=0.8, en;q=0.1"] {
+        let res = test::call_service(&app, get("/missing", Some(language))).await;
+        assert_eq!(res.headers().get(header::CONTENT_LANGUAGE).unwrap(), "es", "{}", language);
+        let body = json_body(res).await;
+        assert_eq!(body["message"], "Elemento no encontrado", "{}", language);
+        assert_eq!(body["error"], "not_found");
+    }
+    for language in ["fr", "es;q=0, en", "en, es;q=0.9", "*", "not a language tag;;"] {
+        let res = test::call_service(&app, get("/missing", Some(language))).await;
+        assert_eq!(json_body(res).await["message"], "Item not found", "{}", language);
+    }
+
+    // Batch positions are kept, and successful responses are never touched
+    let req = test::TestRequest::post().uri("/batch").insert_header((header::ACCEPT_LANGUAGE, "es")).to_request();
+    let body = json_body(test::call_service(&app, req).await).await;
+    assert_eq!(body["message"], "elemento 3: Ya existe un elemento con ese nombre");
+    assert_eq!(body["error"], "conflict");
+    let res = test::call_service(&app, get("/fine", Some("es"))).await;
+    assert!(res.headers().get(header::VARY).is_none());
+    assert_eq!(json_body(res).await["message"], "Item not found");
+
+    // A message without a translation stays English and isn't labelled as Spanish
+    let res = test::call_service(&app, get("/unknown", Some("es"))).await;
+    assert!(res.headers().get(header::CONTENT_LANGUAGE).is_none());
+    assert_eq!(json_body(res).await["message"], "page must be at most 10");
+}
+
+// Every message written out as a literal where an ApiError is built, as found in the source
+fn static_error_messages() -> Vec<&'static str> {
+    let sources = [
+        include_str!("../cache.rs"),
+        include_str!("../db.rs"),
+        include_str!("../error.rs"),
+        include_str!("../flags.rs"),
+        include_str!("../handlers.rs"),
+        include_str!("../middleware.rs"),
+        include_str!("../models.rs"),
+        include_str!("../negotiate.rs"),
+        include_str!("../routes.rs"),
+    ];
+    let mut messages = Vec::new();
+    for source in sources {
+        for (start, _) in source.match_indices("ApiError::") {
+            let rest = source[start + "ApiError::".len()..].trim_start_matches(|c: char| c.is_ascii_alphanumeric());
+            if let Some(literal) = rest.strip_prefix('(').map(str::trim_start).and_then(|rest| rest.strip_prefix('"')) {
+                messages.push(&literal[..literal.find('"').expect("the literal is closed")]);
+            }
+        }
+    }
+    // The messages From<sqlx::Error> picks by constraint
+    for line in include_str!("../error.rs").lines() {
+        if let Some(message) = line.split_once("=> \"").and_then(|(_, literal)| literal.strip_suffix("\".to_string(),")) {
+            messages.push(message);
+        }
+    }
+    messages
+}
+
+#[actix_web::test]
+async fn every_static_error_message_has_a_translation() {
+    let messages = static_error_messages();
+    assert!(messages.contains(&"Item not found") && messages.contains(&"Cannot delete category with existing items"));
+    let missing: Vec<_> = messages.into_iter().filter(|message| matches!(i18n::translate(Language::Spanish, message), Cow::Borrowed(_))).collect();
+    assert!(missing.is_empty(), "messages without a Spanish translation: {:#?}", missing);
+}
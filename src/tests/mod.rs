@@ -0,0 +1,137 @@
+// Integration tests against a real Postgres named by TEST_DATABASE_URL.
+// Tests share the database, so each one works on rows named with its own unique prefix
+// and removes them when it finishes.
+
+mod config;
+mod items;
+mod middleware;
+mod probes;
+mod tenants;
+
+use actix_http::Request;
+use actix_web::body::MessageBody;
+use actix_web::dev::{Service, ServiceResponse};
+use actix_web::{test, web, App, Error};
+use serde_json::Value;
+use sqlx::PgPool;
+use std::env;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::cache::ItemCache;
+use crate::config::Config;
+use crate::db::{self, PoolSettings, ReadPool, RetryPolicy, TransactionRetry};
+use crate::error::{form_error_handler, json_error_handler};
+use crate::events::ItemEvents;
+use crate::flags::FeatureFlags;
+use crate::handlers::{BulkTruncate, DevMode, ImportLimits, MaintenanceOps, MigrationCheck, Readiness};
+use crate::middleware::{ExportLinks, MaintenanceMode, Metrics};
+use crate::routes::{self, ApiRoutes};
+use crate::DEFAULT_MAX_JSON_BODY_BYTES;
+
+fn test_database_url() -> String {
+    env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL must be set to run the tests")
+}
+
+// Connect to the test database and apply migrations. A finished test's pool isn't closed, so its
+// idle connections are reaped quickly to keep the suite under the server's connection limit.
+async fn test_pool() -> PgPool {
+    db::connect(&test_database_url(), &PoolSettings { idle_timeout_secs: 1, ..PoolSettings::default() }).await
+}
+
+// The configuration of a deployment that sets nothing but DATABASE_URL
+fn test_config() -> Config {
+    let url = test_database_url();
+    Config::from_lookup(|name| (name == "DATABASE_URL").then(|| url.clone())).expect("the defaults are a valid configuration")
+}
+
+// The application with every route and the state handlers expect, but none of the middleware
+// beyond the path normalization routing relies on
+async fn init_app(pool: &PgPool) -> impl Service<Request, Response = ServiceResponse<impl MessageBody>, Error = Error> {
+    init_app_with_routes(pool, ApiRoutes::default()).await
+}
+
+// The test application with the resource routes mounted as `api` describes
+async fn init_app_with_routes(pool: &PgPool, api: ApiRoutes) -> impl Service<Request, Response = ServiceResponse<impl MessageBody>, Error = Error> {
+    init_app_with(pool, api, ItemCache::new(0, Duration::ZERO)).await
+}
+
+// The test application with the given routes and item cache
+async fn init_app_with(pool: &PgPool, api: ApiRoutes, cache: ItemCache) -> impl Service<Request, Response = ServiceResponse<impl MessageBody>, Error = Error> {
+    let maintenance = MaintenanceMode::default();
+    test::init_service(
+        App::new()
+            .app_data(web::Data::new(test_config()))
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(ReadPool(pool.clone())))
+            .app_data(web::Data::new(RetryPolicy { max_retries: 0, base_delay: Duration::ZERO }))
+            .app_data(web::Data::new(TransactionRetry { max_retries: 3, base_delay: Duration::from_millis(1) }))
+            .app_data(web::Data::new(ItemEvents::new()))
+            .app_data(web::Data::new(cache))
+            .app_data(web::Data::new(Readiness::default()))
+            .app_data(web::Data::new(FeatureFlags::new(maintenance.clone())))
+            .app_data(web::Data::new(maintenance))
+            .app_data(web::Data::new(Metrics::new()))
+            .app_data(web::Data::new(ImportLimits { max_bytes: DEFAULT_MAX_JSON_BODY_BYTES }))
+            .app_data(web::Data::new(BulkTruncate { allowed: false }))
+            .app_data(web::Data::new(MaintenanceOps { allowed: false }))
+            .app_data(web::Data::new(DevMode { enabled: false }))
+            .app_data(web::Data::new(MigrationCheck { strict: false }))
+            .app_data(web::Data::new(ExportLinks::new(None, Duration::from_secs(900))))
+            .app_data(web::JsonConfig::default().limit(DEFAULT_MAX_JSON_BODY_BYTES).error_handler(json_error_handler))
+            .app_data(web::FormConfig::default().limit(DEFAULT_MAX_JSON_BODY_BYTES).error_handler(form_error_handler))
+            .app_data(web::PayloadConfig::new(DEFAULT_MAX_JSON_BODY_BYTES))
+            .wrap(api.normalize_path())
+            .configure(|cfg| routes::configure(cfg, &api)),
+    )
+    .await
+}
+
+// A name prefix no other test run will use, so tests can find and clean up only their own rows
+fn unique_prefix() -> String {
+    format!("test-{}-", Uuid::new_v4().simple())
+}
+
+// Delete every item (and idempotency key or audit record pointing at one), category and tag whose name starts with `prefix`
+async fn cleanup(pool: &PgPool, prefix: &str) {
+    let pattern = format!("{}%", prefix);
+    sqlx::query("DELETE FROM idempotency_keys WHERE item_id IN (SELECT id FROM items WHERE name LIKE $1)")
+        .bind(&pattern)
+        .execute(pool)
+        .await
+        .expect("clean up idempotency keys");
+    sqlx::query("DELETE FROM items WHERE name LIKE $1")
+        .bind(&pattern)
+        .execute(pool)
+        .await
+        .expect("clean up items");
+    sqlx::query("DELETE FROM item_audit WHERE snapshot->>'name' LIKE $1")
+        .bind(&pattern)
+        .execute(pool)
+        .await
+        .expect("clean up audit records");
+    sqlx::query("DELETE FROM categories WHERE name LIKE $1")
+        .bind(&pattern)
+        .execute(pool)
+        .await
+        .expect("clean up categories");
+    sqlx::query("DELETE FROM tags WHERE name LIKE $1")
+        .bind(&pattern)
+        .execute(pool)
+        .await
+        .expect("clean up tags");
+}
+
+// The response's Content-Type, or "" when it has none
+fn content_type<B>(res: &ServiceResponse<B>) -> &str {
+    res.headers()
+        .get(actix_web::http::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("")
+}
+
+// Read the response body as JSON
+async fn json_body<B: MessageBody>(res: ServiceResponse<B>) -> Value {
+    let body = test::read_body(res).await;
+    serde_json::from_slice(&body).unwrap_or_else(|err| panic!("response is not JSON ({}): {:?}", err, body))
+}
@@ -0,0 +1,368 @@
+use actix_web::http::{header, StatusCode};
+use actix_web::{test, web, App, HttpResponse};
+use actix_web::rt::time::sleep;
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use serde_json::json;
+use sqlx::PgPool;
+use uuid::Uuid;
+use std::env;
+use std::str::FromStr;
+use std::time::Duration;
+
+use super::{cleanup, content_type, init_app, json_body, test_pool, unique_prefix};
+use crate::db::{self, with_statement_timeout, PoolSettings, ReadPool, RetryPolicy, TransactionRetry};
+use crate::error::ApiError;
+use crate::flags::{FeatureFlags, FUZZY_SEARCH};
+use crate::events::ItemEvents;
+use crate::handlers::{DevMode, MaintenanceOps, MigrationCheck, Readiness};
+use crate::middleware::MaintenanceMode;
+use crate::routes::{self, ApiRoutes};
+
+#[actix_web::test]
+async fn probes_report_ok() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+
+    for uri in ["/health", "/livez", "/readyz"] {
+        let res = test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK, "{}", uri);
+        assert_eq!(json_body(res).await["status"], "ok");
+    }
+}
+
+#[actix_web::test]
+async fn readiness_fails_once_draining() {
+    let pool = test_pool().await;
+    let readiness = Readiness::default();
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(ReadPool(pool.clone())))
+            .app_data(web::Data::new(readiness.clone()))
+            .configure(|cfg| routes::configure(cfg, &ApiRoutes::default())),
+    )
+    .await;
+
+    readiness.begin_draining();
+    let res = test::call_service(&app, test::TestRequest::get().uri("/readyz").to_request()).await;
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(json_body(res).await["status"], "draining");
+
+    // Liveness is unaffected: the process is still up while it drains
+    let res = test::call_service(&app, test::TestRequest::get().uri("/livez").to_request()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn drained_instances_refuse_writes_until_undrained() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let prefix = unique_prefix();
+    let post = |uri: &str| test::TestRequest::post().uri(uri).to_request();
+    let create = || test::TestRequest::post().uri("/api/v1/items").set_json(json!({ "name": format!("{}drained", prefix) })).to_request();
+
+    let res = test::call_service(&app, post("/admin/drain")).await;
+    assert_eq!(json_body(res).await["draining"], true);
+    let res = test::call_service(&app, test::TestRequest::get().uri("/readyz").to_request()).await;
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let res = test::call_service(&app, create()).await;
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert!(res.headers().contains_key(header::RETRY_AFTER));
+    let res = test::call_service(&app, test::TestRequest::get().uri("/api/v1/items?per_page=1").to_request()).await;
+    assert!(res.status().is_success());
+
+    let res = test::call_service(&app, post("/admin/undrain")).await;
+    assert_eq!(json_body(res).await["draining"], false);
+    let res = test::call_service(&app, test::TestRequest::get().uri("/readyz").to_request()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(test::call_service(&app, create()).await.status(), StatusCode::CREATED);
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn seeding_needs_dev_mode_and_repeats_for_a_seed() {
+    let pool = test_pool().await;
+    let res = test::call_service(&init_app(&pool).await, test::TestRequest::post().uri("/admin/seed").to_request()).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(TransactionRetry { max_retries: 0, base_delay: Duration::ZERO }))
+            .app_data(web::Data::new(DevMode { enabled: true }))
+            .app_data(web::Data::new(ItemEvents::new()))
+            .configure(|cfg| routes::configure(cfg, &ApiRoutes::default())),
+    )
+    .await;
+    let seed = |tenant: Uuid, query: &str| test::TestRequest::post().uri(&format!("/admin/seed?{}", query)).insert_header(("X-Tenant-Id", tenant.to_string())).to_request();
+    let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+    assert_eq!(test::call_service(&app, seed(first, "count=1001")).await.status(), StatusCode::BAD_REQUEST);
+
+    let res = test::call_service(&app, seed(first, "count=5&seed=7")).await;
+    assert_eq!(json_body(res).await, json!({ "created": 5, "seed": 7 }));
+    test::call_service(&app, seed(second, "count=5&seed=7")).await;
+    let names = |tenant: Uuid| sqlx::query_scalar::<_, String>("SELECT name FROM items WHERE tenant_id = $1 ORDER BY name").bind(tenant).fetch_all(&pool);
+    let seeded = names(first).await.unwrap();
+    assert_eq!(seeded.len(), 5);
+    assert_eq!(seeded, names(second).await.unwrap());
+
+    // The same seed regenerates the same names, which the tenant already has
+    assert_eq!(test::call_service(&app, seed(first, "count=5&seed=7")).await.status(), StatusCode::CONFLICT);
+
+    sqlx::query("DELETE FROM items WHERE tenant_id = ANY($1)").bind([first, second]).execute(&pool).await.unwrap();
+}
+
+#[actix_web::test]
+async fn feature_flags_switch_at_runtime_and_reach_other_instances() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let flag = |name: &str, enabled: bool| test::TestRequest::put().uri(&format!("/admin/flags/{}", name)).set_json(json!({ "enabled": enabled })).to_request();
+    let fuzzy = || test::TestRequest::get().uri("/api/v1/items?fuzzy=wigdet").to_request();
+
+    let res = test::call_service(&app, test::TestRequest::get().uri("/admin/flags").to_request()).await;
+    assert_eq!(json_body(res).await, json!([{ "name": "fuzzy_search", "enabled": true }, { "name": "maintenance_mode", "enabled": false }]));
+    assert!(test::call_service(&app, fuzzy()).await.status().is_success());
+
+    let res = test::call_service(&app, flag("fuzzy_search", false)).await;
+    assert_eq!(json_body(res).await, json!({ "name": "fuzzy_search", "enabled": false }));
+    assert_eq!(test::call_service(&app, fuzzy()).await.status(), StatusCode::BAD_REQUEST);
+
+    // Another instance follows on its next refresh, maintenance mode included
+    test::call_service(&app, flag("maintenance_mode", true)).await;
+    let res = test::call_service(&app, test::TestRequest::post().uri("/api/v1/items").set_json(json!({ "name": "refused" })).to_request()).await;
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let maintenance = MaintenanceMode::default();
+    let other = FeatureFlags::new(maintenance.clone());
+    assert!(other.is_enabled(FUZZY_SEARCH));
+    other.refresh(&pool).await.unwrap();
+    assert!(!other.is_enabled(FUZZY_SEARCH));
+    assert!(maintenance.enabled());
+
+    assert_eq!(test::call_service(&app, flag("telepathy", true)).await.status(), StatusCode::NOT_FOUND);
+
+    sqlx::query("DELETE FROM feature_flags").execute(&pool).await.unwrap();
+}
+
+#[actix_web::test]
+async fn migration_status_counts_what_has_not_run() {
+    let pool = test_pool().await;
+    let res = test::call_service(&init_app(&pool).await, test::TestRequest::get().uri("/health/migrations").to_request()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(json_body(res).await, json!({ "migrations": "up_to_date", "pending_count": 0 }));
+
+    // A schema whose migration table lacks the newest version stands in for a deploy that skipped it
+    let schema = format!("migrations_{}", Uuid::new_v4().simple());
+    sqlx::query(&format!("CREATE SCHEMA {}", schema)).execute(&pool).await.unwrap();
+    sqlx::query(&format!(
+        "CREATE TABLE {0}._sqlx_migrations AS SELECT version, success FROM public._sqlx_migrations WHERE version < (SELECT MAX(version) FROM public._sqlx_migrations)",
+        schema
+    ))
+    .execute(&pool)
+    .await
+    .unwrap();
+    let behind = PgPoolOptions::new()
+        .max_connections(1)
+        .connect_with(
+            PgConnectOptions::from_str(&env::var("TEST_DATABASE_URL").unwrap()).unwrap().options([("search_path", schema.as_str())]),
+        )
+        .await
+        .unwrap();
+    for (strict, status) in [(false, StatusCode::OK), (true, StatusCode::SERVICE_UNAVAILABLE)] {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(behind.clone()))
+                .app_data(web::Data::new(MigrationCheck { strict }))
+                .app_data(web::Data::new(RetryPolicy { max_retries: 0, base_delay: Duration::ZERO }))
+                .configure(|cfg| routes::configure(cfg, &ApiRoutes::default())),
+        )
+        .await;
+        let res = test::call_service(&app, test::TestRequest::get().uri("/health/migrations").to_request()).await;
+        assert_eq!(res.status(), status);
+        assert_eq!(json_body(res).await, json!({ "migrations": "pending", "pending_count": 1 }));
+    }
+
+    behind.close().await;
+    sqlx::query(&format!("DROP SCHEMA {} CASCADE", schema)).execute(&pool).await.unwrap();
+}
+
+#[actix_web::test]
+async fn unreachable_replica_fails_probes() {
+    let pool = test_pool().await;
+    let replica = PgPoolOptions::new()
+        .acquire_timeout(Duration::from_millis(200))
+        .connect_lazy("postgres://postgres@127.0.0.1:1/unreachable")
+        .expect("valid replica URL");
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(ReadPool(replica)))
+            .app_data(web::Data::new(Readiness::default()))
+            .configure(|cfg| routes::configure(cfg, &ApiRoutes::default())),
+    )
+    .await;
+
+    for (uri, status) in [("/health", "degraded"), ("/readyz", "unavailable")] {
+        let res = test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE, "{}", uri);
+        assert_eq!(json_body(res).await["status"], status);
+    }
+}
+
+#[actix_web::test]
+async fn pool_stats_report_capacity() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+
+    let res = test::call_service(&app, test::TestRequest::get().uri("/admin/pool").to_request()).await;
+    assert!(matches!(res.status(), StatusCode::OK | StatusCode::SERVICE_UNAVAILABLE));
+    let stats = json_body(res).await;
+    assert!(stats["size"].as_u64().unwrap() <= stats["max"].as_u64().unwrap());
+    assert!(stats["idle"].is_u64());
+}
+
+#[actix_web::test]
+async fn slow_statements_are_cancelled_as_timeouts() {
+    let url = env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL must be set to run the tests");
+    let pool = with_statement_timeout(PgPoolOptions::new().max_connections(1), 50)
+        .connect(&url)
+        .await
+        .expect("connect with a statement timeout");
+    let app = test::init_service(App::new().app_data(web::Data::new(pool)).route(
+        "/slow",
+        web::get().to(|pool: web::Data<PgPool>| async move {
+            sqlx::query("SELECT pg_sleep(5)").execute(pool.get_ref()).await?;
+            Ok::<_, ApiError>(HttpResponse::Ok().finish())
+        }),
+    ))
+    .await;
+
+    let res = test::call_service(&app, test::TestRequest::get().uri("/slow").to_request()).await;
+    assert_eq!(res.status(), StatusCode::GATEWAY_TIMEOUT);
+    assert_eq!(content_type(&res), "application/json");
+    assert_eq!(json_body(res).await["error"], "timeout");
+}
+
+#[actix_web::test]
+async fn connections_are_recycled_after_their_lifetime() {
+    let url = env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL must be set to run the tests");
+    let settings = PoolSettings { max_connections: 1, max_lifetime_secs: 1, ..PoolSettings::default() };
+    let pool = db::connect(&url, &settings).await;
+    let backend = || async { sqlx::query_scalar::<_, i32>("SELECT pg_backend_pid()").fetch_one(&pool).await.expect("backend pid") };
+
+    let first = backend().await;
+    assert_eq!(backend().await, first);
+    sleep(Duration::from_millis(1100)).await;
+    assert_ne!(backend().await, first);
+
+    assert_eq!(PoolSettings { max_lifetime_secs: 0, ..settings }.max_lifetime(), None);
+}
+
+#[actix_web::test]
+async fn exhausted_pools_ask_clients_to_back_off() {
+    let url = env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL must be set to run the tests");
+    let pool = PgPoolOptions::new()
+        .max_connections(1)
+        .acquire_timeout(Duration::from_millis(100))
+        .connect(&url)
+        .await
+        .expect("connect with a single connection");
+    let app = init_app(&pool).await;
+
+    let held = pool.acquire().await.expect("hold the only connection");
+    let res = test::call_service(&app, test::TestRequest::get().uri("/api/v1/items").to_request()).await;
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(res.headers().get(header::RETRY_AFTER).unwrap(), "1");
+    assert_eq!(json_body(res).await["error"], "overloaded");
+
+    drop(held);
+    let res = test::call_service(&app, test::TestRequest::get().uri("/api/v1/items").to_request()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn maintenance_mode_blocks_writes_but_not_reads() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let prefix = unique_prefix();
+    let create = || test::TestRequest::post().uri("/api/v1/items").set_json(json!({ "name": format!("{}kept-out", prefix), "description": "" }));
+
+    let req = test::TestRequest::post().uri("/admin/maintenance").set_json(json!({ "enabled": true })).to_request();
+    assert_eq!(json_body(test::call_service(&app, req).await).await, json!({ "enabled": true }));
+
+    let res = test::call_service(&app, test::TestRequest::get().uri("/api/v1/items").to_request()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let res = test::call_service(&app, test::TestRequest::post().uri("/api/v1/items/batch-get").set_json(json!({ "ids": [] })).to_request()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = test::call_service(&app, create().to_request()).await;
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert!(res.headers().contains_key(header::RETRY_AFTER));
+    assert_eq!(json_body(res).await["error"], "maintenance");
+    let res = test::call_service(&app, test::TestRequest::delete().uri(&format!("/api/v1/items/{}", Uuid::new_v4())).to_request()).await;
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    // Switching it off takes effect immediately, without a restart
+    let req = test::TestRequest::post().uri("/admin/maintenance").set_json(json!({ "enabled": false })).to_request();
+    test::call_service(&app, req).await;
+    let res = test::call_service(&app, test::TestRequest::get().uri("/admin/maintenance").to_request()).await;
+    assert_eq!(json_body(res).await, json!({ "enabled": false }));
+    assert_eq!(test::call_service(&app, create().to_request()).await.status(), StatusCode::CREATED);
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn a_read_only_database_refuses_writes_but_serves_reads() {
+    let pool = test_pool().await;
+    let prefix = unique_prefix();
+    let req = test::TestRequest::post().uri("/api/v1/items").set_json(json!({ "name": format!("{}before", prefix), "description": "" }));
+    let created = json_body(test::call_service(&init_app(&pool).await, req.to_request()).await).await;
+
+    // Every session on this pool starts read-only, as on a standby promoted in the primary's place
+    let read_only = PgPoolOptions::new()
+        .max_connections(1)
+        .connect_with(PgConnectOptions::from_str(&env::var("TEST_DATABASE_URL").unwrap()).unwrap().options([("default_transaction_read_only", "on")]))
+        .await
+        .unwrap();
+    let app = init_app(&read_only).await;
+
+    let req = test::TestRequest::post().uri("/api/v1/items").set_json(json!({ "name": format!("{}refused", prefix), "description": "" }));
+    let res = test::call_service(&app, req.to_request()).await;
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert!(res.headers().contains_key(header::RETRY_AFTER));
+    assert_eq!(json_body(res).await["error"], "read_only");
+
+    let res = test::call_service(&app, test::TestRequest::get().uri(&format!("/api/v1/items/{}", created["id"].as_str().unwrap())).to_request()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(json_body(res).await["name"], format!("{}before", prefix));
+
+    read_only.close().await;
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn analyze_needs_the_flag_and_maintenance_mode() {
+    let pool = test_pool().await;
+    let res = test::call_service(&init_app(&pool).await, test::TestRequest::post().uri("/admin/analyze").to_request()).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    let maintenance = MaintenanceMode::default();
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::new(MaintenanceOps { allowed: true }))
+            .app_data(web::Data::new(maintenance.clone()))
+            .configure(|cfg| routes::configure(cfg, &ApiRoutes::default())),
+    )
+    .await;
+    let res = test::call_service(&app, test::TestRequest::post().uri("/admin/analyze").to_request()).await;
+    assert_eq!(res.status(), StatusCode::CONFLICT);
+
+    maintenance.set(true);
+    let res = test::call_service(&app, test::TestRequest::post().uri("/admin/analyze").to_request()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let report = json_body(res).await;
+    assert_eq!(report["table"], "items");
+    assert!(report["elapsed_ms"].is_u64());
+}
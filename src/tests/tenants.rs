@@ -0,0 +1,232 @@
+use actix_web::http::{Method, StatusCode};
+use actix_web::test;
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use super::{cleanup, init_app, json_body, test_pool, unique_prefix};
+use crate::db;
+
+const TENANT_HEADER: &str = "X-Tenant-Id";
+
+#[actix_web::test]
+async fn tenants_cannot_see_each_others_items() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let prefix = unique_prefix();
+    let (owner, other) = (Uuid::new_v4().to_string(), Uuid::new_v4().to_string());
+    let name = format!("{}shared-name", prefix);
+
+    let req = test::TestRequest::post()
+        .uri("/api/v1/items")
+        .insert_header((TENANT_HEADER, owner.as_str()))
+        .set_json(json!({ "name": name, "description": "" }))
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let item = json_body(res).await;
+    let uri = format!("/api/v1/items/{}", item["id"].as_str().unwrap());
+
+    let req = test::TestRequest::get().uri(&uri).insert_header((TENANT_HEADER, owner.as_str())).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+    // Another tenant, or a request naming none, gets 404s as if the item didn't exist
+    for tenant in [Some(other.as_str()), None] {
+        let with_tenant = |req: test::TestRequest| match tenant {
+            Some(tenant) => req.insert_header((TENANT_HEADER, tenant)),
+            None => req,
+        };
+        for req in [
+            test::TestRequest::get().uri(&uri),
+            test::TestRequest::default().method(Method::HEAD).uri(&uri),
+            test::TestRequest::get().uri(&format!("{}/history", uri)),
+            test::TestRequest::patch().uri(&uri).set_json(json!({ "name": "stolen", "version": 1 })),
+            test::TestRequest::put().uri(&uri).set_json(json!({ "name": "stolen", "description": "", "version": 1 })),
+            test::TestRequest::delete().uri(&uri),
+            test::TestRequest::post().uri(&format!("{}/restore", uri)),
+        ] {
+            let res = test::call_service(&app, with_tenant(req).to_request()).await;
+            assert_eq!(res.status(), StatusCode::NOT_FOUND, "tenant {:?}", tenant);
+        }
+
+        let req = with_tenant(test::TestRequest::get().uri(&format!("/api/v1/items?q={}", prefix))).to_request();
+        assert_eq!(json_body(test::call_service(&app, req).await).await, json!([]));
+    }
+
+    // Nor can its version be bumped from another tenant
+    let id = Uuid::parse_str(item["id"].as_str().unwrap()).unwrap();
+    let mut conn = pool.acquire().await.unwrap();
+    let touched = db::touch_item(&mut conn, Uuid::parse_str(&other).unwrap(), id, None).await;
+    assert!(matches!(touched, Err(sqlx::Error::RowNotFound)), "{:?}", touched);
+    drop(conn);
+    let req = test::TestRequest::get().uri(&uri).insert_header((TENANT_HEADER, owner.as_str())).to_request();
+    assert_eq!(json_body(test::call_service(&app, req).await).await["version"], 1);
+
+    // Names only collide within a tenant
+    let req = test::TestRequest::post()
+        .uri("/api/v1/items")
+        .insert_header((TENANT_HEADER, other.as_str()))
+        .set_json(json!({ "name": name, "description": "" }))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::CREATED);
+
+    let req = test::TestRequest::get().uri(&uri).insert_header((TENANT_HEADER, owner.as_str())).to_request();
+    assert_eq!(json_body(test::call_service(&app, req).await).await["name"], name);
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn malformed_tenant_header_is_rejected() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+
+    let req = test::TestRequest::get().uri("/api/v1/items").insert_header((TENANT_HEADER, "not-a-uuid")).to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(json_body(res).await["error"], "validation");
+}
+
+#[actix_web::test]
+async fn item_counts_come_from_the_counter_and_can_be_reconciled() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let prefix = unique_prefix();
+    let tenant = Uuid::new_v4();
+    let count = || async {
+        let req = test::TestRequest::get()
+            .uri("/api/v1/items/count")
+            .insert_header((TENANT_HEADER, tenant.to_string()))
+            .to_request();
+        json_body(test::call_service(&app, req).await).await["count"].clone()
+    };
+
+    // A tenant without items has no counter yet and is counted from the table
+    assert_eq!(count().await, 0);
+
+    let mut ids = Vec::new();
+    for n in 0..3 {
+        let req = test::TestRequest::post()
+            .uri("/api/v1/items")
+            .insert_header((TENANT_HEADER, tenant.to_string()))
+            .set_json(json!({ "name": format!("{}counted-{}", prefix, n), "description": "" }))
+            .to_request();
+        ids.push(json_body(test::call_service(&app, req).await).await["id"].as_str().unwrap().to_string());
+    }
+    assert_eq!(count().await, 3);
+
+    // Deleted and archived items drop out of the count
+    for req in [
+        test::TestRequest::delete().uri(&format!("/api/v1/items/{}", ids[0])),
+        test::TestRequest::post().uri(&format!("/api/v1/items/{}/archive", ids[1])),
+    ] {
+        let res = test::call_service(&app, req.insert_header((TENANT_HEADER, tenant.to_string())).to_request()).await;
+        assert!(res.status().is_success());
+    }
+    assert_eq!(count().await, 1);
+
+    // A drifted counter is what the count reports, until it is reconciled
+    sqlx::query("UPDATE item_counters SET live = 7 WHERE tenant_id = $1").bind(tenant).execute(&pool).await.unwrap();
+    assert_eq!(count().await, 7);
+    let req = test::TestRequest::post()
+        .uri("/api/v1/items/count/reconcile")
+        .insert_header((TENANT_HEADER, tenant.to_string()))
+        .to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(json_body(res).await, json!({ "count": 1, "drift": 6 }));
+    assert_eq!(count().await, 1);
+
+    cleanup(&pool, &prefix).await;
+    sqlx::query("DELETE FROM item_counters WHERE tenant_id = $1").bind(tenant).execute(&pool).await.unwrap();
+}
+
+#[actix_web::test]
+async fn item_stats_count_each_category_and_the_uncategorized() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let prefix = unique_prefix();
+    let tenant = Uuid::new_v4().to_string();
+    let stats = |query: &'static str| {
+        let req = test::TestRequest::get().uri(&format!("/api/v1/items/stats{}", query)).insert_header((TENANT_HEADER, tenant.as_str())).to_request();
+        test::call_service(&app, req)
+    };
+    assert_eq!(json_body(stats("").await).await, json!([]));
+
+    let req = test::TestRequest::post().uri("/api/v1/categories").set_json(json!({ "name": format!("{}tools", prefix) })).to_request();
+    let category = json_body(test::call_service(&app, req).await).await["id"].clone();
+    for (name, category_id) in [("hammer", category.clone()), ("saw", category.clone()), ("misc", Value::Null)] {
+        let req = test::TestRequest::post()
+            .uri("/api/v1/items")
+            .insert_header((TENANT_HEADER, tenant.as_str()))
+            .set_json(json!({ "name": format!("{}{}", prefix, name), "description": "", "category_id": category_id }))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::CREATED);
+    }
+
+    let res = stats("").await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(json_body(res).await, json!([{ "category_id": category, "count": 2 }, { "category_id": null, "count": 1 }]));
+    assert_eq!(json_body(stats("?since=2999-01-01T00:00:00Z").await).await, json!([]));
+    assert_eq!(stats("?since=yesterday").await.status(), StatusCode::BAD_REQUEST);
+
+    cleanup(&pool, &prefix).await;
+}
+
+#[actix_web::test]
+async fn an_empty_table_lists_as_an_empty_page() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let tenant = Uuid::new_v4().to_string();
+    let list = |query: &str| test::TestRequest::get().uri(&format!("/api/v1/items{}", query)).insert_header((TENANT_HEADER, tenant.as_str())).to_request();
+
+    // The brand-new tenant has no items at all, which is a result like any other
+    for query in ["", "?page=3", "?q=anything", "?limit=10"] {
+        let res = test::call_service(&app, list(query)).await;
+        assert_eq!(res.status(), StatusCode::OK, "{}", query);
+        if !query.starts_with("?limit") {
+            assert_eq!(res.headers().get("X-Total-Count").unwrap(), "0", "{}", query);
+        }
+        assert_eq!(json_body(res).await, json!([]), "{}", query);
+    }
+
+    let res = test::call_service(&app, list("?envelope=true")).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers().get("X-Total-Count").unwrap(), "0");
+    let body = json_body(res).await;
+    assert_eq!(body["data"], json!([]));
+    assert_eq!(body["meta"]["total"], 0);
+}
+
+#[actix_web::test]
+async fn bounds_name_the_newest_and_oldest_items() {
+    let pool = test_pool().await;
+    let app = init_app(&pool).await;
+    let prefix = unique_prefix();
+    let tenant = Uuid::new_v4().to_string();
+    let bounds = || test::TestRequest::get().uri("/api/v1/items/bounds").insert_header((TENANT_HEADER, tenant.as_str())).to_request();
+    let create = |name: &str| {
+        test::TestRequest::post()
+            .uri("/api/v1/items")
+            .insert_header((TENANT_HEADER, tenant.as_str()))
+            .set_json(json!({ "name": format!("{}{}", prefix, name), "description": "" }))
+            .to_request()
+    };
+
+    let res = test::call_service(&app, bounds()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(json_body(res).await, json!({ "newest": null, "oldest": null }));
+
+    // A single item is both the newest and the oldest
+    let first = json_body(test::call_service(&app, create("first")).await).await;
+    let body = json_body(test::call_service(&app, bounds()).await).await;
+    assert_eq!(body["newest"], first);
+    assert_eq!(body["oldest"], first);
+
+    test::call_service(&app, create("second")).await;
+    let third = json_body(test::call_service(&app, create("third")).await).await;
+    let body = json_body(test::call_service(&app, bounds()).await).await;
+    assert_eq!(body["newest"], third);
+    assert_eq!(body["oldest"], first);
+
+    cleanup(&pool, &prefix).await;
+}